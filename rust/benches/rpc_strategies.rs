@@ -0,0 +1,46 @@
+// Per-call vs batched vs cached RPC strategies for the ownership check and the fee
+// verification, against a synthetic node with a fixed round trip time. `bench` on the
+// command line runs the same comparison against a live node.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use rust::bench::{check_ownership, verify_fees, Cache, Fixture, STRATEGIES};
+use std::time::Duration;
+
+// About what a round trip to a local bitcoind costs
+const LATENCY: Duration = Duration::from_micros(200);
+
+fn ownership(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ownership");
+    for count in [10, 50] {
+        let fixture = Fixture::new(count, 2, LATENCY);
+        for strategy in STRATEGIES {
+            let cache = Cache::default();
+            group.bench_with_input(
+                BenchmarkId::new(format!("{strategy:?}"), fixture.addresses.len()),
+                &fixture,
+                |b, fixture| {
+                    b.iter(|| check_ownership(fixture, &fixture.addresses, strategy, &cache))
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn fees(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fees");
+    for count in [10, 50] {
+        let fixture = Fixture::new(count, 2, LATENCY);
+        for strategy in STRATEGIES {
+            let cache = Cache::default();
+            group.bench_with_input(
+                BenchmarkId::new(format!("{strategy:?}"), fixture.children.len()),
+                &fixture,
+                |b, fixture| b.iter(|| verify_fees(fixture, &fixture.children, strategy, &cache)),
+            );
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ownership, fees);
+criterion_main!(benches);
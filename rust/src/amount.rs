@@ -0,0 +1,142 @@
+use bitcoincore_rpc::bitcoin::amount::{Denomination, ParseAmountError};
+use bitcoincore_rpc::bitcoin::Amount;
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    UnknownUnit(String),
+    Invalid(ParseAmountError),
+    // More than will ever exist
+    TooMuch(Amount),
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AmountError::UnknownUnit(unit) => {
+                write!(f, "unknown unit {unit:?}, use btc or sat")
+            }
+            AmountError::Invalid(e) => write!(f, "{e}"),
+            AmountError::TooMuch(amount) => {
+                write!(
+                    f,
+                    "{amount} is more than the {} there will ever be",
+                    Amount::MAX_MONEY
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+// Parse amounts the way people type them: "12.5btc", "12.5 BTC", "1250000000sat",
+// "1250000000 sats", "20" (plain numbers are BTC). mBTC, uBTC and bits work too.
+pub fn parse_amount(s: &str) -> Result<Amount, AmountError> {
+    let s = s.trim();
+    let split = s.find(|c: char| c.is_alphabetic()).unwrap_or(s.len());
+    let (number, unit) = (s[..split].trim(), s[split..].trim().to_lowercase());
+
+    let denomination = match unit.as_str() {
+        "" | "btc" => Denomination::Bitcoin,
+        "sat" | "sats" | "satoshi" | "satoshis" => Denomination::Satoshi,
+        "mbtc" => Denomination::MilliBitcoin,
+        "ubtc" => Denomination::MicroBitcoin,
+        "bit" | "bits" => Denomination::Bit,
+        _ => return Err(AmountError::UnknownUnit(unit)),
+    };
+    let amount = Amount::from_str_in(number, denomination).map_err(AmountError::Invalid)?;
+    if amount > Amount::MAX_MONEY {
+        return Err(AmountError::TooMuch(amount));
+    }
+    Ok(amount)
+}
+
+// An amount in a JSON request: a string with a unit ("0.5btc", "10000sat") or a plain
+// number of BTC
+pub fn from_json(amount: &Value) -> Result<Amount, String> {
+    match amount {
+        Value::String(s) => parse_amount(s).map_err(|e| e.to_string()),
+        Value::Number(n) => n
+            .as_f64()
+            .and_then(|btc| Amount::from_btc(btc).ok())
+            .filter(|amount| *amount <= Amount::MAX_MONEY)
+            .ok_or_else(|| format!("{n} is not an amount of BTC")),
+        other => Err(format!("amount must be a string or a number, not {other}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_btc_and_satoshis() {
+        let amount = Amount::from_sat(1_250_000_000);
+        for typed in [
+            "12.5btc",
+            "12.5 BTC",
+            " 12.5  Btc ",
+            "12.5",
+            "1250000000sat",
+            "1250000000 sats",
+            "1250000000 Satoshis",
+            "12500 mBTC",
+        ] {
+            assert_eq!(parse_amount(typed), Ok(amount), "{typed}");
+        }
+        assert_eq!(parse_amount("20"), Ok(Amount::from_int_btc(20)));
+        assert_eq!(parse_amount("0.00000001"), Ok(Amount::ONE_SAT));
+        assert_eq!(parse_amount("21000000 btc"), Ok(Amount::MAX_MONEY));
+
+        assert_eq!(
+            parse_amount("5 dollars"),
+            Err(AmountError::UnknownUnit("dollars".to_owned()))
+        );
+        for bad in [
+            "0.000000001btc",
+            "1.5sat",
+            "-1btc",
+            "-100sat",
+            "21000000.00000001btc",
+            "99999999999999999999sat",
+            "",
+            "btc",
+            "1.2.3",
+        ] {
+            assert!(
+                matches!(
+                    parse_amount(bad),
+                    Err(AmountError::Invalid(_) | AmountError::TooMuch(_))
+                ),
+                "{bad}"
+            );
+        }
+        assert_eq!(
+            parse_amount("21000000.00000001btc"),
+            Err(AmountError::TooMuch(Amount::MAX_MONEY + Amount::ONE_SAT))
+        );
+    }
+
+    #[test]
+    fn reads_json_strings_and_numbers() {
+        assert_eq!(
+            from_json(&json!("0.5btc")),
+            Ok(Amount::from_sat(50_000_000))
+        );
+        assert_eq!(from_json(&json!("10000sat")), Ok(Amount::from_sat(10_000)));
+        assert_eq!(from_json(&json!(0.5)), Ok(Amount::from_sat(50_000_000)));
+        assert_eq!(from_json(&json!(2)), Ok(Amount::from_int_btc(2)));
+        // A number is always BTC, even when it looks like satoshis
+        assert_eq!(from_json(&json!(10000)), Ok(Amount::from_int_btc(10_000)));
+
+        assert!(from_json(&json!(-1)).is_err());
+        assert!(from_json(&json!(21_000_001)).is_err());
+        assert!(from_json(&json!(0.000000001)).is_err());
+        assert!(from_json(&json!("1.5sat")).is_err());
+        assert!(from_json(&json!(true)).is_err());
+        assert!(from_json(&json!(null)).is_err());
+    }
+}
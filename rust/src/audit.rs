@@ -0,0 +1,314 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash};
+use bitcoincore_rpc::bitcoin::hex::FromHex;
+use bitcoincore_rpc::bitcoin::psbt::{Input, Psbt};
+use bitcoincore_rpc::bitcoin::{OutPoint, Transaction, Txid};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::Result;
+use crate::store;
+
+// Where signing is recorded unless --audit-log says otherwise, next to the checkpoint
+pub const DEFAULT_LOG: &str = ".signer-audit.jsonl";
+
+// Wallet calls that sign. walletprocesspsbt only does when its `sign` isn't false.
+const SIGNING_METHODS: &[&str] = &[
+    "signrawtransactionwithwallet",
+    "walletprocesspsbt",
+    "send",
+    "sendall",
+    "sendmany",
+    "sendtoaddress",
+    "bumpfee",
+];
+
+// Set once at startup, the lock keeps appends from different threads whole and in order
+static LOG: OnceLock<Mutex<PathBuf>> = OnceLock::new();
+
+// One signing operation. `previous` is the SHA256 of the line before it, so a record
+// removed or edited later breaks the chain from there on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    // Unix seconds
+    pub timestamp: u64,
+    // The wallet the node signed with, or `key:<address>` for keys held by this program
+    pub wallet: String,
+    pub method: String,
+    pub txid: Option<Txid>,
+    // Of the PSBT the signer handed back, what's passed along to the next step
+    pub psbt_sha256: Option<sha256::Hash>,
+    // The inputs this operation added signatures to, when the call says which
+    pub inputs_signed: Option<Vec<OutPoint>>,
+    pub complete: bool,
+    pub previous: Option<sha256::Hash>,
+}
+
+// A record read back, and whether the line before it is the one it was chained to
+#[derive(Debug)]
+pub struct Entry {
+    pub record: Record,
+    pub intact: bool,
+}
+
+pub fn log_to(path: &Path) {
+    let _ = LOG.set(Mutex::new(path.to_owned()));
+}
+
+pub fn is_signing(method: &str) -> bool {
+    SIGNING_METHODS.contains(&method)
+}
+
+// The wallet of a /wallet/<name> endpoint, "" being the node's default wallet
+fn wallet_of(url: &str) -> String {
+    url.split_once("/wallet/")
+        .map_or("", |(_, wallet)| wallet)
+        .to_owned()
+}
+
+fn decode_tx(hex: &str) -> Option<Transaction> {
+    encode::deserialize(&Vec::<u8>::from_hex(hex).ok()?).ok()
+}
+
+fn decode_psbt(base64: &str) -> Option<(Vec<u8>, Psbt)> {
+    let bytes = BASE64.decode(base64).ok()?;
+    let psbt = Psbt::deserialize(&bytes).ok()?;
+    Some((bytes, psbt))
+}
+
+fn has_signature(input: &Input) -> bool {
+    !input.partial_sigs.is_empty()
+        || input.tap_key_sig.is_some()
+        || !input.tap_script_sigs.is_empty()
+        || input.final_script_sig.is_some()
+        || input.final_script_witness.is_some()
+}
+
+// What a successful signing call did, from its parameters and result. None when it
+// didn't sign anything (walletprocesspsbt with sign=false) or isn't a signing call.
+pub fn describe(url: &str, method: &str, params: &[Value], result: &Value) -> Option<Record> {
+    let mut record = Record {
+        timestamp: 0,
+        wallet: wallet_of(url),
+        method: method.to_owned(),
+        txid: None,
+        psbt_sha256: None,
+        inputs_signed: None,
+        complete: result["complete"].as_bool().unwrap_or(true),
+        previous: None,
+    };
+    match method {
+        "signrawtransactionwithwallet" => {
+            let tx = decode_tx(result["hex"].as_str()?)?;
+            let failed: Vec<OutPoint> = result["errors"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|e| {
+                    Some(OutPoint::new(
+                        e["txid"].as_str()?.parse().ok()?,
+                        e["vout"].as_u64()? as u32,
+                    ))
+                })
+                .collect();
+            record.inputs_signed = Some(
+                tx.input
+                    .iter()
+                    .map(|i| i.previous_output)
+                    .filter(|o| !failed.contains(o))
+                    .collect(),
+            );
+            record.txid = Some(tx.txid());
+        }
+        "walletprocesspsbt" => {
+            if params.get(1).and_then(Value::as_bool) == Some(false) {
+                return None;
+            }
+            let before = params.first().and_then(Value::as_str).and_then(decode_psbt);
+            let (bytes, after) = decode_psbt(result["psbt"].as_str()?)?;
+            let signed_before = |i: usize| {
+                before
+                    .as_ref()
+                    .is_some_and(|(_, psbt)| psbt.inputs.get(i).is_some_and(has_signature))
+            };
+            record.inputs_signed = Some(
+                after
+                    .unsigned_tx
+                    .input
+                    .iter()
+                    .zip(&after.inputs)
+                    .enumerate()
+                    .filter(|(i, (_, input))| has_signature(input) && !signed_before(*i))
+                    .map(|(_, (txin, _))| txin.previous_output)
+                    .collect(),
+            );
+            record.txid = Some(after.unsigned_tx.txid());
+            record.psbt_sha256 = Some(sha256::Hash::hash(&bytes));
+        }
+        // The wallet funds these itself, only `send` says with what and only when it
+        // returns the transaction instead of broadcasting it
+        _ => {
+            let tx = result["hex"].as_str().and_then(decode_tx);
+            record.inputs_signed = tx
+                .as_ref()
+                .map(|tx| tx.input.iter().map(|i| i.previous_output).collect());
+            record.txid = match result {
+                Value::String(txid) => txid.parse().ok(),
+                _ => result["txid"]
+                    .as_str()
+                    .and_then(|txid| txid.parse().ok())
+                    .or(tx.map(|tx| tx.txid())),
+            };
+        }
+    }
+    Some(record)
+}
+
+// The hash the next record chains to
+fn line_hash(line: &str) -> sha256::Hash {
+    sha256::Hash::hash(line.as_bytes())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// Timestamp `record`, chain it to the log's last line and append it
+pub fn append(path: &Path, mut record: Record) -> Result<()> {
+    // Held from reading the last line to writing the next, or two processes signing at
+    // once would both chain to the same line
+    let _lock = store::lock(path)?;
+    let existing = match fs::read_to_string(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        read => read?,
+    };
+    record.timestamp = now();
+    record.previous = existing.lines().last().map(line_hash);
+    let mut log = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(log, "{}", serde_json::to_string(&record)?)?;
+    Ok(())
+}
+
+// Append `record` to the log set with `log_to`, if there is one. A failure to record is
+// reported rather than failing the signing that already happened.
+pub fn note(record: Record) {
+    if let Some(log) = LOG.get() {
+        let path = log.lock().unwrap();
+        if let Err(e) = append(&path, record) {
+            eprintln!(
+                "Failed to write the signer audit log {}: {e}",
+                path.display()
+            );
+        }
+    }
+}
+
+pub fn read(path: &Path) -> Result<Vec<Entry>> {
+    let mut previous = None;
+    let mut entries = vec![];
+    let log = {
+        let _lock = store::lock_shared(path)?;
+        fs::read_to_string(path)?
+    };
+    for line in log.lines() {
+        let record: Record = serde_json::from_str(line)?;
+        entries.push(Entry {
+            intact: record.previous == previous,
+            record,
+        });
+        previous = Some(line_hash(line));
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::TxIn;
+    use serde_json::json;
+
+    #[test]
+    fn describes_signing_calls() {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Txid::all_zeros(), vout),
+                    ..TxIn::default()
+                })
+                .collect(),
+            output: vec![],
+        };
+        let hex = encode::serialize_hex(&tx);
+        let first = tx.input[0].previous_output;
+        let result = json!({
+            "hex": hex,
+            "complete": false,
+            "errors": [{ "txid": first.txid, "vout": first.vout, "error": "Input not found" }],
+        });
+        let record = describe(
+            "http://127.0.0.1:18443/wallet/Miner",
+            "signrawtransactionwithwallet",
+            &[json!(hex)],
+            &result,
+        )
+        .unwrap();
+        assert_eq!(record.wallet, "Miner");
+        assert_eq!(record.txid, Some(tx.txid()));
+        assert_eq!(
+            record.inputs_signed,
+            Some(vec![tx.input[1].previous_output])
+        );
+        assert!(!record.complete);
+
+        let txid = tx.txid().to_string();
+        let sent = describe("/wallet/Miner", "sendtoaddress", &[], &json!(txid)).unwrap();
+        assert_eq!(sent.txid, Some(tx.txid()));
+        assert_eq!(sent.inputs_signed, None);
+
+        assert!(describe(
+            "",
+            "walletprocesspsbt",
+            &[json!("cHNidP8="), json!(false)],
+            &json!({})
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn chains_records() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let record = describe(
+            "/wallet/Miner",
+            "sendtoaddress",
+            &[],
+            &json!(Txid::all_zeros()),
+        )
+        .unwrap();
+        append(&path, record.clone()).unwrap();
+        append(&path, record.clone()).unwrap();
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.intact));
+        assert_eq!(entries[0].record.previous, None);
+
+        // Dropping the first record breaks the second's link
+        let log = fs::read_to_string(&path).unwrap();
+        fs::write(&path, log.lines().nth(1).unwrap()).unwrap();
+        assert!(!read(&path).unwrap()[0].intact);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,364 @@
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::{Address, Amount, ScriptBuf, SignedAmount, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use crate::amount;
+use crate::builder::{
+    OUTPUT_BASE_VBYTES, P2WPKH_INPUT_VBYTES, P2WPKH_SCRIPT_LEN, TX_OVERHEAD_VBYTES,
+};
+use crate::config;
+use crate::error::Result;
+use crate::http::{Request, Response, Service};
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    // Flush as soon as this many payments are waiting
+    pub max_payments: usize,
+    // Flush payments that have waited this long even if the batch isn't full
+    pub interval: Duration,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptions {
+            max_payments: 10,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Payment {
+    pub address: Address,
+    pub amount: Amount,
+}
+
+// `amount` as in `amount::from_json`
+#[derive(Debug, Deserialize)]
+struct PaymentRequest {
+    address: Address<NetworkUnchecked>,
+    amount: Value,
+}
+
+impl Payment {
+    // A `{address, amount}` request body, the address for the active profile's network
+    pub fn from_json(body: &[u8]) -> std::result::Result<Payment, String> {
+        let request: PaymentRequest = serde_json::from_slice(body)
+            .map_err(|e| format!("expected {{address, amount}}: {e}"))?;
+        let address = request
+            .address
+            .require_network(config::active().network)
+            .map_err(|e| e.to_string())?;
+        let amount = amount::from_json(&request.amount)?;
+        if amount == Amount::ZERO {
+            return Err("amount must be positive".to_owned());
+        }
+        Ok(Payment { address, amount })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchReport {
+    pub txid: Txid,
+    pub payments: usize,
+    pub vsize: u64,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee: Amount,
+    // What the payments would have paid sent one by one at the batch's feerate, each
+    // spending one input and making change
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub individual_fee: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub savings: SignedAmount,
+}
+
+// Collects payments and sends them together in one sendmany, when enough have queued up
+// or the oldest has waited an interval
+#[derive(Debug)]
+pub struct Batcher {
+    options: BatchOptions,
+    queue: Vec<Payment>,
+    // When the oldest payment in the queue arrived
+    oldest: Option<Instant>,
+    // Not due again before this, after a flush failed
+    retry_at: Option<Instant>,
+}
+
+impl Batcher {
+    pub fn new(options: BatchOptions) -> Self {
+        Batcher {
+            options,
+            queue: vec![],
+            oldest: None,
+            retry_at: None,
+        }
+    }
+
+    pub fn queued(&self) -> &[Payment] {
+        &self.queue
+    }
+
+    pub fn push(&mut self, payment: Payment, now: Instant) {
+        self.oldest.get_or_insert(now);
+        self.queue.push(payment);
+    }
+
+    pub fn due(&self, now: Instant) -> bool {
+        if self.retry_at.is_some_and(|retry_at| now < retry_at) {
+            return false;
+        }
+        self.queue.len() >= self.options.max_payments
+            || self.oldest.is_some_and(|oldest| {
+                now.saturating_duration_since(oldest) >= self.options.interval
+            })
+    }
+
+    // A flush failed: wait an interval before the next try, however full the queue is, and
+    // count the interval from now so the same error isn't repeated every poll
+    pub fn back_off(&mut self, now: Instant) {
+        self.retry_at = Some(now + self.options.interval);
+        if self.oldest.is_some() {
+            self.oldest = Some(now);
+        }
+    }
+
+    // Send whatever is queued from `wallet` in one transaction, None if nothing is. Once
+    // sendmany returns the payments are out of the queue, even if reading the transaction
+    // back fails, or the next flush would pay them all again.
+    pub fn flush(&mut self, wallet: &impl RpcApi) -> Result<Option<BatchReport>> {
+        if self.queue.is_empty() {
+            return Ok(None);
+        }
+        // sendmany takes each address once, payments to the same one are added up
+        let mut amounts = BTreeMap::<String, Amount>::new();
+        for payment in &self.queue {
+            *amounts.entry(payment.address.to_string()).or_default() += payment.amount;
+        }
+        let amounts: BTreeMap<_, _> = amounts
+            .into_iter()
+            .map(|(address, amount)| (address, amount.to_btc()))
+            .collect();
+        let txid: Txid = wallet.call("sendmany", &[json!(""), json!(amounts)])?;
+        let payments = std::mem::take(&mut self.queue);
+        self.oldest = None;
+        self.retry_at = None;
+        let read_back = |e: bitcoincore_rpc::Error| {
+            bitcoincore_rpc::Error::ReturnedError(format!(
+                "sent {} payment(s) in {txid} but could not read it back: {e}",
+                payments.len()
+            ))
+        };
+        let sent = wallet.get_transaction(&txid, None).map_err(read_back)?;
+        let tx = sent
+            .transaction()
+            .map_err(|e| read_back(bitcoincore_rpc::Error::from(e)))?;
+        let fee = sent.fee.map_or(Amount::ZERO, |fee| {
+            Amount::from_sat(fee.to_sat().unsigned_abs())
+        });
+        let recipients: Vec<ScriptBuf> =
+            payments.iter().map(|p| p.address.script_pubkey()).collect();
+        Ok(Some(compare(txid, &tx, fee, &recipients)))
+    }
+}
+
+// The batcher over HTTP: `POST /pay {address, amount}` queues a payment, `POST /flush`
+// sends the queue now, `GET /queue` shows it and `GET /batches` what was sent so far
+pub struct BatchService<R> {
+    wallet: R,
+    batcher: Batcher,
+    pub batches: Vec<BatchReport>,
+}
+
+impl<R: RpcApi> BatchService<R> {
+    pub fn new(wallet: R, options: BatchOptions) -> Self {
+        BatchService {
+            wallet,
+            batcher: Batcher::new(options),
+            batches: vec![],
+        }
+    }
+
+    // Send the queue now, e.g. what's left when the service stops
+    pub fn flush(&mut self) -> Result<Option<&BatchReport>> {
+        let Some(report) = self.batcher.flush(&self.wallet)? else {
+            return Ok(None);
+        };
+        println!(
+            "Sent {} payment(s) in {} for {}, {} less than one by one",
+            report.payments, report.txid, report.fee, report.savings
+        );
+        self.batches.push(report);
+        Ok(self.batches.last())
+    }
+}
+
+impl<R: RpcApi> Service for BatchService<R> {
+    fn handle(&mut self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/pay") => match Payment::from_json(&request.body) {
+                Ok(payment) => {
+                    self.batcher.push(payment, Instant::now());
+                    Response::json(200, &json!({ "queued": self.batcher.queued().len() }))
+                }
+                Err(e) => Response::error(400, e),
+            },
+            ("POST", "/flush") => match self.flush() {
+                Ok(report) => Response::json(200, &json!({ "batch": report })),
+                Err(e) => Response::error(500, e.to_string()),
+            },
+            ("GET", "/queue") => {
+                let queued = self.batcher.queued();
+                let total: Amount = queued.iter().map(|p| p.amount).sum();
+                Response::json(
+                    200,
+                    &json!({ "payments": queued.len(), "amount": total.to_btc() }),
+                )
+            }
+            ("GET", "/batches") => {
+                let saved: SignedAmount = self.batches.iter().map(|b| b.savings).sum();
+                Response::json(
+                    200,
+                    &json!({ "batches": self.batches, "savings": saved.to_btc() }),
+                )
+            }
+            _ => Response::not_found(),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.batcher.due(Instant::now()) {
+            if let Err(e) = self.flush() {
+                eprintln!(
+                    "Flushing failed, {} payment(s) still queued: {e}",
+                    self.batcher.queued().len()
+                );
+                self.batcher.back_off(Instant::now());
+            }
+        }
+    }
+}
+
+// Put the batch next to the same payments sent one at a time
+pub fn compare(txid: Txid, tx: &Transaction, fee: Amount, recipients: &[ScriptBuf]) -> BatchReport {
+    let vsize = tx.vsize() as u64;
+    // The individual sends are assumed to get P2WPKH change when the batch had none
+    let change_len = tx
+        .output
+        .iter()
+        .find(|o| !recipients.contains(&o.script_pubkey))
+        .map_or(P2WPKH_SCRIPT_LEN, |o| o.script_pubkey.len() as u64);
+    let individual_vsize: u64 = recipients
+        .iter()
+        .map(|script| {
+            TX_OVERHEAD_VBYTES
+                + P2WPKH_INPUT_VBYTES
+                + OUTPUT_BASE_VBYTES
+                + script.len() as u64
+                + OUTPUT_BASE_VBYTES
+                + change_len
+        })
+        .sum();
+    let individual_fee = Amount::from_sat(
+        (fee.to_sat() as f64 * individual_vsize as f64 / vsize.max(1) as f64).round() as u64,
+    );
+    BatchReport {
+        txid,
+        payments: recipients.len(),
+        vsize,
+        fee,
+        individual_fee,
+        savings: SignedAmount::from_sat(individual_fee.to_sat() as i64 - fee.to_sat() as i64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{OutPoint, TxIn, TxOut, WPubkeyHash, Witness};
+
+    fn p2wpkh(byte: u8) -> ScriptBuf {
+        ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([byte; 20]))
+    }
+
+    #[test]
+    fn flushes_when_full_or_old() {
+        let options = BatchOptions {
+            max_payments: 2,
+            interval: Duration::from_secs(30),
+        };
+        let address: Address = "bcrt1qv5plgft75j0hegtvf6zs5pajh7k0gxg2dhj224"
+            .parse::<Address<_>>()
+            .unwrap()
+            .assume_checked();
+        let payment = Payment {
+            address,
+            amount: Amount::from_sat(10_000),
+        };
+        let start = Instant::now();
+        let mut batcher = Batcher::new(options);
+        assert!(!batcher.due(start));
+        batcher.push(payment.clone(), start);
+        assert!(!batcher.due(start + Duration::from_secs(10)));
+        assert!(batcher.due(start + Duration::from_secs(30)));
+        batcher.push(payment.clone(), start);
+        assert!(batcher.due(start));
+
+        // A failed flush waits an interval, full or not
+        batcher.back_off(start);
+        assert!(!batcher.due(start + Duration::from_secs(29)));
+        assert!(batcher.due(start + Duration::from_secs(30)));
+
+        // Sent is sent: the payments leave the queue even though the lookup after failed
+        let wallet = MockClient::new()
+            .returns("sendmany", json!("11".repeat(32)))
+            .fails("gettransaction", -5, "Invalid or non-wallet transaction id");
+        let error = batcher.flush(&wallet).unwrap_err().to_string();
+        assert!(error.contains("sent 2 payment(s)"), "{error}");
+        assert!(batcher.queued().is_empty());
+        assert!(!batcher.due(start + Duration::from_secs(60)));
+        wallet.assert_done();
+    }
+
+    #[test]
+    fn estimates_what_batching_saved() {
+        // Two inputs, three payments and change, signed P2WPKH
+        let input = TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), 0),
+            witness: Witness::from_slice(&[vec![0; 72], vec![0; 33]]),
+            ..TxIn::default()
+        };
+        let recipients = [p2wpkh(1), p2wpkh(2), p2wpkh(3)];
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![input.clone(), input],
+            output: recipients
+                .iter()
+                .chain([&p2wpkh(9)])
+                .map(|script| TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: script.clone(),
+                })
+                .collect(),
+        };
+        let vsize = tx.vsize() as u64;
+        // 1 sat/vB
+        let report = compare(Txid::all_zeros(), &tx, Amount::from_sat(vsize), &recipients);
+        assert_eq!(report.payments, 3);
+        // 11 + 68 + 31 + 31 per payment
+        assert_eq!(report.individual_fee, Amount::from_sat(3 * 141));
+        assert_eq!(
+            report.savings,
+            SignedAmount::from_sat(3 * 141 - vsize as i64)
+        );
+        assert!(report.savings > SignedAmount::ZERO);
+    }
+}
@@ -0,0 +1,455 @@
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::hex::FromHex;
+use bitcoincore_rpc::bitcoin::secp256k1::SecretKey;
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, Network, OutPoint, ScriptBuf, Sequence, SignedAmount, Transaction, TxIn,
+    TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::walletless::LocalKey;
+
+// How the ownership check and the fee verification get what they need from the node
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    // One request per address or parent transaction, as `wallet::is_mine` does
+    PerCall,
+    // All of them in one JSON-RPC batch
+    Batched,
+    // Per call, but each answer is kept and never asked for again
+    Cached,
+}
+
+pub const STRATEGIES: [Strategy; 3] = [Strategy::PerCall, Strategy::Batched, Strategy::Cached];
+
+// A client that can send many calls of one method in a single round trip
+pub trait BatchRpc: RpcApi {
+    fn call_batch<T: DeserializeOwned>(
+        &self,
+        cmd: &str,
+        args: &[Vec<Value>],
+    ) -> bitcoincore_rpc::Result<Vec<T>> {
+        args.iter().map(|a| self.call(cmd, a)).collect()
+    }
+}
+
+impl BatchRpc for Client {
+    fn call_batch<T: DeserializeOwned>(
+        &self,
+        cmd: &str,
+        args: &[Vec<Value>],
+    ) -> bitcoincore_rpc::Result<Vec<T>> {
+        let client = self.get_jsonrpc_client();
+        let params = args
+            .iter()
+            .map(|a| a.iter().map(serde_json::value::to_raw_value).collect())
+            .collect::<serde_json::Result<Vec<Vec<_>>>>()?;
+        let requests: Vec<_> = params
+            .iter()
+            .map(|p| client.build_request(cmd, p))
+            .collect();
+        let responses = client
+            .send_batch(&requests)
+            .map_err(bitcoincore_rpc::Error::from)?;
+        responses
+            .into_iter()
+            .map(|response| {
+                let response = response.ok_or_else(|| {
+                    bitcoincore_rpc::Error::ReturnedError(format!("no response to a {cmd}"))
+                })?;
+                Ok(response.result()?)
+            })
+            .collect()
+    }
+}
+
+// Answers kept by the cached strategy, shared between runs
+#[derive(Default)]
+pub struct Cache {
+    owned: Mutex<HashMap<String, bool>>,
+    transactions: Mutex<HashMap<Txid, Transaction>>,
+}
+
+// Whether the wallet behind `rpc` owns each of `addresses`
+pub fn check_ownership(
+    rpc: &impl BatchRpc,
+    addresses: &[Address],
+    strategy: Strategy,
+    cache: &Cache,
+) -> Result<Vec<bool>> {
+    let is_mine = |info: Value| info["ismine"].as_bool().unwrap_or(false);
+    let one = |address: &Address| -> Result<bool> {
+        Ok(is_mine(rpc.call("getaddressinfo", &[json!(address)])?))
+    };
+    match strategy {
+        Strategy::PerCall => addresses.iter().map(one).collect(),
+        Strategy::Batched => {
+            let args: Vec<_> = addresses.iter().map(|a| vec![json!(a)]).collect();
+            let infos: Vec<Value> = rpc.call_batch("getaddressinfo", &args)?;
+            Ok(infos.into_iter().map(is_mine).collect())
+        }
+        Strategy::Cached => addresses
+            .iter()
+            .map(|address| {
+                let key = address.to_string();
+                if let Some(&owned) = cache.owned.lock().unwrap().get(&key) {
+                    return Ok(owned);
+                }
+                let owned = one(address)?;
+                cache.owned.lock().unwrap().insert(key, owned);
+                Ok(owned)
+            })
+            .collect(),
+    }
+}
+
+// Fee of each of `transactions`, from the outputs their inputs spend. These come from
+// getrawtransaction, so the node needs -txindex for confirmed parents.
+pub fn verify_fees(
+    rpc: &impl BatchRpc,
+    transactions: &[Transaction],
+    strategy: Strategy,
+    cache: &Cache,
+) -> Result<Vec<SignedAmount>> {
+    let mut parents: Vec<Txid> = transactions
+        .iter()
+        .flat_map(|tx| tx.input.iter().map(|i| i.previous_output.txid))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    parents.sort();
+    let fetch = |txid: &Txid| -> Result<Transaction> {
+        let hex: String = rpc.call("getrawtransaction", &[json!(txid), json!(false)])?;
+        decode(&hex)
+    };
+
+    let known: HashMap<Txid, Transaction> = match strategy {
+        Strategy::PerCall => parents
+            .iter()
+            .map(|txid| Ok((*txid, fetch(txid)?)))
+            .collect::<Result<_>>()?,
+        Strategy::Batched => {
+            let args: Vec<_> = parents
+                .iter()
+                .map(|txid| vec![json!(txid), json!(false)])
+                .collect();
+            let hexes: Vec<String> = rpc.call_batch("getrawtransaction", &args)?;
+            parents
+                .iter()
+                .copied()
+                .zip(hexes.iter().map(|h| decode(h)))
+                .map(|(txid, tx)| Ok((txid, tx?)))
+                .collect::<Result<_>>()?
+        }
+        Strategy::Cached => {
+            let mut known = HashMap::new();
+            for txid in &parents {
+                let cached = cache.transactions.lock().unwrap().get(txid).cloned();
+                let tx = match cached {
+                    Some(tx) => tx,
+                    None => {
+                        let tx = fetch(txid)?;
+                        cache.transactions.lock().unwrap().insert(*txid, tx.clone());
+                        tx
+                    }
+                };
+                known.insert(*txid, tx);
+            }
+            known
+        }
+    };
+
+    transactions
+        .iter()
+        .map(|tx| {
+            let mut input_value = Amount::ZERO;
+            for input in &tx.input {
+                let OutPoint { txid, vout } = input.previous_output;
+                let spent = known[&txid].output.get(vout as usize).ok_or_else(|| {
+                    bitcoincore_rpc::Error::ReturnedError(format!(
+                        "{} spends {txid}:{vout}, which doesn't exist",
+                        tx.txid()
+                    ))
+                })?;
+                input_value += spent.value;
+            }
+            let output_value: Amount = tx.output.iter().map(|o| o.value).sum();
+            Ok(SignedAmount::from_sat(
+                input_value.to_sat() as i64 - output_value.to_sat() as i64,
+            ))
+        })
+        .collect()
+}
+
+fn decode(hex: &str) -> Result<Transaction> {
+    let bytes = Vec::<u8>::from_hex(hex)
+        .map_err(|e| bitcoincore_rpc::Error::ReturnedError(format!("not hex: {e}")))?;
+    Ok(encode::deserialize(&bytes).map_err(bitcoincore_rpc::Error::from)?)
+}
+
+// A synthetic node serving parents, children and a wallet's ownership, with each round
+// trip (a single call or a whole batch) costing `latency`, as a local node roughly does
+pub struct Fixture {
+    pub children: Vec<Transaction>,
+    pub addresses: Vec<Address>,
+    pub fees: Vec<SignedAmount>,
+    parents: HashMap<Txid, String>,
+    owned: HashSet<String>,
+    latency: Duration,
+}
+
+impl Fixture {
+    // `count` children, each spending an output of `inputs` different parents and paying two
+    // addresses, the first of which the wallet owns
+    pub fn new(count: usize, inputs: usize, latency: Duration) -> Fixture {
+        let key = |i: usize| {
+            let mut secret = [1; 32];
+            secret[..8].copy_from_slice(&(i as u64 + 1).to_be_bytes());
+            LocalKey::from_secret(SecretKey::from_slice(&secret).unwrap(), Network::Regtest)
+        };
+        let tx = |input: Vec<TxIn>, output: Vec<TxOut>| Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input,
+            output,
+        };
+        let spend = |outpoint| TxIn {
+            previous_output: outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        };
+
+        let mut fixture = Fixture {
+            children: vec![],
+            addresses: vec![],
+            fees: vec![],
+            parents: HashMap::new(),
+            owned: HashSet::new(),
+            latency,
+        };
+        for c in 0..count {
+            let mut spent = vec![];
+            for p in 0..inputs {
+                let parent = tx(
+                    vec![spend(OutPoint::new(
+                        Txid::all_zeros(),
+                        (c * inputs + p) as u32,
+                    ))],
+                    vec![TxOut {
+                        value: Amount::from_sat(100_000),
+                        script_pubkey: key(2 * c).script_pubkey(),
+                    }],
+                );
+                spent.push(spend(OutPoint::new(parent.txid(), 0)));
+                fixture
+                    .parents
+                    .insert(parent.txid(), encode::serialize_hex(&parent));
+            }
+            let (ours, theirs) = (key(2 * c), key(2 * c + 1));
+            let paid = Amount::from_sat(100_000) * inputs as u64 - Amount::from_sat(1_000);
+            fixture.children.push(tx(
+                spent,
+                vec![
+                    TxOut {
+                        value: paid / 2,
+                        script_pubkey: ours.script_pubkey(),
+                    },
+                    TxOut {
+                        value: paid - paid / 2,
+                        script_pubkey: theirs.script_pubkey(),
+                    },
+                ],
+            ));
+            fixture.fees.push(SignedAmount::from_sat(1_000));
+            fixture.owned.insert(ours.address.to_string());
+            fixture.addresses.extend([ours.address, theirs.address]);
+        }
+        fixture
+    }
+
+    fn answer(&self, cmd: &str, args: &[Value]) -> bitcoincore_rpc::Result<Value> {
+        let arg = args.first().and_then(Value::as_str).unwrap_or_default();
+        match cmd {
+            "getaddressinfo" => Ok(json!({ "address": arg, "ismine": self.owned.contains(arg) })),
+            "getrawtransaction" => arg
+                .parse::<Txid>()
+                .ok()
+                .and_then(|txid| self.parents.get(&txid))
+                .map(|hex| json!(hex))
+                .ok_or_else(|| {
+                    bitcoincore_rpc::Error::ReturnedError(format!("no transaction {arg}"))
+                }),
+            _ => Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "the fixture doesn't serve {cmd}"
+            ))),
+        }
+    }
+}
+
+impl RpcApi for Fixture {
+    fn call<T: DeserializeOwned>(&self, cmd: &str, args: &[Value]) -> bitcoincore_rpc::Result<T> {
+        thread::sleep(self.latency);
+        Ok(serde_json::from_value(self.answer(cmd, args)?)?)
+    }
+}
+
+impl BatchRpc for Fixture {
+    fn call_batch<T: DeserializeOwned>(
+        &self,
+        cmd: &str,
+        args: &[Vec<Value>],
+    ) -> bitcoincore_rpc::Result<Vec<T>> {
+        thread::sleep(self.latency);
+        args.iter()
+            .map(|a| Ok(serde_json::from_value(self.answer(cmd, a)?)?))
+            .collect()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BenchReport {
+    pub transactions: usize,
+    pub addresses: usize,
+    pub iterations: u32,
+    pub results: Vec<StrategyTiming>,
+    // Every strategy came to the same ownership and fees
+    pub consistent: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StrategyTiming {
+    pub check: &'static str,
+    pub strategy: Strategy,
+    // Over all iterations, the first of which fills the cache for `Cached`
+    pub mean_secs: f64,
+    pub first_secs: f64,
+}
+
+// Time every strategy at both checks over `transactions` and the addresses they pay,
+// `iterations` times each
+pub fn run(
+    rpc: &impl BatchRpc,
+    transactions: &[Transaction],
+    addresses: &[Address],
+    iterations: u32,
+) -> Result<BenchReport> {
+    let iterations = iterations.max(1);
+    let mut results = vec![];
+    let mut owned = vec![];
+    let mut fees = vec![];
+    for strategy in STRATEGIES {
+        let cache = Cache::default();
+        let (timing, answer) = time(iterations, || {
+            check_ownership(rpc, addresses, strategy, &cache)
+        })?;
+        results.push(StrategyTiming {
+            check: "ownership",
+            strategy,
+            mean_secs: timing.0,
+            first_secs: timing.1,
+        });
+        owned.push(answer);
+
+        let (timing, answer) = time(iterations, || {
+            verify_fees(rpc, transactions, strategy, &cache)
+        })?;
+        results.push(StrategyTiming {
+            check: "fees",
+            strategy,
+            mean_secs: timing.0,
+            first_secs: timing.1,
+        });
+        fees.push(answer);
+    }
+    Ok(BenchReport {
+        transactions: transactions.len(),
+        addresses: addresses.len(),
+        iterations,
+        results,
+        consistent: owned.windows(2).all(|w| w[0] == w[1]) && fees.windows(2).all(|w| w[0] == w[1]),
+    })
+}
+
+// ((mean, first) seconds, last answer)
+fn time<T>(iterations: u32, mut f: impl FnMut() -> Result<T>) -> Result<((f64, f64), T)> {
+    let mut total = 0.0;
+    let mut first = 0.0;
+    let mut answer = None;
+    for i in 0..iterations {
+        let start = Instant::now();
+        answer = Some(f()?);
+        let elapsed = start.elapsed().as_secs_f64();
+        if i == 0 {
+            first = elapsed;
+        }
+        total += elapsed;
+    }
+    Ok((
+        (total / f64::from(iterations), first),
+        answer.expect("at least one iteration"),
+    ))
+}
+
+// The wallet's latest `count` sends (its coinbases have no fee to verify) and the addresses
+// they pay, for timing against a live node
+pub fn wallet_sample(wallet: &Client, count: usize) -> Result<(Vec<Transaction>, Vec<Address>)> {
+    let mut transactions = vec![];
+    let mut seen = HashSet::new();
+    for entry in wallet
+        .list_transactions(None, Some(count * 4), None, None)?
+        .into_iter()
+        .rev()
+    {
+        if transactions.len() == count || !seen.insert(entry.info.txid) {
+            continue;
+        }
+        let tx = wallet
+            .get_transaction(&entry.info.txid, None)?
+            .transaction()
+            .map_err(bitcoincore_rpc::Error::from)?;
+        if !tx.is_coinbase() {
+            transactions.push(tx);
+        }
+    }
+    let network = crate::config::active().network;
+    let addresses = transactions
+        .iter()
+        .flat_map(|tx| &tx.output)
+        .filter_map(|o| Address::from_script(&o.script_pubkey, network).ok())
+        .collect();
+    Ok((transactions, addresses))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategies_agree_on_the_fixture() {
+        let fixture = Fixture::new(3, 2, Duration::ZERO);
+        let report = run(&fixture, &fixture.children, &fixture.addresses, 2).unwrap();
+        assert!(report.consistent);
+        assert_eq!(report.results.len(), 6);
+
+        let cache = Cache::default();
+        let owned =
+            check_ownership(&fixture, &fixture.addresses, Strategy::Batched, &cache).unwrap();
+        assert_eq!(owned, [true, false, true, false, true, false]);
+        let fees = verify_fees(&fixture, &fixture.children, Strategy::Cached, &cache).unwrap();
+        assert_eq!(fees, fixture.fees);
+        assert_eq!(cache.transactions.lock().unwrap().len(), 6);
+    }
+}
@@ -0,0 +1,210 @@
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::hashes::{sha256, Hash, HashEngine};
+use bitcoincore_rpc::bitcoin::opcodes::all::OP_RETURN;
+use bitcoincore_rpc::bitcoin::opcodes::OP_0;
+use bitcoincore_rpc::bitcoin::script::Builder;
+use bitcoincore_rpc::bitcoin::secp256k1::{Message, Secp256k1, XOnlyPublicKey};
+use bitcoincore_rpc::bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    ecdsa, taproot, Address, Amount, OutPoint, PublicKey, Script, ScriptBuf, Sequence, Transaction,
+    TxIn, TxOut, Txid, Witness,
+};
+use bitcoincore_rpc::json::SignRawTransactionInput;
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+
+const TAG: &[u8] = b"BIP0322-signed-message";
+
+// A BIP322 "simple" signature: `address` signed `message`, checkable with nothing but
+// the three fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Proof {
+    pub address: Address<NetworkUnchecked>,
+    pub message: String,
+    // Base64 of the to_sign transaction's witness
+    pub signature: String,
+}
+
+fn invalid(why: String) -> bitcoincore_rpc::Error {
+    bitcoincore_rpc::Error::ReturnedError(why)
+}
+
+// SHA256 tagged with "BIP0322-signed-message"
+pub fn message_hash(message: &str) -> sha256::Hash {
+    let tag = sha256::Hash::hash(TAG);
+    let mut engine = sha256::Hash::engine();
+    engine.input(tag.as_ref());
+    engine.input(tag.as_ref());
+    engine.input(message.as_bytes());
+    sha256::Hash::from_engine(engine)
+}
+
+// The virtual transaction paying `script_pubkey`, committing to the message
+pub fn to_spend(script_pubkey: &Script, message: &str) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(Txid::all_zeros(), u32::MAX),
+            script_sig: Builder::new()
+                .push_opcode(OP_0)
+                .push_slice(message_hash(message).to_byte_array())
+                .into_script(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: script_pubkey.to_owned(),
+        }],
+    }
+}
+
+// The virtual transaction the signature is for, spending `to_spend` to OP_RETURN
+pub fn to_sign(to_spend: &Transaction) -> Transaction {
+    Transaction {
+        version: Version(0),
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: OutPoint::new(to_spend.txid(), 0),
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ZERO,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value: Amount::ZERO,
+            script_pubkey: Builder::new().push_opcode(OP_RETURN).into_script(),
+        }],
+    }
+}
+
+// Sign `message` for `address` with the wallet owning it. signrawtransactionwithwallet is
+// told about the virtual output, so any segwit address the wallet can spend works.
+pub fn sign(
+    wallet: &impl RpcApi,
+    address: &Address,
+    message: &str,
+) -> bitcoincore_rpc::Result<Proof> {
+    let spend = to_spend(&address.script_pubkey(), message);
+    let unsigned = to_sign(&spend);
+    let input = SignRawTransactionInput {
+        txid: spend.txid(),
+        vout: 0,
+        script_pub_key: address.script_pubkey(),
+        redeem_script: None,
+        amount: Some(Amount::ZERO),
+    };
+    let signed = wallet.sign_raw_transaction_with_wallet(&unsigned, Some(&[input]), None)?;
+    if !signed.complete {
+        return Err(invalid(format!(
+            "the wallet can't sign for {address}: {:?}",
+            signed.errors
+        )));
+    }
+    let witness = signed.transaction()?.input[0].witness.clone();
+    Ok(Proof {
+        address: address.as_unchecked().clone(),
+        message: message.to_owned(),
+        signature: BASE64.encode(encode::serialize(&witness)),
+    })
+}
+
+// Check a proof locally. P2WPKH and P2TR key path signatures are understood, anything else
+// is an error rather than a false "invalid".
+pub fn verify(proof: &Proof) -> bitcoincore_rpc::Result<bool> {
+    let script_pubkey = proof.address.clone().assume_checked().script_pubkey();
+    let bytes = BASE64
+        .decode(&proof.signature)
+        .map_err(|e| invalid(format!("signature is not base64: {e}")))?;
+    let witness: Witness = encode::deserialize(&bytes)?;
+    let spend = to_spend(&script_pubkey, &proof.message);
+    let mut signing = to_sign(&spend);
+    signing.input[0].witness = witness.clone();
+    let mut cache = SighashCache::new(&signing);
+    let secp = Secp256k1::verification_only();
+
+    if script_pubkey.is_p2wpkh() {
+        let (Some(signature), Some(key), 2) = (witness.nth(0), witness.nth(1), witness.len())
+        else {
+            return Ok(false);
+        };
+        let (Ok(signature), Ok(key)) = (
+            ecdsa::Signature::from_slice(signature),
+            PublicKey::from_slice(key),
+        ) else {
+            return Ok(false);
+        };
+        if key.wpubkey_hash().map(|h| ScriptBuf::new_p2wpkh(&h)) != Some(script_pubkey.clone()) {
+            return Ok(false);
+        }
+        let sighash = cache
+            .p2wpkh_signature_hash(0, &script_pubkey, Amount::ZERO, signature.hash_ty)
+            .map_err(|e| invalid(e.to_string()))?;
+        let message = Message::from_digest(sighash.to_byte_array());
+        Ok(secp
+            .verify_ecdsa(&message, &signature.sig, &key.inner)
+            .is_ok())
+    } else if script_pubkey.is_p2tr() {
+        let (Some(signature), 1) = (witness.nth(0), witness.len()) else {
+            return Ok(false);
+        };
+        let Ok(signature) = taproot::Signature::from_slice(signature) else {
+            return Ok(false);
+        };
+        let key = XOnlyPublicKey::from_slice(&script_pubkey.as_bytes()[2..])
+            .map_err(|e| invalid(e.to_string()))?;
+        let sighash = cache
+            .taproot_key_spend_signature_hash(0, &Prevouts::All(&spend.output), signature.hash_ty)
+            .map_err(|e| invalid(e.to_string()))?;
+        let message = Message::from_digest(sighash.to_byte_array());
+        Ok(secp.verify_schnorr(&signature.sig, &message, &key).is_ok())
+    } else {
+        Err(invalid(format!(
+            "{} is neither P2WPKH nor P2TR, which is all BIP322 checking supports here",
+            proof.address.clone().assume_checked()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn proof(message: &str, signature: &str) -> Proof {
+        Proof {
+            address: Address::from_str("bc1q9vza2e8x573nczrlzms0wvx3gsqjx7vavgkx0l").unwrap(),
+            message: message.to_owned(),
+            signature: signature.to_owned(),
+        }
+    }
+
+    // BIP322's test vectors
+    #[test]
+    fn hashes_messages() {
+        assert_eq!(
+            message_hash("").to_string(),
+            "c90c269c4f8fcbe6880f72a721ddfbf1914268a794cbb21cfafee13770ae19f1"
+        );
+        assert_eq!(
+            message_hash("Hello World").to_string(),
+            "f0eb03b1a75ac6d9847f55c624a99169b5dccba2a31f5b23bea77ba270de0a7a"
+        );
+    }
+
+    #[test]
+    fn verifies_p2wpkh_signatures() {
+        let hello = "AkcwRAIgZRfIY3p7/DoVTty6YZbWS71bc5Vct9p9Fia83eRmw2QCICK/ENGfwLtptFluMGs2KsqoNSk89pO7F29zJLUx9a/sASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert!(verify(&proof("Hello World", hello)).unwrap());
+        let empty = "AkcwRAIgM2gBAQqvZX15ZiysmKmQpDrG83avLIT492QBzLnQIxYCIBaTpOaD20qRlEylyxFSeEA2ba9YOixpX8z46TSDtS40ASECx/EgAxlkQpQ9hYjgGu6EBCPMVPwVIVJqO4XCsMvViHI=";
+        assert!(verify(&proof("", empty)).unwrap());
+        // The right signature for the wrong message
+        assert!(!verify(&proof("Hello World!", hello)).unwrap());
+        assert!(verify(&proof("Hello World", "not base64!")).is_err());
+    }
+}
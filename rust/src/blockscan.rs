@@ -0,0 +1,295 @@
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::encode::{self, Decodable, VarInt};
+use bitcoincore_rpc::bitcoin::{Amount, BlockHash, Transaction};
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+use serde_json::json;
+use std::io;
+use std::time::Instant;
+
+use crate::error::Result;
+
+// Blocks back from the tip scanned when no range is given
+pub const DEFAULT_BLOCKS: u64 = 10;
+
+// What a scan takes from every block. Both ways of fetching must agree on all of it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BlockSummary {
+    pub hash: BlockHash,
+    pub transactions: usize,
+    pub inputs: usize,
+    pub outputs: usize,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub output_value: Amount,
+    pub size: u64,
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FetchMethod {
+    // getblock at verbosity 0, decoded one transaction at a time straight from the hex
+    Streaming,
+    // getblock at verbosity 1 for the txids, then getrawtransaction for each of them
+    PerTx,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScanRun {
+    pub method: FetchMethod,
+    pub blocks: usize,
+    pub transactions: usize,
+    pub rpc_calls: usize,
+    pub elapsed_secs: f64,
+    pub tx_per_sec: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BlockScanReport {
+    pub from_height: u64,
+    pub to_height: u64,
+    pub streaming: ScanRun,
+    // Only when asked to compare against the per-transaction fetch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub per_tx: Option<ScanRun>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub speedup: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summaries_match: Option<bool>,
+    pub summaries: Vec<BlockSummary>,
+}
+
+// Reads the bytes a hex string encodes, two digits at a time as the decoder asks for them,
+// so the block never exists as one decoded buffer (let alone as verbose JSON)
+pub struct HexReader<'a> {
+    hex: &'a [u8],
+}
+
+impl<'a> HexReader<'a> {
+    pub fn new(hex: &'a str) -> HexReader<'a> {
+        HexReader {
+            hex: hex.as_bytes(),
+        }
+    }
+}
+
+impl io::Read for HexReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.hex.len() / 2);
+        for (byte, pair) in buf[..n].iter_mut().zip(self.hex.chunks_exact(2)) {
+            *byte = digit(pair[0])? << 4 | digit(pair[1])?;
+        }
+        self.hex = &self.hex[n * 2..];
+        Ok(n)
+    }
+}
+
+fn digit(c: u8) -> io::Result<u8> {
+    match c {
+        b'0'..=b'9' => Ok(c - b'0'),
+        b'a'..=b'f' => Ok(c - b'a' + 10),
+        b'A'..=b'F' => Ok(c - b'A' + 10),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{:?} is not a hex digit", c as char),
+        )),
+    }
+}
+
+// Summary of a serialized block, each transaction decoded, counted and dropped in turn
+pub fn summarize_hex(hex: &str) -> Result<BlockSummary> {
+    let mut reader = HexReader::new(hex);
+    let decode_error = |e: encode::Error| bitcoincore_rpc::Error::from(e);
+    let header = Header::consensus_decode(&mut reader).map_err(decode_error)?;
+    let count = VarInt::consensus_decode(&mut reader).map_err(decode_error)?;
+    // The header and the transaction count are non-witness data, four weight units a byte
+    let mut summary = BlockSummary {
+        hash: header.block_hash(),
+        transactions: count.0 as usize,
+        inputs: 0,
+        outputs: 0,
+        output_value: Amount::ZERO,
+        size: hex.len() as u64 / 2,
+        weight: 4 * (80 + count.size() as u64),
+    };
+    for _ in 0..count.0 {
+        let tx = Transaction::consensus_decode(&mut reader).map_err(decode_error)?;
+        add(&mut summary, &tx);
+    }
+    if !reader.hex.is_empty() {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "block {} has {} bytes after its last transaction",
+            summary.hash,
+            reader.hex.len() / 2
+        ))
+        .into());
+    }
+    Ok(summary)
+}
+
+fn add(summary: &mut BlockSummary, tx: &Transaction) {
+    summary.inputs += tx.input.len();
+    summary.outputs += tx.output.len();
+    summary.output_value += tx.output.iter().map(|o| o.value).sum();
+    summary.weight += tx.weight().to_wu();
+}
+
+pub fn fetch_streaming(rpc: &impl RpcApi, hash: &BlockHash) -> Result<BlockSummary> {
+    let hex: String = rpc.call("getblock", &[json!(hash), json!(0)])?;
+    summarize_hex(&hex)
+}
+
+// The slow way: one getrawtransaction per transaction, as scanning with the verbose or
+// per-transaction RPCs does. Returns the RPC calls it took too.
+pub fn fetch_per_tx(rpc: &impl RpcApi, hash: &BlockHash) -> Result<(BlockSummary, usize)> {
+    let info = rpc.get_block_info(hash)?;
+    let mut summary = BlockSummary {
+        hash: *hash,
+        transactions: info.tx.len(),
+        inputs: 0,
+        outputs: 0,
+        output_value: Amount::ZERO,
+        size: info.size as u64,
+        weight: 4 * (80 + VarInt(info.tx.len() as u64).size() as u64),
+    };
+    for txid in &info.tx {
+        add(&mut summary, &rpc.get_raw_transaction(txid, Some(hash))?);
+    }
+    Ok((summary, 1 + info.tx.len()))
+}
+
+fn scan(
+    rpc: &impl RpcApi,
+    hashes: &[BlockHash],
+    method: FetchMethod,
+) -> Result<(ScanRun, Vec<BlockSummary>)> {
+    let start = Instant::now();
+    let mut summaries = vec![];
+    let mut rpc_calls = 0;
+    for hash in hashes {
+        let (summary, calls) = match method {
+            FetchMethod::Streaming => (fetch_streaming(rpc, hash)?, 1),
+            FetchMethod::PerTx => fetch_per_tx(rpc, hash)?,
+        };
+        summaries.push(summary);
+        rpc_calls += calls;
+    }
+    let elapsed_secs = start.elapsed().as_secs_f64();
+    let transactions = summaries.iter().map(|s| s.transactions).sum();
+    let run = ScanRun {
+        method,
+        blocks: summaries.len(),
+        transactions,
+        rpc_calls,
+        elapsed_secs,
+        tx_per_sec: transactions as f64 / elapsed_secs.max(f64::EPSILON),
+    };
+    Ok((run, summaries))
+}
+
+// Scan blocks `from_height..=to_height` by streaming decode, and with `compare` the
+// per-transaction way as well, to time the two against each other and check they agree
+pub fn run(
+    rpc: &impl RpcApi,
+    from_height: u64,
+    to_height: u64,
+    compare: bool,
+) -> Result<BlockScanReport> {
+    let hashes = (from_height..=to_height)
+        .map(|height| rpc.get_block_hash(height))
+        .collect::<bitcoincore_rpc::Result<Vec<_>>>()?;
+    let (streaming, summaries) = scan(rpc, &hashes, FetchMethod::Streaming)?;
+    let mut report = BlockScanReport {
+        from_height,
+        to_height,
+        streaming,
+        per_tx: None,
+        speedup: None,
+        summaries_match: None,
+        summaries,
+    };
+    if compare {
+        let (per_tx, summaries) = scan(rpc, &hashes, FetchMethod::PerTx)?;
+        report.speedup =
+            Some(per_tx.elapsed_secs / report.streaming.elapsed_secs.max(f64::EPSILON));
+        report.summaries_match = Some(summaries == report.summaries);
+        report.per_tx = Some(per_tx);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::block::Version as BlockVersion;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{
+        Block, CompactTarget, OutPoint, ScriptBuf, Sequence, TxIn, TxMerkleNode, TxOut, Txid,
+        Witness,
+    };
+
+    fn block(transactions: u32) -> Block {
+        let txdata = (0..transactions)
+            .map(|i| Transaction {
+                version: Version::TWO,
+                lock_time: LockTime::ZERO,
+                input: vec![TxIn {
+                    previous_output: OutPoint::new(Txid::all_zeros(), i),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::MAX,
+                    witness: Witness::from_slice(&[vec![i as u8; 72]]),
+                }],
+                output: vec![
+                    TxOut {
+                        value: Amount::from_sat(1_000 + u64::from(i)),
+                        script_pubkey: ScriptBuf::new(),
+                    };
+                    2
+                ],
+            })
+            .collect();
+        Block {
+            header: Header {
+                version: BlockVersion::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        }
+    }
+
+    #[test]
+    fn streams_the_same_summary_as_a_full_decode() {
+        // Over 252 transactions the count takes a three byte varint
+        let block = block(300);
+        let summary = summarize_hex(&encode::serialize_hex(&block)).unwrap();
+        assert_eq!(summary.hash, block.block_hash());
+        assert_eq!(summary.transactions, 300);
+        assert_eq!(summary.inputs, 300);
+        assert_eq!(summary.outputs, 600);
+        assert_eq!(
+            summary.output_value,
+            block
+                .txdata
+                .iter()
+                .flat_map(|t| &t.output)
+                .map(|o| o.value)
+                .sum()
+        );
+        assert_eq!(summary.size, block.total_size() as u64);
+        assert_eq!(summary.weight, block.weight().to_wu());
+    }
+
+    #[test]
+    fn rejects_bad_hex_and_trailing_bytes() {
+        let hex = encode::serialize_hex(&block(2));
+        assert!(summarize_hex(&hex.replacen('0', "g", 1)).is_err());
+        assert!(summarize_hex(&format!("{hex}00")).is_err());
+        assert!(summarize_hex(&hex[..hex.len() - 2]).is_err());
+    }
+}
@@ -0,0 +1,483 @@
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+use bitcoincore_rpc::json::{AddressType, ListUnspentResultEntry};
+use bitcoincore_rpc::RpcApi;
+use rand::Rng;
+use std::fmt;
+
+use crate::coins::{self, DUST_RELAY_FEE};
+use crate::error::Result;
+
+// Virtual sizes used to estimate the fee before signing, for P2WPKH inputs
+pub const TX_OVERHEAD_VBYTES: u64 = 11;
+pub const P2WPKH_INPUT_VBYTES: u64 = 68;
+// Output value and script length prefix, the script itself comes on top
+pub const OUTPUT_BASE_VBYTES: u64 = 9;
+// A P2WPKH output script: version, push and the 20 byte key hash
+pub const P2WPKH_SCRIPT_LEN: u64 = 22;
+
+#[derive(Debug)]
+pub enum BuildError {
+    NoInputs,
+    NoOutputs,
+    Insufficient { needed: Amount, available: Amount },
+    // signrawtransactionwithwallet could not sign every input
+    Incomplete(Vec<String>),
+    // The signed transaction breaks these limits of its `Budget`
+    OverBudget(Vec<String>),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::NoInputs => write!(f, "no inputs to spend"),
+            BuildError::NoOutputs => write!(f, "nothing to pay"),
+            BuildError::Insufficient { needed, available } => {
+                write!(f, "inputs hold {available} but {needed} is needed")
+            }
+            BuildError::Incomplete(errors) => {
+                write!(f, "could not sign all inputs: {}", errors.join(", "))
+            }
+            BuildError::OverBudget(limits) => {
+                write!(f, "not broadcasting, over budget: {}", limits.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+// BIP125 signaling for a transaction: `rbf` picks the sequence of every input (None leaves
+// it to the wallet), `sequences` overrides single inputs
+#[derive(Debug, Clone, Default)]
+pub struct SequencePolicy {
+    pub rbf: Option<bool>,
+    pub sequences: Vec<(OutPoint, Sequence)>,
+}
+
+impl SequencePolicy {
+    pub fn sequence_for(&self, outpoint: &OutPoint) -> Option<Sequence> {
+        self.sequences
+            .iter()
+            .find(|(o, _)| o == outpoint)
+            .map(|(_, sequence)| *sequence)
+    }
+}
+
+// Where a transaction's change goes: a fresh wallet address of `address_type` (None leaves
+// the type to the wallet) or exactly `address`, and whether it's put at a random position
+// among the outputs instead of wherever the builder or the wallet would put it
+#[derive(Debug, Clone, Default)]
+pub struct ChangeControl {
+    pub address_type: Option<AddressType>,
+    pub address: Option<Address>,
+    pub random_position: bool,
+}
+
+impl ChangeControl {
+    // A position for the change among `payments` payment outputs, when it's to be random
+    pub fn position(&self, payments: usize) -> Option<usize> {
+        self.random_position
+            .then(|| rand::thread_rng().gen_range(0..=payments))
+    }
+}
+
+// Limits a signed transaction has to stay within to be broadcast, so an experiment that
+// goes wrong fails instead of sending something huge or expensive. None means no limit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    pub max_vsize: Option<u64>,
+    pub max_inputs: Option<usize>,
+    pub max_fee: Option<Amount>,
+}
+
+impl Budget {
+    // `input_value` is what the inputs of `tx` hold, the fee is whatever the outputs leave
+    pub fn check(&self, tx: &Transaction, input_value: Amount) -> Result<()> {
+        let mut over = vec![];
+        let vsize = tx.vsize() as u64;
+        if let Some(max) = self.max_vsize.filter(|max| vsize > *max) {
+            over.push(format!("{vsize} vB > {max} vB"));
+        }
+        if let Some(max) = self.max_inputs.filter(|max| tx.input.len() > *max) {
+            over.push(format!("{} inputs > {max}", tx.input.len()));
+        }
+        let output_value: Amount = tx.output.iter().map(|o| o.value).sum();
+        let fee = input_value
+            .checked_sub(output_value)
+            .unwrap_or(Amount::ZERO);
+        if let Some(max) = self.max_fee.filter(|max| fee > *max) {
+            over.push(format!("fee {fee} > {max}"));
+        }
+        if over.is_empty() {
+            Ok(())
+        } else {
+            Err(BuildError::OverBudget(over).into())
+        }
+    }
+}
+
+// Puts a transaction together input by input instead of leaving it to the wallet's `send`,
+// for flows that need control over exactly what gets spent. Inputs are expected to be
+// P2WPKH (what the wallets here hand out) for the fee estimate.
+#[derive(Debug, Clone)]
+pub struct TxBuilder {
+    inputs: Vec<(OutPoint, Amount)>,
+    outputs: Vec<TxOut>,
+    change: Option<ScriptBuf>,
+    random_change_position: bool,
+    fee_rate: FeeRate,
+    policy: SequencePolicy,
+}
+
+impl Default for TxBuilder {
+    fn default() -> Self {
+        TxBuilder {
+            inputs: vec![],
+            outputs: vec![],
+            change: None,
+            random_change_position: false,
+            fee_rate: FeeRate::BROADCAST_MIN,
+            policy: SequencePolicy::default(),
+        }
+    }
+}
+
+impl TxBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spend(self, utxo: &ListUnspentResultEntry) -> Self {
+        self.spend_output(OutPoint::new(utxo.txid, utxo.vout), utxo.amount)
+    }
+
+    // An input no wallet knows about, e.g. one found with scantxoutset
+    pub fn spend_output(mut self, outpoint: OutPoint, value: Amount) -> Self {
+        self.inputs.push((outpoint, value));
+        self
+    }
+
+    pub fn pay(mut self, address: &Address, amount: Amount) -> Self {
+        self.outputs.push(TxOut {
+            value: amount,
+            script_pubkey: address.script_pubkey(),
+        });
+        self
+    }
+
+    // Where whatever is left after payments and fee goes. Without one it all goes to the fee.
+    pub fn change_to(mut self, address: &Address) -> Self {
+        self.change = Some(address.script_pubkey());
+        self
+    }
+
+    // Put the change at a random position among the payments instead of last, so it can't
+    // be told apart by where it is
+    pub fn random_change_position(mut self, random: bool) -> Self {
+        self.random_change_position = random;
+        self
+    }
+
+    // `change_to` the explicit address, or else `fallback`, and the position as `change` says
+    pub fn change_control(self, change: &ChangeControl, fallback: &Address) -> Self {
+        let random = change.random_position;
+        self.change_to(change.address.as_ref().unwrap_or(fallback))
+            .random_change_position(random)
+    }
+
+    pub fn fee_rate(mut self, fee_rate: FeeRate) -> Self {
+        self.fee_rate = fee_rate;
+        self
+    }
+
+    // Signal replaceability on every input (or explicitly not)
+    pub fn rbf(mut self, rbf: bool) -> Self {
+        self.policy.rbf = Some(rbf);
+        self
+    }
+
+    // Exact sequence for one input, wins over `rbf`
+    pub fn sequence(mut self, outpoint: OutPoint, sequence: Sequence) -> Self {
+        self.policy.sequences.push((outpoint, sequence));
+        self
+    }
+
+    pub fn sequence_policy(mut self, policy: &SequencePolicy) -> Self {
+        self.policy = policy.clone();
+        self
+    }
+
+    fn sequence_for(&self, outpoint: &OutPoint) -> Sequence {
+        match (self.policy.sequence_for(outpoint), self.policy.rbf) {
+            (Some(sequence), _) => sequence,
+            (None, Some(true)) => Sequence::ENABLE_RBF_NO_LOCKTIME,
+            (None, _) => Sequence::ENABLE_LOCKTIME_NO_RBF,
+        }
+    }
+
+    fn vsize(&self, with_change: bool) -> u64 {
+        let scripts = self
+            .outputs
+            .iter()
+            .map(|o| &o.script_pubkey)
+            .chain(self.change.iter().filter(|_| with_change));
+        TX_OVERHEAD_VBYTES
+            + P2WPKH_INPUT_VBYTES * self.inputs.len() as u64
+            + scripts
+                .map(|s| OUTPUT_BASE_VBYTES + s.len() as u64)
+                .sum::<u64>()
+    }
+
+    fn fee(&self, with_change: bool) -> Amount {
+        self.fee_rate
+            .fee_vb(self.vsize(with_change))
+            .unwrap_or(Amount::MAX_MONEY)
+    }
+
+    // The unsigned transaction. Payments come in the order they were added, then the change
+    // output (or somewhere among them with `random_change_position`) unless it would be dust
+    // at the builder's fee rate (or the dust relay fee, whichever is higher), in which case it
+    // goes to the fee.
+    pub fn build(&self) -> std::result::Result<Transaction, BuildError> {
+        if self.inputs.is_empty() {
+            return Err(BuildError::NoInputs);
+        }
+        if self.outputs.is_empty() {
+            return Err(BuildError::NoOutputs);
+        }
+        let available: Amount = self.inputs.iter().map(|(_, value)| *value).sum();
+        let paid: Amount = self.outputs.iter().map(|o| o.value).sum();
+
+        let mut output = self.outputs.clone();
+        let needed = paid + self.fee(false);
+        if available < needed {
+            return Err(BuildError::Insufficient { needed, available });
+        }
+        if let Some(script) = &self.change {
+            let change = available.checked_sub(paid + self.fee(true));
+            let dust = coins::dust_threshold(script, self.fee_rate.max(DUST_RELAY_FEE));
+            if let Some(value) = change.filter(|v| *v >= dust) {
+                let position = if self.random_change_position {
+                    rand::thread_rng().gen_range(0..=output.len())
+                } else {
+                    output.len()
+                };
+                output.insert(
+                    position,
+                    TxOut {
+                        value,
+                        script_pubkey: script.clone(),
+                    },
+                );
+            }
+        }
+
+        Ok(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: self
+                .inputs
+                .iter()
+                .map(|(outpoint, _)| TxIn {
+                    previous_output: *outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: self.sequence_for(outpoint),
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output,
+        })
+    }
+}
+
+// Have the wallet sign `tx` and broadcast it
+pub fn sign_and_send(rpc: &impl RpcApi, tx: &Transaction) -> Result<Txid> {
+    let tx = sign(rpc, tx)?;
+    Ok(rpc.send_raw_transaction(&tx)?)
+}
+
+// Have the wallet sign `tx`, every input has to end up signed
+pub fn sign(rpc: &impl RpcApi, tx: &Transaction) -> Result<Transaction> {
+    let signed = rpc.sign_raw_transaction_with_wallet(tx, None, None)?;
+    if !signed.complete {
+        let errors = signed
+            .errors
+            .unwrap_or_default()
+            .into_iter()
+            .map(|e| format!("{}:{} {}", e.txid, e.vout, e.error))
+            .collect();
+        return Err(BuildError::Incomplete(errors).into());
+    }
+    Ok(signed.transaction().map_err(bitcoincore_rpc::Error::from)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::Network;
+    use std::str::FromStr;
+
+    fn utxo(vout: u32, sat: u64) -> ListUnspentResultEntry {
+        ListUnspentResultEntry {
+            txid: Txid::all_zeros(),
+            vout,
+            address: None,
+            label: None,
+            redeem_script: None,
+            witness_script: None,
+            script_pub_key: ScriptBuf::new(),
+            amount: Amount::from_sat(sat),
+            confirmations: 1,
+            spendable: true,
+            solvable: true,
+            descriptor: None,
+            safe: true,
+        }
+    }
+
+    fn addr(s: &str) -> Address {
+        Address::from_str(s)
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap()
+    }
+
+    fn recipient() -> Address {
+        addr("bcrt1qak6gpu2p6zjpwrhvd4dvdnp4rt3ysm9rpst3wu")
+    }
+
+    fn change() -> Address {
+        addr("bcrt1qxw3msnuqps0kgn6dprs9ldlz79yfj63swqupd0")
+    }
+
+    #[test]
+    fn pays_recipient_then_change() {
+        let tx = TxBuilder::new()
+            .spend(&utxo(0, 100_000))
+            .pay(&recipient(), Amount::from_sat(60_000))
+            .change_to(&change())
+            .build()
+            .unwrap();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[0].value, Amount::from_sat(60_000));
+        assert_eq!(tx.output[1].script_pubkey, change().script_pubkey());
+        // 11 + 68 + 2 * (9 + 22) vbytes at 1 sat/vB
+        assert_eq!(tx.output[1].value, Amount::from_sat(100_000 - 60_000 - 141));
+    }
+
+    #[test]
+    fn change_control_picks_the_address_and_position() {
+        let explicit = ChangeControl {
+            address: Some(recipient()),
+            random_position: true,
+            ..Default::default()
+        };
+        let positions: std::collections::BTreeSet<usize> = (0..50)
+            .map(|_| {
+                let tx = TxBuilder::new()
+                    .spend(&utxo(0, 100_000))
+                    .pay(&change(), Amount::from_sat(10_000))
+                    .pay(&change(), Amount::from_sat(10_000))
+                    .change_control(&explicit, &change())
+                    .build()
+                    .unwrap();
+                tx.output
+                    .iter()
+                    .position(|o| o.script_pubkey == recipient().script_pubkey())
+                    .unwrap()
+            })
+            .collect();
+        // Three places for it, 50 draws all landing on one is as good as impossible
+        assert!(positions.len() > 1);
+        assert!(positions.iter().all(|p| *p <= 2));
+
+        let tx = TxBuilder::new()
+            .spend(&utxo(0, 100_000))
+            .pay(&recipient(), Amount::from_sat(60_000))
+            .change_control(&ChangeControl::default(), &change())
+            .build()
+            .unwrap();
+        assert_eq!(tx.output[1].script_pubkey, change().script_pubkey());
+    }
+
+    #[test]
+    fn dust_change_goes_to_the_fee() {
+        let tx = TxBuilder::new()
+            .spend(&utxo(0, 60_300))
+            .pay(&recipient(), Amount::from_sat(60_000))
+            .change_to(&change())
+            .build()
+            .unwrap();
+        assert_eq!(tx.output.len(), 1);
+    }
+
+    #[test]
+    fn sequences_follow_the_policy() {
+        let build = |builder: TxBuilder| {
+            builder
+                .spend(&utxo(0, 50_000))
+                .spend(&utxo(1, 50_000))
+                .pay(&recipient(), Amount::from_sat(60_000))
+                .build()
+                .unwrap()
+        };
+        let default = build(TxBuilder::new());
+        assert!(!default.is_explicitly_rbf());
+
+        let rbf = build(TxBuilder::new().rbf(true));
+        assert!(rbf.input.iter().all(|i| i.sequence.is_rbf()));
+
+        let exact = build(
+            TxBuilder::new()
+                .rbf(false)
+                .sequence(OutPoint::new(Txid::all_zeros(), 1), Sequence(10)),
+        );
+        assert_eq!(exact.input[0].sequence, Sequence::ENABLE_LOCKTIME_NO_RBF);
+        assert_eq!(exact.input[1].sequence, Sequence(10));
+        assert!(exact.is_explicitly_rbf());
+    }
+
+    #[test]
+    fn reports_insufficient_inputs() {
+        let err = TxBuilder::new()
+            .spend(&utxo(0, 30_000))
+            .spend(&utxo(1, 30_000))
+            .pay(&recipient(), Amount::from_sat(60_000))
+            .build()
+            .unwrap_err();
+        assert!(matches!(err, BuildError::Insufficient { available, .. }
+            if available == Amount::from_sat(60_000)));
+    }
+
+    #[test]
+    fn budget_lists_every_limit_broken() {
+        let tx = TxBuilder::new()
+            .spend(&utxo(0, 50_000))
+            .spend(&utxo(1, 50_000))
+            .pay(&recipient(), Amount::from_sat(60_000))
+            .change_to(&change())
+            .build()
+            .unwrap();
+        let input_value = Amount::from_sat(100_000);
+        assert!(Budget::default().check(&tx, input_value).is_ok());
+
+        let tight = Budget {
+            max_vsize: Some(50),
+            max_inputs: Some(1),
+            max_fee: Some(Amount::from_sat(100)),
+        };
+        match tight.check(&tx, input_value) {
+            Err(crate::error::Error::Build(BuildError::OverBudget(over))) => {
+                assert_eq!(over.len(), 3, "{over:?}")
+            }
+            other => panic!("expected OverBudget, got {other:?}"),
+        }
+    }
+}
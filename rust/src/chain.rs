@@ -0,0 +1,85 @@
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{Block, BlockHash, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde_json::Value;
+
+// Height of `block`. The BIP34 height in the coinbase is missing before activation and
+// can't be decoded from every coinbase, so the node's getblockheader is the source and
+// BIP34 is only used to cross-check it (or as the answer when the node can't tell).
+pub fn block_height(rpc: &impl RpcApi, block: &Block) -> bitcoincore_rpc::Result<u64> {
+    let bip34 = block.bip34_block_height().ok();
+    let header = rpc.get_block_header_info(&block.block_hash());
+
+    match (header, bip34) {
+        (Ok(info), Some(coinbase)) if info.height as u64 != coinbase => {
+            Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "block {} is at height {} but its coinbase claims {coinbase}",
+                block.block_hash(),
+                info.height
+            )))
+        }
+        (Ok(info), _) => Ok(info.height as u64),
+        (Err(_), Some(coinbase)) => Ok(coinbase),
+        (Err(e), None) => Err(e),
+    }
+}
+
+// Find the block that confirmed `txid`. The wallet's gettransaction knows the block hash
+// directly; for transactions it doesn't know about, the last `depth` blocks from the tip are
+// searched, so blocks mined in between by something else on a shared node don't matter.
+pub fn find_confirmation(
+    rpc: &impl RpcApi,
+    txid: &Txid,
+    depth: u64,
+) -> bitcoincore_rpc::Result<Option<Block>> {
+    if let Ok(tx) = rpc.get_transaction(txid, None) {
+        return match tx.info.blockhash {
+            Some(hash) => rpc.get_block(&hash).map(Some),
+            None => Ok(None),
+        };
+    }
+
+    let mut hash = rpc.get_best_block_hash()?;
+    for _ in 0..depth {
+        let block = rpc.get_block(&hash)?;
+        if block.txdata.iter().any(|tx| tx.txid() == *txid) {
+            return Ok(Some(block));
+        }
+        if block.header.prev_blockhash == BlockHash::all_zeros() {
+            break;
+        }
+        hash = block.header.prev_blockhash;
+    }
+    Ok(None)
+}
+
+// Whether the node runs with -txindex, so getrawtransaction finds any confirmed transaction
+pub fn has_txindex(rpc: &impl RpcApi) -> bool {
+    rpc.call::<Value>("getindexinfo", &["txindex".into()])
+        .map(|info| info.get("txindex").is_some())
+        .unwrap_or(false)
+}
+
+// Whether the node keeps BIP158 block filters (-blockfilterindex), which scanblocks needs
+pub fn has_blockfilterindex(rpc: &impl RpcApi) -> bool {
+    rpc.call::<Value>("getindexinfo", &["basic block filter index".into()])
+        .map(|info| info.get("basic block filter index").is_some())
+        .unwrap_or(false)
+}
+
+// getrawtransaction that also works without -txindex. Given the containing block it can
+// always look there; otherwise a wallet transaction is decoded from gettransaction, and
+// only mempool transactions are left for a plain getrawtransaction.
+pub fn get_transaction(
+    rpc: &impl RpcApi,
+    txid: &Txid,
+    block_hash: Option<&BlockHash>,
+) -> bitcoincore_rpc::Result<Transaction> {
+    if block_hash.is_some() || has_txindex(rpc) {
+        return rpc.get_raw_transaction(txid, block_hash);
+    }
+    match rpc.get_transaction(txid, None) {
+        Ok(tx) => Ok(tx.transaction()?),
+        Err(_) => rpc.get_raw_transaction(txid, None),
+    }
+}
@@ -0,0 +1,633 @@
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::{Address, Amount, BlockHash, OutPoint, Sequence, Txid};
+use bitcoincore_rpc::json::AddressType;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use rust::amount::parse_amount;
+use rust::audit::DEFAULT_LOG;
+use rust::gap;
+use rust::report::{FeeDisplay, ReportFormat};
+use rust::resume::DEFAULT_CHECKPOINT;
+use rust::traffic::{AmountKind, ArrivalKind, FeeRateKind};
+use rust::work;
+
+// Running without a subcommand does the capstone flow and writes ../out.txt,
+// that's what run-rust.sh and the autograder expect
+#[derive(Debug, Parser)]
+#[command(about = "Capstone project: interacting with a regtest Bitcoin Core node")]
+pub struct Cli {
+    /// Config file with node profiles [default: config.toml if it exists]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Node profile to connect to [default: the config's default_profile, or local-regtest]
+    #[arg(long, global = true)]
+    pub profile: Option<String>,
+    /// Log every JSON-RPC request and response to this file (passphrases redacted)
+    #[arg(long, global = true)]
+    pub trace_rpc: Option<PathBuf>,
+    /// When the run fails, write its exit code, phase and cause as JSON to this file
+    #[arg(long, global = true)]
+    pub failure_report: Option<PathBuf>,
+    /// Append-only log every signing operation is recorded in
+    #[arg(long, global = true, default_value = DEFAULT_LOG)]
+    pub audit_log: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Run the capstone flow with a different payment, writes ../out.txt as well
+    Send {
+        #[arg(long, default_value = "Miner")]
+        from: String,
+        #[arg(long, default_value = "Trader")]
+        to: String,
+        /// e.g. 12.5btc or 1250000000sat, plain numbers are BTC
+        #[arg(long, default_value = "20btc", value_parser = parse_amount)]
+        amount: Amount,
+        /// Spend exactly these UTXOs (txid:vout,...)
+        #[arg(long, value_delimiter = ',', conflicts_with = "avoid")]
+        spend_only: Vec<OutPoint>,
+        /// Never spend these UTXOs (txid:vout,...)
+        #[arg(long, value_delimiter = ',')]
+        avoid: Vec<OutPoint>,
+        /// Build and sign the transaction by hand instead of through the wallet's send
+        #[arg(long)]
+        manual: bool,
+        /// Signal BIP125 replaceability, the wallet's -walletrbf decides when not given
+        #[arg(long, value_enum)]
+        rbf: Option<Toggle>,
+        /// Exact nSequence for an input, e.g. txid:vout=4294967293 (repeatable)
+        #[arg(long, value_parser = parse_sequence)]
+        sequence: Vec<(OutPoint, Sequence)>,
+        /// Refuse to broadcast a transaction bigger than this many vbytes
+        #[arg(long)]
+        max_vsize: Option<u64>,
+        /// Refuse to broadcast a transaction spending more inputs than this
+        #[arg(long)]
+        max_inputs: Option<usize>,
+        /// Refuse to broadcast a transaction paying a higher fee, e.g. 0.001btc
+        #[arg(long, value_parser = parse_amount)]
+        max_fee: Option<Amount>,
+        /// Use avoid_reuse wallets, spend whole address groups and make change of the
+        /// recipient's address type
+        #[arg(long)]
+        privacy: bool,
+        /// Write the fee as the wallet reports it (signed) or without a sign
+        #[arg(long, value_enum, default_value_t = FeeDisplay::Absolute)]
+        fee_display: FeeDisplay,
+        /// Lay out ../out.txt with this minijinja template instead, e.g. {{ txid }} or
+        /// {{ fee_sat }}; amounts come with 8 decimals and as <name>_sat
+        #[arg(long)]
+        template: Option<PathBuf>,
+        /// Also write the report as JSON, with the fee in both conventions
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Write --report as CBOR instead, to archive many; `report decode` reads it back
+        #[arg(long, value_enum, default_value_t = ReportFormat::Json, requires = "report")]
+        report_format: ReportFormat,
+        /// Check the JSON report against its schema (schemas/) and fail instead of writing
+        /// one that doesn't match
+        #[arg(long, requires = "report")]
+        validate: bool,
+        /// Add BIP322 signatures by both wallets for their outputs to the JSON report
+        #[arg(long, requires = "report")]
+        prove_ownership: bool,
+        /// Label the recipient's address with this in both wallets, shown in the JSON report
+        #[arg(long)]
+        label: Option<String>,
+        /// Fail unless the recipient gets exactly --amount and the change is the inputs
+        /// less the amount and the fee, to the satoshi
+        #[arg(long)]
+        exact_amounts: bool,
+        /// Address type of the change, instead of the wallet's default (or with --privacy
+        /// the recipient's)
+        #[arg(long, value_enum, conflicts_with = "change_address")]
+        change_type: Option<ChangeType>,
+        /// Send the change here instead of to a new address of the sender's
+        #[arg(long)]
+        change_address: Option<Address<NetworkUnchecked>>,
+        /// Put the change at a random position among the outputs
+        #[arg(long)]
+        random_change_position: bool,
+    },
+    /// Finish a flow that stopped after broadcasting: confirm the payment and write the
+    /// reports, without funding or paying again
+    Resume {
+        #[arg(long, default_value = DEFAULT_CHECKPOINT)]
+        checkpoint: PathBuf,
+        #[arg(long, value_enum, default_value_t = FeeDisplay::Absolute)]
+        fee_display: FeeDisplay,
+        #[arg(long)]
+        template: Option<PathBuf>,
+        #[arg(long)]
+        report: Option<PathBuf>,
+        #[arg(long, value_enum, default_value_t = ReportFormat::Json, requires = "report")]
+        report_format: ReportFormat,
+        #[arg(long, requires = "report")]
+        validate: bool,
+    },
+    /// Work with written reports, offline
+    Report {
+        #[command(subcommand)]
+        action: ReportAction,
+    },
+    /// Check the ownership proofs in a JSON report from `send --prove-ownership`, offline
+    VerifyOwnership { report: PathBuf },
+    /// Run a scripted exercise from a TOML file (see scenarios/)
+    Scenario {
+        path: PathBuf,
+        /// Write a JSON pass/fail report of every step and assertion to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Pass a Miner coinbase output along a chain of wallets, one hop per block
+    MultiHop {
+        /// Wallets the coins flow through after leaving Miner
+        #[arg(long, value_delimiter = ',', default_values = ["A", "B", "C"])]
+        via: Vec<String>,
+        /// Write the lineage report as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Mine to a watch-only payout wallet and spend the rewards with a separate signer
+    PoolPayout {
+        /// Blocks mined to the payout descriptor
+        #[arg(long, default_value_t = 1)]
+        blocks: u64,
+        #[arg(long, default_value = "Trader")]
+        to: String,
+        #[arg(long, default_value = "10btc", value_parser = parse_amount)]
+        amount: Amount,
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Pay with a payjoin: the receiver adds one of its coins to the sender's transaction
+    Payjoin {
+        #[arg(long, default_value = "Miner")]
+        from: String,
+        #[arg(long, default_value = "Trader")]
+        to: String,
+        #[arg(long, default_value = "10btc", value_parser = parse_amount)]
+        amount: Amount,
+        /// Write who owns which input and output as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Pay with keys held here instead of Core wallets, using only chain RPCs
+    Walletless {
+        #[arg(long, default_value = "20btc", value_parser = parse_amount)]
+        amount: Amount,
+        /// Write the payment and what scantxoutset shows afterwards as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Take keys from the HD accounts in this state file (created if missing) instead
+        /// of throwaway ones
+        #[arg(long)]
+        accounts: Option<PathBuf>,
+    },
+    /// Show the HD accounts in a state file, optionally recovering their indexes from the chain
+    Accounts {
+        state: PathBuf,
+        /// Scan the chain for used addresses and move the next indexes past them
+        #[arg(long)]
+        recover: bool,
+        /// Unused addresses in a row after which recovery stops scanning a chain
+        #[arg(long, default_value_t = gap::DEFAULT_GAP_LIMIT)]
+        gap_limit: u32,
+    },
+    /// Find the used addresses of a ranged descriptor, up to a gap of unused ones
+    GapScan {
+        descriptor: String,
+        /// Unused addresses in a row after which scanning stops
+        #[arg(long, default_value_t = gap::DEFAULT_GAP_LIMIT)]
+        gap_limit: u32,
+        /// Addresses derived and checked per round
+        #[arg(long, default_value_t = gap::DEFAULT_CHUNK_SIZE,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        chunk_size: u32,
+    },
+    /// Check a descriptor's checksum and print it normalized, with the right checksum
+    Descriptor {
+        descriptor: String,
+        /// Only check the checksum, failing when it's missing or wrong
+        #[arg(long)]
+        check: bool,
+        /// Fix the checksum without asking the node, keeping any private keys
+        #[arg(long, conflicts_with = "check")]
+        local: bool,
+    },
+    /// Rebuild a wallet from its descriptors into a new wallet and compare balances
+    Recover {
+        #[arg(long, default_value = "Trader")]
+        wallet: String,
+        /// Private descriptor to recover from instead of the wallet's own (repeatable)
+        #[arg(long)]
+        descriptor: Vec<String>,
+        #[arg(long, default_value_t = gap::DEFAULT_GAP_LIMIT)]
+        gap_limit: u32,
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Show where a coin came from and where it went, as a tree
+    Trace {
+        /// Output to trace, as txid:vout
+        outpoint: OutPoint,
+        /// Stop following transactions after this many hops in either direction
+        #[arg(long, default_value_t = 10)]
+        depth: usize,
+    },
+    /// Export the transaction graph of a block range for Graphviz or Gephi
+    Graph {
+        #[arg(long)]
+        from_height: u64,
+        /// Last block to include, defaults to the chain tip
+        #[arg(long)]
+        to_height: Option<u64>,
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+        /// Write to this file instead of stdout
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// List (or clear) outputs locked with lockunspent
+    Locks {
+        #[arg(long, default_value = "Miner")]
+        wallet: String,
+        /// Unlock everything instead of listing
+        #[arg(long)]
+        clear: bool,
+    },
+    /// List a wallet's UTXOs, flagging those worth less than spending them costs
+    Utxos {
+        #[arg(long, default_value = "Miner")]
+        wallet: String,
+        /// Fee rate to judge by, in sat/vB [default: the node's estimate, at least 3]
+        #[arg(long)]
+        fee_rate: Option<u64>,
+        /// Write the list as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Hammer the node with concurrent senders, each with its own wallet and client
+    Stress {
+        /// Number of sender threads (and wallets)
+        #[arg(long, default_value_t = 4)]
+        senders: usize,
+        /// Transactions per second, per sender
+        #[arg(long, default_value_t = 2.0, value_parser = parse_positive)]
+        rate: f64,
+        /// How long to keep sending, in seconds
+        #[arg(long, default_value_t = 30)]
+        duration: u64,
+        /// Value of every stress transaction, e.g. 0.001btc or 100000sat. The median
+        /// with --amounts lognormal.
+        #[arg(long, default_value = "0.001btc", value_parser = parse_amount)]
+        amount: Amount,
+        /// How transaction values are drawn
+        #[arg(long, value_enum, default_value_t = AmountKind::Fixed)]
+        amounts: AmountKind,
+        /// Spread of log-normal values, the standard deviation of their logarithm
+        #[arg(long, default_value_t = 1.0, value_parser = parse_non_negative)]
+        sigma: f64,
+        /// How the time until a sender's next transaction is drawn
+        #[arg(long, value_enum, default_value_t = ArrivalKind::Fixed)]
+        arrivals: ArrivalKind,
+        /// How fee rates are drawn, wallet leaves them to its fee estimation
+        #[arg(long = "feerates", value_enum, default_value_t = FeeRateKind::Wallet)]
+        fee_rates: FeeRateKind,
+        /// Fee rate of the low-priority crowd with --feerates bimodal, in sat/vB
+        #[arg(long, default_value_t = 2.0, value_parser = parse_non_negative)]
+        fee_low: f64,
+        /// Fee rate of the high-priority crowd with --feerates bimodal, in sat/vB
+        #[arg(long, default_value_t = 30.0, value_parser = parse_non_negative)]
+        fee_high: f64,
+        /// Share of transactions paying the high fee rate, between 0 and 1
+        #[arg(long, default_value_t = 0.2, value_parser = parse_share)]
+        fee_high_share: f64,
+        /// Seed the random draws to repeat a run's traffic shape
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Write the results as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Difficulty, target, chain work and block intervals, from the last blocks' headers
+    Chainwork {
+        /// Blocks back from the tip to measure intervals and work over
+        #[arg(long, default_value_t = work::DEFAULT_WINDOW)]
+        window: u64,
+        /// Write the statistics as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Scan a range of blocks by decoding getblock's raw hex as it streams in, optionally
+    /// timed against fetching every transaction with its own RPC
+    BlockScan {
+        /// First height, by default the last blockscan::DEFAULT_BLOCKS blocks are scanned
+        #[arg(long)]
+        from: Option<u64>,
+        /// Last height, the tip by default
+        #[arg(long)]
+        to: Option<u64>,
+        /// Scan the range the per-transaction way too and compare speed and results
+        #[arg(long)]
+        compare: bool,
+        /// Write the timings and block summaries as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Work with the node's block template (getblocktemplate)
+    Template {
+        #[command(subcommand)]
+        action: TemplateAction,
+    },
+    /// Time per-call, batched and cached RPCs for the ownership check and fee verification
+    /// over a wallet's recent transactions (`cargo bench` does the same on a fixture)
+    Bench {
+        #[arg(long, default_value = "Miner")]
+        wallet: String,
+        /// Recent wallet transactions to check
+        #[arg(long, default_value_t = 50)]
+        transactions: usize,
+        #[arg(long, default_value_t = 5)]
+        iterations: u32,
+        /// Write the timings as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Run against several nodes at once and merge the results: the flow on the active
+    /// profile and read-only transaction reports on any profile
+    MultiChain {
+        /// Run the capstone flow on the active profile
+        #[arg(long)]
+        flow: bool,
+        /// Report on a transaction on another node, as PROFILE=TXID (repeatable)
+        #[arg(long, value_parser = parse_lookup)]
+        lookup: Vec<(String, Txid)>,
+        /// Write the combined report as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Build deliberately invalid transactions and check the node rejects each for the
+    /// right reason
+    FuzzRejects {
+        /// Write the compliance report as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Experiments that show how the protocol behaves
+    Experiment {
+        #[command(subcommand)]
+        kind: ExperimentKind,
+    },
+    /// Live view of wallet balances, the mempool, recent blocks and the flow's progress,
+    /// refreshed by polling the node every second
+    #[cfg(feature = "tui")]
+    Dashboard {
+        /// Run the capstone flow (writing ../out.txt) while watching
+        #[arg(long)]
+        flow: bool,
+    },
+    /// Export or import node snapshots
+    Snapshot {
+        #[command(subcommand)]
+        kind: SnapshotKind,
+    },
+    /// Show the signing operations recorded in --audit-log, checking none were removed
+    /// or changed
+    AuditLog {
+        /// Only this wallet's (`key:<address>` for keys this program signed with)
+        #[arg(long)]
+        wallet: Option<String>,
+    },
+    /// Serve a wallet as a faucet: POST /send {address, amount} pays an address (mining
+    /// first when the wallet is short) and GET /balance shows what it has
+    Faucet {
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+        #[arg(long, default_value = "Miner")]
+        wallet: String,
+        /// Largest payment a single request can ask for
+        #[arg(long, default_value = "10btc", value_parser = parse_amount)]
+        max_amount: Amount,
+        /// Seconds an address has to wait between payments
+        #[arg(long, default_value_t = 60)]
+        cooldown: u64,
+        /// Blocks to mine after each payment so it arrives confirmed, 0 leaves it in the
+        /// mempool
+        #[arg(long, default_value_t = 1)]
+        confirmations: u64,
+    },
+    /// Take payments over HTTP and send them in batches: POST /pay {address, amount}
+    /// queues one, POST /flush sends the queue, GET /queue and GET /batches show them
+    Batcher {
+        #[arg(long, default_value = "127.0.0.1:8081")]
+        listen: SocketAddr,
+        #[arg(long, default_value = "Miner")]
+        wallet: String,
+        /// Send as soon as this many payments are queued
+        #[arg(long, default_value_t = 10)]
+        max_payments: usize,
+        /// Seconds the oldest queued payment waits at most before the batch goes out
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+        /// When stopped, write the batches sent and their fee savings as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Make a wallet pick up a confirmed payment it missed: importprunedfunds with the
+    /// transaction's merkle proof on a pruned node, a rescan of its block otherwise
+    ImportFunds {
+        txid: Txid,
+        #[arg(long, default_value = "Trader")]
+        wallet: String,
+        /// The block that confirmed it, needed on a pruned node and on any other without
+        /// -txindex
+        #[arg(long)]
+        block_hash: Option<BlockHash>,
+    },
+    /// Run as a daemon watching the network health of one or more nodes: uptime, traffic
+    /// and known peer addresses, polled every interval and served as JSON at GET /status
+    /// and for Prometheus at GET /metrics
+    Monitor {
+        #[arg(long, default_value = "127.0.0.1:8082")]
+        listen: SocketAddr,
+        /// Config profile of a node to watch (repeatable), the active profile if none
+        #[arg(long = "node")]
+        nodes: Vec<String>,
+        /// Seconds between polls
+        #[arg(long, default_value_t = 15)]
+        interval: u64,
+    },
+    /// Look at unconfirmed transactions
+    Mempool {
+        #[command(subcommand)]
+        action: MempoolAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum TemplateAction {
+    /// Check the template as a miner would: coinbase value, weight and sigop limits, and
+    /// transaction order
+    Check {
+        /// Write the checks as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ExperimentKind {
+    /// Sign with each sighash flag (ALL, NONE, SINGLE, each with ANYONECANPAY too) and
+    /// show which later changes to the transaction its signature survives
+    Sighash {
+        /// Write every flag's outcomes and decoded transaction as JSON to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+    },
+    /// Run a payment once per combination of the parameters in a TOML file (amount,
+    /// inputs, outputs, fee_rate), each in a fresh wallet, and write what each cost as CSV
+    Sweep {
+        path: PathBuf,
+        #[arg(long, default_value = "sweep.csv")]
+        csv: PathBuf,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum SnapshotKind {
+    /// UTXO set snapshots (assumeutxo) via dumptxoutset/loadtxoutset
+    Utxo {
+        #[command(subcommand)]
+        action: UtxoAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ReportAction {
+    /// Print a CBOR report from --report-format cbor as the JSON report
+    Decode {
+        path: PathBuf,
+        /// Check the decoded report against its schema
+        #[arg(long)]
+        validate: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MempoolAction {
+    /// Show a transaction's unconfirmed ancestors and descendants as a tree, with each one's
+    /// fee, vsize and feerate and the package feerates CPFP works with
+    Tree { txid: Txid },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum UtxoAction {
+    /// Write the node's UTXO set to PATH (relative paths are inside the node's datadir)
+    Export {
+        path: PathBuf,
+        /// Roll back to this height before dumping (Bitcoin Core 28+), loadtxoutset only
+        /// accepts snapshots at heights hardcoded in the chainparams
+        #[arg(long)]
+        height: Option<u64>,
+    },
+    /// Load a UTXO snapshot from PATH into the node
+    Import { path: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ChangeType {
+    Bech32,
+    Bech32m,
+}
+
+impl From<ChangeType> for AddressType {
+    fn from(change_type: ChangeType) -> AddressType {
+        match change_type {
+            ChangeType::Bech32 => AddressType::Bech32,
+            ChangeType::Bech32m => AddressType::Bech32m,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Toggle {
+    On,
+    Off,
+}
+
+// PROFILE=TXID
+fn parse_lookup(s: &str) -> Result<(String, Txid), String> {
+    let (profile, txid) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected profile=txid, got {s:?}"))?;
+    let txid = txid
+        .parse()
+        .map_err(|e| format!("bad txid {txid:?}: {e}"))?;
+    Ok((profile.to_owned(), txid))
+}
+
+// A finite number, for the traffic shape: what the distributions can't take panics in the
+// sender threads, so it's turned away here
+fn parse_finite(s: &str) -> Result<f64, String> {
+    let value: f64 = s
+        .parse()
+        .map_err(|e| format!("{s:?} is not a number: {e}"))?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(format!("{s:?} is not a finite number"))
+    }
+}
+
+fn parse_positive(s: &str) -> Result<f64, String> {
+    let value = parse_finite(s)?;
+    if value > 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("must be more than 0, got {value}"))
+    }
+}
+
+fn parse_non_negative(s: &str) -> Result<f64, String> {
+    let value = parse_finite(s)?;
+    if value >= 0.0 {
+        Ok(value)
+    } else {
+        Err(format!("can't be negative, got {value}"))
+    }
+}
+
+fn parse_share(s: &str) -> Result<f64, String> {
+    let value = parse_finite(s)?;
+    if (0.0..=1.0).contains(&value) {
+        Ok(value)
+    } else {
+        Err(format!("must be between 0 and 1, got {value}"))
+    }
+}
+
+// txid:vout=N with N in decimal or 0x-prefixed hex
+fn parse_sequence(s: &str) -> Result<(OutPoint, Sequence), String> {
+    let (outpoint, sequence) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected txid:vout=sequence, got {s:?}"))?;
+    let outpoint = outpoint.parse().map_err(|e| format!("{e}"))?;
+    let sequence = match sequence.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => sequence.parse(),
+    }
+    .map_err(|e| format!("bad sequence {sequence:?}: {e}"))?;
+    Ok((outpoint, Sequence(sequence)))
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum GraphFormat {
+    Dot,
+    Graphml,
+}
@@ -0,0 +1,300 @@
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, FeeRate, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid,
+    Witness,
+};
+use bitcoincore_rpc::json::AddressType;
+use bitcoincore_rpc::{Client, RpcApi};
+use rand::seq::SliceRandom;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::builder::{
+    OUTPUT_BASE_VBYTES, P2WPKH_INPUT_VBYTES, P2WPKH_SCRIPT_LEN, TX_OVERHEAD_VBYTES,
+};
+use crate::coins::{self, FEE_HEADROOM};
+use crate::error::{Error, Result};
+
+// Every output here pays a P2WPKH script
+const P2WPKH_OUTPUT_VBYTES: u64 = OUTPUT_BASE_VBYTES + P2WPKH_SCRIPT_LEN;
+
+// What one wallet brings to the join
+#[derive(Debug, Clone)]
+pub struct Contribution {
+    pub inputs: Vec<(OutPoint, Amount)>,
+    // Receives the denomination
+    pub mix: Address,
+    pub change: Address,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CoinjoinReport {
+    pub txid: Txid,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub denomination: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee: Amount,
+    pub participants: Vec<ParticipantReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParticipantReport {
+    pub wallet: String,
+    pub inputs: Vec<OutPoint>,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub input_value: Amount,
+    // The equal-valued output
+    pub mix_address: Address,
+    // None when the change would have been dust and went to the fee
+    pub change_address: Option<Address>,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub change: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee_share: Amount,
+}
+
+// What each participant pays at `fee_rate`: its own inputs, its two outputs and an equal
+// share (rounded up) of the transaction overhead. Shares are in the same order as
+// `inputs`, the number of inputs of each participant.
+pub fn fee_shares(inputs: &[usize], fee_rate: FeeRate) -> Vec<Amount> {
+    let overhead = TX_OVERHEAD_VBYTES.div_ceil(inputs.len().max(1) as u64);
+    inputs
+        .iter()
+        .map(|&n| {
+            let vsize = overhead + P2WPKH_INPUT_VBYTES * n as u64 + 2 * P2WPKH_OUTPUT_VBYTES;
+            fee_rate.fee_vb(vsize).unwrap_or(Amount::MAX_MONEY)
+        })
+        .collect()
+}
+
+// The unsigned join: every contribution's inputs, one `denomination` output each and each
+// one's change, less its fee share. Change that would be dust goes to the fee instead.
+// Inputs and outputs are shuffled so their order doesn't tell who is who. Also returns the
+// change and fee actually paid by each contribution.
+pub fn build(
+    contributions: &[Contribution],
+    denomination: Amount,
+    fee_rate: FeeRate,
+) -> std::result::Result<(Transaction, Vec<(Amount, Amount)>), String> {
+    let inputs: Vec<usize> = contributions.iter().map(|c| c.inputs.len()).collect();
+    let shares = fee_shares(&inputs, fee_rate);
+
+    let mut input = vec![];
+    let mut output = vec![];
+    let mut paid = vec![];
+    for (i, (contribution, share)) in contributions.iter().zip(shares).enumerate() {
+        let value: Amount = contribution.inputs.iter().map(|(_, v)| *v).sum();
+        let change = value.checked_sub(denomination + share).ok_or_else(|| {
+            format!(
+                "participant {} brings {value}, {} is needed",
+                i + 1,
+                denomination + share
+            )
+        })?;
+        input.extend(contribution.inputs.iter().map(|(outpoint, _)| TxIn {
+            previous_output: *outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+            witness: Witness::new(),
+        }));
+        output.push(TxOut {
+            value: denomination,
+            script_pubkey: contribution.mix.script_pubkey(),
+        });
+        let script = contribution.change.script_pubkey();
+        if change >= coins::dust_threshold(&script, fee_rate.max(coins::DUST_RELAY_FEE)) {
+            output.push(TxOut {
+                value: change,
+                script_pubkey: script,
+            });
+            paid.push((change, share));
+        } else {
+            paid.push((Amount::ZERO, share + change));
+        }
+    }
+
+    let mut rng = rand::thread_rng();
+    input.shuffle(&mut rng);
+    output.shuffle(&mut rng);
+    let tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input,
+        output,
+    };
+    Ok((tx, paid))
+}
+
+// A naive coinjoin of `wallets` (name and wallet client), each paying `denomination` to
+// itself, at `fee_rate` or else the node's estimate. Each wallet picks its own coins; the
+// unsigned transaction goes round as a PSBT with every wallet signing its inputs, the last
+// one's result is complete.
+pub fn run(
+    wallets: &[(&str, &Client)],
+    denomination: Amount,
+    fee_rate: Option<FeeRate>,
+) -> Result<CoinjoinReport> {
+    if wallets.len() < 2 {
+        return Err(bitcoincore_rpc::Error::ReturnedError(
+            "a coinjoin needs at least two participants".to_owned(),
+        )
+        .into());
+    }
+    let (_, coordinator) = wallets[0];
+    let fee_rate = match fee_rate {
+        Some(rate) => rate,
+        None => coins::current_fee_rate(coordinator)?,
+    };
+
+    let mut contributions = Vec::with_capacity(wallets.len());
+    for (name, rpc) in wallets {
+        let unspent = rpc.list_unspent(Some(1), None, None, None, None)?;
+        let target = denomination + FEE_HEADROOM;
+        let selected =
+            coins::select_utxos(&unspent, target).ok_or_else(|| Error::InsufficientFunds {
+                wallet: name.to_string(),
+                needed: target,
+                available: unspent
+                    .iter()
+                    .filter(|u| u.spendable && u.safe)
+                    .map(|u| u.amount)
+                    .sum(),
+            })?;
+        contributions.push(Contribution {
+            inputs: selected
+                .iter()
+                .map(|u| (OutPoint::new(u.txid, u.vout), u.amount))
+                .collect(),
+            mix: rpc
+                .get_new_address(None, Some(AddressType::Bech32))?
+                .assume_checked(),
+            change: rpc
+                .get_raw_change_address(Some(AddressType::Bech32))?
+                .assume_checked(),
+        });
+    }
+
+    let (tx, paid) = build(&contributions, denomination, fee_rate)
+        .map_err(bitcoincore_rpc::Error::ReturnedError)?;
+    let mut psbt: String =
+        coordinator.call("converttopsbt", &[json!(encode::serialize_hex(&tx))])?;
+    let mut complete = false;
+    for (name, rpc) in wallets {
+        let processed = rpc.wallet_process_psbt(&psbt, Some(true), None, None)?;
+        println!("  {name} signed its inputs");
+        psbt = processed.psbt;
+        complete = processed.complete;
+    }
+    if !complete {
+        return Err(bitcoincore_rpc::Error::ReturnedError(
+            "the coinjoin is missing signatures after every participant signed".to_owned(),
+        )
+        .into());
+    }
+    let hex = coordinator
+        .finalize_psbt(&psbt, Some(true))?
+        .hex
+        .ok_or_else(|| {
+            bitcoincore_rpc::Error::ReturnedError("finalizepsbt returned no transaction".to_owned())
+        })?;
+    let txid = coordinator.send_raw_transaction(&hex)?;
+
+    let participants: Vec<ParticipantReport> = wallets
+        .iter()
+        .zip(contributions)
+        .zip(&paid)
+        .map(|(((name, _), c), &(change, fee_share))| ParticipantReport {
+            wallet: name.to_string(),
+            inputs: c.inputs.iter().map(|(outpoint, _)| *outpoint).collect(),
+            input_value: c.inputs.iter().map(|(_, v)| *v).sum(),
+            mix_address: c.mix,
+            change_address: (change > Amount::ZERO).then_some(c.change),
+            change,
+            fee_share,
+        })
+        .collect();
+    Ok(CoinjoinReport {
+        txid,
+        denomination,
+        fee: participants.iter().map(|p| p.fee_share).sum(),
+        participants,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::{Network, WPubkeyHash};
+
+    fn contribution(seed: u8, values: &[u64]) -> Contribution {
+        let script = |n: u8| ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([n; 20]));
+        let mix = Address::from_script(&script(seed), Network::Regtest).unwrap();
+        let change = Address::from_script(&script(seed + 100), Network::Regtest).unwrap();
+        Contribution {
+            inputs: values
+                .iter()
+                .enumerate()
+                .map(|(vout, v)| {
+                    (
+                        OutPoint::new(Txid::from_byte_array([seed; 32]), vout as u32),
+                        Amount::from_sat(*v),
+                    )
+                })
+                .collect(),
+            mix,
+            change,
+        }
+    }
+
+    #[test]
+    fn fee_shares_cover_the_transaction() {
+        let rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let shares = fee_shares(&[1, 1, 2], rate);
+        assert_eq!(shares[0], shares[1]);
+        assert_eq!(
+            shares[2] - shares[0],
+            rate.fee_vb(P2WPKH_INPUT_VBYTES).unwrap()
+        );
+
+        let vsize = TX_OVERHEAD_VBYTES + 4 * P2WPKH_INPUT_VBYTES + 6 * P2WPKH_OUTPUT_VBYTES;
+        let total: Amount = shares.iter().copied().sum();
+        assert!(total >= rate.fee_vb(vsize).unwrap());
+    }
+
+    #[test]
+    fn builds_equal_outputs_and_change() {
+        let denomination = Amount::ONE_BTC;
+        let rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let contributions = [
+            contribution(1, &[150_000_000]),
+            contribution(2, &[60_000_000, 60_000_000]),
+            // Change below the dust limit goes to the fee
+            contribution(3, &[100_000_300]),
+        ];
+        let (tx, paid) = build(&contributions, denomination, rate).unwrap();
+
+        assert_eq!(tx.input.len(), 4);
+        assert_eq!(tx.output.len(), 5);
+        assert_eq!(
+            tx.output.iter().filter(|o| o.value == denomination).count(),
+            3
+        );
+        assert_eq!(paid[2].0, Amount::ZERO);
+        assert_eq!(paid[2].1, Amount::from_sat(300));
+
+        let total_in = Amount::from_sat(150_000_000 + 120_000_000 + 100_000_300);
+        let total_out: Amount = tx.output.iter().map(|o| o.value).sum();
+        let fees: Amount = paid.iter().map(|(_, fee)| *fee).sum();
+        assert_eq!(total_in - total_out, fees);
+    }
+
+    #[test]
+    fn rejects_a_participant_short_of_the_denomination() {
+        let rate = FeeRate::from_sat_per_vb(2).unwrap();
+        let contributions = [contribution(1, &[150_000_000]), contribution(2, &[1_000])];
+        assert!(build(&contributions, Amount::ONE_BTC, rate).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::{Amount, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::builder::Budget;
+use crate::error::Result;
+
+// A hot wallet keeps a small float to pay from. The rest sits in a cold wallet that only
+// watches: it sees the coins and builds spends of them, but the keys are with a signer
+// that never does anything but sign the PSBTs it's handed.
+#[derive(Debug, Serialize)]
+pub struct Refill {
+    // Spendable and pending balance of the hot wallet before the refill
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub hot_balance: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee: Amount,
+    pub txid: Txid,
+}
+
+// Let `cold` watch everything `signer` can spend by importing the signer's active public
+// descriptors, receive and change alike, so the cold wallet can hand out addresses for
+// both. Returns how many were imported, none if `cold` already has descriptors.
+pub fn watch(cold: &impl RpcApi, signer: &impl RpcApi) -> bitcoincore_rpc::Result<usize> {
+    let existing: Value = cold.call("listdescriptors", &[])?;
+    if existing["descriptors"]
+        .as_array()
+        .is_some_and(|d| !d.is_empty())
+    {
+        return Ok(0);
+    }
+    let listed: Value = signer.call("listdescriptors", &[])?;
+    let requests: Vec<Value> = listed["descriptors"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|d| d["active"].as_bool() == Some(true))
+        .map(|d| {
+            json!({
+                "desc": d["desc"],
+                "timestamp": d["timestamp"],
+                "active": true,
+                "internal": d["internal"].as_bool().unwrap_or(false),
+                "range": d["range"],
+                "next_index": d["next"],
+            })
+        })
+        .collect();
+    let results: Vec<Value> = cold.call("importdescriptors", &[json!(requests)])?;
+    if let Some(failed) = results.iter().find(|r| r["success"] != json!(true)) {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "importing the signer's descriptors failed: {}",
+            failed["error"]
+        )));
+    }
+    Ok(requests.len())
+}
+
+// Top `hot` back up to `target` from `cold` if its balance (pending coins included, so a
+// refill on its way isn't sent twice) has dropped below `threshold`. The cold wallet
+// funds the PSBT, `signer` signs it, and `budget` has the last word before it's broadcast.
+pub fn refill(
+    hot: &impl RpcApi,
+    cold: &impl RpcApi,
+    signer: &impl RpcApi,
+    threshold: Amount,
+    target: Amount,
+    budget: &Budget,
+) -> Result<Option<Refill>> {
+    let balances = hot.get_balances()?.mine;
+    let hot_balance = balances.trusted + balances.untrusted_pending;
+    if hot_balance >= threshold {
+        return Ok(None);
+    }
+    let amount = target.checked_sub(hot_balance).ok_or_else(|| {
+        bitcoincore_rpc::Error::ReturnedError(format!(
+            "the float target {target} is below the refill threshold {threshold}"
+        ))
+    })?;
+
+    let address = hot.get_new_address(None, None)?.assume_checked();
+    let outputs = HashMap::from([(address.to_string(), amount)]);
+    let unsigned = cold.wallet_create_funded_psbt(&[], &outputs, None, None, None)?;
+    let signed = signer.wallet_process_psbt(&unsigned.psbt, Some(true), None, None)?;
+    if !signed.complete {
+        return Err(bitcoincore_rpc::Error::ReturnedError(
+            "the cold signer could not sign the refill".to_owned(),
+        )
+        .into());
+    }
+    let finalized = cold.finalize_psbt(&signed.psbt, Some(true))?;
+    let hex = finalized.hex.ok_or_else(|| {
+        bitcoincore_rpc::Error::ReturnedError("finalizepsbt returned no transaction".to_owned())
+    })?;
+    let tx: Transaction = encode::deserialize(&hex).map_err(bitcoincore_rpc::Error::from)?;
+    let output_value: Amount = tx.output.iter().map(|o| o.value).sum();
+    budget.check(&tx, output_value + unsigned.fee)?;
+    let txid = cold.send_raw_transaction(&hex)?;
+    Ok(Some(Refill {
+        hot_balance,
+        amount,
+        fee: unsigned.fee,
+        txid,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+
+    #[test]
+    fn watches_the_signers_active_descriptors() {
+        let signer = MockClient::new().returns(
+            "listdescriptors",
+            json!({ "wallet_name": "ColdSigner", "descriptors": [
+                { "desc": "wpkh(tpub/84h/1h/0h/0/*)#aaaaaaaa", "timestamp": 1, "active": true,
+                  "internal": false, "range": [0, 999], "next": 0 },
+                { "desc": "wpkh(tpub/84h/1h/0h/1/*)#bbbbbbbb", "timestamp": 1, "active": true,
+                  "internal": true, "range": [0, 999], "next": 0 },
+                { "desc": "pkh(tpub/44h/1h/0h/0/*)#cccccccc", "timestamp": 1, "active": false,
+                  "range": [0, 999], "next": 0 },
+            ]}),
+        );
+        let cold = MockClient::new()
+            .returns("listdescriptors", json!({ "descriptors": [] }))
+            .returns(
+                "importdescriptors",
+                json!([{ "success": true }, { "success": true }]),
+            );
+        assert_eq!(watch(&cold, &signer).unwrap(), 2);
+        cold.assert_done();
+
+        // Already watching
+        let cold = MockClient::new().returns(
+            "listdescriptors",
+            json!({ "descriptors": [{ "desc": "wpkh(tpub/84h/1h/0h/0/*)#aaaaaaaa" }] }),
+        );
+        assert_eq!(watch(&cold, &MockClient::new()).unwrap(), 0);
+    }
+
+    #[test]
+    fn leaves_a_funded_float_alone() {
+        let hot = MockClient::new().returns(
+            "getbalances",
+            json!({ "mine": { "trusted": 4.0, "untrusted_pending": 2.0, "immature": 0.0 } }),
+        );
+        let refilled = refill(
+            &hot,
+            &MockClient::new(),
+            &MockClient::new(),
+            Amount::from_int_btc(5),
+            Amount::from_int_btc(10),
+            &Budget::default(),
+        )
+        .unwrap();
+        assert!(refilled.is_none());
+        hot.assert_done();
+    }
+}
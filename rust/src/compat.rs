@@ -0,0 +1,133 @@
+use bitcoincore_rpc::RpcApi;
+use serde_json::Value;
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+// Oldest and newest Bitcoin Core the tool is tested against
+pub const MIN_VERSION: NodeVersion = NodeVersion(24_00_00);
+pub const MAX_TESTED_VERSION: NodeVersion = NodeVersion(28_99_99);
+
+// Version as getnetworkinfo reports it, e.g. 240001 for 24.0.1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NodeVersion(pub u32);
+
+impl NodeVersion {
+    pub fn major(self) -> u32 {
+        self.0 / 1_00_00
+    }
+}
+
+impl fmt::Display for NodeVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (major, minor, patch) = (self.0 / 1_00_00, self.0 / 100 % 100, self.0 % 100);
+        write!(f, "{major}.{minor}.{patch}")
+    }
+}
+
+// RPCs and options that not every supported version has, each checked where it's used.
+// The send, sendtoaddress and sendmany options the tool passes are the same from 24 to 28
+// and nothing sends with sendall or submitpackage, so there is nothing to adapt for those:
+// a feature goes here together with the code that needs it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    LoadTxOutSet,
+    DumpTxOutSetRollback,
+    ScanBlocks,
+}
+
+impl Feature {
+    pub fn min_version(self) -> NodeVersion {
+        match self {
+            Feature::ScanBlocks => NodeVersion(25_00_00),
+            Feature::LoadTxOutSet => NodeVersion(26_00_00),
+            Feature::DumpTxOutSetRollback => NodeVersion(28_00_00),
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Feature::LoadTxOutSet => "loadtxoutset",
+            Feature::DumpTxOutSetRollback => "dumptxoutset with rollback",
+            Feature::ScanBlocks => "scanblocks",
+        })
+    }
+}
+
+// How a wallet is told about a confirmed transaction it missed. A rescan reads the blocks,
+// which a pruned node may have thrown away; importprunedfunds only needs the transaction and
+// a merkle proof of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Discovery {
+    Rescan,
+    PrunedImport,
+}
+
+impl fmt::Display for Discovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Discovery::Rescan => "rescan",
+            Discovery::PrunedImport => "importprunedfunds",
+        })
+    }
+}
+
+// What the connected node can do, detected once at startup so RPC calls can be adapted
+// up front instead of failing with whatever JSON error an older node returns
+#[derive(Debug, Clone, Copy)]
+pub struct Compat {
+    pub version: NodeVersion,
+}
+
+impl Compat {
+    // getnetworkinfo is read untyped, its `warnings` field changed type in 28
+    pub fn detect(rpc: &impl RpcApi) -> Result<Compat> {
+        let info: Value = rpc.call("getnetworkinfo", &[])?;
+        let version = NodeVersion(info["version"].as_u64().unwrap_or_default() as u32);
+        if version < MIN_VERSION {
+            return Err(Error::UnsupportedNode {
+                version,
+                needed: MIN_VERSION,
+                feature: None,
+            });
+        }
+        if version > MAX_TESTED_VERSION {
+            eprintln!("Bitcoin Core {version} is newer than any tested version, continuing anyway");
+        }
+        Ok(Compat { version })
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.version >= feature.min_version()
+    }
+
+    pub fn require(&self, feature: Feature) -> Result<()> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(Error::UnsupportedNode {
+                version: self.version,
+                needed: feature.min_version(),
+                feature: Some(feature),
+            })
+        }
+    }
+
+    // getblockchaininfo, untyped for the same reason as getnetworkinfo: 28 turned `warnings`
+    // into an array, which the typed result can't read
+    pub fn blockchain_info(&self, rpc: &impl RpcApi) -> Result<Value> {
+        Ok(rpc.call("getblockchaininfo", &[])?)
+    }
+
+    // Pruned nodes import transactions with their proofs, the others rescan
+    pub fn discovery(&self, rpc: &impl RpcApi) -> Result<Discovery> {
+        let info = self.blockchain_info(rpc)?;
+        Ok(if info["pruned"].as_bool() == Some(true) {
+            Discovery::PrunedImport
+        } else {
+            Discovery::Rescan
+        })
+    }
+}
@@ -0,0 +1,184 @@
+use bitcoincore_rpc::bitcoin::Network;
+use bitcoincore_rpc::Auth;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::{fmt, fs, io};
+
+use crate::consensus::{ChainOverrides, ChainParams};
+use crate::hooks::Hook;
+use crate::{RPC_PASS, RPC_URL, RPC_USER};
+
+// Read from the working directory when --config isn't given
+pub const DEFAULT_CONFIG: &str = "config.toml";
+// What runs without any config file: the docker-compose regtest node
+pub const BUILTIN_PROFILE: &str = "local-regtest";
+
+// Connection settings of one node
+#[derive(Debug, Clone, Deserialize)]
+pub struct Profile {
+    // The network's default RPC port on localhost when left out
+    #[serde(default)]
+    pub url: String,
+    pub user: Option<String>,
+    pub password: Option<String>,
+    // Cookie file, used instead of user/password when given
+    pub cookie: Option<PathBuf>,
+    #[serde(default = "default_network")]
+    pub network: Network,
+    // Where the chain differs from its network's usual parameters
+    #[serde(default)]
+    pub chain: ChainOverrides,
+}
+
+fn default_network() -> Network {
+    Network::Regtest
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Profile {
+            url: RPC_URL.to_owned(),
+            user: Some(RPC_USER.to_owned()),
+            password: Some(RPC_PASS.to_owned()),
+            cookie: None,
+            network: Network::Regtest,
+            chain: ChainOverrides::default(),
+        }
+    }
+}
+
+impl Profile {
+    pub fn params(&self) -> ChainParams {
+        ChainParams::for_network(self.network).with(&self.chain)
+    }
+
+    pub fn auth(&self) -> Auth {
+        match (&self.cookie, &self.user) {
+            (Some(cookie), _) => Auth::CookieFile(cookie.clone()),
+            (None, Some(user)) => {
+                Auth::UserPass(user.clone(), self.password.clone().unwrap_or_default())
+            }
+            (None, None) => Auth::None,
+        }
+    }
+}
+
+// The config file, see config.example.toml
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+    // Run on lifecycle events, see `hooks`
+    #[serde(default)]
+    pub hooks: Vec<Hook>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnknownProfile(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "cannot read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {e}", path.display()),
+            ConfigError::UnknownProfile(name) => write!(f, "no profile named {name:?}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Config, ConfigError> {
+        let text = fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_owned(), e))?;
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_owned(), e))
+    }
+
+    // Which profile `profile` picks for `name`
+    pub fn profile_name<'a>(&'a self, name: Option<&'a str>) -> &'a str {
+        name.or(self.default_profile.as_deref())
+            .unwrap_or(BUILTIN_PROFILE)
+    }
+
+    // `name`, or the file's default profile, or the built-in one. The built-in profile can
+    // be overridden by defining one of the same name.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, ConfigError> {
+        let name = self.profile_name(name);
+        match self.profiles.get(name) {
+            Some(profile) if profile.url.is_empty() => Ok(Profile {
+                url: format!("http://127.0.0.1:{}", profile.params().rpc_port),
+                ..profile.clone()
+            }),
+            Some(profile) => Ok(profile.clone()),
+            None if name == BUILTIN_PROFILE => Ok(Profile::default()),
+            None => Err(ConfigError::UnknownProfile(name.to_owned())),
+        }
+    }
+}
+
+static ACTIVE: OnceLock<Profile> = OnceLock::new();
+static HOOKS: OnceLock<Vec<Hook>> = OnceLock::new();
+
+// The config file at `path`, or the default one. A config file given explicitly has to
+// exist, the default one is optional.
+pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+    match path {
+        Some(path) => Config::from_file(path),
+        None if Path::new(DEFAULT_CONFIG).exists() => Config::from_file(Path::new(DEFAULT_CONFIG)),
+        None => Ok(Config::default()),
+    }
+}
+
+// Pick the profile every client connects with
+pub fn select(path: Option<&Path>, name: Option<&str>) -> Result<&'static Profile, ConfigError> {
+    let config = load(path)?;
+    let profile = config.profile(name)?;
+    let _ = HOOKS.set(config.hooks);
+    Ok(ACTIVE.get_or_init(|| profile))
+}
+
+pub fn active() -> &'static Profile {
+    ACTIVE.get_or_init(Profile::default)
+}
+
+// The selected config file's hooks, none before `select`
+pub fn hooks() -> &'static [Hook] {
+    HOOKS.get().map_or(&[], Vec::as_slice)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_example_config() {
+        let config: Config = toml::from_str(include_str!("../config.example.toml")).unwrap();
+        let signet = config.profile(Some("signet-box")).unwrap();
+        assert_eq!(signet.network, Network::Signet);
+        assert!(matches!(signet.auth(), Auth::CookieFile(_)));
+        assert_eq!(config.profile(None).unwrap().url, "http://127.0.0.1:18443");
+        assert_eq!(config.hooks.len(), 1);
+
+        let custom = config.profile(Some("custom-signet")).unwrap();
+        assert_eq!(custom.url, "http://127.0.0.1:38332");
+        assert_eq!(custom.params().coinbase_maturity, 100);
+        assert_eq!(custom.params().halving_interval, 1_000);
+    }
+
+    #[test]
+    fn falls_back_to_the_builtin_profile() {
+        let config = Config::default();
+        assert_eq!(config.profile(None).unwrap().url, RPC_URL);
+        assert!(matches!(
+            config.profile(Some("nope")),
+            Err(ConfigError::UnknownProfile(_))
+        ));
+    }
+}
@@ -0,0 +1,404 @@
+use bitcoincore_rpc::bitcoin::hashes::{sha256d, Hash};
+use bitcoincore_rpc::bitcoin::{Amount, Block, Network, Wtxid};
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::config;
+
+// OP_RETURN, a 36-byte push and BIP141's commitment header 0xaa21a9ed
+const WITNESS_COMMITMENT_HEADER: [u8; 6] = [0x6a, 0x24, 0xaa, 0x21, 0xa9, 0xed];
+
+pub const INITIAL_SUBSIDY: Amount = Amount::from_int_btc(50);
+
+// What the funding math and the default connection assume about a chain. Every network
+// Core knows has its values built in, a profile's `[profiles.<name>.chain]` table
+// overrides them for nodes started with e.g. a different -testactivationheight or a
+// custom signet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChainParams {
+    // Blocks between halvings of the block reward
+    pub halving_interval: u64,
+    // Confirmations a coinbase output needs before it can be spent
+    pub coinbase_maturity: u64,
+    // Port the node's RPC server listens on by default
+    pub rpc_port: u16,
+}
+
+impl ChainParams {
+    // Regtest halves the block reward every 150 blocks instead of mainnet's 210000
+    pub const REGTEST: ChainParams = ChainParams {
+        halving_interval: 150,
+        coinbase_maturity: 100,
+        rpc_port: 18443,
+    };
+
+    pub fn for_network(network: Network) -> ChainParams {
+        let rpc_port = match network {
+            Network::Regtest => return ChainParams::REGTEST,
+            Network::Testnet => 18332,
+            Network::Signet => 38332,
+            _ => 8332,
+        };
+        ChainParams {
+            halving_interval: 210_000,
+            coinbase_maturity: 100,
+            rpc_port,
+        }
+    }
+
+    pub fn with(self, overrides: &ChainOverrides) -> ChainParams {
+        ChainParams {
+            halving_interval: overrides.halving_interval.unwrap_or(self.halving_interval),
+            coinbase_maturity: overrides
+                .coinbase_maturity
+                .unwrap_or(self.coinbase_maturity),
+            rpc_port: overrides.rpc_port.unwrap_or(self.rpc_port),
+        }
+    }
+
+    // New coins a block at `height` may create, as in Core's GetBlockSubsidy
+    pub fn subsidy(&self, height: u64) -> Amount {
+        let halvings = height / self.halving_interval;
+        if halvings >= 64 {
+            return Amount::ZERO;
+        }
+        Amount::from_sat(INITIAL_SUBSIDY.to_sat() >> halvings)
+    }
+
+    // How many blocks starting at `from` it takes for their subsidy to add up to `target`,
+    // None once the reward has run out before that
+    pub fn blocks_to_earn(&self, from: u64, target: Amount) -> Option<u64> {
+        let mut earned = Amount::ZERO;
+        let mut height = from;
+        while earned < target {
+            let reward = self.subsidy(height);
+            if reward == Amount::ZERO {
+                return None;
+            }
+            earned += reward;
+            height += 1;
+        }
+        Some(height - from)
+    }
+}
+
+// A profile's changes to its network's ChainParams, anything left out keeps the default
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainOverrides {
+    pub halving_interval: Option<u64>,
+    pub coinbase_maturity: Option<u64>,
+    pub rpc_port: Option<u16>,
+}
+
+// The active profile's chain
+pub fn params() -> ChainParams {
+    config::active().params()
+}
+
+pub fn subsidy(height: u64) -> Amount {
+    params().subsidy(height)
+}
+
+pub fn blocks_to_earn(from: u64, target: Amount) -> Option<u64> {
+    params().blocks_to_earn(from, target)
+}
+
+// The coinbase of `block` should claim the subsidy plus the fees of the block. More means
+// the node and these chain parameters disagree about the subsidy, an error. Less is valid,
+// something mined the block oddly, and only warned about: the payment is confirmed by now.
+pub fn check_coinbase(
+    rpc: &impl RpcApi,
+    block: &Block,
+    height: u64,
+) -> bitcoincore_rpc::Result<()> {
+    let claimed: Amount = block.txdata[0].output.iter().map(|o| o.value).sum();
+    let stats: Value = rpc.call("getblockstats", &[json!(height), json!(["totalfee"])])?;
+    let fees = Amount::from_sat(stats["totalfee"].as_u64().unwrap_or_default());
+    let expected = subsidy(height) + fees;
+    let claims = format!(
+        "coinbase at height {height} claims {claimed}, expected {expected} (subsidy {} + \
+         fees {fees})",
+        subsidy(height)
+    );
+    if claimed > expected {
+        return Err(bitcoincore_rpc::Error::ReturnedError(claims));
+    }
+    if claimed < expected {
+        eprintln!("Warning: {claims}, the rest is never to be claimed");
+    }
+    Ok(())
+}
+
+// Where a transaction's wtxid sits in its block's witness commitment, recomputed here from
+// the block's transactions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WitnessCommitment {
+    // What the coinbase commits to: SHA256d of the witness root and the reserved value
+    pub commitment: sha256d::Hash,
+    // Merkle root of every wtxid in the block, the coinbase's counted as all zeros
+    pub witness_root: sha256d::Hash,
+    // The transaction's index in the block
+    pub position: usize,
+    // Sibling hashes from the wtxid up to the witness root
+    pub branch: Vec<sha256d::Hash>,
+}
+
+fn hash_pair(left: &sha256d::Hash, right: &sha256d::Hash) -> sha256d::Hash {
+    let mut both = [0u8; 64];
+    both[..32].copy_from_slice(left.as_byte_array());
+    both[32..].copy_from_slice(right.as_byte_array());
+    sha256d::Hash::hash(&both)
+}
+
+// The merkle root over `leaves` (an odd level's last hash paired with itself), and the
+// branch proving the leaf at `position`
+fn merkle_branch(
+    mut leaves: Vec<sha256d::Hash>,
+    mut position: usize,
+) -> (sha256d::Hash, Vec<sha256d::Hash>) {
+    let mut branch = vec![];
+    while leaves.len() > 1 {
+        if leaves.len() % 2 == 1 {
+            leaves.push(*leaves.last().unwrap());
+        }
+        branch.push(leaves[position ^ 1]);
+        leaves = leaves
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+        position /= 2;
+    }
+    (leaves[0], branch)
+}
+
+// Hash `leaf` up `branch` from `position`, the root it should arrive at
+pub fn fold_branch(
+    leaf: sha256d::Hash,
+    mut position: usize,
+    branch: &[sha256d::Hash],
+) -> sha256d::Hash {
+    branch.iter().fold(leaf, |hash, sibling| {
+        let parent = if position.is_multiple_of(2) {
+            hash_pair(&hash, sibling)
+        } else {
+            hash_pair(sibling, &hash)
+        };
+        position /= 2;
+        parent
+    })
+}
+
+// Check that `block`'s coinbase commits to the witnesses of every transaction in it and that
+// `wtxid` is one of them. The commitment is the last coinbase output with the BIP141 header,
+// the reserved value the coinbase input's single witness item.
+pub fn check_witness_commitment(
+    block: &Block,
+    wtxid: &Wtxid,
+) -> bitcoincore_rpc::Result<WitnessCommitment> {
+    let fail = |why: String| {
+        bitcoincore_rpc::Error::ReturnedError(format!(
+            "witness commitment of block {}: {why}",
+            block.block_hash()
+        ))
+    };
+    let coinbase = block
+        .txdata
+        .first()
+        .ok_or_else(|| fail("the block is empty".to_owned()))?;
+    let committed = coinbase
+        .output
+        .iter()
+        .rev()
+        .map(|o| o.script_pubkey.as_bytes())
+        .find(|script| script.len() >= 38 && script.starts_with(&WITNESS_COMMITMENT_HEADER))
+        .map(|script| sha256d::Hash::from_slice(&script[6..38]).unwrap())
+        .ok_or_else(|| fail("the coinbase has no commitment output".to_owned()))?;
+    let reserved = match coinbase.input.first().map(|i| i.witness.to_vec()) {
+        Some(items) if items.len() == 1 && items[0].len() == 32 => items[0].clone(),
+        _ => return Err(fail("no 32-byte witness reserved value".to_owned())),
+    };
+
+    let leaves: Vec<sha256d::Hash> = block
+        .txdata
+        .iter()
+        .enumerate()
+        .map(|(i, tx)| {
+            if i == 0 {
+                sha256d::Hash::all_zeros()
+            } else {
+                tx.wtxid().to_raw_hash()
+            }
+        })
+        .collect();
+    let position = leaves
+        .iter()
+        .skip(1)
+        .position(|leaf| leaf == &wtxid.to_raw_hash())
+        .map(|i| i + 1)
+        .ok_or_else(|| fail(format!("{wtxid} is not in the block")))?;
+    let (witness_root, branch) = merkle_branch(leaves, position);
+
+    let mut preimage = witness_root.to_byte_array().to_vec();
+    preimage.extend_from_slice(&reserved);
+    let commitment = sha256d::Hash::hash(&preimage);
+    if commitment != committed {
+        return Err(fail(format!(
+            "the coinbase commits to {committed}, the transactions hash to {commitment}"
+        )));
+    }
+    Ok(WitnessCommitment {
+        commitment,
+        witness_root,
+        position,
+        branch,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::block::{Header, Version};
+    use bitcoincore_rpc::bitcoin::{
+        transaction, BlockHash, CompactTarget, OutPoint, ScriptBuf, Transaction, TxIn,
+        TxMerkleNode, TxOut, Txid, Witness,
+    };
+
+    fn tx(input: TxIn, output: TxOut) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![input],
+            output: vec![output],
+        }
+    }
+
+    // A coinbase and three spends with witnesses, the coinbase committing to them
+    fn block() -> Block {
+        let coinbase = tx(
+            TxIn {
+                witness: Witness::from_slice(&[[0u8; 32]]),
+                ..TxIn::default()
+            },
+            TxOut {
+                value: Amount::from_int_btc(50),
+                script_pubkey: ScriptBuf::new(),
+            },
+        );
+        let spends = (0..3).map(|i| {
+            tx(
+                TxIn {
+                    previous_output: OutPoint::new(Txid::all_zeros(), i),
+                    witness: Witness::from_slice(&[vec![i as u8; 72], vec![2; 33]]),
+                    ..TxIn::default()
+                },
+                TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: ScriptBuf::new(),
+                },
+            )
+        });
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: BlockHash::all_zeros(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata: std::iter::once(coinbase).chain(spends).collect(),
+        };
+        let root = block.witness_root().unwrap();
+        let commitment = Block::compute_witness_commitment(&root, &[0u8; 32]);
+        let mut script = WITNESS_COMMITMENT_HEADER.to_vec();
+        script.extend_from_slice(commitment.as_byte_array());
+        block.txdata[0].output.push(TxOut {
+            value: Amount::ZERO,
+            script_pubkey: ScriptBuf::from_bytes(script),
+        });
+        block
+    }
+
+    #[test]
+    fn recomputes_the_witness_commitment() {
+        let block = block();
+        assert!(block.check_witness_commitment());
+        let transfer = &block.txdata[3];
+        assert_ne!(
+            transfer.wtxid().to_raw_hash(),
+            transfer.txid().to_raw_hash()
+        );
+
+        let checked = check_witness_commitment(&block, &transfer.wtxid()).unwrap();
+        assert_eq!(checked.position, 3);
+        assert_eq!(
+            checked.witness_root,
+            block.witness_root().unwrap().to_raw_hash()
+        );
+        assert_eq!(
+            fold_branch(transfer.wtxid().to_raw_hash(), 3, &checked.branch),
+            checked.witness_root
+        );
+
+        // A witness swapped after the coinbase was built no longer matches
+        let mut malleated = block.clone();
+        malleated.txdata[2].input[0].witness = Witness::from_slice(&[vec![9; 71], vec![2; 33]]);
+        assert!(check_witness_commitment(&malleated, &transfer.wtxid()).is_err());
+        assert!(check_witness_commitment(&block, &Wtxid::all_zeros()).is_err());
+    }
+
+    #[test]
+    fn only_a_coinbase_claiming_too_much_fails() {
+        let fees =
+            |sat: u64| MockClient::new().returns("getblockstats", json!({ "totalfee": sat }));
+        // 50 BTC claimed
+        let block = block();
+        assert!(check_coinbase(&fees(0), &block, 0).is_ok());
+        assert!(check_coinbase(&fees(1_000), &block, 0).is_ok());
+        let halved = check_coinbase(&fees(1_000), &block, 150).unwrap_err();
+        assert!(halved.to_string().contains("claims 50 BTC"), "{halved}");
+    }
+
+    #[test]
+    fn halves_every_interval() {
+        assert_eq!(subsidy(0), Amount::from_int_btc(50));
+        assert_eq!(subsidy(149), Amount::from_int_btc(50));
+        assert_eq!(subsidy(150), Amount::from_int_btc(25));
+        assert_eq!(subsidy(300), Amount::from_sat(1_250_000_000));
+        assert_eq!(subsidy(150 * 33), Amount::from_sat(0));
+        assert_eq!(subsidy(150 * 64), Amount::ZERO);
+        assert_eq!(subsidy(u64::MAX), Amount::ZERO);
+    }
+
+    #[test]
+    fn overrides_the_network_defaults() {
+        let signet = ChainParams::for_network(Network::Signet);
+        assert_eq!(signet.halving_interval, 210_000);
+        assert_eq!(signet.rpc_port, 38332);
+
+        let custom = signet.with(&ChainOverrides {
+            halving_interval: Some(1_000),
+            ..Default::default()
+        });
+        assert_eq!(custom.coinbase_maturity, 100);
+        assert_eq!(custom.subsidy(999), Amount::from_int_btc(50));
+        assert_eq!(custom.subsidy(1_000), Amount::from_int_btc(25));
+        assert_eq!(
+            custom.blocks_to_earn(999, Amount::from_int_btc(75)),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn counts_blocks_across_a_halving() {
+        assert_eq!(blocks_to_earn(0, Amount::ZERO), Some(0));
+        assert_eq!(blocks_to_earn(0, Amount::from_int_btc(20)), Some(1));
+        assert_eq!(blocks_to_earn(149, Amount::from_int_btc(75)), Some(2));
+        assert_eq!(blocks_to_earn(149, Amount::from_int_btc(76)), Some(3));
+        assert_eq!(blocks_to_earn(0, Amount::MAX_MONEY), None);
+    }
+}
@@ -0,0 +1,257 @@
+use bitcoincore_rpc::bitcoin::{Amount, BlockHash};
+use bitcoincore_rpc::{Client, RpcApi};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table};
+use ratatui::Frame;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::error::Result;
+use crate::get_client_at_url;
+use crate::shutdown::{self, Phase};
+
+// How often the node is polled. There's no event feed, every refresh asks again: nothing
+// here subscribes to the node's ZMQ notifications yet, so a block or transaction shows up
+// up to one interval late.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+// Longest the loop waits for a key press before checking on the node and the flow
+const INPUT_POLL: Duration = Duration::from_millis(100);
+const RECENT_BLOCKS: u64 = 8;
+
+const PHASES: [Phase; 5] = [
+    Phase::Setup,
+    Phase::Mining,
+    Phase::Sending,
+    Phase::Confirming,
+    Phase::Reporting,
+];
+
+// What the node looked like at the last refresh
+#[derive(Debug, Default)]
+struct NodeView {
+    height: u64,
+    balances: Vec<(String, Amount)>,
+    mempool_txs: usize,
+    mempool_bytes: usize,
+    mempool_fees: Amount,
+    blocks: Vec<BlockLine>,
+    // Why the last refresh failed, the view before it stays up
+    error: Option<String>,
+}
+
+#[derive(Debug)]
+struct BlockLine {
+    height: u64,
+    hash: BlockHash,
+    transactions: usize,
+    time: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FlowState {
+    // Nothing to run, the dashboard only watches
+    Idle,
+    Running,
+    Stopping,
+    Completed,
+    Failed(String),
+}
+
+// A live view of `rpc`'s node, and the state of `task` (the capstone flow, say) while it
+// runs on another thread. Quits on q, Esc or Ctrl-C; a task still running is asked to stop
+// at its next safe point first (see `shutdown`) and the dashboard waits for it. The task's
+// own error, if any, is what this returns.
+pub fn show<F>(rpc: &Client, task: Option<F>) -> Result<()>
+where
+    F: FnOnce() -> Result<()> + Send,
+{
+    thread::scope(|scope| {
+        let mut handle = task.map(|task| scope.spawn(task));
+        let mut outcome = Ok(());
+        let mut state = match handle {
+            Some(_) => FlowState::Running,
+            None => FlowState::Idle,
+        };
+
+        let mut terminal = ratatui::init();
+        let mut view = NodeView::default();
+        let mut refreshed: Option<Instant> = None;
+        let mut quit = false;
+        let drawn = loop {
+            if refreshed.is_none_or(|at| at.elapsed() >= REFRESH_INTERVAL) {
+                refresh(rpc, &mut view);
+                refreshed = Some(Instant::now());
+            }
+            if let Err(e) = terminal.draw(|frame| draw(frame, &view, &state)) {
+                break Err(e);
+            }
+
+            if quit && !matches!(state, FlowState::Running | FlowState::Stopping) {
+                break Ok(());
+            }
+            match event::poll(INPUT_POLL).and_then(|ready| ready.then(event::read).transpose()) {
+                Ok(Some(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    let ctrl_c = key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL);
+                    if ctrl_c || matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) {
+                        quit = true;
+                        if state == FlowState::Running {
+                            shutdown::request();
+                            state = FlowState::Stopping;
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => break Err(e),
+            }
+            if let Some(finished) = handle.take_if(|h| h.is_finished()) {
+                outcome = join(finished);
+                state = match &outcome {
+                    Ok(()) => FlowState::Completed,
+                    Err(e) => FlowState::Failed(e.to_string()),
+                };
+            }
+        };
+        ratatui::restore();
+
+        // Only left running when drawing failed
+        if let Some(running) = handle {
+            shutdown::request();
+            outcome = join(running);
+        }
+        drawn?;
+        outcome
+    })
+}
+
+fn join(handle: thread::ScopedJoinHandle<Result<()>>) -> Result<()> {
+    handle
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+}
+
+fn refresh(rpc: &Client, view: &mut NodeView) {
+    match poll_node(rpc, view) {
+        Ok(()) => view.error = None,
+        Err(e) => view.error = Some(e.to_string()),
+    }
+}
+
+fn poll_node(rpc: &Client, view: &mut NodeView) -> bitcoincore_rpc::Result<()> {
+    let height = rpc.get_block_count()?;
+    let mut balances = vec![];
+    for name in rpc.list_wallets()? {
+        let wallet = get_client_at_url(&format!("/wallet/{name}"))?;
+        balances.push((name, wallet.get_balance(None, None)?));
+    }
+    let mempool = rpc.get_mempool_info()?;
+    let mut blocks = vec![];
+    for height in (height.saturating_sub(RECENT_BLOCKS - 1)..=height).rev() {
+        let hash = rpc.get_block_hash(height)?;
+        let info = rpc.get_block_header_info(&hash)?;
+        blocks.push(BlockLine {
+            height,
+            hash,
+            transactions: info.n_tx,
+            time: info.time as u64,
+        });
+    }
+
+    *view = NodeView {
+        height,
+        balances,
+        mempool_txs: mempool.size,
+        mempool_bytes: mempool.bytes,
+        mempool_fees: mempool.total_fee.unwrap_or(Amount::ZERO),
+        blocks,
+        error: None,
+    };
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, view: &NodeView, state: &FlowState) {
+    let [top, middle, bottom, status] = Layout::vertical([
+        Constraint::Length(7),
+        Constraint::Min(6),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let [wallets, mempool] =
+        Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)]).areas(top);
+
+    let balances: Vec<ListItem> = view
+        .balances
+        .iter()
+        .map(|(name, balance)| ListItem::new(format!("{name:<16} {balance}")))
+        .collect();
+    frame.render_widget(
+        List::new(balances).block(Block::bordered().title(" Wallets ")),
+        wallets,
+    );
+    frame.render_widget(
+        Paragraph::new(vec![
+            Line::from(format!("{} transaction(s)", view.mempool_txs)),
+            Line::from(format!("{} bytes", view.mempool_bytes)),
+            Line::from(format!("{} in fees", view.mempool_fees)),
+        ])
+        .block(Block::bordered().title(" Mempool ")),
+        mempool,
+    );
+
+    let rows = view.blocks.iter().map(|b| {
+        Row::new(vec![
+            b.height.to_string(),
+            b.hash.to_string(),
+            b.transactions.to_string(),
+            b.time.to_string(),
+        ])
+    });
+    frame.render_widget(
+        Table::new(
+            rows,
+            [
+                Constraint::Length(8),
+                Constraint::Min(20),
+                Constraint::Length(5),
+                Constraint::Length(11),
+            ],
+        )
+        .header(Row::new(["height", "hash", "txs", "time"]).bold())
+        .block(Block::bordered().title(format!(" Recent blocks (tip {}) ", view.height))),
+        middle,
+    );
+
+    // The flow's phases in order, the current one highlighted while it runs
+    let current = shutdown::phase();
+    let mut phases = vec![];
+    for phase in PHASES {
+        let style = match state {
+            FlowState::Running | FlowState::Stopping if phase == current => Style::new().reversed(),
+            _ => Style::new(),
+        };
+        phases.push(Span::styled(format!(" {phase} "), style));
+        phases.push(Span::raw(" > "));
+    }
+    phases.pop();
+    let flow = match state {
+        FlowState::Idle => " Flow (not running) ".to_owned(),
+        FlowState::Running => " Flow ".to_owned(),
+        FlowState::Stopping => format!(" Flow (stopping at the next safe point in {current}) "),
+        FlowState::Completed => " Flow (done) ".to_owned(),
+        FlowState::Failed(e) => format!(" Flow (failed: {e}) "),
+    };
+    frame.render_widget(
+        Paragraph::new(Line::from(phases)).block(Block::bordered().title(flow)),
+        bottom,
+    );
+
+    let line = match &view.error {
+        Some(e) => Line::from(format!("Refresh failed: {e}")).red(),
+        None => Line::from("q to quit").dim(),
+    };
+    frame.render_widget(Paragraph::new(line), status);
+}
@@ -0,0 +1,162 @@
+use bitcoincore_rpc::RpcApi;
+use serde_json::{json, Value};
+
+use crate::error::Result;
+use crate::rpc::error_code;
+
+// Characters a descriptor may contain, in the order BIP380's checksum groups them
+const INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const CHECKSUM_CHARSET: &[u8] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const CHECKSUM_LENGTH: usize = 8;
+
+// What's after the `#` of a descriptor, compared with what it should be
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    Valid,
+    Missing { expected: String },
+    Wrong { found: String, expected: String },
+}
+
+fn polymod(c: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [
+        0xf5dee51989,
+        0xa9fdca3312,
+        0x1bab10e32d,
+        0x3706b1677a,
+        0x644d626ffd,
+    ];
+    let top = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (bit, generator) in GENERATOR.iter().enumerate() {
+        if top >> bit & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+// The descriptor without its checksum, and the checksum if it has one
+pub fn split(descriptor: &str) -> (&str, Option<&str>) {
+    match descriptor.rsplit_once('#') {
+        Some((body, checksum)) => (body, Some(checksum)),
+        None => (descriptor, None),
+    }
+}
+
+// The BIP380 checksum of `body`, which mustn't have one already
+pub fn checksum(body: &str) -> bitcoincore_rpc::Result<String> {
+    let mut c = 1;
+    let mut classes = 0;
+    let mut count = 0;
+    for ch in body.chars() {
+        let position = INPUT_CHARSET.find(ch).ok_or_else(|| {
+            bitcoincore_rpc::Error::ReturnedError(format!(
+                "{ch:?} can't appear in a descriptor, in {body}"
+            ))
+        })? as u64;
+        c = polymod(c, position & 31);
+        classes = classes * 3 + (position >> 5);
+        count += 1;
+        if count == 3 {
+            c = polymod(c, classes);
+            classes = 0;
+            count = 0;
+        }
+    }
+    if count > 0 {
+        c = polymod(c, classes);
+    }
+    for _ in 0..CHECKSUM_LENGTH {
+        c = polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..CHECKSUM_LENGTH)
+        .map(|i| CHECKSUM_CHARSET[(c >> (5 * (CHECKSUM_LENGTH - 1 - i)) & 31) as usize] as char)
+        .collect())
+}
+
+// Check the checksum locally, without a node
+pub fn check(descriptor: &str) -> bitcoincore_rpc::Result<Checksum> {
+    let (body, found) = split(descriptor);
+    let expected = checksum(body)?;
+    Ok(match found {
+        Some(found) if found == expected => Checksum::Valid,
+        Some(found) => Checksum::Wrong {
+            found: found.to_owned(),
+            expected,
+        },
+        None => Checksum::Missing { expected },
+    })
+}
+
+// `descriptor` with the right checksum, replacing a wrong one. Private keys are kept, so
+// the result can go straight to importdescriptors.
+pub fn with_checksum(descriptor: &str) -> bitcoincore_rpc::Result<String> {
+    let (body, _) = split(descriptor);
+    Ok(format!("{body}#{}", checksum(body)?))
+}
+
+// The node's canonical form of `descriptor` (public keys, `h` for hardened steps) with its
+// checksum. When getdescriptorinfo can't be reached the checksum is fixed locally instead,
+// a descriptor the node rejects is an error either way.
+pub fn normalize(rpc: &impl RpcApi, descriptor: &str) -> Result<String> {
+    let (body, _) = split(descriptor);
+    match rpc.call::<Value>("getdescriptorinfo", &[json!(body)]) {
+        Ok(info) => match info["descriptor"].as_str() {
+            Some(canonical) => Ok(with_checksum(canonical)?),
+            None => Ok(with_checksum(body)?),
+        },
+        Err(e) if error_code(&e).is_some() => Err(e.into()),
+        Err(e) => {
+            eprintln!("getdescriptorinfo failed ({e}), only fixing the checksum");
+            Ok(with_checksum(body)?)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+
+    // BIP380's test vectors
+    #[test]
+    fn checks_checksums() {
+        assert_eq!(check("raw(deadbeef)#89f8spxm").unwrap(), Checksum::Valid);
+        assert_eq!(
+            check("raw(deadbeef)").unwrap(),
+            Checksum::Missing {
+                expected: "89f8spxm".into()
+            }
+        );
+        assert!(matches!(
+            check("raw(deadbeef)#89f8spxn").unwrap(),
+            Checksum::Wrong { .. }
+        ));
+        assert!(matches!(
+            check("raw(deadbeef)#89f8spxmx").unwrap(),
+            Checksum::Wrong { .. }
+        ));
+        assert!(check("raw(deadbeef\u{e9})").is_err());
+        assert_eq!(
+            with_checksum("raw(deadbeef)#8g9f8spxm").unwrap(),
+            "raw(deadbeef)#89f8spxm"
+        );
+    }
+
+    #[test]
+    fn normalizes_through_the_node() {
+        let rpc = MockClient::new().returns(
+            "getdescriptorinfo",
+            json!({ "descriptor": "raw(deadbeef)#89f8spxm", "checksum": "89f8spxm" }),
+        );
+        assert_eq!(
+            normalize(&rpc, "raw(DEADBEEF)").unwrap(),
+            "raw(deadbeef)#89f8spxm"
+        );
+
+        let rpc = MockClient::new().fails("getdescriptorinfo", -5, "Invalid descriptor");
+        assert!(normalize(&rpc, "raw(xyz)").is_err());
+    }
+}
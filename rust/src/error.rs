@@ -0,0 +1,332 @@
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint, Txid};
+use bitcoincore_rpc::jsonrpc;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::builder::BuildError;
+use crate::compat::{Feature, NodeVersion};
+use crate::config::ConfigError;
+use crate::rpc::error_code;
+use crate::scenario::ScenarioError;
+use crate::shutdown::{self, Phase};
+
+#[derive(Debug)]
+pub enum Error {
+    Rpc(bitcoincore_rpc::Error),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Scenario(ScenarioError),
+    Config(ConfigError),
+    Build(BuildError),
+    // A --template for out.txt doesn't parse or render
+    Template(minijinja::Error),
+    // The wallet cannot cover `needed` even after mining what it was allowed to
+    InsufficientFunds {
+        wallet: String,
+        needed: Amount,
+        available: Amount,
+    },
+    // A selected UTXO was spent in the meantime, `alternatives` could be used instead
+    OutpointSpent {
+        outpoint: OutPoint,
+        alternatives: Vec<OutPoint>,
+    },
+    // A wallet rebuilt from its descriptors doesn't hold what the original does
+    RecoveryMismatch {
+        wallet: String,
+        expected: Amount,
+        recovered: Amount,
+    },
+    // The node is too old for the tool, or for `feature` when given
+    UnsupportedNode {
+        version: NodeVersion,
+        needed: NodeVersion,
+        feature: Option<Feature>,
+    },
+    // The transaction wasn't in any of the last `depth` blocks after mining one and waiting
+    // for more
+    Unconfirmed {
+        txid: Txid,
+        depth: u64,
+    },
+    // The confirmed transaction has no output the report needs a line for, e.g. no change
+    // because it was dust and went to the fee
+    MissingOutput {
+        txid: Txid,
+        output: &'static str,
+    },
+    // A report or ../out.txt could not be written
+    ReportWrite {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    // A JSON report doesn't match its schema, one entry per violation
+    InvalidReport(Vec<String>),
+    // --exact-amounts found the payment or the change off by something, one entry each
+    InexactAmounts(Vec<String>),
+    // A compact (CBOR) report couldn't be encoded, or what was read isn't one
+    CompactReport(String),
+    // SIGINT or SIGTERM stopped the run at a safe point during `phase`
+    Aborted {
+        phase: Phase,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Rpc(e) => write!(f, "RPC error: {e}"),
+            Error::Io(e) => write!(f, "I/O error: {e}"),
+            Error::Json(e) => write!(f, "JSON error: {e}"),
+            Error::Scenario(e) => write!(f, "scenario error: {e}"),
+            Error::Config(e) => write!(f, "config error: {e}"),
+            Error::Build(e) => write!(f, "transaction error: {e}"),
+            Error::Template(e) => write!(f, "template error: {e}"),
+            Error::InsufficientFunds {
+                wallet,
+                needed,
+                available,
+            } => write!(
+                f,
+                "insufficient funds in {wallet}: need {needed}, only {available} spendable"
+            ),
+            Error::OutpointSpent {
+                outpoint,
+                alternatives,
+            } => {
+                write!(f, "{outpoint} is already spent")?;
+                if alternatives.is_empty() {
+                    write!(f, ", no other UTXO would cover it")
+                } else {
+                    let list: Vec<_> = alternatives.iter().map(OutPoint::to_string).collect();
+                    write!(f, ", try {}", list.join(", "))
+                }
+            }
+            Error::RecoveryMismatch {
+                wallet,
+                expected,
+                recovered,
+            } => write!(
+                f,
+                "recovered {recovered} for {wallet}, but the original holds {expected}"
+            ),
+            Error::UnsupportedNode {
+                version,
+                needed,
+                feature: Some(feature),
+            } => write!(
+                f,
+                "{feature} needs Bitcoin Core {needed} or newer, the node runs {version}"
+            ),
+            Error::UnsupportedNode {
+                version,
+                needed,
+                feature: None,
+            } => write!(
+                f,
+                "Bitcoin Core {version} is not supported, {needed} or newer is needed"
+            ),
+            Error::Unconfirmed { txid, depth } => {
+                write!(f, "{txid} did not confirm, not in the last {depth} blocks")
+            }
+            Error::MissingOutput { txid, output } => write!(
+                f,
+                "{txid} confirmed without a {output} output, the report has no line to leave out"
+            ),
+            Error::ReportWrite { path, source } => {
+                write!(f, "cannot write {}: {source}", path.display())
+            }
+            Error::InvalidReport(errors) => {
+                write!(f, "report does not match its schema: {}", errors.join("; "))
+            }
+            Error::InexactAmounts(errors) => {
+                write!(f, "amounts are not exact: {}", errors.join("; "))
+            }
+            Error::CompactReport(e) => write!(f, "compact report: {e}"),
+            Error::Aborted { phase } => write!(f, "aborted by signal during {phase}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// What went wrong, coarsely enough for CI to tell a broken setup from a broken flow. Each
+// kind has its own exit code, these are stable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailureKind {
+    NodeUnreachable,
+    Wallet,
+    BroadcastRejected,
+    ConfirmationTimeout,
+    ReportWrite,
+    // Exit code depends on the phase, see `Phase::abort_exit_code`
+    Aborted,
+    Other,
+}
+
+impl FailureKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            FailureKind::Other => 1,
+            FailureKind::NodeUnreachable => 10,
+            FailureKind::Wallet => 11,
+            FailureKind::BroadcastRejected => 12,
+            FailureKind::ConfirmationTimeout => 13,
+            FailureKind::ReportWrite => 14,
+            FailureKind::Aborted => shutdown::phase().abort_exit_code(),
+        }
+    }
+}
+
+// Classify by the node's error codes from rpc/protocol.h: the wallet ones (-4, -6, -11 to
+// -19, -35, -36) and those sendrawtransaction rejects with (-25 to -27)
+fn rpc_failure(e: &bitcoincore_rpc::Error) -> FailureKind {
+    match (e, error_code(e)) {
+        (bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Transport(_)), _) => {
+            FailureKind::NodeUnreachable
+        }
+        (_, Some(-4 | -6 | -19..=-11 | -35 | -36)) => FailureKind::Wallet,
+        (_, Some(-27..=-25)) => FailureKind::BroadcastRejected,
+        _ => FailureKind::Other,
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> FailureKind {
+        match self {
+            Error::Rpc(e) | Error::Scenario(ScenarioError::Rpc(e)) => rpc_failure(e),
+            Error::InsufficientFunds { .. }
+            | Error::OutpointSpent { .. }
+            | Error::RecoveryMismatch { .. } => FailureKind::Wallet,
+            Error::Unconfirmed { .. } => FailureKind::ConfirmationTimeout,
+            Error::ReportWrite { .. } => FailureKind::ReportWrite,
+            Error::Aborted { .. } => FailureKind::Aborted,
+            _ => FailureKind::Other,
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Error::Aborted { phase } => phase.abort_exit_code(),
+            _ => self.kind().exit_code(),
+        }
+    }
+}
+
+// Written by --failure-report when a run fails, one JSON object
+#[derive(Debug, Serialize)]
+pub struct FailureReport {
+    pub kind: FailureKind,
+    pub exit_code: u8,
+    // What the run was busy with when it failed
+    pub phase: Phase,
+    pub cause: String,
+    // The node's error code, for RPC errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rpc_code: Option<i32>,
+}
+
+impl FailureReport {
+    pub fn new(error: &Error) -> FailureReport {
+        let rpc_code = match error {
+            Error::Rpc(e) | Error::Scenario(ScenarioError::Rpc(e)) => error_code(e),
+            _ => None,
+        };
+        FailureReport {
+            kind: error.kind(),
+            exit_code: error.exit_code(),
+            phase: match error {
+                Error::Aborted { phase } => *phase,
+                _ => shutdown::phase(),
+            },
+            cause: error.to_string(),
+            rpc_code,
+        }
+    }
+}
+
+impl From<bitcoincore_rpc::Error> for Error {
+    fn from(e: bitcoincore_rpc::Error) -> Self {
+        Error::Rpc(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Json(e)
+    }
+}
+
+impl From<ScenarioError> for Error {
+    fn from(e: ScenarioError) -> Self {
+        Error::Scenario(e)
+    }
+}
+
+impl From<minijinja::Error> for Error {
+    fn from(e: minijinja::Error) -> Self {
+        Error::Template(e)
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        Error::Build(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Error::Config(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::jsonrpc::error::RpcError;
+
+    fn rpc_error(code: i32) -> Error {
+        Error::Rpc(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Rpc(
+            RpcError {
+                code,
+                message: String::new(),
+                data: None,
+            },
+        )))
+    }
+
+    #[test]
+    fn classifies_by_rpc_error_code() {
+        assert_eq!(rpc_error(-6).kind(), FailureKind::Wallet);
+        assert_eq!(rpc_error(-18).kind(), FailureKind::Wallet);
+        assert_eq!(rpc_error(-26).kind(), FailureKind::BroadcastRejected);
+        assert_eq!(rpc_error(-8).kind(), FailureKind::Other);
+        let refused = Error::Rpc(bitcoincore_rpc::Error::JsonRpc(jsonrpc::Error::Transport(
+            "connection refused".into(),
+        )));
+        assert_eq!(refused.exit_code(), 10);
+    }
+
+    #[test]
+    fn failure_report_carries_the_rpc_code() {
+        let report = FailureReport::new(&rpc_error(-26));
+        assert_eq!(report.exit_code, 12);
+        assert_eq!(report.rpc_code, Some(-26));
+        let aborted = FailureReport::new(&Error::Aborted {
+            phase: Phase::Sending,
+        });
+        assert_eq!(aborted.phase, Phase::Sending);
+        assert_eq!(aborted.exit_code, Phase::Sending.abort_exit_code());
+    }
+}
@@ -0,0 +1,229 @@
+use bitcoincore_rpc::bitcoin::{Amount, Network};
+use bitcoincore_rpc::RpcApi;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::batcher::Payment;
+use crate::coins;
+use crate::config;
+use crate::error::Result;
+use crate::http::{Request, Response, Service};
+
+// Kept on top of the amount when deciding whether to mine, for the fee
+const FEE_MARGIN: Amount = Amount::from_sat(100_000);
+
+#[derive(Debug, Clone, Copy)]
+pub struct FaucetOptions {
+    // Largest payment one request can ask for
+    pub max_amount: Amount,
+    // How long an address waits between payments
+    pub cooldown: Duration,
+    // Blocks mined after each payment, so it arrives confirmed
+    pub confirmations: u64,
+}
+
+// When each address was last paid, to keep one client from draining the faucet
+#[derive(Debug)]
+pub struct RateLimiter {
+    cooldown: Duration,
+    last_paid: HashMap<String, Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(cooldown: Duration) -> Self {
+        RateLimiter {
+            cooldown,
+            last_paid: HashMap::new(),
+        }
+    }
+
+    // How much longer `address` has to wait, if at all
+    pub fn wait(&self, address: &str, now: Instant) -> Option<Duration> {
+        let paid = self.last_paid.get(address)?;
+        self.cooldown
+            .checked_sub(now.saturating_duration_since(*paid))
+            .filter(|left| !left.is_zero())
+    }
+
+    pub fn paid(&mut self, address: &str, now: Instant) {
+        self.last_paid.insert(address.to_owned(), now);
+    }
+}
+
+// The Miner wallet as a faucet: `POST /send {address, amount}` pays an address, mining
+// first if the wallet can't cover it, and `GET /balance` says what's left
+pub struct Faucet<R> {
+    wallet: R,
+    options: FaucetOptions,
+    limiter: RateLimiter,
+}
+
+impl<R: RpcApi> Faucet<R> {
+    pub fn new(wallet: R, options: FaucetOptions) -> Self {
+        Faucet {
+            wallet,
+            options,
+            limiter: RateLimiter::new(options.cooldown),
+        }
+    }
+}
+
+impl<R: RpcApi> Service for Faucet<R> {
+    fn handle(&mut self, request: &Request) -> Response {
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/send") => self.send(request),
+            ("GET", "/balance") => match self.wallet.get_balances() {
+                Ok(balances) => Response::json(
+                    200,
+                    &json!({
+                        "balance": balances.mine.trusted.to_btc(),
+                        "immature": balances.mine.immature.to_btc(),
+                    }),
+                ),
+                Err(e) => Response::error(500, e.to_string()),
+            },
+            _ => Response::not_found(),
+        }
+    }
+}
+
+impl<R: RpcApi> Faucet<R> {
+    fn send(&mut self, request: &Request) -> Response {
+        let Payment { address, amount } = match Payment::from_json(&request.body) {
+            Ok(payment) => payment,
+            Err(e) => return Response::error(400, e),
+        };
+        if amount > self.options.max_amount {
+            return Response::error(
+                400,
+                format!("at most {} per request", self.options.max_amount),
+            );
+        }
+        let key = address.to_string();
+        if let Some(left) = self.limiter.wait(&key, Instant::now()) {
+            return Response::json(
+                429,
+                &json!({
+                    "error": format!("{key} was paid recently"),
+                    "retry_after": left.as_secs().max(1),
+                }),
+            );
+        }
+
+        let paid = self.fund(amount).and_then(|mined| {
+            let txid = self
+                .wallet
+                .send_to_address(&address, amount, None, None, None, None, None, None)?;
+            if self.options.confirmations > 0 {
+                let change = self.wallet.get_new_address(None, None)?.assume_checked();
+                self.wallet
+                    .generate_to_address(self.options.confirmations, &change)?;
+            }
+            Ok((txid, mined))
+        });
+        match paid {
+            Ok((txid, mined)) => {
+                self.limiter.paid(&key, Instant::now());
+                println!("Paid {amount} to {key} in {txid}");
+                Response::json(
+                    200,
+                    &json!({
+                        "txid": txid,
+                        "address": key,
+                        "amount": amount.to_btc(),
+                        "blocks_mined": mined + self.options.confirmations,
+                    }),
+                )
+            }
+            Err(e) => Response::error(500, e.to_string()),
+        }
+    }
+
+    // Mine to the faucet's wallet until it can pay `amount`, returning the blocks mined
+    fn fund(&self, amount: Amount) -> Result<u64> {
+        coins::mine_until(&self.wallet, amount + FEE_MARGIN, coins::MAX_FUNDING_BLOCKS)
+    }
+}
+
+// Only regtest lets the faucet mine whatever it needs
+pub fn check_network() -> bitcoincore_rpc::Result<()> {
+    let network = config::active().network;
+    if network == Network::Regtest {
+        Ok(())
+    } else {
+        Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "the faucet mines its coins, which only works on regtest, not {network}"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use serde_json::Value;
+
+    fn options() -> FaucetOptions {
+        FaucetOptions {
+            max_amount: Amount::from_int_btc(10),
+            cooldown: Duration::from_secs(60),
+            confirmations: 1,
+        }
+    }
+
+    fn post(body: Value) -> Request {
+        Request {
+            method: "POST".into(),
+            path: "/send".into(),
+            body: serde_json::to_vec(&body).unwrap(),
+        }
+    }
+
+    #[test]
+    fn limits_each_address() {
+        let mut limiter = RateLimiter::new(Duration::from_secs(60));
+        let start = Instant::now();
+        assert_eq!(limiter.wait("a", start), None);
+        limiter.paid("a", start);
+        assert_eq!(
+            limiter.wait("a", start + Duration::from_secs(20)),
+            Some(Duration::from_secs(40))
+        );
+        assert_eq!(limiter.wait("b", start), None);
+        assert_eq!(limiter.wait("a", start + Duration::from_secs(60)), None);
+    }
+
+    #[test]
+    fn pays_and_then_refuses_the_same_address() {
+        let address = "bcrt1qv5plgft75j0hegtvf6zs5pajh7k0gxg2dhj224";
+        let balances =
+            json!({ "mine": { "trusted": 50.0, "untrusted_pending": 0.0, "immature": 0.0 } });
+        let txid = "11".repeat(32);
+        let wallet = MockClient::new()
+            .returns("getnewaddress", json!(address))
+            .returns("getbalances", balances)
+            .returns("sendtoaddress", json!(txid))
+            .returns("getnewaddress", json!(address))
+            .returns("generatetoaddress", json!(["22".repeat(32)]));
+        let mut faucet = Faucet::new(wallet, options());
+
+        let response = faucet.handle(&post(json!({ "address": address, "amount": "1.5btc" })));
+        assert_eq!(
+            response.status,
+            200,
+            "{}",
+            String::from_utf8_lossy(&response.body)
+        );
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body["txid"], json!(txid));
+        assert_eq!(body["blocks_mined"], json!(1));
+        faucet.wallet.assert_done();
+
+        let again = faucet.handle(&post(json!({ "address": address, "amount": 1 })));
+        assert_eq!(again.status, 429);
+        let too_much = faucet.handle(&post(json!({ "address": address, "amount": 11 })));
+        assert_eq!(too_much.status, 400);
+        assert_eq!(faucet.handle(&post(json!({ "amount": 1 }))).status, 400);
+    }
+}
@@ -0,0 +1,573 @@
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint, ScriptBuf, TxOut};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde_json::json;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::builder::{self, Budget, ChangeControl, SequencePolicy, TxBuilder};
+use crate::coins::{self, CoinControl, UtxoLock};
+use crate::compat::Compat;
+use crate::error::{Error, Result};
+use crate::hooks::{self, Event};
+use crate::report::{OwnershipProofs, PrivacyMeasure, TxReport};
+use crate::resume::Checkpoint;
+use crate::script;
+use crate::shutdown::{self, Phase};
+use crate::waiter::BlockWaiter;
+use crate::wallet::{self, is_mine, CreateOptions};
+use crate::{bip322, chain, compose, consensus, script_to_addr};
+
+// e1ec30: How many more blocks the flow may mine when the Miner can't fund the payment yet
+const MAX_EXTRA_FUNDING_BLOCKS: u64 = 100;
+// e1ec30: How far back from the tip to look for the confirming block
+const CONFIRMATION_SEARCH_DEPTH: u64 = 10;
+// e1ec30: How long to wait for the confirming block when the one just mined doesn't have it
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(30);
+
+// e1ec30: Who pays whom in the flow. The defaults are the capstone's 20 BTC from Miner to
+// Trader, the "miner"/"trader" names below stand for the sender and the recipient.
+#[derive(Debug, Clone)]
+pub struct Flow {
+    pub from: String,
+    pub to: String,
+    pub amount: Amount,
+    pub coin_control: CoinControl,
+    // Build and sign the transaction here instead of through the wallet's `send`
+    pub manual: bool,
+    pub sequences: SequencePolicy,
+    // Checked against the signed transaction before it's broadcast
+    pub budget: Budget,
+    // avoid_reuse wallets, whole address groups as inputs and change of the recipient's
+    // address type
+    pub privacy: bool,
+    // Don't print the blockchain info, for when something else owns the terminal
+    pub quiet: bool,
+    // Have both wallets sign for their outputs (BIP322) and put the proofs in the report
+    pub prove_ownership: bool,
+    // Where to save what `resume` needs once the payment is broadcast
+    pub checkpoint: Option<PathBuf>,
+    // Wallet label for the recipient's address in both wallets, carried into the report
+    pub label: Option<String>,
+    // Fail unless the recipient got exactly `amount` and the change is the inputs less
+    // the amount and the fee, to the satoshi
+    pub exact_amounts: bool,
+    // Change address type, an explicit change address and a random change position, on
+    // both the wallet's `send` and the manual builder. An address type here wins over the
+    // one `privacy` would match to the recipient's.
+    pub change: ChangeControl,
+}
+
+impl Default for Flow {
+    fn default() -> Self {
+        Flow {
+            from: "Miner".to_owned(),
+            to: "Trader".to_owned(),
+            amount: Amount::from_int_btc(20),
+            coin_control: CoinControl::default(),
+            manual: false,
+            sequences: SequencePolicy::default(),
+            budget: Budget::default(),
+            privacy: false,
+            quiet: false,
+            prove_ownership: false,
+            checkpoint: None,
+            label: None,
+            exact_amounts: false,
+            change: ChangeControl::default(),
+        }
+    }
+}
+
+// Sets up a `Flow` for embedding, e.g.
+// `Flow::builder().miner("Miner").trader("Trader").amount(Amount::from_int_btc(20)).build()`.
+// Anything left out keeps the capstone's default.
+#[derive(Debug, Clone, Default)]
+pub struct FlowBuilder {
+    flow: Flow,
+}
+
+impl FlowBuilder {
+    // The wallet paying, only "Miner" mines its own funds
+    pub fn miner(mut self, wallet: &str) -> Self {
+        self.flow.from = wallet.to_owned();
+        self
+    }
+
+    // The wallet paid
+    pub fn trader(mut self, wallet: &str) -> Self {
+        self.flow.to = wallet.to_owned();
+        self
+    }
+
+    pub fn amount(mut self, amount: Amount) -> Self {
+        self.flow.amount = amount;
+        self
+    }
+
+    pub fn coin_control(mut self, coin_control: CoinControl) -> Self {
+        self.flow.coin_control = coin_control;
+        self
+    }
+
+    pub fn manual(mut self, manual: bool) -> Self {
+        self.flow.manual = manual;
+        self
+    }
+
+    pub fn sequences(mut self, sequences: SequencePolicy) -> Self {
+        self.flow.sequences = sequences;
+        self
+    }
+
+    pub fn budget(mut self, budget: Budget) -> Self {
+        self.flow.budget = budget;
+        self
+    }
+
+    pub fn privacy(mut self, privacy: bool) -> Self {
+        self.flow.privacy = privacy;
+        self
+    }
+
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.flow.quiet = quiet;
+        self
+    }
+
+    pub fn prove_ownership(mut self, prove_ownership: bool) -> Self {
+        self.flow.prove_ownership = prove_ownership;
+        self
+    }
+
+    pub fn checkpoint(mut self, path: &Path) -> Self {
+        self.flow.checkpoint = Some(path.to_owned());
+        self
+    }
+
+    pub fn label(mut self, label: Option<&str>) -> Self {
+        self.flow.label = label.map(str::to_owned);
+        self
+    }
+
+    pub fn exact_amounts(mut self, exact_amounts: bool) -> Self {
+        self.flow.exact_amounts = exact_amounts;
+        self
+    }
+
+    pub fn change(mut self, change: ChangeControl) -> Self {
+        self.flow.change = change;
+        self
+    }
+
+    pub fn build(self) -> Flow {
+        self.flow
+    }
+}
+
+// What a finished flow did: the transaction as the report shows it, and what it spent
+#[derive(Debug, Clone)]
+pub struct FlowOutcome {
+    pub report: TxReport,
+    pub inputs: Vec<OutPoint>,
+}
+
+impl Flow {
+    pub fn builder() -> FlowBuilder {
+        FlowBuilder::default()
+    }
+
+    // e1ec30: The capstone flow itself, what the autograder checks. Wallet clients connect
+    // through the active config profile (see `config::select`), `rpc` should point at the
+    // same node.
+    pub fn run(&self, rpc: &Client) -> Result<FlowOutcome> {
+        // Get blockchain info
+        let blockchain_info = Compat::detect(rpc)?.blockchain_info(rpc)?;
+        if !self.quiet {
+            println!("Blockchain Info: {blockchain_info:?}");
+        }
+
+        // Create/Load the wallets, named 'Miner' and 'Trader'. Have logic to optionally create/load them if they do not exist or not loaded already.
+        shutdown::enter(Phase::Setup);
+        let options = CreateOptions {
+            avoid_reuse: self.privacy,
+            ..Default::default()
+        };
+        let (miner_wallet_rpc, _miner_guard) = wallet::open_guarded(rpc, &self.from, options)?;
+        let (trader_wallet_rpc, _trader_guard) = wallet::open_guarded(rpc, &self.to, options)?;
+        let mut privacy = vec![];
+        if self.privacy {
+            // e1ec30: Wallets that existed before need the flag set afterwards
+            wallet::set_avoid_reuse(&miner_wallet_rpc)?;
+            wallet::set_avoid_reuse(&trader_wallet_rpc)?;
+            privacy.push(PrivacyMeasure::AvoidReuse);
+        }
+
+        // println!("Miner wallet created: {miner_wallet:?}");
+        // println!("Trader wallet created: {trader_wallet:?}");
+
+        // Generate spendable balances in the Miner wallet. How many blocks needs to be mined?
+        // e1ec30: Only the Miner mines, any other sender has to have the funds already
+        let miner_address = miner_wallet_rpc
+            .get_new_address(None, None)?
+            .assume_checked();
+        let is_miner = self.from == "Miner";
+        shutdown::check()?;
+        shutdown::enter(Phase::Mining);
+        if is_miner {
+            // e1ec30: One block's reward, matured by the blocks on top of it
+            let blocks = consensus::params().coinbase_maturity + 1;
+            miner_wallet_rpc.generate_to_address(blocks, &miner_address)?;
+        }
+
+        // e1ec30: Get a single utxo that can be used in the transaction, since the tests require it.
+        // If none is big enough, keep mining (or combine several) instead of giving up.
+        let amount = self.amount;
+        let mut coin_control = self.coin_control.clone();
+        coin_control.avoid_partial_spends |= self.privacy;
+        let selected = coins::select_or_mine(
+            &miner_wallet_rpc,
+            &self.from,
+            amount + coins::FEE_HEADROOM,
+            &coin_control,
+            is_miner.then_some(&miner_address),
+            MAX_EXTRA_FUNDING_BLOCKS,
+        )?;
+        // e1ec30: Double-check with the node that nothing spent them since listunspent
+        coins::verify_unspent(&miner_wallet_rpc, &selected)?;
+        let inputs: Vec<OutPoint> = selected
+            .iter()
+            .map(|u| OutPoint::new(u.txid, u.vout))
+            .collect();
+        // e1ec30: Lock it so nothing else spends it before we do, unlocked again if anything fails
+        let funding_lock = UtxoLock::acquire(&miner_wallet_rpc, &inputs)?;
+        hooks::fire(
+            Event::WalletFunded,
+            &json!({
+                "wallet": self.from,
+                "inputs": inputs,
+                "amount": selected.iter().map(|u| u.amount).sum::<Amount>().to_btc(),
+            }),
+        );
+
+        // Load Trader wallet and generate a new address
+        let trader_address = trader_wallet_rpc
+            .get_new_address(self.label.as_deref(), None)?
+            .assume_checked();
+        if let Some(label) = &self.label {
+            // e1ec30: The sender's address book too, that's where its gettransaction gets it
+            wallet::set_label(&miner_wallet_rpc, &trader_address, label)?;
+        }
+        // println!("trader_address: {trader_address}");
+
+        // Send 20 BTC from Miner to Trader
+        shutdown::check()?;
+        shutdown::enter(Phase::Sending);
+        if coin_control.avoid_partial_spends {
+            privacy.push(PrivacyMeasure::AvoidPartialSpends);
+        }
+        let mut change = self.change.clone();
+        if change.address.is_none() && change.address_type.is_none() && self.privacy {
+            change.address_type = wallet::change_type_for(&trader_address);
+            if change.address_type.is_some() {
+                privacy.push(PrivacyMeasure::MatchingChangeType);
+            }
+        }
+
+        // e1ec30: Signed but not broadcast yet, so the budget can stop it
+        let tx = if self.manual {
+            let change_address = match &change.address {
+                Some(address) => address.clone(),
+                None => miner_wallet_rpc
+                    .get_raw_change_address(change.address_type)?
+                    .assume_checked(),
+            };
+            let tx = selected
+                .iter()
+                .fold(TxBuilder::new(), TxBuilder::spend)
+                .pay(&trader_address, amount)
+                .change_control(&change, &change_address)
+                .sequence_policy(&self.sequences)
+                .build()?;
+            builder::sign(&miner_wallet_rpc, &tx)?
+        } else {
+            compose(
+                &miner_wallet_rpc,
+                &trader_address.to_string(),
+                amount,
+                &inputs,
+                &self.sequences,
+                &change,
+            )?
+        };
+        let input_value = selected.iter().map(|u| u.amount).sum();
+        self.budget.check(&tx, input_value)?;
+        let txid_transfer = miner_wallet_rpc.send_raw_transaction(&tx)?;
+        funding_lock.spent();
+        hooks::fire(
+            Event::TxBroadcast,
+            &json!({ "wallet": self.from, "txid": txid_transfer }),
+        );
+        // println!("Transaction Hash: {txid_transfer}");
+
+        // e1ec30: From here on starting over would pay twice, so remember where we are
+        let checkpoint = Checkpoint {
+            from: self.from.clone(),
+            to: self.to.clone(),
+            txid: txid_transfer,
+            inputs,
+            input_amount: selected.iter().map(|u| u.amount).sum(),
+            miner_address: miner_address.as_unchecked().clone(),
+            privacy,
+            prove_ownership: self.prove_ownership,
+            label: self.label.clone(),
+            amount,
+            exact_amounts: self.exact_amounts,
+            change_address: change.address.as_ref().map(|a| a.as_unchecked().clone()),
+        };
+        if let Some(path) = &self.checkpoint {
+            checkpoint.save(path)?;
+        }
+        Flow::finish(
+            rpc,
+            &miner_wallet_rpc,
+            &trader_wallet_rpc,
+            &checkpoint,
+            true,
+        )
+    }
+
+    // e1ec30: Pick up a flow that died after broadcasting: confirm the payment, mining only
+    // if it's still unconfirmed, and build the report, without funding or paying again
+    pub fn resume(rpc: &Client, checkpoint: &Checkpoint) -> Result<FlowOutcome> {
+        shutdown::enter(Phase::Setup);
+        let options = CreateOptions::default();
+        let (miner_wallet_rpc, _miner_guard) =
+            wallet::open_guarded(rpc, &checkpoint.from, options)?;
+        let (trader_wallet_rpc, _trader_guard) =
+            wallet::open_guarded(rpc, &checkpoint.to, options)?;
+        let sent = miner_wallet_rpc.get_transaction(&checkpoint.txid, None)?;
+        if sent.info.confirmations < 0 {
+            return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "{} was replaced by a conflicting transaction, nothing to resume",
+                checkpoint.txid
+            ))
+            .into());
+        }
+        let unconfirmed = sent.info.confirmations == 0;
+        if unconfirmed {
+            // e1ec30: A node restarted without its mempool has forgotten it, the node takes
+            // a transaction it already has without complaint
+            let tx = sent.transaction().map_err(bitcoincore_rpc::Error::from)?;
+            miner_wallet_rpc.send_raw_transaction(&tx)?;
+        }
+        Flow::finish(
+            rpc,
+            &miner_wallet_rpc,
+            &trader_wallet_rpc,
+            checkpoint,
+            unconfirmed,
+        )
+    }
+
+    // e1ec30: Everything after the broadcast. `mine` confirms the payment with a block of
+    // our own, otherwise it's in one already.
+    fn finish(
+        rpc: &Client,
+        miner_wallet_rpc: &Client,
+        trader_wallet_rpc: &Client,
+        checkpoint: &Checkpoint,
+        mine: bool,
+    ) -> Result<FlowOutcome> {
+        let txid_transfer = checkpoint.txid;
+        let miner_address = checkpoint.miner_address.clone().assume_checked();
+
+        // Check transaction in mempool
+        let tx_res = miner_wallet_rpc.get_transaction(&txid_transfer, None)?;
+        let fee = tx_res.fee.unwrap();
+        let mut metadata = wallet::tx_metadata(miner_wallet_rpc, &txid_transfer)?;
+        metadata.label = metadata.label.or_else(|| checkpoint.label.clone());
+
+        // Mine 1 block to confirm the transaction
+        shutdown::enter(Phase::Confirming);
+        if mine {
+            rpc.generate_to_address(1, &miner_address)?;
+        }
+
+        // Extract all required transaction details
+        // e1ec30: Find the block that confirmed my transaction, it isn't necessarily the one I just
+        // mined if something else is mining on the same node. If it's in none of them yet (a
+        // block template built before the broadcast), wait for the next blocks.
+        let block = BlockWaiter::detect(rpc)
+            .wait_for_confirmation(
+                miner_wallet_rpc,
+                &txid_transfer,
+                CONFIRMATION_SEARCH_DEPTH,
+                CONFIRMATION_TIMEOUT,
+            )?
+            .ok_or(Error::Unconfirmed {
+                txid: txid_transfer,
+                depth: CONFIRMATION_SEARCH_DEPTH,
+            })?;
+        let confirmed_tx = block
+            .txdata
+            .iter()
+            .find(|tx| tx.txid() == txid_transfer)
+            .unwrap();
+
+        // e1ec30: Also get the transaction containing the input I used
+        let viable = &checkpoint.inputs[0];
+        let input_tx = chain::get_transaction(miner_wallet_rpc, &viable.txid, None)?;
+
+        // e1ec30: Extract Miner's input address and amount. With several inputs the address is the
+        // first one's and the amount is the total going in.
+        let output_spent = input_tx.output.get(viable.vout as usize).unwrap();
+        let miner_in_addr = script_to_addr(&output_spent.script_pubkey);
+        let miner_in_amount = checkpoint.input_amount;
+
+        // e1ec30: Extract Trader's Output address and amount, and Miner's Change address and
+        // amount
+        let change_script = checkpoint
+            .change_address
+            .as_ref()
+            .map(|a| a.clone().assume_checked().script_pubkey());
+        let (trader_out, miner_change) = classify_outputs(
+            &confirmed_tx.output,
+            change_script.as_ref(),
+            |script| is_mine(trader_wallet_rpc, script),
+            |script| is_mine(miner_wallet_rpc, script),
+        );
+        let trader_out = trader_out.ok_or(Error::MissingOutput {
+            txid: txid_transfer,
+            output: "payment",
+        })?;
+        let miner_change = miner_change.ok_or(Error::MissingOutput {
+            txid: txid_transfer,
+            output: "change",
+        })?;
+
+        // e1ec30: Make sure the block didn't pay the miner more (or less) than it should have,
+        // and commits to the witnesses the transfer was mined with
+        let block_height = chain::block_height(rpc, &block)?;
+        consensus::check_coinbase(rpc, &block, block_height)?;
+        let wtxid = confirmed_tx.wtxid();
+        let witness_commitment = consensus::check_witness_commitment(&block, &wtxid)?;
+        hooks::fire(
+            Event::TxConfirmed,
+            &json!({
+                "txid": txid_transfer,
+                "block_hash": block.block_hash(),
+                "block_height": block_height,
+            }),
+        );
+
+        // e1ec30: Signed by the wallets themselves, so the report's addresses can be trusted
+        // without asking a node
+        let trader_output_address = script_to_addr(&trader_out.script_pubkey);
+        let miner_change_address = script_to_addr(&miner_change.script_pubkey);
+        let ownership_proofs = if checkpoint.prove_ownership {
+            let txid = confirmed_tx.txid();
+            Some(OwnershipProofs {
+                trader_output_address: bip322::sign(
+                    trader_wallet_rpc,
+                    &trader_output_address,
+                    &OwnershipProofs::message(&trader_output_address, &txid),
+                )?,
+                miner_change_address: bip322::sign(
+                    miner_wallet_rpc,
+                    &miner_change_address,
+                    &OwnershipProofs::message(&miner_change_address, &txid),
+                )?,
+            })
+        } else {
+            None
+        };
+
+        let report = TxReport {
+            txid: confirmed_tx.txid(),
+            miner_input_address: miner_in_addr,
+            miner_input_amount: miner_in_amount,
+            trader_output_address,
+            trader_output_amount: trader_out.value,
+            miner_change_address,
+            miner_change_amount: miner_change.value,
+            fee,
+            fee_sat: fee.to_sat().unsigned_abs(),
+            bip125_replaceable: confirmed_tx.is_explicitly_rbf(),
+            block_height,
+            block_hash: block.block_hash(),
+            wtxid: Some(wtxid),
+            witness_commitment: Some(witness_commitment),
+            outputs: script::outputs(confirmed_tx),
+            privacy: checkpoint.privacy.clone(),
+            ownership_proofs,
+            metadata: (!metadata.is_empty()).then_some(metadata),
+        };
+        if checkpoint.exact_amounts {
+            report.check_exact_amounts(checkpoint.amount)?;
+        }
+        Ok(FlowOutcome {
+            report,
+            inputs: checkpoint.inputs.clone(),
+        })
+    }
+}
+
+// e1ec30: Which output pays the recipient and which is the change, wherever in the
+// transaction they are. An explicit change address is found by its script, since it needn't
+// be the sender's, otherwise the change is the output the sender's wallet owns. The payment
+// is the recipient's output that isn't the change.
+fn classify_outputs<'a>(
+    outputs: &'a [TxOut],
+    change_script: Option<&ScriptBuf>,
+    is_recipients: impl Fn(&ScriptBuf) -> bool,
+    is_senders: impl Fn(&ScriptBuf) -> bool,
+) -> (Option<&'a TxOut>, Option<&'a TxOut>) {
+    let change = outputs.iter().position(|o| match change_script {
+        Some(script) => &o.script_pubkey == script,
+        None => is_senders(&o.script_pubkey),
+    });
+    let payment = outputs
+        .iter()
+        .enumerate()
+        .find(|(i, o)| Some(*i) != change && is_recipients(&o.script_pubkey))
+        .map(|(_, o)| o);
+    (payment, change.map(|i| &outputs[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builder_keeps_the_capstone_defaults() {
+        let flow = Flow::builder()
+            .trader("Alice")
+            .amount(Amount::from_int_btc(5))
+            .build();
+        assert_eq!(flow.from, "Miner");
+        assert_eq!(flow.to, "Alice");
+        assert_eq!(flow.amount, Amount::from_int_btc(5));
+        assert!(!flow.manual);
+        assert_eq!(Flow::builder().build().amount, Amount::from_int_btc(20));
+    }
+
+    #[test]
+    fn classifies_outputs_wherever_they_are() {
+        let script = |byte: u8| ScriptBuf::from_bytes(vec![0, 20, byte]);
+        let output = |byte: u8, sat: u64| TxOut {
+            value: Amount::from_sat(sat),
+            script_pubkey: script(byte),
+        };
+        // Change first, to a third party's address the recipient's wallet also watches
+        let outputs = [output(3, 1_000), output(1, 20_000), output(2, 5_000)];
+        let recipients = |s: &ScriptBuf| *s == script(1) || *s == script(3);
+        let senders = |s: &ScriptBuf| *s == script(2);
+        let (payment, change) = classify_outputs(&outputs, Some(&script(3)), recipients, senders);
+        assert_eq!(payment.unwrap().value, Amount::from_sat(20_000));
+        assert_eq!(change.unwrap().value, Amount::from_sat(1_000));
+
+        let (payment, change) = classify_outputs(&outputs[1..], None, recipients, senders);
+        assert_eq!(payment.unwrap().value, Amount::from_sat(20_000));
+        assert_eq!(change.unwrap().value, Amount::from_sat(5_000));
+    }
+}
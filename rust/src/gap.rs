@@ -0,0 +1,129 @@
+use bitcoincore_rpc::bitcoin::{BlockHash, ScriptBuf};
+use bitcoincore_rpc::json::ScanTxOutRequest;
+use bitcoincore_rpc::RpcApi;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::chain;
+use crate::compat::{Compat, Feature};
+use crate::error::Result;
+
+// The usual BIP44 gap limit
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+pub const DEFAULT_CHUNK_SIZE: u32 = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScanMethod {
+    // scanblocks over the block filter index, sees every address that ever received coins
+    BlockFilters,
+    // scantxoutset, only sees addresses that still hold coins
+    UtxoSet,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GapScan {
+    // The descriptor as it was scanned, public and with checksum
+    pub descriptor: String,
+    pub method: ScanMethod,
+    // Derivation indexes found in use, ascending
+    pub used: Vec<u32>,
+    pub highest_used: Option<u32>,
+    // Number of addresses derived and checked
+    pub scanned: u32,
+}
+
+// Derive addresses from a ranged `descriptor` `chunk_size` at a time and check which of
+// them have been used, until `gap_limit` unused ones follow the last used one. This is how
+// a wallet restored from a seed knows how far to look ahead.
+pub fn scan(
+    rpc: &impl RpcApi,
+    compat: &Compat,
+    descriptor: &str,
+    gap_limit: u32,
+    chunk_size: u32,
+) -> Result<GapScan> {
+    let descriptor = rpc.get_descriptor_info(descriptor)?.descriptor;
+    let method = if compat.supports(Feature::ScanBlocks) && chain::has_blockfilterindex(rpc) {
+        ScanMethod::BlockFilters
+    } else {
+        ScanMethod::UtxoSet
+    };
+
+    let mut used = vec![];
+    let mut start = 0;
+    // Keep going while fewer than `gap_limit` addresses after the last used one were checked
+    while start - used.last().map_or(0, |i| i + 1) < gap_limit {
+        let end = start + chunk_size - 1;
+        let scripts: Vec<ScriptBuf> = rpc
+            .derive_addresses(&descriptor, Some([start, end]))?
+            .into_iter()
+            .map(|a| a.assume_checked().script_pubkey())
+            .collect();
+        let found = match method {
+            ScanMethod::BlockFilters => received_in_blocks(rpc, &scripts)?,
+            ScanMethod::UtxoSet => holding_coins(rpc, &scripts)?,
+        };
+        used.extend(
+            scripts
+                .iter()
+                .enumerate()
+                .filter(|(_, script)| found.contains(script))
+                .map(|(i, _)| start + i as u32),
+        );
+        start = end + 1;
+    }
+
+    Ok(GapScan {
+        descriptor,
+        method,
+        highest_used: used.last().copied(),
+        used,
+        scanned: start,
+    })
+}
+
+fn raw_descriptors(scripts: &[ScriptBuf]) -> Vec<String> {
+    scripts
+        .iter()
+        .map(|s| format!("raw({})", s.to_hex_string()))
+        .collect()
+}
+
+// Scripts of `scripts` paid to anywhere in the chain. scanblocks only says which blocks
+// may be relevant (filters have false positives), so those blocks are checked by hand.
+fn received_in_blocks(rpc: &impl RpcApi, scripts: &[ScriptBuf]) -> Result<Vec<ScriptBuf>> {
+    #[derive(Deserialize)]
+    struct ScanBlocksResult {
+        relevant_blocks: Vec<BlockHash>,
+    }
+    let result: ScanBlocksResult = rpc.call(
+        "scanblocks",
+        &[json!("start"), json!(raw_descriptors(scripts))],
+    )?;
+
+    let mut found = vec![];
+    for hash in result.relevant_blocks {
+        let block = rpc.get_block(&hash)?;
+        for output in block.txdata.iter().flat_map(|tx| &tx.output) {
+            if scripts.contains(&output.script_pubkey) && !found.contains(&output.script_pubkey) {
+                found.push(output.script_pubkey.clone());
+            }
+        }
+    }
+    Ok(found)
+}
+
+// Scripts of `scripts` with unspent outputs right now
+fn holding_coins(rpc: &impl RpcApi, scripts: &[ScriptBuf]) -> Result<Vec<ScriptBuf>> {
+    let requests: Vec<_> = raw_descriptors(scripts)
+        .into_iter()
+        .map(ScanTxOutRequest::Single)
+        .collect();
+    let result = rpc.scan_tx_out_set_blocking(&requests)?;
+    Ok(result
+        .unspents
+        .into_iter()
+        .map(|u| u.script_pub_key)
+        .collect())
+}
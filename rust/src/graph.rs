@@ -0,0 +1,177 @@
+use bitcoincore_rpc::bitcoin::{Address, Amount, ScriptBuf, Txid};
+use bitcoincore_rpc::RpcApi;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::config;
+use crate::rpc::CachingClient;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeKind {
+    Tx { coinbase: bool },
+    Address,
+    // Outputs without an address form (OP_RETURN, bare multisig, ...)
+    Script,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub kind: NodeKind,
+    pub label: String,
+}
+
+// Value moving from an address into a transaction (an input) or out of one (an output)
+#[derive(Debug)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+    pub value: Amount,
+}
+
+// Transactions and addresses of a block range, connected by the value flowing between them
+#[derive(Debug, Default)]
+pub struct TxGraph {
+    // Keyed by node id, ordered so exports are deterministic
+    pub nodes: BTreeMap<String, Node>,
+    pub edges: Vec<Edge>,
+}
+
+impl TxGraph {
+    fn add_tx(&mut self, txid: Txid, coinbase: bool) -> String {
+        let id = format!("tx:{txid}");
+        self.nodes.entry(id.clone()).or_insert(Node {
+            kind: NodeKind::Tx { coinbase },
+            label: txid.to_string(),
+        });
+        id
+    }
+
+    fn add_script(&mut self, script: &ScriptBuf) -> String {
+        let (id, kind, label) = match Address::from_script(script, config::active().network) {
+            Ok(addr) => (format!("addr:{addr}"), NodeKind::Address, addr.to_string()),
+            Err(_) => {
+                let hex = script.to_hex_string();
+                (format!("script:{hex}"), NodeKind::Script, hex)
+            }
+        };
+        self.nodes.entry(id.clone()).or_insert(Node { kind, label });
+        id
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph txgraph {\n    rankdir=LR;\n");
+        for (id, node) in &self.nodes {
+            let shape = match node.kind {
+                NodeKind::Tx { coinbase: true } => "box, style=filled, fillcolor=gold",
+                NodeKind::Tx { coinbase: false } => "box",
+                NodeKind::Address => "ellipse",
+                NodeKind::Script => "note",
+            };
+            writeln!(
+                dot,
+                "    \"{id}\" [label=\"{}\", shape={shape}];",
+                node.label
+            )
+            .unwrap();
+        }
+        for edge in &self.edges {
+            writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                edge.value.to_btc()
+            )
+            .unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"kind\" for=\"node\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+            "  <key id=\"value\" for=\"edge\" attr.name=\"value_btc\" attr.type=\"double\"/>\n",
+            "  <graph id=\"txgraph\" edgedefault=\"directed\">\n",
+        ));
+        for (id, node) in &self.nodes {
+            let kind = match node.kind {
+                NodeKind::Tx { coinbase: true } => "coinbase",
+                NodeKind::Tx { coinbase: false } => "tx",
+                NodeKind::Address => "address",
+                NodeKind::Script => "script",
+            };
+            writeln!(
+                xml,
+                "    <node id=\"{id}\"><data key=\"kind\">{kind}</data><data key=\"label\">{}</data></node>",
+                node.label
+            )
+            .unwrap();
+        }
+        for (i, edge) in self.edges.iter().enumerate() {
+            writeln!(
+                xml,
+                "    <edge id=\"e{i}\" source=\"{}\" target=\"{}\"><data key=\"value\">{}</data></edge>",
+                edge.from,
+                edge.to,
+                edge.value.to_btc()
+            )
+            .unwrap();
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+}
+
+// Build the graph for blocks `from..=to` (up to the tip if `to` is None). Inputs are
+// resolved to the address they spend from, which needs a lookup of the funding
+// transaction, hence the cache.
+pub fn build<R: RpcApi>(
+    rpc: &CachingClient<R>,
+    from: u64,
+    to: Option<u64>,
+) -> bitcoincore_rpc::Result<TxGraph> {
+    let to = match to {
+        Some(to) => to,
+        None => rpc.get_block_count()?,
+    };
+    let mut graph = TxGraph::default();
+
+    for height in from..=to {
+        let block = rpc.get_block(&rpc.get_block_hash(height)?)?;
+        for tx in &block.txdata {
+            let coinbase = tx.is_coinbase();
+            let tx_id = graph.add_tx(tx.txid(), coinbase);
+
+            if !coinbase {
+                for input in &tx.input {
+                    let prev = input.previous_output;
+                    let funding = rpc.get_raw_transaction(&prev.txid, None)?;
+                    let spent = &funding.output[prev.vout as usize];
+                    let from_id = graph.add_script(&spent.script_pubkey);
+                    graph.edges.push(Edge {
+                        from: from_id,
+                        to: tx_id.clone(),
+                        value: spent.value,
+                    });
+                }
+            }
+            for output in &tx.output {
+                // Skip zero-value outputs such as the coinbase witness commitment
+                if output.value == Amount::ZERO {
+                    continue;
+                }
+                let to_id = graph.add_script(&output.script_pubkey);
+                graph.edges.push(Edge {
+                    from: tx_id.clone(),
+                    to: to_id,
+                    value: output.value,
+                });
+            }
+        }
+    }
+    Ok(graph)
+}
@@ -0,0 +1,395 @@
+use bitcoincore_rpc::bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv, Xpub};
+use bitcoincore_rpc::bitcoin::hex::{DisplayHex, FromHex};
+use bitcoincore_rpc::bitcoin::secp256k1::{rand, Secp256k1};
+use bitcoincore_rpc::bitcoin::Network;
+use bitcoincore_rpc::RpcApi;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::compat::Compat;
+use crate::error::Result;
+use crate::gap::{self, GapScan};
+use crate::store;
+use crate::walletless::LocalKey;
+
+// BIP32 hardened index flag (the h in 84h)
+const HARDENED: u32 = 1 << 31;
+
+// Which BIP44-style scheme an account follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Purpose {
+    // P2WPKH, m/84'/coin'/account'
+    Bip84,
+    // P2TR key path spends, m/86'/coin'/account'
+    Bip86,
+}
+
+impl Purpose {
+    fn index(self) -> u32 {
+        match self {
+            Purpose::Bip84 => 84,
+            Purpose::Bip86 => 86,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyChain {
+    Receive = 0,
+    Change = 1,
+}
+
+impl fmt::Display for KeyChain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            KeyChain::Receive => "receive",
+            KeyChain::Change => "change",
+        })
+    }
+}
+
+// One account's next unused index on each chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountState {
+    pub purpose: Purpose,
+    pub account: u32,
+    pub next_receive: u32,
+    pub next_change: u32,
+}
+
+// What the state file holds. The seed is in the clear: this is for regtest keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HdState {
+    pub seed: String,
+    pub network: Network,
+    #[serde(default)]
+    pub accounts: Vec<AccountState>,
+}
+
+// A key from an account, and where it came from
+#[derive(Debug, Clone)]
+pub struct DerivedKey {
+    pub path: DerivationPath,
+    pub key: LocalKey,
+}
+
+// BIP32 accounts from one seed. Handing out an address moves its chain's next index on,
+// and `save` writes that to the state file, so later runs don't hand it out again.
+pub struct AccountManager {
+    path: Option<PathBuf>,
+    master: Xpriv,
+    state: HdState,
+}
+
+impl AccountManager {
+    pub fn from_seed(seed: &[u8], network: Network) -> Result<AccountManager> {
+        Ok(AccountManager {
+            path: None,
+            master: Xpriv::new_master(network, seed).map_err(bip32_error)?,
+            state: HdState {
+                seed: seed.to_lower_hex_string(),
+                network,
+                accounts: vec![],
+            },
+        })
+    }
+
+    pub fn from_state(state: HdState) -> Result<AccountManager> {
+        let seed = Vec::<u8>::from_hex(&state.seed)
+            .map_err(|e| bitcoincore_rpc::Error::ReturnedError(format!("seed is not hex: {e}")))?;
+        Ok(AccountManager {
+            master: Xpriv::new_master(state.network, &seed).map_err(bip32_error)?,
+            path: None,
+            state,
+        })
+    }
+
+    // The state file at `path`, or a new random seed saved there
+    pub fn open(path: &Path, network: Network) -> Result<AccountManager> {
+        let _lock = store::lock(path)?;
+        let mut manager = if let Some(state) = read_state(path)? {
+            if state.network != network {
+                return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                    "{} holds {} keys, the node is on {network}",
+                    path.display(),
+                    state.network
+                ))
+                .into());
+            }
+            AccountManager::from_state(state)?
+        } else {
+            let mut seed = [0; 32];
+            rand::thread_rng().fill_bytes(&mut seed);
+            AccountManager::from_seed(&seed, network)?
+        };
+        manager.path = Some(path.to_owned());
+        manager.write_locked()?;
+        Ok(manager)
+    }
+
+    // Merged with the state file first: another process using it may have handed out
+    // addresses since this one read it, and those have to stay handed out
+    pub fn save(&mut self) -> Result<()> {
+        let _lock = self.path.as_deref().map(store::lock).transpose()?;
+        self.reload_locked()?;
+        self.write_locked()
+    }
+
+    fn reload_locked(&mut self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(on_file) = read_state(path)? {
+            for theirs in &on_file.accounts {
+                let ours = self.account_mut(theirs.purpose, theirs.account);
+                ours.next_receive = ours.next_receive.max(theirs.next_receive);
+                ours.next_change = ours.next_change.max(theirs.next_change);
+            }
+        }
+        Ok(())
+    }
+
+    fn write_locked(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            store::write_locked(path, serde_json::to_string_pretty(&self.state)?.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn state(&self) -> &HdState {
+        &self.state
+    }
+
+    // m/purpose'/coin'/account', coin type 1 on every network but mainnet
+    pub fn account_path(&self, purpose: Purpose, account: u32) -> DerivationPath {
+        let coin = match self.state.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        [purpose.index(), coin, account]
+            .iter()
+            .map(|&i| ChildNumber::from(HARDENED | i))
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    pub fn derive(
+        &self,
+        purpose: Purpose,
+        account: u32,
+        chain: KeyChain,
+        index: u32,
+    ) -> Result<DerivedKey> {
+        let secp = Secp256k1::new();
+        let path = self.account_path(purpose, account).extend([
+            ChildNumber::from_normal_idx(chain as u32).map_err(bip32_error)?,
+            ChildNumber::from_normal_idx(index).map_err(bip32_error)?,
+        ]);
+        let secret = self
+            .master
+            .derive_priv(&secp, &path)
+            .map_err(bip32_error)?
+            .private_key;
+        let key = match purpose {
+            Purpose::Bip84 => LocalKey::from_secret(secret, self.state.network),
+            Purpose::Bip86 => LocalKey::taproot(secret, self.state.network),
+        };
+        Ok(DerivedKey { path, key })
+    }
+
+    // The chain's next unused key, which is then used. With a state file the index is
+    // reserved there before the key is handed out, so no other process takes it too.
+    pub fn next(&mut self, purpose: Purpose, account: u32, chain: KeyChain) -> Result<DerivedKey> {
+        let _lock = self.path.as_deref().map(store::lock).transpose()?;
+        self.reload_locked()?;
+        let state = self.account_mut(purpose, account);
+        let next = match chain {
+            KeyChain::Receive => &mut state.next_receive,
+            KeyChain::Change => &mut state.next_change,
+        };
+        let index = *next;
+        *next += 1;
+        self.write_locked()?;
+        self.derive(purpose, account, chain, index)
+    }
+
+    // Make sure the next keys come after `index`, e.g. after finding it used on chain
+    pub fn mark_used(&mut self, purpose: Purpose, account: u32, chain: KeyChain, index: u32) {
+        let state = self.account_mut(purpose, account);
+        let next = match chain {
+            KeyChain::Receive => &mut state.next_receive,
+            KeyChain::Change => &mut state.next_change,
+        };
+        *next = (*next).max(index + 1);
+    }
+
+    // Public ranged descriptor of an account's chain, e.g. for `gap::scan`
+    pub fn descriptor(&self, purpose: Purpose, account: u32, chain: KeyChain) -> Result<String> {
+        let secp = Secp256k1::new();
+        let path = self.account_path(purpose, account);
+        let xpub = Xpub::from_priv(
+            &secp,
+            &self.master.derive_priv(&secp, &path).map_err(bip32_error)?,
+        );
+        let origin = format!(
+            "[{}/{}]",
+            self.master.fingerprint(&secp),
+            path_without_m(&path)
+        );
+        let key = format!("{origin}{xpub}/{}/*", chain as u32);
+        Ok(match purpose {
+            Purpose::Bip84 => format!("wpkh({key})"),
+            Purpose::Bip86 => format!("tr({key})"),
+        })
+    }
+
+    // Scan both chains of each account for used addresses and move the next indexes past
+    // the highest one found, so a state rebuilt from the seed alone doesn't hand out used
+    // addresses again
+    pub fn recover(
+        &mut self,
+        rpc: &impl RpcApi,
+        compat: &Compat,
+        accounts: &[(Purpose, u32)],
+        gap_limit: u32,
+    ) -> Result<Vec<GapScan>> {
+        let mut scans = vec![];
+        for &(purpose, account) in accounts {
+            for chain in [KeyChain::Receive, KeyChain::Change] {
+                let descriptor = self.descriptor(purpose, account, chain)?;
+                let scan = gap::scan(rpc, compat, &descriptor, gap_limit, gap::DEFAULT_CHUNK_SIZE)?;
+                if let Some(index) = scan.highest_used {
+                    self.mark_used(purpose, account, chain, index);
+                } else {
+                    // Listed even when nothing was used, so the state shows it was looked at
+                    self.account_mut(purpose, account);
+                }
+                scans.push(scan);
+            }
+        }
+        self.save()?;
+        Ok(scans)
+    }
+
+    fn account_mut(&mut self, purpose: Purpose, account: u32) -> &mut AccountState {
+        let position = self
+            .state
+            .accounts
+            .iter()
+            .position(|a| a.purpose == purpose && a.account == account);
+        let position = position.unwrap_or_else(|| {
+            self.state.accounts.push(AccountState {
+                purpose,
+                account,
+                next_receive: 0,
+                next_change: 0,
+            });
+            self.state.accounts.len() - 1
+        });
+        &mut self.state.accounts[position]
+    }
+}
+
+fn read_state(path: &Path) -> Result<Option<HdState>> {
+    Ok(match store::read_locked(path)? {
+        Some(bytes) => Some(serde_json::from_slice(&bytes)?),
+        None => None,
+    })
+}
+
+// Descriptors want 84h/1h/0h, DerivationPath displays m/84'/1'/0'
+fn path_without_m(path: &DerivationPath) -> String {
+    path.to_string().trim_start_matches("m/").replace('\'', "h")
+}
+
+fn bip32_error(e: impl fmt::Display) -> bitcoincore_rpc::Error {
+    bitcoincore_rpc::Error::ReturnedError(format!("BIP32: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP32 test vector 1's seed
+    fn manager() -> AccountManager {
+        let seed = Vec::<u8>::from_hex("000102030405060708090a0b0c0d0e0f").unwrap();
+        AccountManager::from_seed(&seed, Network::Regtest).unwrap()
+    }
+
+    #[test]
+    fn derives_bip84_and_bip86_paths() {
+        let m = manager();
+        let key = m.derive(Purpose::Bip84, 0, KeyChain::Change, 5).unwrap();
+        assert_eq!(key.path.to_string(), "m/84'/1'/0'/1/5");
+        assert!(key.key.address.to_string().starts_with("bcrt1q"));
+
+        let key = m.derive(Purpose::Bip86, 2, KeyChain::Receive, 0).unwrap();
+        assert_eq!(key.path.to_string(), "m/86'/1'/2'/0/0");
+        assert!(key.key.address.to_string().starts_with("bcrt1p"));
+
+        let descriptor = m.descriptor(Purpose::Bip84, 0, KeyChain::Receive).unwrap();
+        assert!(
+            descriptor.starts_with("wpkh([3442193e/84h/1h/0h]tpub"),
+            "{descriptor}"
+        );
+        assert!(descriptor.ends_with("/0/*)"));
+    }
+
+    #[test]
+    fn never_hands_out_an_address_twice() {
+        let mut m = manager();
+        let first = m.next(Purpose::Bip84, 0, KeyChain::Receive).unwrap();
+        let second = m.next(Purpose::Bip84, 0, KeyChain::Receive).unwrap();
+        let change = m.next(Purpose::Bip84, 0, KeyChain::Change).unwrap();
+        assert_ne!(first.key.address, second.key.address);
+        assert_ne!(first.key.address, change.key.address);
+
+        // A manager reopened from the saved state carries on where this one stopped
+        let json = serde_json::to_string(m.state()).unwrap();
+        let mut reopened =
+            AccountManager::from_state(serde_json::from_str(&json).unwrap()).unwrap();
+        let third = reopened.next(Purpose::Bip84, 0, KeyChain::Receive).unwrap();
+        assert_eq!(third.path.to_string(), "m/84'/1'/0'/0/2");
+    }
+
+    #[test]
+    fn processes_sharing_a_state_file_never_share_an_address() {
+        let path = std::env::temp_dir().join(format!("hd-state-{}.json", std::process::id()));
+        let mut first = AccountManager::open(&path, Network::Regtest).unwrap();
+        let mut second = AccountManager::open(&path, Network::Regtest).unwrap();
+        let a = first.next(Purpose::Bip84, 0, KeyChain::Receive).unwrap();
+        let b = second.next(Purpose::Bip84, 0, KeyChain::Receive).unwrap();
+        assert_ne!(a.key.address, b.key.address);
+
+        // Saving what it read earlier doesn't take back the index the other one handed out
+        first.mark_used(Purpose::Bip84, 0, KeyChain::Change, 4);
+        first.save().unwrap();
+        let reopened = AccountManager::open(&path, Network::Regtest).unwrap();
+        let account = &reopened.state().accounts[0];
+        assert_eq!((account.next_receive, account.next_change), (2, 5));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("json.lock")).unwrap();
+    }
+
+    #[test]
+    fn recovers_the_same_keys_from_the_seed() {
+        let mut m = manager();
+        let used = m.next(Purpose::Bip84, 1, KeyChain::Receive).unwrap();
+
+        // Only the seed survived: the address is derived again, and marking it used (as a
+        // chain scan would) moves past it
+        let mut recovered = manager();
+        let again = recovered
+            .derive(Purpose::Bip84, 1, KeyChain::Receive, 0)
+            .unwrap();
+        assert_eq!(again.key.address, used.key.address);
+        recovered.mark_used(Purpose::Bip84, 1, KeyChain::Receive, 0);
+        let next = recovered
+            .next(Purpose::Bip84, 1, KeyChain::Receive)
+            .unwrap();
+        assert_eq!(next.path.to_string(), "m/84'/1'/1'/0/1");
+    }
+}
@@ -0,0 +1,271 @@
+use bitcoincore_rpc::bitcoin::Network;
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::http::{Request, Response, Service};
+
+// Prometheus' text exposition format
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// One node's side of the network as of the last poll. A node that didn't answer keeps only
+// its profile, network and the error.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeHealth {
+    pub profile: String,
+    pub network: Network,
+    pub up: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uptime: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_received: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes_sent: Option<u64>,
+    // Addresses in the node's address manager by network (ipv4, onion, ...), what it could
+    // connect to next
+    pub known_addresses: BTreeMap<String, usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl NodeHealth {
+    // uptime, getnettotals and getnodeaddresses (all of them, count 0)
+    pub fn gather(profile: &str, network: Network, rpc: &impl RpcApi) -> NodeHealth {
+        let mut health = NodeHealth {
+            profile: profile.to_owned(),
+            network,
+            up: false,
+            uptime: None,
+            bytes_received: None,
+            bytes_sent: None,
+            known_addresses: BTreeMap::new(),
+            error: None,
+        };
+        let gathered = (|| -> bitcoincore_rpc::Result<()> {
+            health.uptime = Some(rpc.call("uptime", &[])?);
+            let totals: Value = rpc.call("getnettotals", &[])?;
+            health.bytes_received = totals["totalbytesrecv"].as_u64();
+            health.bytes_sent = totals["totalbytessent"].as_u64();
+            let addresses: Vec<Value> = rpc.call("getnodeaddresses", &[json!(0)])?;
+            for address in &addresses {
+                let network = address["network"].as_str().unwrap_or("unknown");
+                *health
+                    .known_addresses
+                    .entry(network.to_owned())
+                    .or_default() += 1;
+            }
+            Ok(())
+        })();
+        match gathered {
+            Ok(()) => health.up = true,
+            Err(e) => health.error = Some(e.to_string()),
+        }
+        health
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthSummary {
+    // Unix seconds of the last poll
+    pub checked_at: u64,
+    pub nodes: Vec<NodeHealth>,
+    // Nodes that answered every call
+    pub up: usize,
+}
+
+impl HealthSummary {
+    // Gauges and counters per node, labelled by profile
+    pub fn metrics(&self) -> String {
+        let mut out = String::new();
+        let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, u64)>| {
+            writeln!(out, "# HELP {name} {help}").unwrap();
+            writeln!(out, "# TYPE {name} {kind}").unwrap();
+            for (labels, value) in samples {
+                writeln!(out, "{name}{{{labels}}} {value}").unwrap();
+            }
+        };
+        let per_node = |value: &dyn Fn(&NodeHealth) -> Option<u64>| {
+            self.nodes
+                .iter()
+                .filter_map(|node| Some((label(node), value(node)?)))
+                .collect()
+        };
+        family(
+            "capstone_node_up",
+            "gauge",
+            "Whether the node answered the last poll",
+            per_node(&|node| Some(node.up as u64)),
+        );
+        family(
+            "capstone_node_uptime_seconds",
+            "gauge",
+            "Seconds since the node started",
+            per_node(&|node| node.uptime),
+        );
+        family(
+            "capstone_node_received_bytes_total",
+            "counter",
+            "Bytes the node received from peers",
+            per_node(&|node| node.bytes_received),
+        );
+        family(
+            "capstone_node_sent_bytes_total",
+            "counter",
+            "Bytes the node sent to peers",
+            per_node(&|node| node.bytes_sent),
+        );
+        family(
+            "capstone_node_known_addresses",
+            "gauge",
+            "Addresses in the node's address manager",
+            self.nodes
+                .iter()
+                .flat_map(|node| {
+                    node.known_addresses.iter().map(|(network, count)| {
+                        (
+                            format!("{},address_network=\"{network}\"", label(node)),
+                            *count as u64,
+                        )
+                    })
+                })
+                .collect(),
+        );
+        out
+    }
+}
+
+fn label(node: &NodeHealth) -> String {
+    let profile = node.profile.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("profile=\"{profile}\",network=\"{}\"", node.network)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+// Daemon mode: polls every node each interval and serves the last summary at `GET /status`
+// as JSON and at `GET /metrics` for Prometheus to scrape, so a topology of several nodes
+// is watched from one place
+pub struct Monitor<R> {
+    // Profile name, its network and a client for it
+    nodes: Vec<(String, Network, R)>,
+    interval: Duration,
+    last_poll: Option<Instant>,
+    pub summary: Option<HealthSummary>,
+}
+
+impl<R: RpcApi> Monitor<R> {
+    pub fn new(nodes: Vec<(String, Network, R)>, interval: Duration) -> Self {
+        Monitor {
+            nodes,
+            interval,
+            last_poll: None,
+            summary: None,
+        }
+    }
+
+    pub fn poll(&mut self) -> &HealthSummary {
+        let nodes: Vec<NodeHealth> = self
+            .nodes
+            .iter()
+            .map(|(profile, network, rpc)| NodeHealth::gather(profile, *network, rpc))
+            .collect();
+        self.last_poll = Some(Instant::now());
+        self.summary.insert(HealthSummary {
+            checked_at: now(),
+            up: nodes.iter().filter(|n| n.up).count(),
+            nodes,
+        })
+    }
+}
+
+impl<R: RpcApi> Service for Monitor<R> {
+    fn handle(&mut self, request: &Request) -> Response {
+        let Some(summary) = &self.summary else {
+            return Response::error(503, "no poll has finished yet");
+        };
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/status") => Response::json(200, summary),
+            ("GET", "/metrics") => Response {
+                status: 200,
+                content_type: METRICS_CONTENT_TYPE,
+                body: summary.metrics().into_bytes(),
+            },
+            _ => Response::not_found(),
+        }
+    }
+
+    fn tick(&mut self) {
+        if self
+            .last_poll
+            .is_none_or(|last| last.elapsed() >= self.interval)
+        {
+            let summary = self.poll();
+            println!("{} of {} node(s) up", summary.up, summary.nodes.len());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+
+    fn get(path: &str) -> Request {
+        Request {
+            method: "GET".into(),
+            path: path.into(),
+            body: vec![],
+        }
+    }
+
+    #[test]
+    fn summarizes_nodes_for_status_and_metrics() {
+        let up = MockClient::new()
+            .returns("uptime", json!(3600))
+            .returns(
+                "getnettotals",
+                json!({ "totalbytesrecv": 1000, "totalbytessent": 2000, "timemillis": 1 }),
+            )
+            .returns(
+                "getnodeaddresses",
+                json!([
+                    { "address": "10.0.0.1", "port": 18444, "network": "ipv4" },
+                    { "address": "10.0.0.2", "port": 18444, "network": "ipv4" },
+                    { "address": "abc.onion", "port": 18444, "network": "onion" },
+                ]),
+            );
+        let down = MockClient::new().fails("uptime", -28, "Loading block index...");
+        let mut monitor = Monitor::new(
+            vec![
+                ("alice".to_owned(), Network::Regtest, up),
+                ("bob".to_owned(), Network::Regtest, down),
+            ],
+            Duration::from_secs(15),
+        );
+        assert_eq!(monitor.handle(&get("/status")).status, 503);
+
+        let summary = monitor.poll().clone();
+        assert_eq!(summary.up, 1);
+        assert_eq!(summary.nodes[0].known_addresses["ipv4"], 2);
+        assert!(summary.nodes[1].error.is_some());
+
+        let status: Value = serde_json::from_slice(&monitor.handle(&get("/status")).body).unwrap();
+        assert_eq!(status["nodes"][0]["bytes_sent"], 2000);
+
+        let metrics = monitor.handle(&get("/metrics"));
+        assert_eq!(metrics.content_type, METRICS_CONTENT_TYPE);
+        let metrics = String::from_utf8(metrics.body).unwrap();
+        assert!(metrics.contains("capstone_node_up{profile=\"bob\",network=\"regtest\"} 0\n"));
+        assert!(metrics.contains(
+            "capstone_node_known_addresses{profile=\"alice\",network=\"regtest\",\
+             address_network=\"onion\"} 1\n"
+        ));
+        assert!(!metrics.contains("capstone_node_uptime_seconds{profile=\"bob\""));
+    }
+}
@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::process::{Command, Stdio};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config;
+
+// Longest a webhook may take to connect and to answer
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Points in a run external tools can hook into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    // The sender's coins for the payment are selected (and mined, if it had to)
+    WalletFunded,
+    TxBroadcast,
+    TxConfirmed,
+    // ../out.txt or a JSON report is on disk
+    ReportWritten,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // The same names as in the config and the payload
+        let name = serde_json::to_value(self).unwrap_or_default();
+        f.write_str(name.as_str().unwrap_or_default())
+    }
+}
+
+// One `[[hooks]]` entry of the config file: a shell command that gets the event on stdin,
+// or an http:// URL it is POSTed to
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hook {
+    // Events the hook fires on, every event when left out
+    #[serde(default)]
+    pub events: Vec<Event>,
+    #[serde(flatten)]
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    Command(String),
+    Url(String),
+}
+
+impl Hook {
+    pub fn fires_on(&self, event: Event) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+// What every hook gets: {"event": ..., "time": unix seconds, "data": {...}}
+pub fn payload(event: Event, data: &impl Serialize) -> Value {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    json!({
+        "event": event,
+        "time": time,
+        "data": serde_json::to_value(data).unwrap_or(Value::Null),
+    })
+}
+
+// Run the configured hooks for `event`, in config order. A failing hook is reported and
+// otherwise ignored: automation around the run shouldn't be able to break it.
+pub fn fire(event: Event, data: &impl Serialize) {
+    let hooks: Vec<&Hook> = config::hooks()
+        .iter()
+        .filter(|h| h.fires_on(event))
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+    let body = payload(event, data).to_string();
+    for hook in hooks {
+        if let Err(e) = deliver(hook, event, &body) {
+            eprintln!("The {event} hook {:?} failed: {e}", hook.target);
+        }
+    }
+}
+
+fn deliver(hook: &Hook, event: Event, body: &str) -> io::Result<()> {
+    match &hook.target {
+        Target::Command(command) => run_command(command, event, body),
+        Target::Url(url) => post(url, body),
+    }
+}
+
+// `sh -c command` with the payload on stdin and the event name in $HOOK_EVENT
+fn run_command(command: &str, event: Event, body: &str) -> io::Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("HOOK_EVENT", event.to_string())
+        .stdin(Stdio::piped())
+        .spawn()?;
+    // A command that doesn't read its input closes the pipe early, which is fine
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(body.as_bytes()) {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e),
+            _ => {}
+        }
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::other(format!("exited with {status}")));
+    }
+    Ok(())
+}
+
+// Host, port and path of a plain http:// URL
+pub fn parse_url(url: &str) -> io::Result<(String, u16, String)> {
+    let invalid = |why: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("{url}: {why}"));
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| invalid("only http:// webhooks are supported"))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse()
+                .map_err(|_| invalid("the port is not a number"))?,
+        ),
+        None => (authority, 80),
+    };
+    if host.is_empty() {
+        return Err(invalid("no host"));
+    }
+    Ok((host.to_owned(), port, path.to_owned()))
+}
+
+// POST `body` as JSON, wanting a 2xx back
+fn post(url: &str, body: &str) -> io::Result<()> {
+    let (host, port, path) = parse_url(url)?;
+    let address = (host.as_str(), port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{host} not found")))?;
+    let mut stream = TcpStream::connect_timeout(&address, WEBHOOK_TIMEOUT)?;
+    stream.set_read_timeout(Some(WEBHOOK_TIMEOUT))?;
+    stream.set_write_timeout(Some(WEBHOOK_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {host}:{port}\r\nContent-Type: application/json\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    let mut status = String::new();
+    BufReader::new(stream).read_line(&mut status)?;
+    match status.split_whitespace().nth(1) {
+        Some(code) if code.starts_with('2') => Ok(()),
+        _ => Err(io::Error::other(format!(
+            "answered {:?}",
+            status.trim_end()
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_hooks_from_the_config() {
+        #[derive(Deserialize)]
+        struct Hooks {
+            hooks: Vec<Hook>,
+        }
+        let config: Hooks = toml::from_str(
+            r#"
+            [[hooks]]
+            events = ["tx_broadcast", "tx_confirmed"]
+            command = "jq . >> events.log"
+
+            [[hooks]]
+            url = "http://127.0.0.1:8080/bitcoin"
+            "#,
+        )
+        .unwrap();
+        let [command, webhook] = config.hooks.as_slice() else {
+            panic!("expected two hooks");
+        };
+        assert_eq!(command.target, Target::Command("jq . >> events.log".into()));
+        assert!(command.fires_on(Event::TxConfirmed));
+        assert!(!command.fires_on(Event::WalletFunded));
+        assert!(webhook.fires_on(Event::ReportWritten));
+
+        let body = payload(Event::TxBroadcast, &json!({ "txid": "ab" }));
+        assert_eq!(body["event"], "tx_broadcast");
+        assert_eq!(body["data"]["txid"], "ab");
+    }
+
+    #[test]
+    fn parses_webhook_urls() {
+        assert_eq!(
+            parse_url("http://hooks.local:8080/run?id=1").unwrap(),
+            ("hooks.local".into(), 8080, "/run?id=1".into())
+        );
+        assert_eq!(
+            parse_url("http://example.com").unwrap(),
+            ("example.com".into(), 80, "/".into())
+        );
+        assert!(parse_url("https://example.com/").is_err());
+        assert!(parse_url("http://:80/").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+use serde::Serialize;
+use serde_json::json;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use crate::shutdown;
+
+// Enough for the small JSON bodies the services here take
+const MAX_BODY: usize = 64 * 1024;
+// A client that stops sending mid-request doesn't hold up the ones behind it for long
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+// How often an idle server checks whether it's been asked to stop
+const ACCEPT_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    // Without the query string
+    pub path: String,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(status: u16, value: &impl Serialize) -> Response {
+        Response {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap_or_default(),
+        }
+    }
+
+    pub fn error(status: u16, message: impl Into<String>) -> Response {
+        Response::json(status, &json!({ "error": message.into() }))
+    }
+
+    pub fn not_found() -> Response {
+        Response::error(404, "no such endpoint")
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        413 => "Payload Too Large",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+fn invalid(why: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, why)
+}
+
+// Request line, headers up to the blank line, and a Content-Length body. Only what the
+// services here need: no chunked bodies, no keep-alive.
+pub fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(invalid("malformed request line"));
+    };
+    let method = method.to_owned();
+    let path = target.split('?').next().unwrap_or(target).to_owned();
+
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            break;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("bad Content-Length"))?;
+            }
+        }
+    }
+    if length > MAX_BODY {
+        return Err(io::Error::new(io::ErrorKind::OutOfMemory, "body too large"));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Request { method, path, body })
+}
+
+pub fn write_response(writer: &mut impl Write, response: &Response) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.content_type,
+        response.body.len()
+    )?;
+    writer.write_all(&response.body)?;
+    writer.flush()
+}
+
+// What `serve` runs: a handler for each request, and a tick between them for work that
+// happens on a timer
+pub trait Service {
+    fn handle(&mut self, request: &Request) -> Response;
+
+    fn tick(&mut self) {}
+}
+
+fn handle_connection(stream: TcpStream, service: &mut impl Service) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let response = match read_request(&mut reader) {
+        Ok(request) => service.handle(&request),
+        Err(e) if e.kind() == io::ErrorKind::OutOfMemory => Response::error(413, e.to_string()),
+        Err(e) => Response::error(400, e.to_string()),
+    };
+    write_response(&mut &stream, &response)
+}
+
+// Answer requests on `addr` one at a time until a signal asks the program to stop. One at
+// a time keeps handlers that move coins from racing each other.
+pub fn serve(addr: SocketAddr, service: &mut impl Service) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    listener.set_nonblocking(true)?;
+    println!("Listening on http://{}", listener.local_addr()?);
+    while !shutdown::requested() {
+        service.tick();
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                if let Err(e) = handle_connection(stream, service) {
+                    eprintln!("Request from {peer} failed: {e}");
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(ACCEPT_POLL),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_requests_and_writes_responses() {
+        let raw = "POST /send?x=1 HTTP/1.1\r\nHost: localhost\r\ncontent-length: 2\r\n\r\n{}";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: "POST".into(),
+                path: "/send".into(),
+                body: b"{}".to_vec(),
+            }
+        );
+        assert!(read_request(&mut "\r\n".as_bytes()).is_err());
+
+        let mut written = vec![];
+        write_response(&mut written, &Response::error(429, "slow down")).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+        assert!(written.ends_with("\r\n\r\n{\"error\":\"slow down\"}"));
+    }
+}
@@ -0,0 +1,211 @@
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::hex::FromHex;
+use bitcoincore_rpc::bitcoin::{Address, Amount, OutPoint, ScriptBuf, Transaction};
+use bitcoincore_rpc::{Client, RpcApi};
+use builder::{ChangeControl, SequencePolicy};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+pub mod amount;
+pub mod audit;
+pub mod batcher;
+pub mod bench;
+pub mod bip322;
+pub mod blockscan;
+pub mod builder;
+pub mod chain;
+pub mod coinjoin;
+pub mod coins;
+pub mod coldhot;
+pub mod compat;
+pub mod config;
+pub mod consensus;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod descriptor;
+pub mod error;
+pub mod faucet;
+pub mod flow;
+pub mod gap;
+pub mod graph;
+pub mod hd;
+pub mod health;
+pub mod hooks;
+pub mod http;
+pub mod mempool;
+pub mod multichain;
+pub mod multihop;
+pub mod passphrase;
+pub mod payjoin;
+pub mod pool;
+pub mod recover;
+pub mod rejects;
+pub mod report;
+pub mod resume;
+pub mod rpc;
+pub mod scenario;
+pub mod script;
+pub mod shutdown;
+pub mod sighash;
+pub mod snapshot;
+pub mod store;
+pub mod stress;
+pub mod sweep;
+pub mod template;
+pub mod trace;
+pub mod traffic;
+pub mod waiter;
+pub mod wallet;
+pub mod walletless;
+pub mod work;
+
+pub use flow::{Flow, FlowBuilder, FlowOutcome};
+
+// Node access params
+pub const RPC_URL: &str = "http://127.0.0.1:18443"; // Default regtest RPC port
+pub const RPC_USER: &str = "alice";
+pub const RPC_PASS: &str = "password";
+
+// You can use calls not provided in RPC lib API using the generic `call` function.
+// An example of using the `send` RPC call, which doesn't have exposed API.
+// You can also use serde_json `Deserialize` derivation to capture the returned json result.
+fn send(
+    rpc: &impl RpcApi,
+    addr: &str,
+    amt: Amount,
+    inputs: &[OutPoint],
+    policy: &SequencePolicy,
+    fee_rate: Option<f64>,
+) -> error::Result<String> {
+    let args = send_args(addr, amt, inputs, policy, fee_rate);
+
+    // Left unsigned the wallet returns the PSBT instead of a txid
+    #[derive(Deserialize)]
+    struct SendResult {
+        complete: bool,
+        txid: Option<String>,
+    }
+    let send_result = rpc.call::<SendResult>("send", &args)?;
+    match send_result.txid {
+        Some(txid) if send_result.complete => Ok(txid),
+        _ => Err(unsigned_send().into()),
+    }
+}
+
+// `send` couldn't sign every input, e.g. keys the wallet doesn't have or a locked wallet
+fn unsigned_send() -> builder::BuildError {
+    builder::BuildError::Incomplete(vec![
+        "the wallet's send left the transaction unsigned".into()
+    ])
+}
+
+// Like `send`, but the wallet only funds and signs: the transaction comes back instead of
+// being broadcast, for checks before it goes out
+fn compose(
+    rpc: &impl RpcApi,
+    addr: &str,
+    amt: Amount,
+    inputs: &[OutPoint],
+    policy: &SequencePolicy,
+    change: &ChangeControl,
+) -> error::Result<Transaction> {
+    let mut args = send_args(addr, amt, inputs, policy, None);
+    args[4]["add_to_wallet"] = json!(false);
+    change_options(&mut args[4], change, 1);
+
+    #[derive(Deserialize)]
+    struct SendResult {
+        complete: bool,
+        hex: Option<String>,
+    }
+    let send_result = rpc.call::<SendResult>("send", &args)?;
+    let hex = match send_result.hex {
+        Some(hex) if send_result.complete => hex,
+        _ => return Err(unsigned_send().into()),
+    };
+    let bytes = Vec::<u8>::from_hex(&hex).map_err(bitcoincore_rpc::Error::from)?;
+    Ok(encode::deserialize(&bytes).map_err(bitcoincore_rpc::Error::from)?)
+}
+
+// The `send` options for `change` among `payments` outputs. The wallet won't take an address
+// type along with an explicit address, the address wins.
+fn change_options(options: &mut Value, change: &ChangeControl, payments: usize) {
+    match (&change.address, change.address_type) {
+        (Some(address), _) => options["change_address"] = json!(address),
+        (None, Some(address_type)) => options["change_type"] = json!(address_type),
+        (None, None) => {}
+    }
+    if let Some(position) = change.position(payments) {
+        options["change_position"] = json!(position);
+    }
+}
+
+fn send_args(
+    addr: &str,
+    amt: Amount,
+    inputs: &[OutPoint],
+    policy: &SequencePolicy,
+    fee_rate: Option<f64>,
+) -> [Value; 5] {
+    let inputs: Vec<_> = inputs
+        .iter()
+        .map(|o| match policy.sequence_for(o) {
+            Some(sequence) => json!({"txid": o.txid, "vout": o.vout, "sequence": sequence.0}),
+            None => json!({"txid": o.txid, "vout": o.vout}),
+        })
+        .collect();
+    let mut options = json!({ "inputs": inputs }); // Spend exactly these inputs
+    if let Some(rbf) = policy.rbf {
+        options["replaceable"] = json!(rbf);
+    }
+    [
+        json!([{addr : amt.to_float_in(bitcoincore_rpc::bitcoin::Denomination::Bitcoin) }]), // recipient address
+        json!(null),     // conf target
+        json!(null),     // estimate mode
+        json!(fee_rate), // fee rate in sats/vb, null lets the wallet estimate
+        options,
+    ]
+}
+
+// e1ec30: A little helper to convert a script to an address
+fn script_to_addr(script: &ScriptBuf) -> Address {
+    Address::from_script(script, config::active().network).unwrap()
+}
+
+// e1ec30: Create a new rpc client each time I need to do something at a specific url
+pub fn get_client_at_url(url: &str) -> bitcoincore_rpc::Result<Client> {
+    let profile = config::active();
+    let new_url = format!("{}{url}", profile.url);
+    rpc::connect(&new_url, profile.auth())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+
+    #[test]
+    fn an_unsigned_send_is_an_error() {
+        let addr = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+        let input = [OutPoint::null()];
+        let policy = SequencePolicy::default();
+        let psbt = json!({"complete": false, "psbt": "cHNidP8BAAoCAAAAAAAAAAAAAA=="});
+
+        let rpc = MockClient::new().returns("send", psbt.clone());
+        let err = send(&rpc, addr, Amount::ONE_BTC, &input, &policy, None).unwrap_err();
+        assert!(matches!(
+            err,
+            error::Error::Build(builder::BuildError::Incomplete(_))
+        ));
+        rpc.assert_done();
+
+        let rpc = MockClient::new().returns("send", psbt);
+        let change = ChangeControl::default();
+        let err = compose(&rpc, addr, Amount::ONE_BTC, &input, &policy, &change).unwrap_err();
+        assert!(
+            err.to_string().contains("left the transaction unsigned"),
+            "{err}"
+        );
+        rpc.assert_done();
+    }
+}
@@ -1,193 +1,1055 @@
-#![allow(unused)]
-use bitcoin::hex::DisplayHex;
-use bitcoincore_rpc::bitcoin::key::rand::seq;
-use bitcoincore_rpc::bitcoin::key::Secp256k1;
-use bitcoincore_rpc::bitcoin::{
-    hex, Address, Amount, BlockHash, Network, PublicKey, ScriptBuf, Transaction, Txid,
+use bitcoincore_rpc::bitcoin::FeeRate;
+use bitcoincore_rpc::{Client, RpcApi};
+use clap::Parser;
+use cli::{
+    Cli, Command, ExperimentKind, GraphFormat, MempoolAction, ReportAction, SnapshotKind,
+    TemplateAction, Toggle, UtxoAction,
 };
-use bitcoincore_rpc::json::LoadWalletResult;
-use bitcoincore_rpc::{Auth, Client, RpcApi};
-use serde::Deserialize;
-use serde_json::json;
-use std::error::Error;
-use std::fmt;
-use std::fs::File;
-use std::io::Write;
-use std::ops::Add;
-use std::str::FromStr;
-
-// Node access params
-const RPC_URL: &str = "http://127.0.0.1:18443"; // Default regtest RPC port
-const RPC_USER: &str = "alice";
-const RPC_PASS: &str = "password";
-
-// You can use calls not provided in RPC lib API using the generic `call` function.
-// An example of using the `send` RPC call, which doesn't have exposed API.
-// You can also use serde_json `Deserialize` derivation to capture the returned json result.
-fn send(
-    rpc: &Client,
-    addr: &str,
-    amt: Amount,
-    txid: &str,
-    vout: u32,
-) -> bitcoincore_rpc::Result<String> {
-    let args = [
-        json!([{addr : amt.to_float_in(bitcoincore_rpc::bitcoin::Denomination::Bitcoin) }]), // recipient address
-        json!(null),                                     // conf target
-        json!(null),                                     // estimate mode
-        json!(null),                                     // fee rate in sats/vb
-        json!({"inputs": [{"txid":txid, "vout":vout}]}), // Empty option object
-    ];
-
-    #[derive(Deserialize)]
-    struct SendResult {
-        complete: bool,
-        txid: String,
+use rust::batcher::{BatchOptions, BatchService};
+use rust::builder::{Budget, ChangeControl, SequencePolicy};
+use rust::coins::{self, CoinControl};
+use rust::compat::Compat;
+use rust::descriptor::{self, Checksum};
+use rust::error::{Error, FailureReport};
+use rust::faucet::{self, Faucet, FaucetOptions};
+use rust::hd::{AccountManager, KeyChain, Purpose};
+use rust::health::Monitor;
+use rust::mempool::Package;
+use rust::report::{self, FeeDisplay, ReportFormat, TxReport};
+use rust::resume::{Checkpoint, DEFAULT_CHECKPOINT};
+use rust::rpc::{self, CachingClient};
+use rust::scenario::{Scenario, ScenarioError};
+use rust::shutdown::{self, Phase, RunStatus};
+use rust::stress::{self, StressConfig};
+use rust::trace::Tracer;
+use rust::traffic::{
+    AmountDist, AmountKind, ArrivalDist, ArrivalKind, FeeRateDist, FeeRateKind, Traffic,
+};
+use rust::{
+    audit, bench, blockscan, config, gap, get_client_at_url, graph, http, multichain, multihop,
+    payjoin, pool, recover, rejects, sighash, snapshot, sweep, template, wallet, walletless, work,
+};
+use rust::{Flow, FlowOutcome};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+mod cli;
+
+fn main() -> ExitCode {
+    if let Err(e) = shutdown::install() {
+        eprintln!("Signals will not stop the run cleanly: {e}");
+    }
+    let cli = Cli::parse();
+    let failure_report = cli.failure_report.clone();
+    match try_main(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            match &e {
+                Error::Aborted { .. } => eprintln!("{e}"),
+                _ => eprintln!("Error: {e:?}"),
+            }
+            if let Some(path) = failure_report {
+                if let Err(e) = report::write_json(&path, &FailureReport::new(&e)) {
+                    eprintln!("{e}");
+                }
+            }
+            ExitCode::from(e.exit_code())
+        }
     }
-    let send_result = rpc.call::<SendResult>("send", &args)?;
-    assert!(send_result.complete);
-    Ok(send_result.txid)
 }
 
-// e1ec30: A little helper to convert a script to an address
-fn script_to_addr(script: &ScriptBuf) -> Address {
-    Address::from_script(script, Network::Regtest).unwrap()
+fn try_main(cli: Cli) -> Result<(), Error> {
+    config::select(cli.config.as_deref(), cli.profile.as_deref())?;
+
+    if let Some(path) = &cli.trace_rpc {
+        rpc::trace_to(path)?;
+    }
+
+    audit::log_to(&cli.audit_log);
+
+    // Nothing to ask the node for, so it doesn't have to be up
+    if let Some(Command::VerifyOwnership { report }) = &cli.command {
+        return verify_ownership(report);
+    }
+    if let Some(Command::AuditLog { wallet }) = &cli.command {
+        return show_audit_log(&cli.audit_log, wallet.as_deref());
+    }
+    if let Some(Command::Report {
+        action: ReportAction::Decode { path, validate },
+    }) = &cli.command
+    {
+        return decode_report(path, *validate);
+    }
+
+    // Connect to Bitcoin Core RPC
+    let rpc = get_client_at_url("")?;
+    let compat = Compat::detect(&rpc)?;
+
+    let result = match cli.command {
+        None => run(
+            &rpc,
+            &Flow::builder()
+                .checkpoint(Path::new(DEFAULT_CHECKPOINT))
+                .build(),
+            &Output::default(),
+        ),
+        Some(Command::Send {
+            from,
+            to,
+            amount,
+            spend_only,
+            avoid,
+            manual,
+            rbf,
+            sequence,
+            max_vsize,
+            max_inputs,
+            max_fee,
+            privacy,
+            fee_display,
+            template,
+            report,
+            report_format,
+            validate,
+            prove_ownership,
+            label,
+            exact_amounts,
+            change_type,
+            change_address,
+            random_change_position,
+        }) => {
+            let change_address = change_address
+                .map(|a| a.require_network(config::active().network))
+                .transpose()
+                .map_err(|e| bitcoincore_rpc::Error::ReturnedError(e.to_string()))?;
+            run(
+                &rpc,
+                &Flow::builder()
+                    .miner(&from)
+                    .trader(&to)
+                    .amount(amount)
+                    .coin_control(CoinControl {
+                        spend_only,
+                        avoid,
+                        ..Default::default()
+                    })
+                    .privacy(privacy)
+                    .prove_ownership(prove_ownership)
+                    .label(label.as_deref())
+                    .exact_amounts(exact_amounts)
+                    .change(ChangeControl {
+                        address_type: change_type.map(Into::into),
+                        address: change_address,
+                        random_position: random_change_position,
+                    })
+                    .checkpoint(Path::new(DEFAULT_CHECKPOINT))
+                    .manual(manual)
+                    .sequences(SequencePolicy {
+                        rbf: rbf.map(|rbf| rbf == Toggle::On),
+                        sequences: sequence,
+                    })
+                    .budget(Budget {
+                        max_vsize,
+                        max_inputs,
+                        max_fee,
+                    })
+                    .build(),
+                &Output {
+                    fee_display,
+                    report,
+                    report_format,
+                    validate,
+                    template,
+                },
+            )
+        }
+        Some(Command::VerifyOwnership { .. }) => unreachable!("verified before connecting"),
+        Some(Command::AuditLog { .. }) => unreachable!("shown before connecting"),
+        Some(Command::Report { .. }) => unreachable!("decoded before connecting"),
+        Some(Command::Resume {
+            checkpoint,
+            fee_display,
+            template,
+            report,
+            report_format,
+            validate,
+        }) => resume(
+            &rpc,
+            &checkpoint,
+            &Output {
+                fee_display,
+                report,
+                report_format,
+                validate,
+                template,
+            },
+        ),
+        Some(Command::Scenario { path, report }) => {
+            let outcome = Scenario::from_file(&path)?.run(&rpc)?;
+            if let Some(report) = report {
+                report::write_json(&report, &outcome)?;
+            }
+            if outcome.status == RunStatus::Aborted {
+                Err(Error::Aborted {
+                    phase: shutdown::phase(),
+                })
+            } else if outcome.passed {
+                Ok(())
+            } else {
+                Err(ScenarioError::Failed.into())
+            }
+        }
+        Some(Command::MultiHop { via, report }) => {
+            let hops: Vec<&str> = via.iter().map(String::as_str).collect();
+            let lineage = multihop::run(&rpc, &hops)?;
+            println!("Total fees along the chain: {}", lineage.total_fees());
+            if let Some(report) = report {
+                report::write_json(&report, &lineage)?;
+            }
+            Ok(())
+        }
+        Some(Command::PoolPayout {
+            blocks,
+            to,
+            amount,
+            report,
+        }) => {
+            let payout = pool::run(&rpc, blocks, &to, amount)?;
+            println!(
+                "Paid {} from the pool payout to {} in {}",
+                payout.amount, payout.recipient, payout.txid
+            );
+            if let Some(report) = report {
+                report::write_json(&report, &payout)?;
+            }
+            Ok(())
+        }
+        Some(Command::Payjoin {
+            from,
+            to,
+            amount,
+            report,
+        }) => {
+            let payjoin = payjoin::run(&rpc, &from, &to, amount)?;
+            println!(
+                "Paid {} from {from} to {to} in {} ({} inputs, {to}'s output holds {}), the original was {}",
+                payjoin.payment,
+                payjoin.txid,
+                payjoin.inputs.len(),
+                payjoin.receiver_output,
+                payjoin.original_txid
+            );
+            if let Some(report) = report {
+                report::write_json(&report, &payjoin)?;
+            }
+            Ok(())
+        }
+        Some(Command::Walletless {
+            amount,
+            report,
+            accounts,
+        }) => {
+            let mut accounts = accounts
+                .map(|path| AccountManager::open(&path, config::active().network))
+                .transpose()?;
+            let payment = walletless::run(&rpc, amount, accounts.as_mut())?;
+            println!(
+                "Paid {} from {} to {} in {}, confirmed at height {}",
+                payment.payment,
+                payment.sender_address,
+                payment.recipient_address,
+                payment.txid,
+                payment.block_height
+            );
+            if let Some(report) = report {
+                report::write_json(&report, &payment)?;
+            }
+            Ok(())
+        }
+        Some(Command::Accounts {
+            state,
+            recover,
+            gap_limit,
+        }) => {
+            let mut accounts = AccountManager::open(&state, config::active().network)?;
+            if recover {
+                // The accounts wallet-less mode uses, plus any the state already knows
+                let mut known = vec![(Purpose::Bip84, 0), (Purpose::Bip84, 1)];
+                for a in &accounts.state().accounts {
+                    if !known.contains(&(a.purpose, a.account)) {
+                        known.push((a.purpose, a.account));
+                    }
+                }
+                for scan in accounts.recover(&rpc, &compat, &known, gap_limit)? {
+                    println!(
+                        "{}: {} used address(es) via {:?}",
+                        scan.descriptor,
+                        scan.used.len(),
+                        scan.method
+                    );
+                }
+            }
+            for a in &accounts.state().accounts {
+                for chain in [KeyChain::Receive, KeyChain::Change] {
+                    let next = match chain {
+                        KeyChain::Receive => a.next_receive,
+                        KeyChain::Change => a.next_change,
+                    };
+                    println!(
+                        "{} next {chain} index {next}",
+                        accounts.descriptor(a.purpose, a.account, chain)?
+                    );
+                }
+            }
+            Ok(())
+        }
+        Some(Command::GapScan {
+            descriptor,
+            gap_limit,
+            chunk_size,
+        }) => {
+            let scan = gap::scan(&rpc, &compat, &descriptor, gap_limit, chunk_size)?;
+            match scan.highest_used {
+                Some(index) => println!(
+                    "{} used address(es), highest index {index}, {} checked via {:?}",
+                    scan.used.len(),
+                    scan.scanned,
+                    scan.method
+                ),
+                None => println!("No used addresses in the first {}", scan.scanned),
+            }
+            Ok(())
+        }
+        Some(Command::Descriptor {
+            descriptor,
+            check,
+            local,
+        }) => {
+            let status = descriptor::check(&descriptor)?;
+            match &status {
+                Checksum::Valid => println!("Checksum: valid"),
+                Checksum::Missing { expected } => {
+                    println!("Checksum: missing, should be #{expected}")
+                }
+                Checksum::Wrong { found, expected } => {
+                    println!("Checksum: #{found} is wrong, should be #{expected}")
+                }
+            }
+            if check {
+                return match status {
+                    Checksum::Valid => Ok(()),
+                    _ => Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                        "{descriptor} has no valid checksum"
+                    ))
+                    .into()),
+                };
+            }
+            if local {
+                println!("{}", descriptor::with_checksum(&descriptor)?);
+            } else {
+                println!("{}", descriptor::normalize(&rpc, &descriptor)?);
+            }
+            Ok(())
+        }
+        Some(Command::Recover {
+            wallet,
+            descriptor,
+            gap_limit,
+            report,
+        }) => {
+            let outcome = recover::run(&rpc, &compat, &wallet, &descriptor, gap_limit)?;
+            for d in &outcome.descriptors {
+                println!("{} up to index {}", d.descriptor, d.range.1);
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &outcome)?;
+            }
+            if outcome.matches {
+                println!(
+                    "{} recovered into {} with all {}",
+                    outcome.original, outcome.recovered, outcome.recovered_balance
+                );
+                Ok(())
+            } else {
+                Err(Error::RecoveryMismatch {
+                    wallet: outcome.original,
+                    expected: outcome.original_balance,
+                    recovered: outcome.recovered_balance,
+                })
+            }
+        }
+        Some(Command::Trace { outpoint, depth }) => {
+            let cached = CachingClient::new(rpc);
+            let mut tracer = Tracer::new(&cached, depth);
+            println!("Origins:\n{}", tracer.backward(outpoint)?);
+            println!("Spends:\n{}", tracer.forward(outpoint)?);
+            let (hits, misses) = cached.stats();
+            println!("({hits} lookups served from cache, {misses} fetched from the node)");
+            Ok(())
+        }
+        Some(Command::Graph {
+            from_height,
+            to_height,
+            format,
+            output,
+        }) => {
+            let graph = graph::build(&CachingClient::new(rpc), from_height, to_height)?;
+            let rendered = match format {
+                GraphFormat::Dot => graph.to_dot(),
+                GraphFormat::Graphml => graph.to_graphml(),
+            };
+            match output {
+                Some(path) => report::write_text(&path, &rendered)?,
+                None => print!("{rendered}"),
+            }
+            Ok(())
+        }
+        Some(Command::Locks { wallet, clear }) => {
+            let wallet_rpc = get_client_at_url(&format!("/wallet/{wallet}"))?;
+            let locked = coins::list_locked(&wallet_rpc)?;
+            if clear {
+                wallet_rpc.unlock_unspent_all()?;
+                println!("Unlocked {} output(s) in {wallet}", locked.len());
+            } else if locked.is_empty() {
+                println!("No locked outputs in {wallet}");
+            } else {
+                for outpoint in locked {
+                    println!("{outpoint}");
+                }
+            }
+            Ok(())
+        }
+        Some(Command::Utxos {
+            wallet,
+            fee_rate,
+            report,
+        }) => {
+            let wallet_rpc = get_client_at_url(&format!("/wallet/{wallet}"))?;
+            let fee_rate = match fee_rate {
+                Some(rate) => FeeRate::from_sat_per_vb(rate).ok_or_else(|| {
+                    bitcoincore_rpc::Error::ReturnedError(format!("fee rate {rate} is too high"))
+                })?,
+                None => coins::current_fee_rate(&wallet_rpc)?,
+            };
+            let utxos = coins::utxo_report(&wallet_rpc, fee_rate)?;
+            for utxo in &utxos {
+                println!(
+                    "{} {} ({} conf){}",
+                    utxo.outpoint,
+                    utxo.amount,
+                    utxo.confirmations,
+                    if utxo.uneconomical {
+                        "  uneconomical"
+                    } else {
+                        ""
+                    }
+                );
+            }
+            let uneconomical = utxos.iter().filter(|u| u.uneconomical).count();
+            println!(
+                "{} UTXO(s), {uneconomical} not worth spending at {} sat/vB",
+                utxos.len(),
+                fee_rate.to_sat_per_vb_ceil()
+            );
+            if let Some(report) = report {
+                report::write_json(&report, &utxos)?;
+            }
+            Ok(())
+        }
+        Some(Command::Stress {
+            senders,
+            rate,
+            duration,
+            amount,
+            amounts,
+            sigma,
+            arrivals,
+            fee_rates,
+            fee_low,
+            fee_high,
+            fee_high_share,
+            seed,
+            report,
+        }) => {
+            let traffic = Traffic {
+                amounts: match amounts {
+                    AmountKind::Fixed => AmountDist::Fixed(amount),
+                    AmountKind::Lognormal => AmountDist::LogNormal {
+                        median: amount,
+                        sigma,
+                    },
+                },
+                arrivals: match arrivals {
+                    ArrivalKind::Fixed => ArrivalDist::Fixed,
+                    ArrivalKind::Exponential => ArrivalDist::Exponential,
+                },
+                fee_rates: match fee_rates {
+                    FeeRateKind::Wallet => FeeRateDist::Wallet,
+                    FeeRateKind::Bimodal => FeeRateDist::Bimodal {
+                        low: fee_low,
+                        high: fee_high,
+                        high_share: fee_high_share,
+                    },
+                },
+            };
+            let config = StressConfig {
+                senders,
+                rate,
+                duration: Duration::from_secs(duration),
+                traffic,
+                seed,
+            };
+            let results = stress::run(&rpc, &config)?;
+            for sender in &results.senders {
+                println!(
+                    "{}: {} sent, {} failed",
+                    sender.wallet, sender.sent, sender.failed
+                );
+                for (error, count) in &sender.errors {
+                    println!("    {count}x {error}");
+                }
+            }
+            println!(
+                "{} tx in {:.1}s: {:.2} tx/s, {:.1}% errors",
+                results.sent,
+                results.elapsed_secs,
+                results.tps,
+                results.error_rate * 100.0
+            );
+            if let Some(report) = report {
+                report::write_json(&report, &results)?;
+            }
+            match results.status {
+                RunStatus::Completed => Ok(()),
+                RunStatus::Aborted => Err(Error::Aborted {
+                    phase: shutdown::phase(),
+                }),
+            }
+        }
+        Some(Command::BlockScan {
+            from,
+            to,
+            compare,
+            report,
+        }) => {
+            let to = match to {
+                Some(to) => to,
+                None => rpc.get_block_count()?,
+            };
+            let from = from.unwrap_or((to + 1).saturating_sub(blockscan::DEFAULT_BLOCKS));
+            let scan = blockscan::run(&rpc, from, to, compare)?;
+            for run in std::iter::once(&scan.streaming).chain(&scan.per_tx) {
+                println!(
+                    "{:?}: {} block(s), {} transaction(s) in {:.3}s ({:.0} tx/s, {} RPC calls)",
+                    run.method,
+                    run.blocks,
+                    run.transactions,
+                    run.elapsed_secs,
+                    run.tx_per_sec,
+                    run.rpc_calls
+                );
+            }
+            if let (Some(speedup), Some(matches)) = (scan.speedup, scan.summaries_match) {
+                println!(
+                    "Streaming was {speedup:.1}x as fast, summaries {}",
+                    if matches { "match" } else { "DIFFER" }
+                );
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &scan)?;
+            }
+            match scan.summaries_match {
+                Some(false) => Err(bitcoincore_rpc::Error::ReturnedError(
+                    "the two fetch methods disagree about the blocks".to_owned(),
+                )
+                .into()),
+                _ => Ok(()),
+            }
+        }
+        Some(Command::Chainwork { window, report }) => {
+            let stats = work::report(&rpc, window)?;
+            println!("Tip {} at height {}", stats.tip_hash, stats.tip_height);
+            println!(
+                "Difficulty {} (bits {}, target {})",
+                stats.difficulty, stats.bits, stats.target
+            );
+            println!(
+                "Chain work 0x{}, 0x{} of it in blocks {}..={}{}",
+                stats.chainwork,
+                stats.window_work,
+                stats.from_height,
+                stats.tip_height,
+                if stats.chainwork_matches {
+                    ""
+                } else {
+                    " (does not add up with the node's)"
+                }
+            );
+            if let Some(average) = stats.intervals.average_interval {
+                println!(
+                    "Block interval {average:.1}s on average, {}s to {}s",
+                    stats.intervals.min_interval.unwrap_or_default(),
+                    stats.intervals.max_interval.unwrap_or_default()
+                );
+            }
+            if let Some(hashrate) = stats.estimated_hashrate {
+                println!("Estimated hashrate {hashrate:.1} H/s");
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &stats)?;
+            }
+            Ok(())
+        }
+        Some(Command::Bench {
+            wallet,
+            transactions,
+            iterations,
+            report,
+        }) => {
+            let wallet = get_client_at_url(&format!("/wallet/{wallet}"))?;
+            let (sample, addresses) = bench::wallet_sample(&wallet, transactions)?;
+            let timed = bench::run(&wallet, &sample, &addresses, iterations)?;
+            println!(
+                "{} transaction(s), {} address(es), {} iteration(s)",
+                timed.transactions, timed.addresses, timed.iterations
+            );
+            for result in &timed.results {
+                println!(
+                    "  {:<10} {:<8?} {:>9.3}ms mean, {:>9.3}ms first",
+                    result.check,
+                    result.strategy,
+                    result.mean_secs * 1000.0,
+                    result.first_secs * 1000.0
+                );
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &timed)?;
+            }
+            if timed.consistent {
+                Ok(())
+            } else {
+                Err(bitcoincore_rpc::Error::ReturnedError(
+                    "the strategies came to different answers".to_owned(),
+                )
+                .into())
+            }
+        }
+        Some(Command::MultiChain {
+            flow,
+            lookup,
+            report,
+        }) => {
+            let config = config::load(cli.config.as_deref())?;
+            let mut targets = vec![];
+            if flow {
+                targets.push(multichain::Target {
+                    profile: config.profile_name(cli.profile.as_deref()).to_owned(),
+                    job: multichain::Job::Flow,
+                });
+            }
+            for (profile, txid) in lookup {
+                targets.push(multichain::Target {
+                    profile,
+                    job: multichain::Job::Lookup(txid),
+                });
+            }
+            let combined = multichain::run(&config, &targets)?;
+            for chain in &combined.chains {
+                let what = match (&chain.flow, &chain.lookup, &chain.error) {
+                    (_, _, Some(e)) => format!("failed: {e}"),
+                    (Some(flow), _, _) => format!("flow paid in {}", flow.txid),
+                    (_, Some(found), _) => format!(
+                        "{} with {} confirmation(s)",
+                        found.txid, found.confirmations
+                    ),
+                    _ => "nothing to do".to_owned(),
+                };
+                println!(
+                    "{} ({}, height {}): {what}",
+                    chain.profile,
+                    chain.chain.as_deref().unwrap_or("unreachable"),
+                    chain
+                        .tip_height
+                        .map_or_else(|| "?".to_owned(), |h| h.to_string())
+                );
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &combined)?;
+            }
+            if combined.complete {
+                Ok(())
+            } else {
+                Err(bitcoincore_rpc::Error::ReturnedError(
+                    "not every chain could be reported on".to_owned(),
+                )
+                .into())
+            }
+        }
+        Some(Command::FuzzRejects { report }) => {
+            let checked = rejects::run(&rpc)?;
+            for case in &checked.cases {
+                println!(
+                    "  {} {:?}: {}",
+                    if case.passed { "PASS" } else { "FAIL" },
+                    case.case,
+                    match (&case.reject_reason, case.accepted) {
+                        (_, true) => "accepted".to_owned(),
+                        (Some(reason), false) => format!("rejected, {reason}"),
+                        (None, false) => "rejected".to_owned(),
+                    }
+                );
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &checked)?;
+            }
+            if checked.passed {
+                Ok(())
+            } else {
+                Err(bitcoincore_rpc::Error::ReturnedError(
+                    "the node's verdicts did not all match the expected ones".to_owned(),
+                )
+                .into())
+            }
+        }
+        Some(Command::Experiment {
+            kind: ExperimentKind::Sighash { report },
+        }) => {
+            let experiment = sighash::run(&rpc)?;
+            for flag in &experiment.flags {
+                println!("{} ({}):", flag.flag, flag.sighash_byte);
+                for change in &flag.changes {
+                    println!(
+                        "  {:<26} signature {}, node {}{}",
+                        change.description,
+                        if change.signature_valid {
+                            "holds"
+                        } else {
+                            "broken"
+                        },
+                        if change.accepted {
+                            "accepts"
+                        } else {
+                            "rejects"
+                        },
+                        change
+                            .reject_reason
+                            .as_ref()
+                            .map(|r| format!(" ({r})"))
+                            .unwrap_or_default()
+                    );
+                }
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &experiment)?;
+            }
+            if experiment.passed {
+                Ok(())
+            } else {
+                Err(bitcoincore_rpc::Error::ReturnedError(
+                    "a sighash flag did not commit to what it should".to_owned(),
+                )
+                .into())
+            }
+        }
+        Some(Command::Experiment {
+            kind: ExperimentKind::Sweep { path, csv },
+        }) => {
+            let experiment = sweep::load(&path)?;
+            if let Some(name) = &experiment.name {
+                println!("Sweep: {name}");
+            }
+            let runs = sweep::run(&rpc, &experiment)?;
+            report::write_text(&csv, &sweep::to_csv(&runs))?;
+            let failed = runs.iter().filter(|run| run.outcome.is_err()).count();
+            println!(
+                "{} run(s), {failed} failed, written to {}",
+                runs.len(),
+                csv.display()
+            );
+            Ok(())
+        }
+        Some(Command::Template {
+            action: TemplateAction::Check { report },
+        }) => {
+            let checked = template::check(&template::fetch(&rpc)?);
+            println!(
+                "Template for height {}: {} transaction(s), {} in fees",
+                checked.height, checked.transactions, checked.fees
+            );
+            for check in &checked.checks {
+                println!(
+                    "  {} {}: {}",
+                    if check.passed { "PASS" } else { "FAIL" },
+                    check.check,
+                    check.detail
+                );
+            }
+            if let Some(report) = report {
+                report::write_json(&report, &checked)?;
+            }
+            if checked.passed {
+                Ok(())
+            } else {
+                Err(bitcoincore_rpc::Error::ReturnedError(
+                    "the block template failed its checks".to_owned(),
+                )
+                .into())
+            }
+        }
+        #[cfg(feature = "tui")]
+        Some(Command::Dashboard { flow }) => {
+            let task = flow.then_some(|| {
+                let rpc = get_client_at_url("")?;
+                run(
+                    &rpc,
+                    &Flow::builder().quiet(true).build(),
+                    &Output::default(),
+                )
+            });
+            rust::dashboard::show(&rpc, task)
+        }
+        Some(Command::Snapshot {
+            kind: SnapshotKind::Utxo { action },
+        }) => match action {
+            UtxoAction::Export { path, height } => {
+                let dump = snapshot::export_utxo_set(&rpc, &compat, &path, height)?;
+                println!(
+                    "Wrote {} coins at height {} ({}) to {}",
+                    dump.coins_written, dump.base_height, dump.base_hash, dump.path
+                );
+                println!("txoutset_hash: {}", dump.txoutset_hash);
+                Ok(())
+            }
+            UtxoAction::Import { path } => {
+                let load = snapshot::import_utxo_set(&rpc, &compat, &path)?;
+                println!(
+                    "Loaded {} coins from {}, snapshot tip {} at height {}",
+                    load.coins_loaded, load.path, load.tip_hash, load.base_height
+                );
+                Ok(())
+            }
+        },
+        Some(Command::Faucet {
+            listen,
+            wallet,
+            max_amount,
+            cooldown,
+            confirmations,
+        }) => {
+            faucet::check_network()?;
+            let options = FaucetOptions {
+                max_amount,
+                cooldown: Duration::from_secs(cooldown),
+                confirmations,
+            };
+            let mut faucet = Faucet::new(wallet::open(&rpc, &wallet)?, options);
+            http::serve(listen, &mut faucet)?;
+            Ok(())
+        }
+        Some(Command::Batcher {
+            listen,
+            wallet,
+            max_payments,
+            interval,
+            report,
+        }) => {
+            let options = BatchOptions {
+                max_payments,
+                interval: Duration::from_secs(interval),
+            };
+            let mut service = BatchService::new(wallet::open(&rpc, &wallet)?, options);
+            http::serve(listen, &mut service)?;
+            service.flush()?;
+            if let Some(report) = report {
+                report::write_json(&report, &service.batches)?;
+            }
+            Ok(())
+        }
+        Some(Command::Monitor {
+            listen,
+            nodes,
+            interval,
+        }) => {
+            let config = config::load(cli.config.as_deref())?;
+            let names = if nodes.is_empty() {
+                vec![config.profile_name(cli.profile.as_deref()).to_owned()]
+            } else {
+                nodes
+            };
+            let mut clients = vec![];
+            for name in names {
+                let profile = config.profile(Some(&name))?;
+                let client = rpc::connect(&profile.url, profile.auth())?;
+                clients.push((name, profile.network, client));
+            }
+            let mut monitor = Monitor::new(clients, Duration::from_secs(interval));
+            http::serve(listen, &mut monitor)?;
+            Ok(())
+        }
+        Some(Command::ImportFunds {
+            txid,
+            wallet: name,
+            block_hash,
+        }) => {
+            let discovery = compat.discovery(&rpc)?;
+            let wallet_rpc = wallet::open(&rpc, &name)?;
+            wallet::learn_transaction(&rpc, &wallet_rpc, &name, &txid, block_hash, discovery)?;
+            println!("{name} picked up {txid} with {discovery}");
+            Ok(())
+        }
+        Some(Command::Mempool {
+            action: MempoolAction::Tree { txid },
+        }) => {
+            print!("{}", Package::fetch(&rpc, &txid)?.render());
+            Ok(())
+        }
+    };
+    // e1ec30: Show what the node actually said, the parsed error often hides it
+    if result.is_err() {
+        if let Some(raw) = rpc::last_response() {
+            eprintln!("Last response from the node: {raw}");
+        }
+    }
+    result
+}
+
+fn show_audit_log(path: &Path, wallet: Option<&str>) -> Result<(), Error> {
+    let entries = audit::read(path)?;
+    let mut broken = 0;
+    for entry in &entries {
+        let record = &entry.record;
+        if !entry.intact {
+            broken += 1;
+            println!("  !! the record before this one is missing or was changed");
+        }
+        if wallet.is_some_and(|w| w != record.wallet) {
+            continue;
+        }
+        let signed = match &record.inputs_signed {
+            Some(inputs) => format!("{} input(s)", inputs.len()),
+            None => "inputs not reported".to_owned(),
+        };
+        println!(
+            "{} {:<12} {:<28} {} {signed}{}",
+            record.timestamp,
+            if record.wallet.is_empty() {
+                "(default)"
+            } else {
+                &record.wallet
+            },
+            record.method,
+            record.txid.map_or("-".to_owned(), |t| t.to_string()),
+            if record.complete { "" } else { " (incomplete)" }
+        );
+        if let Some(hash) = record.psbt_sha256 {
+            println!("  psbt sha256 {hash}");
+        }
+    }
+    println!(
+        "{} signing operation(s) in {}",
+        entries.len(),
+        path.display()
+    );
+    if broken == 0 {
+        Ok(())
+    } else {
+        Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "{} has {broken} break(s) in its record chain",
+            path.display()
+        ))
+        .into())
+    }
 }
 
-// e1ec30: Check if address in script belongs to wallet
-fn is_mine(rpc: &Client, script: &ScriptBuf) -> bool {
-    let addr = script_to_addr(script);
-    rpc.get_address_info(&addr).unwrap().is_mine.unwrap()
+fn decode_report(path: &Path, validate: bool) -> Result<(), Error> {
+    let report = TxReport::from_compact(&fs::read(path)?)?;
+    let json = serde_json::to_value(report.versioned())?;
+    if validate {
+        report::validate(&json)?;
+    }
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
 }
 
-// e1ec30: Create a new rpc client each time I need to do something at a specific url
-fn get_client_at_url(url: &str) -> bitcoincore_rpc::Result<Client> {
-    let new_url = format!("{RPC_URL}{url}");
-    let client = Client::new(
-        &new_url,
-        Auth::UserPass(RPC_USER.to_owned(), RPC_PASS.to_owned()),
-    )?;
-    Ok(client)
+fn verify_ownership(path: &Path) -> Result<(), Error> {
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let checked = report::verify_ownership(&json)?;
+    if checked.is_empty() {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "{} has no ownership proofs, write it with send --prove-ownership",
+            path.display()
+        ))
+        .into());
+    }
+    for (field, valid) in &checked {
+        println!("  {} {field}", if *valid { "PASS" } else { "FAIL" });
+    }
+    if checked.iter().all(|(_, valid)| *valid) {
+        Ok(())
+    } else {
+        Err(
+            bitcoincore_rpc::Error::ReturnedError("not every ownership proof holds".to_owned())
+                .into(),
+        )
+    }
 }
 
-// e1ec30: A little helper to first try loading the wallet before creating it
-fn load_or_create_wallet(name: &str, rpc: &Client) -> bitcoincore_rpc::Result<LoadWalletResult> {
-    let wallet = rpc.load_wallet(name);
+// e1ec30: What to write besides ../out.txt, and how
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub fee_display: FeeDisplay,
+    // Copy of the report, as JSON or CBOR
+    pub report: Option<PathBuf>,
+    pub report_format: ReportFormat,
+    // Check the JSON report against its schema before writing it
+    pub validate: bool,
+    // minijinja template to lay out ../out.txt with instead of the grader's format
+    pub template: Option<PathBuf>,
+}
 
-    match wallet {
-        Ok(wallet) => Ok(wallet),
-        Err(bitcoincore_rpc::Error::JsonRpc(e))
-            if e.to_string().contains("Path does not exist") =>
-        {
-            let wallet = rpc.create_wallet(name, None, None, None, None)?;
-            Ok(wallet)
-        }
-        Err(e) => Err(e),
+fn run(rpc: &Client, flow: &Flow, output: &Output) -> Result<(), Error> {
+    let FlowOutcome { report, .. } = flow.run(rpc)?;
+    write_outputs(&report, output)?;
+    if let Some(path) = &flow.checkpoint {
+        Checkpoint::clear(path)?;
     }
+    Ok(())
 }
 
-fn main() -> bitcoincore_rpc::Result<()> {
-    // Connect to Bitcoin Core RPC
-    let rpc = Client::new(
-        RPC_URL,
-        Auth::UserPass(RPC_USER.to_owned(), RPC_PASS.to_owned()),
-    )?;
-
-    // Get blockchain info
-    let blockchain_info = rpc.get_blockchain_info()?;
-    println!("Blockchain Info: {blockchain_info:?}");
-
-    // Create/Load the wallets, named 'Miner' and 'Trader'. Have logic to optionally create/load them if they do not exist or not loaded already.
-    load_or_create_wallet("Trader", &rpc)?;
-    load_or_create_wallet("Miner", &rpc)?;
-
-    // println!("Miner wallet created: {miner_wallet:?}");
-    // println!("Trader wallet created: {trader_wallet:?}");
-
-    // Generate spendable balances in the Miner wallet. How many blocks needs to be mined?
-    let miner_wallet_rpc = get_client_at_url("/wallet/Miner")?;
-    let miner_address = miner_wallet_rpc
-        .get_new_address(None, None)?
-        .assume_checked();
-    let block = miner_wallet_rpc.generate_to_address(101, &miner_address)?;
-
-    // e1ec30: Get a single utxo that can be used in the transaction, since the tests require it
-    let unspent = miner_wallet_rpc.list_unspent(None, None, None, None, None)?;
-    let viable = unspent.iter().find(|u| u.amount.to_btc() > 20.0).unwrap();
-
-    // Load Trader wallet and generate a new address
-    let trader_wallet_rpc = get_client_at_url("/wallet/Trader")?;
-    let trader_address = trader_wallet_rpc
-        .get_new_address(None, None)?
-        .assume_checked();
-    // println!("trader_address: {trader_address}");
-
-    // Send 20 BTC from Miner to Trader
-    let txhash = send(
-        &miner_wallet_rpc,
-        &trader_address.to_string(),
-        Amount::from_int_btc(20),
-        &viable.txid.to_string(),
-        viable.vout,
-    )?;
-    // println!("Transaction Hash: {txhash}");
-
-    // Check transaction in mempool
-    let txid_transfer = Txid::from_str(&txhash).unwrap();
-    let tx_res = miner_wallet_rpc.get_transaction(&txid_transfer, None)?;
-    let fee = tx_res.fee.unwrap();
-
-    // Mine 1 block to confirm the transaction
-    let mined = miner_wallet_rpc.generate_to_address(1, &miner_address)?;
-
-    // Extract all required transaction details
-    let block = miner_wallet_rpc.get_block(&mined[0])?;
-
-    // e1ec30: Find my transaction in the block
-    let confirmed_tx = block
-        .txdata
-        .iter()
-        .find(|tx| tx.txid() == txid_transfer)
-        .unwrap();
-
-    // e1ec30: Also get the transaction containing the input I used
-    let input_tx = miner_wallet_rpc.get_raw_transaction(&viable.txid, None)?;
-
-    // e1ec30: Extract Miner's input address and amount
-    let output_spent = input_tx.output.get(viable.vout as usize).unwrap();
-    let miner_in_addr = script_to_addr(&output_spent.script_pubkey);
-    let miner_in_amount = output_spent.value.to_btc();
-
-    // e1ec30: Extract Trader's Output address and amount
-    let trader_out = confirmed_tx
-        .output
-        .iter()
-        .find(|o| is_mine(&trader_wallet_rpc, &o.script_pubkey))
-        .unwrap();
-    let trader_out_addr = script_to_addr(&trader_out.script_pubkey);
-    let trader_amount = trader_out.value.to_btc();
-
-    // e1ec30: Extract Miner's Change address and amount
-    let miner_change = confirmed_tx
-        .output
-        .iter()
-        .find(|o| is_mine(&miner_wallet_rpc, &o.script_pubkey))
-        .unwrap();
-    let miner_change_addr = script_to_addr(&miner_change.script_pubkey);
-    let miner_amount = miner_change.value.to_btc();
+// e1ec30: The end of a run that died after broadcasting, from its checkpoint
+fn resume(rpc: &Client, path: &Path, output: &Output) -> Result<(), Error> {
+    let checkpoint = Checkpoint::load(path)?;
+    println!(
+        "Resuming {} from {} to {}",
+        checkpoint.txid, checkpoint.from, checkpoint.to
+    );
+    let FlowOutcome { report, .. } = Flow::resume(rpc, &checkpoint)?;
+    write_outputs(&report, output)?;
+    Checkpoint::clear(path)?;
+    Ok(())
+}
+
+fn write_outputs(report: &TxReport, output: &Output) -> Result<(), Error> {
+    shutdown::enter(Phase::Reporting);
 
     // Write the data to ../out.txt in the specified format given in readme.md
-    let mut f = File::create("../out.txt").unwrap();
-    writeln!(f, "{}", confirmed_tx.txid());
-    writeln!(f, "{miner_in_addr}");
-    writeln!(f, "{miner_in_amount}");
-    writeln!(f, "{trader_out_addr}");
-    writeln!(f, "{trader_amount}");
-    writeln!(f, "{miner_change_addr}");
-    writeln!(f, "{miner_amount}");
-    writeln!(f, "{fee}");
-    writeln!(f, "{}", block.bip34_block_height().unwrap());
-    writeln!(f, "{}", block.block_hash());
+    let out = match &output.template {
+        Some(path) => report.render_template(&fs::read_to_string(path)?, output.fee_display)?,
+        None => report.to_out_txt(output.fee_display),
+    };
+    report::write_text(Path::new("../out.txt"), &out)?;
+    if let Some(path) = &output.report {
+        let json = serde_json::to_value(report.versioned())?;
+        if output.validate {
+            report::validate(&json)?;
+        }
+        match output.report_format {
+            ReportFormat::Json => report::write_json(path, &json)?,
+            ReportFormat::Cbor => report::write_compact(path, report)?,
+        }
+    }
 
     // e1ec30: Forgot to enable GitHub Actions
 
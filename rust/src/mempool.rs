@@ -0,0 +1,233 @@
+use bitcoincore_rpc::bitcoin::{Amount, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::{self, Write};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Fees {
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub base: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub ancestor: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub descendant: Amount,
+}
+
+// The parts of getmempoolentry (and the verbose getmempoolancestors/descendants) the tree
+// shows. Ancestor and descendant sizes include the transaction itself.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Entry {
+    pub vsize: u64,
+    #[serde(rename = "ancestorsize")]
+    pub ancestor_vsize: u64,
+    #[serde(rename = "descendantsize")]
+    pub descendant_vsize: u64,
+    pub fees: Fees,
+    // Unconfirmed parents
+    pub depends: Vec<Txid>,
+    // Unconfirmed children
+    #[serde(rename = "spentby")]
+    pub spent_by: Vec<Txid>,
+}
+
+impl Entry {
+    pub fn feerate(&self) -> f64 {
+        feerate(self.fees.base, self.vsize)
+    }
+
+    // What a miner weighs the transaction at: it can only be mined with its ancestors
+    pub fn ancestor_feerate(&self) -> f64 {
+        feerate(self.fees.ancestor, self.ancestor_vsize)
+    }
+
+    // What its descendants can pay for it, the CPFP side
+    pub fn descendant_feerate(&self) -> f64 {
+        feerate(self.fees.descendant, self.descendant_vsize)
+    }
+}
+
+// sat/vB
+pub fn feerate(fee: Amount, vsize: u64) -> f64 {
+    fee.to_sat() as f64 / vsize.max(1) as f64
+}
+
+// A mempool transaction with all its unconfirmed ancestors and descendants
+#[derive(Debug)]
+pub struct Package {
+    pub root: Txid,
+    // The root included, ordered so the rendering is deterministic
+    pub entries: BTreeMap<Txid, Entry>,
+    pub ancestors: HashSet<Txid>,
+    pub descendants: HashSet<Txid>,
+}
+
+impl Package {
+    pub fn fetch(rpc: &impl RpcApi, txid: &Txid) -> bitcoincore_rpc::Result<Package> {
+        let root: Entry = rpc.call("getmempoolentry", &[json!(txid)])?;
+        let ancestors: BTreeMap<Txid, Entry> =
+            rpc.call("getmempoolancestors", &[json!(txid), json!(true)])?;
+        let descendants: BTreeMap<Txid, Entry> =
+            rpc.call("getmempooldescendants", &[json!(txid), json!(true)])?;
+        let mut package = Package {
+            root: *txid,
+            entries: BTreeMap::from([(*txid, root)]),
+            ancestors: ancestors.keys().copied().collect(),
+            descendants: descendants.keys().copied().collect(),
+        };
+        package.entries.extend(ancestors);
+        package.entries.extend(descendants);
+        Ok(package)
+    }
+
+    pub fn fee(&self) -> Amount {
+        self.entries.values().map(|e| e.fees.base).sum()
+    }
+
+    pub fn vsize(&self) -> u64 {
+        self.entries.values().map(|e| e.vsize).sum()
+    }
+
+    // The root with its ancestors above it (each under the child spending it) and its
+    // descendants below. A transaction reached a second way, as in a diamond of two
+    // children both spent by a third, is listed again without its subtree.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let root = &self.entries[&self.root];
+        let _ = writeln!(
+            out,
+            "{} [ancestor package {:.2} sat/vB, descendant package {:.2} sat/vB]",
+            Line(self.root, root),
+            root.ancestor_feerate(),
+            root.descendant_feerate()
+        );
+        let _ = writeln!(out, "Ancestors ({}):", self.ancestors.len());
+        self.write_children(&mut out, self.root, "", &mut HashSet::new(), |e| &e.depends);
+        let _ = writeln!(out, "Descendants ({}):", self.descendants.len());
+        self.write_children(&mut out, self.root, "", &mut HashSet::new(), |e| {
+            &e.spent_by
+        });
+        let _ = writeln!(
+            out,
+            "Package: {} transaction(s), {} over {} vB, {:.2} sat/vB",
+            self.entries.len(),
+            self.fee(),
+            self.vsize(),
+            feerate(self.fee(), self.vsize())
+        );
+        out
+    }
+
+    fn write_children(
+        &self,
+        out: &mut String,
+        txid: Txid,
+        prefix: &str,
+        shown: &mut HashSet<Txid>,
+        next: fn(&Entry) -> &Vec<Txid>,
+    ) {
+        // Only those in the package, a transaction outside it can't be described
+        let children: Vec<Txid> = next(&self.entries[&txid])
+            .iter()
+            .copied()
+            .filter(|t| self.entries.contains_key(t))
+            .collect();
+        for (i, child) in children.iter().enumerate() {
+            let (branch, indent) = if i + 1 == children.len() {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let entry = &self.entries[child];
+            if !shown.insert(*child) {
+                let _ = writeln!(out, "{prefix}{branch}{child} (shown above)");
+                continue;
+            }
+            let _ = writeln!(out, "{prefix}{branch}{}", Line(*child, entry));
+            self.write_children(out, *child, &format!("{prefix}{indent}"), shown, next);
+        }
+    }
+}
+
+// One transaction's own numbers
+struct Line<'a>(Txid, &'a Entry);
+
+impl fmt::Display for Line<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Line(txid, entry) = self;
+        write!(
+            f,
+            "{txid} fee {} vsize {} vB {:.2} sat/vB",
+            entry.fees.base,
+            entry.vsize,
+            entry.feerate()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use serde_json::Value;
+
+    fn txid(byte: &str) -> Txid {
+        byte.repeat(32).parse().unwrap()
+    }
+
+    fn entry(fee_sat: u64, vsize: u64, depends: &[Txid], spent_by: &[Txid]) -> Value {
+        let btc = |sat: u64| Amount::from_sat(sat).to_btc();
+        // The package-wide numbers aren't what the tree is tested on
+        json!({
+            "vsize": vsize,
+            "ancestorsize": vsize,
+            "descendantsize": vsize,
+            "fees": { "base": btc(fee_sat), "ancestor": btc(fee_sat), "descendant": btc(fee_sat) },
+            "depends": depends,
+            "spentby": spent_by,
+        })
+    }
+
+    #[test]
+    fn renders_ancestors_and_descendants() {
+        // parent -> root -> {left, right}, right also spending left
+        let (parent, root, left, right) = (txid("11"), txid("22"), txid("33"), txid("44"));
+        let rpc = MockClient::new()
+            .returns(
+                "getmempoolentry",
+                entry(200, 100, &[parent], &[left, right]),
+            )
+            .returns(
+                "getmempoolancestors",
+                json!({ parent.to_string(): entry(100, 100, &[], &[root]) }),
+            )
+            .returns(
+                "getmempooldescendants",
+                json!({
+                    left.to_string(): entry(1_000, 100, &[root], &[right]),
+                    right.to_string(): entry(300, 100, &[root, left], &[]),
+                }),
+            );
+        let package = Package::fetch(&rpc, &root).unwrap();
+        rpc.assert_done();
+        assert_eq!(package.fee(), Amount::from_sat(1_600));
+        assert_eq!(package.vsize(), 400);
+
+        let rendered = package.render();
+        let lines: Vec<_> = rendered.lines().collect();
+        assert_eq!(lines[1], "Ancestors (1):");
+        assert_eq!(
+            lines[2],
+            format!("└── {parent} fee 0.000001 BTC vsize 100 vB 1.00 sat/vB")
+        );
+        assert_eq!(lines[3], "Descendants (2):");
+        assert!(lines[4].starts_with(&format!("├── {left} fee")));
+        assert!(lines[5].starts_with(&format!("│   └── {right} fee")));
+        assert_eq!(lines[6], format!("└── {right} (shown above)"));
+        assert_eq!(
+            lines[7],
+            "Package: 4 transaction(s), 0.000016 BTC over 400 vB, 4.00 sat/vB"
+        );
+    }
+}
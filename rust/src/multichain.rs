@@ -0,0 +1,272 @@
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::hex::FromHex;
+use bitcoincore_rpc::bitcoin::{Address, Amount, BlockHash, Network, OutPoint, Transaction, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::thread;
+
+use crate::compat::Compat;
+use crate::config::{self, Config};
+use crate::error::Result;
+use crate::report::{TxReport, SCHEMA_VERSION};
+use crate::{rpc, Flow};
+
+// What to do on one node
+#[derive(Debug, Clone)]
+pub enum Job {
+    // The full capstone flow. Wallet clients go through the active profile, so this only
+    // runs there.
+    Flow,
+    // Report on a transaction that's already on the node's chain or in its mempool,
+    // without changing anything
+    Lookup(Txid),
+}
+
+#[derive(Debug, Clone)]
+pub struct Target {
+    pub profile: String,
+    pub job: Job,
+}
+
+// Every target's result in one document
+#[derive(Debug, Serialize)]
+pub struct CombinedReport {
+    pub schema_version: u32,
+    pub chains: Vec<ChainResult>,
+    // Every target ran without an error
+    pub complete: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChainResult {
+    pub profile: String,
+    pub network: Network,
+    // getblockchaininfo's view, None when the node couldn't be reached
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tip_height: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flow: Option<TxReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lookup: Option<TxLookup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// A transaction as any node with -txindex (or the transaction in its mempool) can describe it
+#[derive(Debug, Serialize)]
+pub struct TxLookup {
+    pub txid: Txid,
+    pub confirmations: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<BlockHash>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u64>,
+    pub inputs: Vec<OutPoint>,
+    pub outputs: Vec<LookupOutput>,
+    pub vsize: u64,
+    // Only when every spent output could be fetched too
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc::opt"
+    )]
+    pub fee: Option<Amount>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LookupOutput {
+    // None for outputs without an address, e.g. OP_RETURN
+    pub address: Option<Address>,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+}
+
+// The read-only half of the report: `txid` as `rpc`'s node sees it
+pub fn lookup(rpc: &impl RpcApi, txid: &Txid, network: Network) -> Result<TxLookup> {
+    let info: Value = rpc.call("getrawtransaction", &[json!(txid), json!(true)])?;
+    let tx = decode(&info["hex"])?;
+    let block_hash = info["blockhash"].as_str().and_then(|h| h.parse().ok());
+    let block_height = match &block_hash {
+        Some(hash) => Some(rpc.get_block_header_info(hash)?.height as u64),
+        None => None,
+    };
+
+    // Spent outputs, as long as the node can find every parent
+    let mut input_value = Some(Amount::ZERO);
+    if !tx.is_coinbase() {
+        for input in &tx.input {
+            if input_value.is_none() {
+                break;
+            }
+            let parent: Option<Value> = rpc
+                .call(
+                    "getrawtransaction",
+                    &[json!(input.previous_output.txid), json!(false)],
+                )
+                .ok();
+            let spent = parent
+                .and_then(|hex| decode(&hex).ok())
+                .and_then(|p| p.output.get(input.previous_output.vout as usize).cloned());
+            input_value = input_value.zip(spent).map(|(sum, o)| sum + o.value);
+        }
+    }
+    let output_value: Amount = tx.output.iter().map(|o| o.value).sum();
+
+    Ok(TxLookup {
+        txid: *txid,
+        confirmations: info["confirmations"].as_u64().unwrap_or(0),
+        block_hash,
+        block_height,
+        inputs: tx.input.iter().map(|i| i.previous_output).collect(),
+        outputs: tx
+            .output
+            .iter()
+            .map(|o| LookupOutput {
+                address: Address::from_script(&o.script_pubkey, network).ok(),
+                amount: o.value,
+            })
+            .collect(),
+        vsize: tx.vsize() as u64,
+        fee: input_value
+            .filter(|_| !tx.is_coinbase())
+            .and_then(|i| i.checked_sub(output_value)),
+    })
+}
+
+fn decode(hex: &Value) -> Result<Transaction> {
+    let bytes = hex.as_str().and_then(|h| Vec::<u8>::from_hex(h).ok());
+    let bytes = bytes.ok_or_else(|| {
+        bitcoincore_rpc::Error::ReturnedError("getrawtransaction returned no hex".to_owned())
+    })?;
+    Ok(encode::deserialize(&bytes).map_err(bitcoincore_rpc::Error::from)?)
+}
+
+// Run every target at once, each on its own thread with a client for its profile, and put the
+// results together. A target that fails is recorded as such, the others still report.
+pub fn run(config: &Config, targets: &[Target]) -> Result<CombinedReport> {
+    let active = config::active();
+    let mut clients = vec![];
+    for target in targets {
+        let profile = config.profile(Some(&target.profile))?;
+        if matches!(target.job, Job::Flow) && profile.url != active.url {
+            return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "the flow can only run on the active profile, not {:?} (pick it with --profile)",
+                target.profile
+            ))
+            .into());
+        }
+        clients.push((rpc::connect(&profile.url, profile.auth())?, profile.network));
+    }
+
+    let chains = thread::scope(|scope| {
+        let handles: Vec<_> = targets
+            .iter()
+            .zip(&clients)
+            .map(|(target, (client, network))| {
+                scope.spawn(move || run_target(target, client, *network))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("target thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    Ok(CombinedReport {
+        schema_version: SCHEMA_VERSION,
+        complete: chains.iter().all(|c| c.error.is_none()),
+        chains,
+    })
+}
+
+fn run_target(target: &Target, rpc: &Client, network: Network) -> ChainResult {
+    let mut result = ChainResult {
+        profile: target.profile.clone(),
+        network,
+        chain: None,
+        tip_height: None,
+        flow: None,
+        lookup: None,
+        error: None,
+    };
+    let outcome = (|| -> Result<()> {
+        let info = Compat::detect(rpc)?.blockchain_info(rpc)?;
+        result.chain = info["chain"].as_str().map(str::to_owned);
+        result.tip_height = info["blocks"].as_u64();
+        match &target.job {
+            Job::Flow => {
+                result.flow = Some(Flow::builder().quiet(true).build().run(rpc)?.report);
+            }
+            Job::Lookup(txid) => result.lookup = Some(lookup(rpc, txid, network)?),
+        }
+        Ok(())
+    })();
+    if let Err(e) = outcome {
+        result.error = Some(e.to_string());
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{ScriptBuf, Sequence, TxIn, TxOut, Witness};
+
+    fn tx(spends: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: spends,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_op_return([1, 2, 3]),
+            }],
+        }
+    }
+
+    #[test]
+    fn looks_up_a_mempool_transaction_and_its_fee() {
+        let parent = tx(OutPoint::new(Txid::all_zeros(), 7), 50_000);
+        let child = tx(OutPoint::new(parent.txid(), 0), 49_000);
+        let rpc = MockClient::new()
+            .returns(
+                "getrawtransaction",
+                json!({ "hex": encode::serialize_hex(&child), "txid": child.txid() }),
+            )
+            .returns("getrawtransaction", json!(encode::serialize_hex(&parent)));
+        let found = lookup(&rpc, &child.txid(), Network::Signet).unwrap();
+        rpc.assert_done();
+        assert_eq!(found.confirmations, 0);
+        assert_eq!(found.block_height, None);
+        assert_eq!(found.inputs, [OutPoint::new(parent.txid(), 0)]);
+        assert_eq!(found.outputs[0].address, None);
+        assert_eq!(found.fee, Some(Amount::from_sat(1_000)));
+    }
+
+    #[test]
+    fn leaves_the_fee_out_without_the_parents() {
+        let child = tx(OutPoint::new(Txid::all_zeros(), 0), 49_000);
+        let rpc = MockClient::new()
+            .returns(
+                "getrawtransaction",
+                json!({ "hex": encode::serialize_hex(&child) }),
+            )
+            .fails(
+                "getrawtransaction",
+                -5,
+                "No such mempool or blockchain transaction",
+            );
+        let found = lookup(&rpc, &child.txid(), Network::Signet).unwrap();
+        assert_eq!(found.fee, None);
+    }
+}
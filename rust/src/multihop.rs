@@ -0,0 +1,268 @@
+use bitcoincore_rpc::bitcoin::{Amount, BlockHash, OutPoint, SignedAmount, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::consensus;
+use crate::error::{Error, Result};
+use crate::{unsigned_send, wallet};
+
+// Where the coins started: a single coinbase output of the Miner wallet
+#[derive(Debug, Serialize)]
+pub struct Origin {
+    pub outpoint: OutPoint,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub value: Amount,
+}
+
+// One payment in the chain, spending the output created by the previous hop
+#[derive(Debug, Serialize)]
+pub struct Hop {
+    pub from: String,
+    pub to: String,
+    pub spent: OutPoint,
+    pub txid: Txid,
+    pub vout: u32,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee: SignedAmount,
+    pub block_height: u32,
+    pub block_hash: BlockHash,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MultiHopReport {
+    pub origin: Origin,
+    pub hops: Vec<Hop>,
+}
+
+impl MultiHopReport {
+    pub fn total_fees(&self) -> Amount {
+        self.hops
+            .iter()
+            .map(|h| Amount::from_sat(h.fee.to_sat().unsigned_abs()))
+            .sum()
+    }
+}
+
+// Pass a whole Miner coinbase output along `hops` (e.g. Miner -> A -> B -> C), one
+// payment per block. Every hop spends exactly the output it received and pays the fee
+// out of it, so each transaction has one input and one output and the lineage of the
+// original coinbase stays a straight line.
+pub fn run(rpc: &Client, hops: &[&str]) -> Result<MultiHopReport> {
+    let miner = wallet::open(rpc, "Miner")?;
+    let miner_address = miner.get_new_address(None, None)?.assume_checked();
+
+    let coinbase = match mature_coinbase(&miner)? {
+        Some(utxo) => utxo,
+        None => {
+            miner.generate_to_address(consensus::params().coinbase_maturity + 1, &miner_address)?;
+            mature_coinbase(&miner)?.ok_or_else(|| {
+                bitcoincore_rpc::Error::ReturnedError("no mature coinbase output".to_owned())
+            })?
+        }
+    };
+    let origin = Origin {
+        outpoint: OutPoint::new(coinbase.txid, coinbase.vout),
+        value: coinbase.amount,
+    };
+    println!(
+        "Starting from coinbase output {} worth {}",
+        origin.outpoint, origin.value
+    );
+
+    let mut report = MultiHopReport {
+        origin,
+        hops: vec![],
+    };
+    let mut sender_name = "Miner";
+    let mut sender = miner;
+    let mut spent = report.origin.outpoint;
+
+    for &name in hops {
+        let receiver = wallet::open(rpc, name)?;
+        let address = receiver.get_new_address(None, None)?.assume_checked();
+
+        let txid = forward(&sender, &address.to_string(), spent)?;
+        let tx_info = sender.get_transaction(&txid, None)?;
+        let fee = tx_info.fee.unwrap_or(SignedAmount::ZERO);
+
+        // Confirm every hop in its own block
+        let block_hash = rpc.generate_to_address(1, &miner_address)?[0];
+        let block_height = receiver.get_transaction(&txid, None)?.info.blockheight;
+
+        let tx = tx_info
+            .transaction()
+            .map_err(bitcoincore_rpc::Error::from)?;
+        let vout = tx
+            .output
+            .iter()
+            .position(|o| o.script_pubkey == address.script_pubkey())
+            .ok_or(Error::MissingOutput {
+                txid,
+                output: "forwarded",
+            })? as u32;
+        let amount = tx.output[vout as usize].value;
+
+        println!("{sender_name} -> {name}: {amount} in {txid}:{vout} (fee {fee})");
+        report.hops.push(Hop {
+            from: sender_name.to_owned(),
+            to: name.to_owned(),
+            spent,
+            txid,
+            vout,
+            amount,
+            fee,
+            block_height: block_height.unwrap_or_default(),
+            block_hash,
+        });
+
+        spent = OutPoint::new(txid, vout);
+        sender_name = name;
+        sender = receiver;
+    }
+    Ok(report)
+}
+
+// e1ec30: A spendable coinbase output in the wallet, if there is one
+fn mature_coinbase(
+    rpc: &Client,
+) -> bitcoincore_rpc::Result<Option<bitcoincore_rpc::json::ListUnspentResultEntry>> {
+    let maturity = consensus::params().coinbase_maturity as usize;
+    let unspent = rpc.list_unspent(Some(maturity), None, None, None, None)?;
+    for utxo in unspent {
+        if rpc
+            .get_transaction(&utxo.txid, None)?
+            .transaction()?
+            .is_coinbase()
+        {
+            return Ok(Some(utxo));
+        }
+    }
+    Ok(None)
+}
+
+// Send the whole of `input` to `addr`, with the fee taken out of the payment
+fn forward(rpc: &impl RpcApi, addr: &str, input: OutPoint) -> Result<Txid> {
+    let value = rpc
+        .get_transaction(&input.txid, None)?
+        .transaction()
+        .map_err(bitcoincore_rpc::Error::from)?
+        .output
+        .get(input.vout as usize)
+        .ok_or_else(|| bitcoincore_rpc::Error::ReturnedError(format!("{input} does not exist")))?
+        .value;
+    let args = [
+        json!([{ addr: value.to_btc() }]),
+        json!(null),
+        json!(null),
+        json!(null),
+        json!({
+            "inputs": [{ "txid": input.txid, "vout": input.vout }],
+            "add_inputs": false,
+            "subtract_fee_from_outputs": [0],
+        }),
+    ];
+
+    // Left unsigned the wallet returns the PSBT instead of a txid
+    #[derive(Deserialize)]
+    struct SendResult {
+        complete: bool,
+        txid: Option<Txid>,
+    }
+    let send_result = rpc.call::<SendResult>("send", &args)?;
+    match send_result.txid {
+        Some(txid) if send_result.complete => Ok(txid),
+        _ => Err(unsigned_send().into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::consensus::encode;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+    use serde_json::Value;
+
+    const ADDRESS: &str = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+
+    // gettransaction for the hop's input, a transaction with a single 1 BTC output
+    fn received() -> (OutPoint, Value) {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::all_zeros(), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::ONE_BTC,
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let info = json!({
+            "amount": 1.0,
+            "confirmations": 1,
+            "txid": tx.txid(),
+            "time": 0,
+            "timereceived": 0,
+            "bip125-replaceable": "no",
+            "walletconflicts": [],
+            "details": [],
+            "hex": encode::serialize_hex(&tx),
+        });
+        (OutPoint::new(tx.txid(), 0), info)
+    }
+
+    #[test]
+    fn forwards_the_whole_output() {
+        let (input, info) = received();
+        let rpc = MockClient::new().returns("gettransaction", info).returns(
+            "send",
+            json!({ "complete": true, "txid": Txid::all_zeros() }),
+        );
+        assert_eq!(forward(&rpc, ADDRESS, input).unwrap(), Txid::all_zeros());
+        rpc.assert_done();
+
+        let args = rpc.args(1);
+        assert_eq!(args[0], json!([{ ADDRESS: 1.0 }]));
+        assert_eq!(
+            args[4]["inputs"],
+            json!([{ "txid": input.txid, "vout": 0 }])
+        );
+        assert_eq!(args[4]["add_inputs"], json!(false));
+        assert_eq!(args[4]["subtract_fee_from_outputs"], json!([0]));
+    }
+
+    #[test]
+    fn a_hop_the_wallet_cannot_sign_is_an_error() {
+        let (input, info) = received();
+        let rpc = MockClient::new().returns("gettransaction", info).returns(
+            "send",
+            json!({ "complete": false, "psbt": "cHNidP8BAAoCAAAAAAAAAAAAAA==" }),
+        );
+        let error = forward(&rpc, ADDRESS, input).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Build(crate::builder::BuildError::Incomplete(_))
+        ));
+        rpc.assert_done();
+
+        let (input, info) = received();
+        let rpc = MockClient::new().returns("gettransaction", info);
+        let missing = OutPoint::new(input.txid, 1);
+        let error = forward(&rpc, ADDRESS, missing).unwrap_err().to_string();
+        assert!(
+            error.contains(&format!("{missing} does not exist")),
+            "{error}"
+        );
+        assert_eq!(rpc.calls(), ["gettransaction"]);
+    }
+}
@@ -0,0 +1,163 @@
+use bitcoincore_rpc::RpcApi;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::hint::black_box;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+// How long walletpassphrase keeps the wallet unlocked if the relock after the step never
+// happens, e.g. because the run is killed
+const UNLOCK_SECONDS: u64 = 300;
+
+// Where a step gets the passphrase from when its wallet turns out to be encrypted:
+// `passphrase = "prompt"` asks on the terminal, `passphrase = { env = "VAR" }` reads it
+// from the environment
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Prompt,
+    Env(String),
+}
+
+// A passphrase that's overwritten with zeros when dropped. Copies made on the way to the
+// node (the JSON request, the environment block for `env`) are outside its reach.
+pub struct Passphrase(Vec<u8>);
+
+impl Passphrase {
+    pub fn read(source: &Source, wallet: &str) -> io::Result<Passphrase> {
+        let bytes = match source {
+            Source::Env(var) => std::env::var(var)
+                .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("${var}: {e}")))?
+                .into_bytes(),
+            Source::Prompt => prompt(&format!("Passphrase for wallet {wallet}: "))?,
+        };
+        Ok(Passphrase(bytes))
+    }
+
+    fn as_str(&self) -> io::Result<&str> {
+        std::str::from_utf8(&self.0)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "passphrase is not UTF-8"))
+    }
+}
+
+impl Drop for Passphrase {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+// black_box keeps the writes from being optimized away as dead stores
+fn wipe(bytes: &mut [u8]) {
+    bytes.fill(0);
+    black_box(bytes);
+}
+
+// Read a line from the terminal with echo off
+fn prompt(question: &str) -> io::Result<Vec<u8>> {
+    let mut tty = OpenOptions::new().read(true).write(true).open("/dev/tty")?;
+    write!(tty, "{question}")?;
+    tty.flush()?;
+    stty(&["-echo"])?;
+    // Room for any sensible passphrase, so reading it never leaves a reallocated copy behind
+    let mut line = Vec::with_capacity(1024);
+    let read = BufReader::new(&tty).read_until(b'\n', &mut line);
+    stty(&["echo"])?;
+    writeln!(tty)?;
+    read?;
+    while matches!(line.last(), Some(b'\n' | b'\r')) {
+        line.pop();
+    }
+    Ok(line)
+}
+
+fn stty(args: &[&str]) -> io::Result<()> {
+    let status = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::from(File::open("/dev/tty")?))
+        .status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("stty {} failed", args.join(" "))))
+    }
+}
+
+// Relocks the wallet it unlocked when dropped
+pub struct Unlocked<'a, R: RpcApi> {
+    rpc: &'a R,
+}
+
+impl<R: RpcApi> Drop for Unlocked<'_, R> {
+    fn drop(&mut self) {
+        if let Err(e) = self.rpc.call::<Value>("walletlock", &[]) {
+            eprintln!("Could not lock the wallet again: {e}");
+        }
+    }
+}
+
+// Unlock `rpc`'s wallet for signing if it's encrypted and locked, with the passphrase from
+// `source`. Unencrypted (or already unlocked) wallets need nothing, so no guard, and the
+// source isn't even read.
+pub fn unlock<'a, R: RpcApi>(
+    rpc: &'a R,
+    wallet: &str,
+    source: Option<&Source>,
+) -> bitcoincore_rpc::Result<Option<Unlocked<'a, R>>> {
+    let info: Value = rpc.call("getwalletinfo", &[])?;
+    match info["unlocked_until"].as_u64() {
+        None => return Ok(None),
+        Some(until) if until > 0 => return Ok(None),
+        Some(_) => {}
+    }
+    let source = source.ok_or_else(|| {
+        bitcoincore_rpc::Error::ReturnedError(format!(
+            "wallet {wallet} is encrypted, give the step a passphrase source"
+        ))
+    })?;
+    let passphrase = Passphrase::read(source, wallet)
+        .map_err(|e| bitcoincore_rpc::Error::ReturnedError(format!("wallet {wallet}: {e}")))?;
+    let mut args = vec![
+        json!(passphrase
+            .as_str()
+            .map_err(|e| bitcoincore_rpc::Error::ReturnedError(e.to_string()))?),
+        json!(UNLOCK_SECONDS),
+    ];
+    let unlocked = rpc.call::<Value>("walletpassphrase", &args);
+    if let Value::String(copy) = args.swap_remove(0) {
+        wipe(&mut copy.into_bytes());
+    }
+    unlocked?;
+    Ok(Some(Unlocked { rpc }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+
+    #[test]
+    fn leaves_unencrypted_wallets_alone() {
+        let rpc = MockClient::new().returns("getwalletinfo", json!({ "walletname": "Miner" }));
+        let source = Source::Env("SURELY_NOT_SET_ANYWHERE".into());
+        assert!(unlock(&rpc, "Miner", Some(&source)).unwrap().is_none());
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn unlocks_and_relocks_encrypted_wallets() {
+        std::env::set_var("PASSPHRASE_TEST_MINER", "correct horse");
+        let rpc = MockClient::new()
+            .returns("getwalletinfo", json!({ "unlocked_until": 0 }))
+            .returns("walletpassphrase", Value::Null)
+            .returns("walletlock", Value::Null);
+        let source = Source::Env("PASSPHRASE_TEST_MINER".into());
+        let guard = unlock(&rpc, "Miner", Some(&source)).unwrap();
+        assert!(guard.is_some());
+        drop(guard);
+        rpc.assert_done();
+
+        let rpc = MockClient::new().returns("getwalletinfo", json!({ "unlocked_until": 0 }));
+        assert!(unlock(&rpc, "Miner", None).is_err());
+    }
+}
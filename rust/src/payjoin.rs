@@ -0,0 +1,372 @@
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint, ScriptBuf, Transaction, TxOut, Txid};
+use bitcoincore_rpc::json::{
+    CreateRawTransactionInput, DecodeRawTransactionResult, ListUnspentResultEntry,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::builder::P2WPKH_INPUT_VBYTES;
+use crate::coins::{self, CoinControl, FEE_HEADROOM};
+use crate::consensus;
+use crate::error::Result;
+use crate::script_to_addr;
+use crate::wallet::{self, is_mine};
+
+// What the receiver gets first when it has no coin of its own to contribute
+const RECEIVER_SEED: Amount = Amount::from_int_btc(1);
+// Upper bound on blocks mined to fund the sender, on top of those maturing the rewards
+const EXTRA_FUNDING_BLOCKS: u64 = 100;
+
+#[derive(Debug, Serialize)]
+pub struct PayjoinInput {
+    pub outpoint: OutPoint,
+    // Wallet the input belongs to
+    pub owner: String,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayjoinOutput {
+    pub address: String,
+    pub owner: String,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PayjoinReport {
+    // The sender's own transaction, which the receiver could have broadcast instead
+    pub original_txid: Txid,
+    pub txid: Txid,
+    pub inputs: Vec<PayjoinInput>,
+    pub outputs: Vec<PayjoinOutput>,
+    // What the sender actually paid the receiver
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub payment: Amount,
+    // What the receiver's output holds, payment and contributed input together. This is
+    // what an observer sees as the payment.
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub receiver_output: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee: Amount,
+    // Paid by the sender for the receiver's extra input, out of its change
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub sender_fee_contribution: Amount,
+    // Whether the common-input-ownership heuristic (all inputs have one owner) holds, which
+    // is exactly what payjoin breaks
+    pub inputs_share_owner: bool,
+}
+
+// A BIP78-style payjoin between two local wallets, with the PSBTs handed over in memory
+// instead of over HTTP. The sender signs a normal payment (the "original", which the
+// receiver checks is broadcastable); the receiver adds one of its own coins as an input,
+// adds its value to its output and signs its input; the sender checks the proposal only
+// took the agreed extra fee from its change and signs again.
+pub fn run(rpc: &Client, from: &str, to: &str, amount: Amount) -> Result<PayjoinReport> {
+    let sender = wallet::open(rpc, from)?;
+    let receiver = wallet::open(rpc, to)?;
+    let sender_address = sender.get_new_address(None, None)?.assume_checked();
+    let mine_to = (from == "Miner").then_some(&sender_address);
+
+    let contribution = match receiver_coin(&receiver)? {
+        Some(utxo) => utxo,
+        None => {
+            coins::select_or_mine(
+                &sender,
+                from,
+                RECEIVER_SEED + FEE_HEADROOM,
+                &CoinControl::default(),
+                mine_to,
+                consensus::params().coinbase_maturity + EXTRA_FUNDING_BLOCKS,
+            )?;
+            let seed_address = receiver.get_new_address(None, None)?.assume_checked();
+            sender.send_to_address(
+                &seed_address,
+                RECEIVER_SEED,
+                None,
+                None,
+                None,
+                None,
+                None,
+                None,
+            )?;
+            rpc.generate_to_address(1, &sender_address)?;
+            receiver_coin(&receiver)?.ok_or_else(|| {
+                bitcoincore_rpc::Error::ReturnedError(format!("{to} has no coin to contribute"))
+            })?
+        }
+    };
+
+    // The original: an ordinary, fully signed payment
+    let selected = coins::select_or_mine(
+        &sender,
+        from,
+        amount + FEE_HEADROOM,
+        &CoinControl::default(),
+        mine_to,
+        consensus::params().coinbase_maturity + EXTRA_FUNDING_BLOCKS,
+    )?;
+    let inputs: Vec<CreateRawTransactionInput> = selected
+        .iter()
+        .map(|u| CreateRawTransactionInput {
+            txid: u.txid,
+            vout: u.vout,
+            sequence: None,
+        })
+        .collect();
+    let receiver_address = receiver.get_new_address(None, None)?.assume_checked();
+    let outputs = HashMap::from([(receiver_address.to_string(), amount)]);
+    let funded = sender.wallet_create_funded_psbt(&inputs, &outputs, None, None, None)?;
+    let original = sign(&sender, &funded.psbt)?;
+    let original_hex = finalize(&sender, &original)?;
+    let original_tx: Transaction =
+        encode::deserialize(&original_hex).map_err(bitcoincore_rpc::Error::from)?;
+
+    // Receiver: the original has to be good to broadcast, or it's no fallback
+    let accepted = rpc.test_mempool_accept(&[&original_hex])?;
+    if let Some(rejected) = accepted.iter().find(|r| !r.allowed) {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "original transaction is not broadcastable: {:?}",
+            rejected.reject_reason
+        ))
+        .into());
+    }
+    let input_value: Amount = selected.iter().map(|u| u.amount).sum();
+    let original_fee = input_value - original_tx.output.iter().map(|o| o.value).sum();
+    // One more P2WPKH input at the original's fee rate
+    let additional_fee = Amount::from_sat(
+        (original_fee.to_sat() * P2WPKH_INPUT_VBYTES).div_ceil(original_tx.vsize() as u64),
+    );
+    let payment_script = receiver_address.script_pubkey();
+    let proposal = propose(
+        rpc,
+        &original_tx,
+        &payment_script,
+        &contribution,
+        additional_fee,
+    )?;
+    let proposal = receiver
+        .wallet_process_psbt(&proposal, Some(true), None, None)?
+        .psbt;
+
+    // Sender: nothing may have changed but the receiver's input and output
+    check_proposal(
+        rpc,
+        &proposal,
+        &original_tx,
+        &payment_script,
+        additional_fee,
+    )?;
+    let signed = sign(&sender, &proposal)?;
+    let hex = finalize(&sender, &signed)?;
+    let payjoin: Transaction = encode::deserialize(&hex).map_err(bitcoincore_rpc::Error::from)?;
+    let txid = rpc.send_raw_transaction(&hex)?;
+    rpc.generate_to_address(1, &sender_address)?;
+
+    let contributed = OutPoint::new(contribution.txid, contribution.vout);
+    let inputs: Vec<PayjoinInput> = payjoin
+        .input
+        .iter()
+        .map(|i| {
+            match selected
+                .iter()
+                .find(|u| u.txid == i.previous_output.txid && u.vout == i.previous_output.vout)
+            {
+                Some(u) => PayjoinInput {
+                    outpoint: i.previous_output,
+                    owner: from.to_owned(),
+                    amount: u.amount,
+                },
+                None => PayjoinInput {
+                    outpoint: contributed,
+                    owner: to.to_owned(),
+                    amount: contribution.amount,
+                },
+            }
+        })
+        .collect();
+    let outputs: Vec<PayjoinOutput> = payjoin
+        .output
+        .iter()
+        .map(|o| PayjoinOutput {
+            address: script_to_addr(&o.script_pubkey).to_string(),
+            owner: owner(&sender, &receiver, from, to, o),
+            amount: o.value,
+        })
+        .collect();
+    let receiver_output = payjoin
+        .output
+        .iter()
+        .find(|o| o.script_pubkey == payment_script)
+        .map_or(Amount::ZERO, |o| o.value);
+    let total_in: Amount = inputs.iter().map(|i| i.amount).sum();
+    let total_out: Amount = payjoin.output.iter().map(|o| o.value).sum();
+
+    Ok(PayjoinReport {
+        original_txid: original_tx.txid(),
+        txid,
+        inputs_share_owner: inputs.iter().all(|i| i.owner == inputs[0].owner),
+        inputs,
+        outputs,
+        payment: amount,
+        receiver_output,
+        fee: total_in - total_out,
+        sender_fee_contribution: additional_fee,
+    })
+}
+
+// A confirmed coin of the receiver's to add to the payment
+fn receiver_coin(receiver: &Client) -> bitcoincore_rpc::Result<Option<ListUnspentResultEntry>> {
+    Ok(receiver
+        .list_unspent(Some(1), None, None, None, None)?
+        .into_iter()
+        .find(|u| u.spendable && u.safe))
+}
+
+fn sign(wallet: &Client, psbt: &str) -> bitcoincore_rpc::Result<String> {
+    let signed = wallet.wallet_process_psbt(psbt, Some(true), None, None)?;
+    if !signed.complete {
+        return Err(bitcoincore_rpc::Error::ReturnedError(
+            "the wallet could not sign every input".to_owned(),
+        ));
+    }
+    Ok(signed.psbt)
+}
+
+fn finalize(wallet: &Client, psbt: &str) -> bitcoincore_rpc::Result<Vec<u8>> {
+    wallet.finalize_psbt(psbt, Some(true))?.hex.ok_or_else(|| {
+        bitcoincore_rpc::Error::ReturnedError("finalizepsbt returned no transaction".to_owned())
+    })
+}
+
+// The receiver's proposal: the original's inputs plus `contribution` at a random position,
+// its payment output grown by the contribution and the sender's change (the payment if
+// there is none) paying `additional_fee`. Signatures don't carry over, every input is
+// signed again.
+fn propose(
+    rpc: &Client,
+    original: &Transaction,
+    payment_script: &ScriptBuf,
+    contribution: &ListUnspentResultEntry,
+    additional_fee: Amount,
+) -> bitcoincore_rpc::Result<String> {
+    let mut inputs: Vec<Value> = original
+        .input
+        .iter()
+        .map(|i| {
+            json!({
+                "txid": i.previous_output.txid,
+                "vout": i.previous_output.vout,
+                "sequence": i.sequence.0,
+            })
+        })
+        .collect();
+    let position = rand::thread_rng().gen_range(0..=inputs.len());
+    inputs.insert(
+        position,
+        json!({
+            "txid": contribution.txid,
+            "vout": contribution.vout,
+            "sequence": original.input[0].sequence.0,
+        }),
+    );
+
+    let has_change = original
+        .output
+        .iter()
+        .any(|o| o.script_pubkey != *payment_script);
+    let outputs: Vec<Value> = original
+        .output
+        .iter()
+        .map(|o| {
+            let mut value = o.value;
+            if o.script_pubkey == *payment_script {
+                value += contribution.amount;
+            }
+            if o.script_pubkey != *payment_script || !has_change {
+                value -= additional_fee;
+            }
+            json!({ script_to_addr(&o.script_pubkey).to_string(): value.to_btc() })
+        })
+        .collect();
+
+    let psbt: String = rpc.call(
+        "createpsbt",
+        &[
+            json!(inputs),
+            json!(outputs),
+            json!(original.lock_time.to_consensus_u32()),
+        ],
+    )?;
+    // Adds the coins being spent, which the signers need
+    rpc.call("utxoupdatepsbt", &[json!(psbt)])
+}
+
+// The sender's checks on a proposal before signing it: all its inputs are still there and
+// its outputs lost at most `additional_fee`
+fn check_proposal(
+    rpc: &Client,
+    proposal: &str,
+    original: &Transaction,
+    payment_script: &ScriptBuf,
+    additional_fee: Amount,
+) -> bitcoincore_rpc::Result<()> {
+    #[derive(Deserialize)]
+    struct DecodedPsbt {
+        tx: DecodeRawTransactionResult,
+    }
+    let decoded: DecodedPsbt = rpc.call("decodepsbt", &[json!(proposal)])?;
+    let reject = |reason: String| {
+        Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "payjoin proposal rejected: {reason}"
+        )))
+    };
+
+    for input in &original.input {
+        let outpoint = input.previous_output;
+        if !decoded
+            .tx
+            .vin
+            .iter()
+            .any(|i| i.txid == Some(outpoint.txid) && i.vout == Some(outpoint.vout))
+        {
+            return reject(format!("{outpoint} was dropped"));
+        }
+    }
+    let value_to = |script: &ScriptBuf| -> Amount {
+        decoded
+            .tx
+            .vout
+            .iter()
+            .filter(|o| o.script_pub_key.hex == script.as_bytes())
+            .map(|o| o.value)
+            .sum()
+    };
+    let ours = |o: &&TxOut| o.script_pubkey != *payment_script;
+    let had: Amount = original.output.iter().filter(ours).map(|o| o.value).sum();
+    let has: Amount = original
+        .output
+        .iter()
+        .filter(ours)
+        .map(|o| value_to(&o.script_pubkey))
+        .sum();
+    if has + additional_fee < had {
+        return reject(format!("change went from {had} to {has}"));
+    }
+    Ok(())
+}
+
+fn owner(sender: &Client, receiver: &Client, from: &str, to: &str, output: &TxOut) -> String {
+    if is_mine(sender, &output.script_pubkey) {
+        from.to_owned()
+    } else if is_mine(receiver, &output.script_pubkey) {
+        to.to_owned()
+    } else {
+        "unknown".to_owned()
+    }
+}
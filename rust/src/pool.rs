@@ -0,0 +1,97 @@
+use bitcoincore_rpc::bitcoin::{Amount, BlockHash, Txid};
+use bitcoincore_rpc::json::WalletCreateFundedPsbtOptions;
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::consensus;
+use crate::wallet;
+
+#[derive(Debug, Serialize)]
+pub struct PoolPayoutReport {
+    // Public descriptor the rewards were mined to
+    pub descriptor: String,
+    pub blocks: u64,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub payout_balance: Amount,
+    pub recipient: String,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    pub txid: Txid,
+}
+
+// Model a pool's key separation: block rewards go to a descriptor held by a blank
+// watch-only payout wallet, which can see and plan spends of the coins but only the
+// separate signing wallet holding the keys can authorize them. The spend goes through
+// a PSBT: created by the payout wallet, signed by the signer.
+pub fn run(
+    rpc: &Client,
+    blocks: u64,
+    to: &str,
+    amount: Amount,
+) -> bitcoincore_rpc::Result<PoolPayoutReport> {
+    let signer = wallet::open(rpc, "PoolSigner")?;
+    let payout = wallet::open_watch_only(rpc, "PoolPayout")?;
+
+    // A single-key descriptor, generatetodescriptor refuses ranged ones
+    let payout_address = signer.get_new_address(None, None)?.assume_checked();
+    let info: Value = signer.call("getaddressinfo", &[json!(payout_address)])?;
+    let descriptor = info["desc"]
+        .as_str()
+        .ok_or_else(|| {
+            bitcoincore_rpc::Error::ReturnedError(format!("no descriptor for {payout_address}"))
+        })?
+        .to_owned();
+
+    let imported = wallet::import_descriptors("PoolPayout", &[&descriptor], Some("payout"))?;
+    if let Some(failed) = imported.iter().find(|r| !r.success) {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "importing the payout descriptor failed: {:?}",
+            failed.error
+        )));
+    }
+
+    rpc.call::<Vec<BlockHash>>("generatetodescriptor", &[json!(blocks), json!(descriptor)])?;
+    // Mature the rewards with blocks that pay someone else
+    let miner = wallet::open(rpc, "Miner")?;
+    let miner_address = miner.get_new_address(None, None)?.assume_checked();
+    rpc.generate_to_address(consensus::params().coinbase_maturity, &miner_address)?;
+
+    let payout_balance = payout.get_balances()?.mine.trusted;
+    println!("Payout wallet holds {payout_balance} without any private keys");
+
+    // The payout wallet has no keys to hand out a change address, change goes to the signer
+    let recipient = wallet::open(rpc, to)?
+        .get_new_address(None, None)?
+        .assume_checked();
+    let change = signer.get_raw_change_address(None)?;
+    let outputs = HashMap::from([(recipient.to_string(), amount)]);
+    let options = WalletCreateFundedPsbtOptions {
+        change_address: Some(change),
+        ..Default::default()
+    };
+    let unsigned = payout.wallet_create_funded_psbt(&[], &outputs, None, Some(options), None)?;
+
+    let signed = signer.wallet_process_psbt(&unsigned.psbt, Some(true), None, None)?;
+    if !signed.complete {
+        return Err(bitcoincore_rpc::Error::ReturnedError(
+            "the signing wallet could not sign the payout".to_owned(),
+        ));
+    }
+    let finalized = signer.finalize_psbt(&signed.psbt, Some(true))?;
+    let hex = finalized.hex.ok_or_else(|| {
+        bitcoincore_rpc::Error::ReturnedError("finalizepsbt returned no transaction".to_owned())
+    })?;
+    let txid = rpc.send_raw_transaction(&hex)?;
+    rpc.generate_to_address(1, &miner_address)?;
+
+    Ok(PoolPayoutReport {
+        descriptor,
+        blocks,
+        payout_balance,
+        recipient: recipient.to_string(),
+        amount,
+        txid,
+    })
+}
@@ -0,0 +1,127 @@
+use bitcoincore_rpc::bitcoin::Amount;
+use bitcoincore_rpc::json::{GetBalancesResult, ImportDescriptors, ImportMultiResult, Timestamp};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::ops::ControlFlow;
+
+use crate::compat::Compat;
+use crate::error::Result;
+use crate::{descriptor, gap, wallet};
+
+#[derive(Debug, Serialize)]
+pub struct RecoveredDescriptor {
+    pub descriptor: String,
+    pub highest_used: Option<u32>,
+    // Range imported into the recovered wallet
+    pub range: (usize, usize),
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryReport {
+    pub original: String,
+    pub recovered: String,
+    pub descriptors: Vec<RecoveredDescriptor>,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub original_balance: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub recovered_balance: Amount,
+    pub matches: bool,
+}
+
+#[derive(Deserialize)]
+struct ListDescriptorsResult {
+    descriptors: Vec<WalletDescriptor>,
+}
+
+#[derive(Deserialize)]
+struct WalletDescriptor {
+    desc: String,
+    #[serde(default)]
+    active: bool,
+    #[serde(default)]
+    internal: bool,
+}
+
+// Recovery drill: rebuild `original` from its private descriptors (what a seed backup
+// amounts to, Core has no BIP39 mnemonics) into a brand-new blank wallet, find how far
+// each descriptor was used with the gap scanner, rescan the chain and check the rebuilt
+// wallet ends up with the same balance. `descriptors` replaces the original's own.
+pub fn run(
+    rpc: &Client,
+    compat: &Compat,
+    original: &str,
+    descriptors: &[String],
+    gap_limit: u32,
+) -> Result<RecoveryReport> {
+    let original_rpc = wallet::open(rpc, original)?;
+    let seeds: Vec<(String, bool)> = if descriptors.is_empty() {
+        let listed: ListDescriptorsResult = original_rpc.call("listdescriptors", &[json!(true)])?;
+        listed
+            .descriptors
+            .into_iter()
+            .filter(|d| d.active)
+            .map(|d| (d.desc, d.internal))
+            .collect()
+    } else {
+        descriptors.iter().map(|d| (d.clone(), false)).collect()
+    };
+
+    // A fresh name every run, an existing wallet would already know its coins
+    let recovered = format!("{original}-recovered-{}", rpc.get_block_count()?);
+    let recovered_rpc = wallet::create_blank(rpc, &recovered)?;
+
+    let mut imported = vec![];
+    let mut requests = vec![];
+    for (descriptor, internal) in &seeds {
+        // importdescriptors insists on a correct checksum
+        let descriptor = descriptor::with_checksum(descriptor)?;
+        let scan = gap::scan(rpc, compat, &descriptor, gap_limit, gap::DEFAULT_CHUNK_SIZE)?;
+        let end = scan.highest_used.map_or(0, |i| i + 1) + gap_limit;
+        let range = (0, end as usize);
+        requests.push(ImportDescriptors {
+            descriptor,
+            // The rescan below covers the whole chain
+            timestamp: Timestamp::Now,
+            active: Some(true),
+            range: Some(range),
+            next_index: Some(scan.highest_used.map_or(0, |i| i as usize + 1)),
+            internal: Some(*internal),
+            label: None,
+        });
+        imported.push(RecoveredDescriptor {
+            descriptor: scan.descriptor,
+            highest_used: scan.highest_used,
+            range,
+        });
+    }
+    let results: Vec<ImportMultiResult> =
+        recovered_rpc.call("importdescriptors", &[serde_json::to_value(requests)?])?;
+    if let Some(failed) = results.iter().find(|r| !r.success) {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "importing into {recovered} failed: {:?}",
+            failed.error
+        ))
+        .into());
+    }
+
+    wallet::rescan(&recovered, .., |p| {
+        println!("Rescanning {recovered}: {:.0}%", p * 100.0);
+        ControlFlow::Continue(())
+    })?;
+
+    let original_balance = total(&original_rpc.get_balances()?);
+    let recovered_balance = total(&recovered_rpc.get_balances()?);
+    Ok(RecoveryReport {
+        original: original.to_owned(),
+        recovered,
+        descriptors: imported,
+        original_balance,
+        recovered_balance,
+        matches: original_balance == recovered_balance,
+    })
+}
+
+fn total(balances: &GetBalancesResult) -> Amount {
+    balances.mine.trusted + balances.mine.untrusted_pending + balances.mine.immature
+}
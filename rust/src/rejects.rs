@@ -0,0 +1,290 @@
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Amount, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Weight, Witness,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Serialize;
+
+use crate::config;
+use crate::consensus;
+use crate::error::Result;
+use crate::shutdown::{self, Phase};
+use crate::walletless::{self, LocalKey, LocalUtxo};
+
+// Core's MAX_STANDARD_TX_WEIGHT, above which a transaction isn't relayed
+pub const MAX_STANDARD_TX_WEIGHT: Weight = Weight::from_wu(400_000);
+
+const FEE: Amount = Amount::from_sat(10_000);
+// What each of the oversize transaction's outputs gets, well above the dust limit
+const SPLIT_OUTPUT: Amount = Amount::from_sat(1_000);
+
+// One way of breaking the rules, and (part of) the reject-reason the node should give
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Case {
+    // The honest spend every other case starts from, which must be accepted
+    Control,
+    BadSignature,
+    Overspend,
+    PrematureCoinbaseSpend,
+    DustOutput,
+    Oversize,
+}
+
+pub const CASES: [Case; 6] = [
+    Case::Control,
+    Case::BadSignature,
+    Case::Overspend,
+    Case::PrematureCoinbaseSpend,
+    Case::DustOutput,
+    Case::Oversize,
+];
+
+impl Case {
+    // None for the control. Script failures are reported as mandatory-, non-mandatory- or
+    // mempool-script-verify-flag-failed depending on the Core version.
+    pub fn expected_reason(self) -> Option<&'static str> {
+        match self {
+            Case::Control => None,
+            Case::BadSignature => Some("script-verify-flag"),
+            Case::Overspend => Some("bad-txns-in-belowout"),
+            Case::PrematureCoinbaseSpend => Some("bad-txns-premature-spend-of-coinbase"),
+            Case::DustOutput => Some("dust"),
+            Case::Oversize => Some("tx-size"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RejectReport {
+    pub mature: LocalUtxo,
+    pub immature: LocalUtxo,
+    pub cases: Vec<CaseReport>,
+    pub passed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CaseReport {
+    pub case: Case,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_reason: Option<&'static str>,
+    pub accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reject_reason: Option<String>,
+    pub weight: u64,
+    pub passed: bool,
+}
+
+// Whether the node's verdict is the one `case` calls for
+pub fn complies(case: Case, accepted: bool, reject_reason: Option<&str>) -> bool {
+    match case.expected_reason() {
+        None => accepted,
+        Some(expected) => !accepted && reject_reason.is_some_and(|r| r.contains(expected)),
+    }
+}
+
+// The coins a case spends: `mature` pays `key`, as does `immature` (a coinbase too young
+// to spend)
+pub struct Coins<'a> {
+    pub key: &'a LocalKey,
+    pub mature: &'a LocalUtxo,
+    pub immature: &'a LocalUtxo,
+    pub to: &'a LocalKey,
+}
+
+// `case`'s transaction, signed by `coins.key` (wrongly, for the bad signature)
+pub fn build(case: Case, coins: &Coins) -> Result<Transaction> {
+    let coin = match case {
+        Case::PrematureCoinbaseSpend => coins.immature,
+        _ => coins.mature,
+    };
+    let outputs = match case {
+        Case::Overspend => vec![coin.amount + Amount::from_sat(1)],
+        Case::DustOutput => vec![
+            Amount::from_sat(100),
+            coin.amount - FEE - Amount::from_sat(100),
+        ],
+        // Enough outputs at 31 vbytes each to go past the standard weight
+        Case::Oversize => {
+            let count = MAX_STANDARD_TX_WEIGHT.to_vbytes_ceil() / 31 + 1;
+            let split = vec![SPLIT_OUTPUT; count as usize];
+            let rest = coin.amount - FEE - SPLIT_OUTPUT * count;
+            [split, vec![rest]].concat()
+        }
+        _ => vec![coin.amount - FEE],
+    };
+    let mut tx = Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: coin.outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: outputs
+            .into_iter()
+            .map(|value| TxOut {
+                value,
+                script_pubkey: coins.to.script_pubkey(),
+            })
+            .collect(),
+    };
+    let prevouts = [TxOut {
+        value: coin.amount,
+        script_pubkey: coins.key.script_pubkey(),
+    }];
+    walletless::sign(&mut tx, &prevouts, coins.key)?;
+    if case == Case::BadSignature {
+        // Sign for the real amount, then claim the output was worth less: BIP143 commits to
+        // the amount spent, so the signature no longer verifies
+        let forged = [TxOut {
+            value: coin.amount - Amount::from_sat(1),
+            ..prevouts[0].clone()
+        }];
+        walletless::sign(&mut tx, &forged, coins.key)?;
+    }
+    Ok(tx)
+}
+
+// Mine a mature and an immature coin to a local key, build every case from them and check
+// that testmempoolaccept takes the control and rejects each of the rest for its reason.
+// Nothing is broadcast.
+pub fn run(rpc: &Client) -> Result<RejectReport> {
+    shutdown::enter(Phase::Setup);
+    let network = config::active().network;
+    let key = LocalKey::generate(network);
+    let to = LocalKey::generate(network);
+
+    shutdown::check()?;
+    shutdown::enter(Phase::Mining);
+    // The first coinbase matures under the next coinbase_maturity blocks, the last of those
+    // is still immature
+    let miner = LocalKey::generate(network);
+    rpc.generate_to_address(1, &key.address)?;
+    rpc.generate_to_address(consensus::params().coinbase_maturity - 1, &miner.address)?;
+    rpc.generate_to_address(1, &key.address)?;
+    let tip = rpc.get_block_count()?;
+    let mut found = walletless::find_utxos(rpc, &key.address)?;
+    found.sort_by_key(|u| u.height);
+    let (mature, immature) = match found.as_slice() {
+        [mature, immature] if mature.is_mature(tip) && !immature.is_mature(tip) => {
+            (mature.clone(), immature.clone())
+        }
+        _ => {
+            return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "expected a mature and an immature coin at {}, found {found:?}",
+                key.address
+            ))
+            .into())
+        }
+    };
+    // The oversize case spends the most
+    let least = FEE + SPLIT_OUTPUT * (MAX_STANDARD_TX_WEIGHT.to_vbytes_ceil() / 31 + 2);
+    if mature.amount < least || immature.amount < least {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "the block subsidy is down to {}, the oversize case needs {least}",
+            mature.amount.min(immature.amount)
+        ))
+        .into());
+    }
+
+    shutdown::check()?;
+    shutdown::enter(Phase::Sending);
+    let coins = Coins {
+        key: &key,
+        mature: &mature,
+        immature: &immature,
+        to: &to,
+    };
+    let mut cases = vec![];
+    for case in CASES {
+        let tx = build(case, &coins)?;
+        let (accepted, reject_reason) = walletless::test_accept(rpc, &tx)?;
+        cases.push(CaseReport {
+            case,
+            expected_reason: case.expected_reason(),
+            passed: complies(case, accepted, reject_reason.as_deref()),
+            accepted,
+            reject_reason,
+            weight: tx.weight().to_wu(),
+        });
+    }
+    Ok(RejectReport {
+        passed: cases.iter().all(|c| c.passed),
+        mature,
+        immature,
+        cases,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::coins::{self, DUST_RELAY_FEE};
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::secp256k1::SecretKey;
+    use bitcoincore_rpc::bitcoin::{Network, OutPoint, Txid};
+
+    fn key(byte: u8) -> LocalKey {
+        LocalKey::from_secret(
+            SecretKey::from_slice(&[byte; 32]).unwrap(),
+            Network::Regtest,
+        )
+    }
+
+    fn utxo(vout: u32, height: u64) -> LocalUtxo {
+        LocalUtxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), vout),
+            amount: Amount::from_int_btc(50),
+            height,
+        }
+    }
+
+    #[test]
+    fn builds_each_case_broken_in_its_own_way() {
+        let (from, to) = (key(1), key(2));
+        let (mature, immature) = (utxo(0, 1), utxo(1, 101));
+        let coins = Coins {
+            key: &from,
+            mature: &mature,
+            immature: &immature,
+            to: &to,
+        };
+        let control = build(Case::Control, &coins).unwrap();
+        assert_eq!(control.output[0].value, mature.amount - FEE);
+
+        let overspend = build(Case::Overspend, &coins).unwrap();
+        assert!(overspend.output[0].value > mature.amount);
+
+        let premature = build(Case::PrematureCoinbaseSpend, &coins).unwrap();
+        assert_eq!(premature.input[0].previous_output, immature.outpoint);
+
+        let dust = build(Case::DustOutput, &coins).unwrap();
+        let limit = coins::dust_threshold(&to.script_pubkey(), DUST_RELAY_FEE);
+        assert!(dust.output[0].value < limit);
+
+        let oversize = build(Case::Oversize, &coins).unwrap();
+        assert!(oversize.weight() > MAX_STANDARD_TX_WEIGHT);
+        assert!(control.weight() < MAX_STANDARD_TX_WEIGHT);
+
+        // Same transaction as the control, only the signature differs
+        let bad = build(Case::BadSignature, &coins).unwrap();
+        assert_eq!(bad.txid(), control.txid());
+        assert_ne!(bad.input[0].witness, control.input[0].witness);
+    }
+
+    #[test]
+    fn complies_only_with_the_expected_verdict() {
+        assert!(complies(Case::Control, true, None));
+        assert!(!complies(Case::Control, false, Some("dust")));
+        assert!(complies(
+            Case::BadSignature,
+            false,
+            Some("mandatory-script-verify-flag-failed (Signature must be zero for failed CHECK(MULTI)SIG operation)")
+        ));
+        assert!(!complies(Case::DustOutput, false, Some("tx-size")));
+        assert!(!complies(Case::Oversize, true, None));
+    }
+}
@@ -0,0 +1,600 @@
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::{Address, Amount, BlockHash, SignedAmount, Txid, Wtxid};
+use clap::ValueEnum;
+use minijinja::syntax::SyntaxConfig;
+use minijinja::value::Serde;
+use minijinja::{Environment, UndefinedBehavior};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::bip322::{self, Proof};
+use crate::consensus::WitnessCommitment;
+use crate::error::{Error, Result};
+use crate::hooks::{self, Event};
+use crate::script::OutputInfo;
+
+// How the fee is written to out.txt. The wallet reports what the sender paid as a negative
+// amount, the grader accepts either sign.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum FeeDisplay {
+    // Wallet convention, e.g. -0.00001410
+    Signed,
+    #[default]
+    Absolute,
+}
+
+// Version of the JSON report's layout, in the report itself as `schema_version`. Bumped
+// whenever a field changes or goes away, schemas/tx-report.v<N>.json describes each.
+pub const SCHEMA_VERSION: u32 = 1;
+const SCHEMA: &str = include_str!("../schemas/tx-report.v1.json");
+
+// How `--report` writes the report: JSON, or CBOR for archiving many of them, about a
+// quarter smaller with hashes as bytes and numbers in binary. `report decode` turns CBOR back
+// into the JSON report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Cbor,
+}
+
+// Everything the grader reads back from ../out.txt, one field per line in this order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxReport {
+    pub txid: Txid,
+    // Only ever read back from reports this program wrote, for the network they name
+    #[serde(deserialize_with = "checked_address")]
+    pub miner_input_address: Address,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub miner_input_amount: Amount,
+    #[serde(deserialize_with = "checked_address")]
+    pub trader_output_address: Address,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub trader_output_amount: Amount,
+    #[serde(deserialize_with = "checked_address")]
+    pub miner_change_address: Address,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub miner_change_amount: Amount,
+    // As gettransaction reports it, negative for the sender
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee: SignedAmount,
+    // The same fee without a sign, in satoshis
+    pub fee_sat: u64,
+    // Whether the transaction signals replaceability (BIP125) on any input
+    pub bip125_replaceable: bool,
+    pub block_height: u64,
+    pub block_hash: BlockHash,
+    // The txid leaves the witnesses out, so re-encoding a signature (malleating the
+    // transaction) changes only this. None in reports from before it was tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wtxid: Option<Wtxid>,
+    // Where the wtxid is in the block's witness commitment, checked against the coinbase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub witness_commitment: Option<WitnessCommitment>,
+    // Every output's script type and standardness, classified here rather than by the node.
+    // Only in the JSON report.
+    #[serde(default)]
+    pub outputs: Vec<OutputInfo>,
+    // What --privacy did for this transaction, only in the JSON report
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub privacy: Vec<PrivacyMeasure>,
+    // From --prove-ownership, only in the JSON report
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ownership_proofs: Option<OwnershipProofs>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<TxMetadata>,
+}
+
+fn checked_address<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Address, D::Error> {
+    Ok(Address::<NetworkUnchecked>::deserialize(deserializer)?.assume_checked())
+}
+
+// What tells one run's transaction from another's without changing the payment
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TxMetadata {
+    // The wallet label of the recipient's address, as the sender's gettransaction shows it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    // sendtoaddress's comment, which only the sending wallet keeps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+impl TxMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.label.is_none() && self.comment.is_none()
+    }
+}
+
+// BIP322 signatures by the wallets the report says hold its outputs, over a message naming
+// the transaction, so the claim can be checked without a node
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnershipProofs {
+    pub trader_output_address: Proof,
+    pub miner_change_address: Proof,
+}
+
+impl OwnershipProofs {
+    pub fn message(address: &Address, txid: &Txid) -> String {
+        format!("{address} is mine, as reported for transaction {txid}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrivacyMeasure {
+    // Both wallets have avoid_reuse set
+    AvoidReuse,
+    // The inputs are whole address groups
+    AvoidPartialSpends,
+    // The change address has the recipient address's type
+    MatchingChangeType,
+}
+
+// The JSON report as written: the report's fields with the schema version alongside
+#[derive(Debug, Serialize)]
+pub struct VersionedTxReport<'a> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub report: &'a TxReport,
+}
+
+impl TxReport {
+    pub fn versioned(&self) -> VersionedTxReport<'_> {
+        VersionedTxReport {
+            schema_version: SCHEMA_VERSION,
+            report: self,
+        }
+    }
+
+    // The compact form: a CBOR `[schema_version, report]` pair, the version first so a
+    // decoder can tell layouts apart before reading the rest
+    pub fn to_compact(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        ciborium::into_writer(&(SCHEMA_VERSION, self), &mut bytes)
+            .map_err(|e| Error::CompactReport(e.to_string()))?;
+        Ok(bytes)
+    }
+
+    pub fn from_compact(bytes: &[u8]) -> Result<TxReport> {
+        let (version, report): (u32, TxReport) =
+            ciborium::from_reader(bytes).map_err(|e| Error::CompactReport(e.to_string()))?;
+        if version != SCHEMA_VERSION {
+            return Err(Error::CompactReport(format!(
+                "schema version {version}, only {SCHEMA_VERSION} can be read"
+            )));
+        }
+        Ok(report)
+    }
+
+    pub fn absolute_fee(&self) -> Amount {
+        Amount::from_sat(self.fee.to_sat().unsigned_abs())
+    }
+
+    // The recipient got exactly `requested`, nothing subtracted or rounded away, and the
+    // change is what's left of the inputs after it and the fee, to the satoshi
+    pub fn check_exact_amounts(&self, requested: Amount) -> Result<()> {
+        let mut errors = vec![];
+        if self.trader_output_amount != requested {
+            errors.push(format!(
+                "{} paid to the trader, {requested} requested",
+                self.trader_output_amount
+            ));
+        }
+        let expected_change = self
+            .miner_input_amount
+            .checked_sub(requested)
+            .and_then(|left| left.checked_sub(self.absolute_fee()));
+        if expected_change != Some(self.miner_change_amount) {
+            errors.push(format!(
+                "change is {}, inputs {} - amount {requested} - fee {} leave {}",
+                self.miner_change_amount,
+                self.miner_input_amount,
+                self.absolute_fee(),
+                expected_change.map_or("less than nothing".to_owned(), |c| c.to_string())
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InexactAmounts(errors))
+        }
+    }
+
+    // Render the grading file. Amounts always get exactly 8 decimals, every line (the last
+    // one included) ends with '\n'.
+    pub fn to_out_txt(&self, fee_display: FeeDisplay) -> String {
+        let fee = self.display_fee(fee_display);
+        let mut out = String::new();
+        let lines = [
+            self.txid.to_string(),
+            self.miner_input_address.to_string(),
+            format_btc(self.miner_input_amount),
+            self.trader_output_address.to_string(),
+            format_btc(self.trader_output_amount),
+            self.miner_change_address.to_string(),
+            format_btc(self.miner_change_amount),
+            fee,
+            self.block_height.to_string(),
+            self.block_hash.to_string(),
+        ];
+        for line in lines {
+            writeln!(out, "{line}").unwrap();
+        }
+        out
+    }
+
+    // The report through a minijinja template (see `DEFAULT_TEMPLATE` and
+    // `TemplateFields`), for graders that want other lines than out.txt's. The output
+    // ends with a newline exactly when the template does.
+    pub fn render_template(&self, template: &str, fee_display: FeeDisplay) -> Result<String> {
+        let fields = TemplateFields {
+            txid: self.txid.to_string(),
+            miner_input_address: self.miner_input_address.to_string(),
+            miner_input_amount: format_btc(self.miner_input_amount),
+            miner_input_amount_sat: self.miner_input_amount.to_sat(),
+            trader_output_address: self.trader_output_address.to_string(),
+            trader_output_amount: format_btc(self.trader_output_amount),
+            trader_output_amount_sat: self.trader_output_amount.to_sat(),
+            miner_change_address: self.miner_change_address.to_string(),
+            miner_change_amount: format_btc(self.miner_change_amount),
+            miner_change_amount_sat: self.miner_change_amount.to_sat(),
+            fee: self.display_fee(fee_display),
+            fee_sat: self.fee_sat,
+            bip125_replaceable: self.bip125_replaceable,
+            block_height: self.block_height,
+            block_hash: self.block_hash.to_string(),
+        };
+        let mut env = Environment::new();
+        env.set_syntax(
+            SyntaxConfig::builder()
+                .keep_trailing_newline(true)
+                .build()?,
+        );
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        Ok(env.render_str(template, Serde(fields))?)
+    }
+
+    fn display_fee(&self, fee_display: FeeDisplay) -> String {
+        match fee_display {
+            FeeDisplay::Signed if self.fee.is_negative() => {
+                format!("-{}", format_btc(self.absolute_fee()))
+            }
+            FeeDisplay::Signed | FeeDisplay::Absolute => format_btc(self.absolute_fee()),
+        }
+    }
+}
+
+// The layout `to_out_txt` writes, as a template for `render_template`. A starting point for
+// a different order or format.
+pub const DEFAULT_TEMPLATE: &str = "\
+{{ txid }}
+{{ miner_input_address }}
+{{ miner_input_amount }}
+{{ trader_output_address }}
+{{ trader_output_amount }}
+{{ miner_change_address }}
+{{ miner_change_amount }}
+{{ fee }}
+{{ block_height }}
+{{ block_hash }}
+";
+
+// The names a template can use. Amounts come formatted like out.txt's (8 decimals, `fee`
+// signed as `fee_display` says) and in satoshis as `<name>_sat`.
+#[derive(Debug, Serialize)]
+struct TemplateFields {
+    txid: String,
+    miner_input_address: String,
+    miner_input_amount: String,
+    miner_input_amount_sat: u64,
+    trader_output_address: String,
+    trader_output_amount: String,
+    trader_output_amount_sat: u64,
+    miner_change_address: String,
+    miner_change_amount: String,
+    miner_change_amount_sat: u64,
+    fee: String,
+    fee_sat: u64,
+    bip125_replaceable: bool,
+    block_height: u64,
+    block_hash: String,
+}
+
+// Fixed-point BTC with 8 decimals, built from the satoshi count so there is never any float
+// rounding, scientific notation ("1.41e-05") or unit suffix involved
+pub fn format_btc(amount: Amount) -> String {
+    let sat = amount.to_sat();
+    format!(
+        "{}.{:08}",
+        sat / Amount::ONE_BTC.to_sat(),
+        sat % Amount::ONE_BTC.to_sat()
+    )
+}
+
+// Check a JSON report against the current schema, listing every violation
+pub fn validate(report: &serde_json::Value) -> Result<()> {
+    let schema: serde_json::Value = serde_json::from_str(SCHEMA)?;
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| Error::InvalidReport(vec![format!("schema does not compile: {e}")]))?;
+    let errors: Vec<String> = validator
+        .iter_errors(report)
+        .map(|e| format!("{} at '{}'", e, e.instance_path()))
+        .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::InvalidReport(errors))
+    }
+}
+
+// Check the ownership proofs of a JSON report: each has to be for the output address the
+// report names, over the message naming its transaction, and verify. One (field, valid)
+// pair per proof, none when the report has no proofs.
+pub fn verify_ownership(report: &serde_json::Value) -> Result<Vec<(&'static str, bool)>> {
+    if report["ownership_proofs"].is_null() {
+        return Ok(vec![]);
+    }
+    let proofs: OwnershipProofs = serde_json::from_value(report["ownership_proofs"].clone())?;
+    let txid: Txid = serde_json::from_value(report["txid"].clone())?;
+    let mut checked = vec![];
+    for (field, proof) in [
+        ("trader_output_address", &proofs.trader_output_address),
+        ("miner_change_address", &proofs.miner_change_address),
+    ] {
+        let address = proof.address.clone().assume_checked();
+        let claimed = report[field].as_str() == Some(address.to_string().as_str());
+        let message = proof.message == OwnershipProofs::message(&address, &txid);
+        checked.push((field, claimed && message && bip322::verify(proof)?));
+    }
+    Ok(checked)
+}
+
+// Pretty-printed JSON of `value` to `path`, failing as a report write error
+pub fn write_json(path: &Path, value: &impl Serialize) -> Result<()> {
+    let written = File::create(path)
+        .and_then(|f| serde_json::to_writer_pretty(f, value).map_err(std::io::Error::from));
+    written.map_err(|source| Error::ReportWrite {
+        path: path.to_owned(),
+        source,
+    })?;
+    hooks::fire(
+        Event::ReportWritten,
+        &json!({ "path": path, "format": "json" }),
+    );
+    Ok(())
+}
+
+// `report` in its compact form to `path`, failing as a report write error
+pub fn write_compact(path: &Path, report: &TxReport) -> Result<()> {
+    fs::write(path, report.to_compact()?).map_err(|source| Error::ReportWrite {
+        path: path.to_owned(),
+        source,
+    })?;
+    hooks::fire(
+        Event::ReportWritten,
+        &json!({ "path": path, "format": "cbor" }),
+    );
+    Ok(())
+}
+
+// `contents` to `path`, e.g. ../out.txt, failing as a report write error
+pub fn write_text(path: &Path, contents: &str) -> Result<()> {
+    fs::write(path, contents).map_err(|source| Error::ReportWrite {
+        path: path.to_owned(),
+        source,
+    })?;
+    hooks::fire(
+        Event::ReportWritten,
+        &json!({ "path": path, "format": "text" }),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::Network;
+    use std::str::FromStr;
+
+    fn addr(s: &str) -> Address {
+        Address::from_str(s)
+            .unwrap()
+            .require_network(Network::Regtest)
+            .unwrap()
+    }
+
+    fn sample() -> TxReport {
+        TxReport {
+            txid: Txid::from_str(
+                "57ecbb84fd3246ebcc734455fd30f5536637878b40fb2742d1a4fced3c28862c",
+            )
+            .unwrap(),
+            miner_input_address: addr("bcrt1qv5plgft75j0hegtvf6zs5pajh7k0gxg2dhj224"),
+            miner_input_amount: Amount::from_int_btc(50),
+            trader_output_address: addr("bcrt1qak6gpu2p6zjpwrhvd4dvdnp4rt3ysm9rpst3wu"),
+            trader_output_amount: Amount::from_int_btc(20),
+            miner_change_address: addr("bcrt1qxw3msnuqps0kgn6dprs9ldlz79yfj63swqupd0"),
+            miner_change_amount: Amount::from_sat(2_999_998_590),
+            fee: SignedAmount::from_sat(-1_410),
+            fee_sat: 1_410,
+            bip125_replaceable: false,
+            block_height: 102,
+            block_hash: BlockHash::all_zeros(),
+            wtxid: None,
+            witness_commitment: None,
+            outputs: vec![],
+            privacy: vec![],
+            ownership_proofs: None,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn formats_btc_with_eight_decimals() {
+        assert_eq!(format_btc(Amount::ZERO), "0.00000000");
+        assert_eq!(format_btc(Amount::from_sat(1)), "0.00000001");
+        assert_eq!(format_btc(Amount::from_sat(1_410)), "0.00001410");
+        assert_eq!(format_btc(Amount::from_int_btc(20)), "20.00000000");
+        assert_eq!(format_btc(Amount::from_sat(2_999_998_590)), "29.99998590");
+        assert_eq!(format_btc(Amount::MAX_MONEY), "21000000.00000000");
+    }
+
+    #[test]
+    fn renders_the_grading_file() {
+        let out = sample().to_out_txt(FeeDisplay::Absolute);
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 10);
+        assert_eq!(lines[2], "50.00000000");
+        assert_eq!(lines[4], "20.00000000");
+        assert_eq!(lines[6], "29.99998590");
+        assert_eq!(lines[8], "102");
+        assert!(out.ends_with('\n') && !out.ends_with("\n\n"));
+    }
+
+    #[test]
+    fn normalizes_negative_fee() {
+        let out = sample().to_out_txt(FeeDisplay::Absolute);
+        assert_eq!(out.lines().nth(7), Some("0.00001410"));
+
+        let positive = TxReport {
+            fee: SignedAmount::from_sat(1_410),
+            ..sample()
+        };
+        assert_eq!(positive.to_out_txt(FeeDisplay::Absolute), out);
+    }
+
+    #[test]
+    fn checks_amounts_to_the_satoshi() {
+        sample()
+            .check_exact_amounts(Amount::from_int_btc(20))
+            .unwrap();
+
+        let short = TxReport {
+            trader_output_amount: Amount::from_int_btc(20) - Amount::from_sat(1_410),
+            miner_change_amount: Amount::from_int_btc(30),
+            ..sample()
+        };
+        match short.check_exact_amounts(Amount::from_int_btc(20)) {
+            Err(Error::InexactAmounts(errors)) => assert_eq!(errors.len(), 2, "{errors:?}"),
+            other => panic!("expected inexact amounts, got {other:?}"),
+        }
+
+        let rounded = TxReport {
+            miner_change_amount: Amount::from_sat(2_999_998_591),
+            ..sample()
+        };
+        assert!(rounded
+            .check_exact_amounts(Amount::from_int_btc(20))
+            .is_err());
+    }
+
+    #[test]
+    fn keeps_wallet_sign_when_asked() {
+        let out = sample().to_out_txt(FeeDisplay::Signed);
+        assert_eq!(out.lines().nth(7), Some("-0.00001410"));
+    }
+
+    #[test]
+    fn default_template_matches_the_grading_file() {
+        for display in [FeeDisplay::Absolute, FeeDisplay::Signed] {
+            let rendered = sample().render_template(DEFAULT_TEMPLATE, display).unwrap();
+            assert_eq!(rendered, sample().to_out_txt(display));
+        }
+    }
+
+    #[test]
+    fn renders_a_custom_template() {
+        let template = "Gebühr: {{ fee_sat }} sat\nBlock {{ block_height }}";
+        let rendered = sample()
+            .render_template(template, FeeDisplay::Absolute)
+            .unwrap();
+        assert_eq!(rendered, "Gebühr: 1410 sat\nBlock 102");
+
+        let unknown = sample().render_template("{{ nope }}", FeeDisplay::Absolute);
+        assert!(matches!(unknown, Err(Error::Template(_))));
+    }
+
+    #[test]
+    fn versioned_report_matches_its_schema() {
+        let json = serde_json::to_value(sample().versioned()).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        validate(&json).unwrap();
+
+        let private = TxReport {
+            privacy: vec![PrivacyMeasure::AvoidReuse],
+            metadata: Some(TxMetadata {
+                label: Some("rent".into()),
+                comment: None,
+            }),
+            outputs: vec![OutputInfo {
+                vout: 0,
+                value: Amount::from_int_btc(20),
+                script_type: crate::script::ScriptType::P2wpkh,
+                standard: true,
+                segwit: true,
+                taproot: false,
+                witness_version: Some(0),
+            }],
+            wtxid: Some(Wtxid::all_zeros()),
+            witness_commitment: Some(WitnessCommitment {
+                commitment: Hash::all_zeros(),
+                witness_root: Hash::all_zeros(),
+                position: 1,
+                branch: vec![Hash::all_zeros()],
+            }),
+            ..sample()
+        };
+        validate(&serde_json::to_value(private.versioned()).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn compact_report_round_trips() {
+        let report = TxReport {
+            privacy: vec![PrivacyMeasure::AvoidReuse],
+            ..sample()
+        };
+        let compact = report.to_compact().unwrap();
+        let json = serde_json::to_vec(&report.versioned()).unwrap();
+        assert!(compact.len() < json.len());
+
+        let decoded = TxReport::from_compact(&compact).unwrap();
+        assert_eq!(
+            serde_json::to_value(decoded.versioned()).unwrap(),
+            serde_json::to_value(report.versioned()).unwrap()
+        );
+
+        let mut future = vec![];
+        ciborium::into_writer(&(SCHEMA_VERSION + 1, &report), &mut future).unwrap();
+        assert!(matches!(
+            TxReport::from_compact(&future),
+            Err(Error::CompactReport(_))
+        ));
+        assert!(TxReport::from_compact(&json).is_err());
+    }
+
+    #[test]
+    fn schema_catches_format_drift() {
+        let mut json = serde_json::to_value(sample().versioned()).unwrap();
+        json["fee_sat"] = serde_json::json!("1410");
+        json.as_object_mut().unwrap().remove("block_hash");
+        match validate(&json) {
+            Err(Error::InvalidReport(errors)) => assert_eq!(errors.len(), 2, "{errors:?}"),
+            other => panic!("expected a schema violation, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn json_report_has_both_fee_conventions() {
+        let json = serde_json::to_value(sample()).unwrap();
+        assert_eq!(json["fee"], serde_json::json!(-0.0000141));
+        assert_eq!(json["fee_sat"], 1_410);
+        assert_eq!(json["trader_output_amount"], serde_json::json!(20.0));
+    }
+}
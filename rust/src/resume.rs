@@ -0,0 +1,98 @@
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::{Address, Amount, OutPoint, Txid};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::error::Result;
+use crate::report::PrivacyMeasure;
+use crate::store;
+
+// Where the flow leaves its checkpoint, next to Cargo.toml like ../out.txt's writer runs
+pub const DEFAULT_CHECKPOINT: &str = ".flow-checkpoint.json";
+
+// Everything the flow knows once the payment is broadcast, the point from which starting
+// over would mean mining and paying again. Confirmation and the report only need this.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub from: String,
+    pub to: String,
+    pub txid: Txid,
+    pub inputs: Vec<OutPoint>,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub input_amount: Amount,
+    // The sender's address the confirming block is mined to
+    pub miner_address: Address<NetworkUnchecked>,
+    #[serde(default)]
+    pub privacy: Vec<PrivacyMeasure>,
+    #[serde(default)]
+    pub prove_ownership: bool,
+    // The label the payment was sent with
+    #[serde(default)]
+    pub label: Option<String>,
+    // What the recipient was to get, checked to the satoshi with `exact_amounts`
+    #[serde(default, with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    #[serde(default)]
+    pub exact_amounts: bool,
+    // Where the change was sent when it wasn't the sender's own choice of address
+    #[serde(default)]
+    pub change_address: Option<Address<NetworkUnchecked>>,
+}
+
+impl Checkpoint {
+    // Replaced whole, so a run dying mid-write leaves the old one (or none) rather than
+    // half a checkpoint
+    pub fn save(&self, path: &Path) -> Result<()> {
+        store::write(path, &serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Checkpoint> {
+        let _lock = store::lock_shared(path)?;
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    // Once the report is written there's nothing left to resume
+    pub fn clear(path: &Path) -> Result<()> {
+        let _lock = store::lock(path)?;
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use std::str::FromStr;
+
+    #[test]
+    fn saves_loads_and_clears() {
+        let checkpoint = Checkpoint {
+            from: "Miner".into(),
+            to: "Trader".into(),
+            txid: Txid::all_zeros(),
+            inputs: vec![OutPoint::new(Txid::all_zeros(), 1)],
+            input_amount: Amount::from_int_btc(50),
+            miner_address: Address::from_str("bcrt1qv5plgft75j0hegtvf6zs5pajh7k0gxg2dhj224")
+                .unwrap(),
+            privacy: vec![PrivacyMeasure::AvoidReuse],
+            prove_ownership: false,
+            label: Some("rent".into()),
+            amount: Amount::from_int_btc(20),
+            exact_amounts: true,
+            change_address: None,
+        };
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.json", std::process::id()));
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint);
+        Checkpoint::clear(&path).unwrap();
+        assert!(!path.exists());
+        // Clearing twice is fine, a finished run has nothing to clear
+        Checkpoint::clear(&path).unwrap();
+    }
+}
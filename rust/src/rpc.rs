@@ -0,0 +1,206 @@
+use bitcoincore_rpc::jsonrpc::simple_http::SimpleHttpTransport;
+use bitcoincore_rpc::jsonrpc::{self, Request, Response, Transport};
+use bitcoincore_rpc::{Auth, Client, RpcApi};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use crate::audit;
+
+#[cfg(test)]
+pub mod mock;
+
+// Wraps a client and memoizes responses that can never change: raw transactions, blocks
+// and headers fetched by hash. Verbose forms are passed through since they carry fields
+// such as `confirmations` that move with the chain tip.
+pub struct CachingClient<R> {
+    inner: R,
+    cache: Mutex<HashMap<String, Value>>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl<R: RpcApi> CachingClient<R> {
+    pub fn new(inner: R) -> Self {
+        CachingClient {
+            inner,
+            cache: Mutex::new(HashMap::new()),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &R {
+        &self.inner
+    }
+
+    // (hits, misses) since the client was created
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+}
+
+fn is_cacheable(cmd: &str, args: &[Value]) -> bool {
+    // false and 0 select the raw (non-verbose) encoding, what an absent argument means
+    // depends on the call
+    let raw = |arg: Option<&Value>, default: bool| match arg {
+        None | Some(Value::Null) => default,
+        Some(Value::Bool(b)) => !b,
+        Some(Value::Number(n)) => n.as_u64() == Some(0),
+        _ => false,
+    };
+    match cmd {
+        "getrawtransaction" => raw(args.get(1), true),
+        "getblock" | "getblockheader" => raw(args.get(1), false),
+        _ => false,
+    }
+}
+
+impl<R: RpcApi> RpcApi for CachingClient<R> {
+    fn call<T: DeserializeOwned>(&self, cmd: &str, args: &[Value]) -> bitcoincore_rpc::Result<T> {
+        if !is_cacheable(cmd, args) {
+            return self.inner.call(cmd, args);
+        }
+
+        let key = format!("{cmd}{}", Value::from(args));
+        let cached = self.cache.lock().unwrap().get(&key).cloned();
+        let value = match cached {
+            Some(value) => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                value
+            }
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                let value: Value = self.inner.call(cmd, args)?;
+                self.cache.lock().unwrap().insert(key, value.clone());
+                value
+            }
+        };
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+// The node's error code (RPC_WALLET_NOT_FOUND etc.) if `e` is an error the node returned
+pub fn error_code(e: &bitcoincore_rpc::Error) -> Option<i32> {
+    match e {
+        bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::error::Error::Rpc(e)) => {
+            Some(e.code)
+        }
+        _ => None,
+    }
+}
+
+// Where --trace-rpc logs to, set once at startup
+static TRACE_LOG: OnceLock<Mutex<File>> = OnceLock::new();
+// Raw body of the most recent response from the node, shown when the run fails
+static LAST_RESPONSE: Mutex<Option<String>> = Mutex::new(None);
+
+// Calls whose parameters carry wallet passphrases
+const SECRET_PARAMS: &[&str] = &[
+    "createwallet",
+    "encryptwallet",
+    "walletpassphrase",
+    "walletpassphrasechange",
+];
+
+// Log every request and response as one JSON line to `path`
+pub fn trace_to(path: &Path) -> io::Result<()> {
+    let file = File::create(path)?;
+    let _ = TRACE_LOG.set(Mutex::new(file));
+    Ok(())
+}
+
+pub fn last_response() -> Option<String> {
+    LAST_RESPONSE.lock().unwrap().clone()
+}
+
+// Client on the plain HTTP transport, wrapped so the traffic can be traced. The
+// credentials only live in the transport's auth header and are never logged.
+pub fn connect(url: &str, auth: Auth) -> bitcoincore_rpc::Result<Client> {
+    let mut builder = SimpleHttpTransport::builder()
+        .url(url)
+        .map_err(|e| bitcoincore_rpc::Error::JsonRpc(e.into()))?;
+    if let (Some(user), pass) = auth.get_user_pass()? {
+        builder = builder.auth(user, pass);
+    }
+    let inner = builder.build();
+    let transport = TracingTransport {
+        inner,
+        url: url.to_owned(),
+    };
+    Ok(Client::from_jsonrpc(jsonrpc::Client::with_transport(
+        transport,
+    )))
+}
+
+struct TracingTransport {
+    inner: SimpleHttpTransport,
+    url: String,
+}
+
+impl TracingTransport {
+    fn record(&self, method: &str, request: &Request, outcome: &Result<Response, jsonrpc::Error>) {
+        let raw = match outcome {
+            Ok(response) => serde_json::to_string(response).unwrap_or_default(),
+            Err(e) => e.to_string(),
+        };
+        *LAST_RESPONSE.lock().unwrap() = Some(raw.clone());
+
+        if audit::is_signing(method) {
+            if let Ok(result) = outcome.as_ref().map(|r| r.result::<Value>()) {
+                let params = json!(request.params);
+                let params = params.as_array().map_or(&[][..], Vec::as_slice);
+                if let Some(record) = result
+                    .ok()
+                    .and_then(|result| audit::describe(&self.url, method, params, &result))
+                {
+                    audit::note(record);
+                }
+            }
+        }
+
+        if let Some(log) = TRACE_LOG.get() {
+            let params = if SECRET_PARAMS.contains(&method) {
+                json!("<redacted>")
+            } else {
+                json!(request.params)
+            };
+            let entry = json!({
+                "url": self.url,
+                "method": method,
+                "params": params,
+                "response": serde_json::from_str::<Value>(&raw).unwrap_or(Value::String(raw)),
+            });
+            let mut log = log.lock().unwrap();
+            if let Err(e) = writeln!(log, "{entry}") {
+                eprintln!("Failed to write the RPC trace: {e}");
+            }
+        }
+    }
+}
+
+impl Transport for TracingTransport {
+    fn send_request(&self, request: Request) -> Result<Response, jsonrpc::Error> {
+        let method = request.method;
+        let outcome = self.inner.send_request(request.clone());
+        self.record(method, &request, &outcome);
+        outcome
+    }
+
+    fn send_batch(&self, requests: &[Request]) -> Result<Vec<Response>, jsonrpc::Error> {
+        self.inner.send_batch(requests)
+    }
+
+    fn fmt_target(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.inner.fmt_target(f)
+    }
+}
@@ -0,0 +1,81 @@
+use bitcoincore_rpc::jsonrpc::error::{Error as JsonRpcError, RpcError};
+use bitcoincore_rpc::RpcApi;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+// A scripted node for tests: every call must match the next expected method and gets the
+// canned result (or node error) back. Calls are recorded so tests can check what was sent.
+#[derive(Default)]
+pub struct MockClient {
+    expected: Mutex<VecDeque<(String, Result<Value, RpcError>)>>,
+    calls: Mutex<Vec<(String, Vec<Value>)>>,
+}
+
+impl MockClient {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn returns(self, cmd: &str, result: Value) -> Self {
+        self.expected
+            .lock()
+            .unwrap()
+            .push_back((cmd.to_owned(), Ok(result)));
+        self
+    }
+
+    pub fn fails(self, cmd: &str, code: i32, message: &str) -> Self {
+        let error = RpcError {
+            code,
+            message: message.to_owned(),
+            data: None,
+        };
+        self.expected
+            .lock()
+            .unwrap()
+            .push_back((cmd.to_owned(), Err(error)));
+        self
+    }
+
+    // Methods called so far, in order
+    pub fn calls(&self) -> Vec<String> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(cmd, _)| cmd.clone())
+            .collect()
+    }
+
+    // Arguments of the `n`th call made
+    pub fn args(&self, n: usize) -> Vec<Value> {
+        self.calls.lock().unwrap()[n].1.clone()
+    }
+
+    pub fn assert_done(&self) {
+        let left = self.expected.lock().unwrap();
+        assert!(left.is_empty(), "expected calls never made: {left:?}");
+    }
+}
+
+impl RpcApi for MockClient {
+    fn call<T: DeserializeOwned>(&self, cmd: &str, args: &[Value]) -> bitcoincore_rpc::Result<T> {
+        self.calls
+            .lock()
+            .unwrap()
+            .push((cmd.to_owned(), args.to_vec()));
+        let (expected, result) = self
+            .expected
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| panic!("unexpected call to {cmd}"));
+        assert_eq!(expected, cmd, "called {cmd} while {expected} was expected");
+        match result {
+            Ok(value) => Ok(serde_json::from_value(value)?),
+            Err(e) => Err(JsonRpcError::Rpc(e).into()),
+        }
+    }
+}
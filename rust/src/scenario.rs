@@ -0,0 +1,1117 @@
+use bitcoincore_rpc::bitcoin::{Amount, FeeRate, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::amount::parse_amount;
+use crate::batcher::{BatchOptions, BatchReport, Batcher, Payment};
+use crate::builder::Budget;
+use crate::coinjoin::{self, CoinjoinReport};
+use crate::coins;
+use crate::coldhot;
+use crate::passphrase::{self, Source};
+use crate::report::TxMetadata;
+use crate::shutdown::{self, Phase, RunStatus};
+use crate::wallet;
+
+// A scripted exercise, e.g.
+//
+//   name = "Pay the trader"
+//
+//   [[steps]]
+//   action = "fund"
+//   wallet = "Miner"
+//
+//   [[steps]]
+//   action = "send"
+//   from = "Miner"
+//   to = "Trader"
+//   amount = "20 BTC"
+//
+//   [[steps.assert]]
+//   type = "assert_output_count"
+//   count = 2
+//
+// Steps that sign (`send`, `coinjoin`, `refill`, `queue`, `flush`) can carry
+// `passphrase = "prompt"` or `passphrase = { env = "VAR" }` for when their wallets are
+// encrypted.
+#[derive(Debug, Deserialize)]
+pub struct Scenario {
+    pub name: Option<String>,
+    // When `queue` steps send their batch, see `Batching`
+    #[serde(default)]
+    pub batching: Batching,
+    pub steps: Vec<Step>,
+}
+
+// `[batching]`: a sender's queued payments go out once `max_payments` are waiting or the
+// oldest has waited `interval` seconds, checked at each `queue` step
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Batching {
+    pub max_payments: usize,
+    pub interval: u64,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        let options = BatchOptions::default();
+        Batching {
+            max_payments: options.max_payments,
+            interval: options.interval.as_secs(),
+        }
+    }
+}
+
+impl Batching {
+    fn options(&self) -> BatchOptions {
+        BatchOptions {
+            max_payments: self.max_payments,
+            interval: Duration::from_secs(self.interval),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Step {
+    #[serde(flatten)]
+    pub action: Action,
+    // Checked right after the action ran
+    #[serde(default, rename = "assert")]
+    pub assertions: Vec<Assertion>,
+    // Used when a wallet the action signs with is encrypted, see `passphrase::unlock`
+    pub passphrase: Option<Source>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    // Mine to `wallet` until its spendable balance reaches `amount` (any positive balance
+    // if no amount is given)
+    Fund {
+        wallet: String,
+        #[serde(default, deserialize_with = "de_opt_amount")]
+        amount: Option<Amount>,
+    },
+    // Pay `amount` from wallet `from` to a fresh address of wallet `to`, labelled `label`
+    // in both wallets and with `comment` kept by the sender, to tell it apart afterwards
+    Send {
+        from: String,
+        to: String,
+        #[serde(deserialize_with = "de_amount")]
+        amount: Amount,
+        label: Option<String>,
+        comment: Option<String>,
+    },
+    // Mine `blocks` blocks with the rewards going to wallet `to`
+    Mine {
+        blocks: u64,
+        to: String,
+    },
+    // Join coins of all `wallets` into one transaction paying each of them `amount`, see
+    // `coinjoin::run`. The wallet's fee estimate is used unless `fee_rate` (sat/vB) is given.
+    Coinjoin {
+        wallets: Vec<String>,
+        #[serde(deserialize_with = "de_amount")]
+        amount: Amount,
+        fee_rate: Option<u64>,
+    },
+    // Queue a payment of `amount` from `from` to a fresh address of `to`, sent with the
+    // payments queued before it once `[batching]` says the batch is due
+    Queue {
+        from: String,
+        to: String,
+        #[serde(deserialize_with = "de_amount")]
+        amount: Amount,
+    },
+    // Send whatever `from` has queued now
+    Flush {
+        from: String,
+    },
+    // Make `wallet` a watch-only copy of `signer`: it's created without keys and gets the
+    // signer's public descriptors, see `coldhot::watch`
+    WatchOnly {
+        wallet: String,
+        signer: String,
+    },
+    // Top `hot` back up to `target` from the watch-only `cold` wallet, with `signer`
+    // signing, if `hot` has less than `below`. The refill mustn't pay more than `max_fee`.
+    Refill {
+        hot: String,
+        cold: String,
+        signer: String,
+        #[serde(deserialize_with = "de_amount")]
+        below: Amount,
+        #[serde(deserialize_with = "de_amount")]
+        target: Amount,
+        #[serde(default, deserialize_with = "de_opt_amount")]
+        max_fee: Option<Amount>,
+    },
+    // Does nothing, for steps that only carry assertions
+    Check,
+}
+
+// Assertions about a transaction default to the one sent by the most recent `send` step
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type")]
+pub enum Assertion {
+    // Exactly `amount`, give or take `tolerance` (what fees took, say), or anywhere from
+    // `min` to `max` with either end left open
+    #[serde(rename = "assert_balance")]
+    Balance {
+        wallet: String,
+        #[serde(default, deserialize_with = "de_opt_amount")]
+        amount: Option<Amount>,
+        #[serde(default, deserialize_with = "de_opt_amount")]
+        tolerance: Option<Amount>,
+        #[serde(default, deserialize_with = "de_opt_amount")]
+        min: Option<Amount>,
+        #[serde(default, deserialize_with = "de_opt_amount")]
+        max: Option<Amount>,
+    },
+    #[serde(rename = "assert_confirmations")]
+    Confirmations { txid: Option<Txid>, min: i32 },
+    #[serde(rename = "assert_fee_below")]
+    FeeBelow {
+        txid: Option<Txid>,
+        #[serde(deserialize_with = "de_amount")]
+        amount: Amount,
+    },
+    #[serde(rename = "assert_output_count")]
+    OutputCount { txid: Option<Txid>, count: usize },
+}
+
+impl Action {
+    fn wallets(&self) -> Vec<&str> {
+        match self {
+            Action::Fund { wallet, .. } => vec![wallet],
+            Action::Send { from, to, .. } => vec![from, to],
+            Action::Mine { to, .. } => vec![to],
+            Action::Coinjoin { wallets, .. } => wallets.iter().map(String::as_str).collect(),
+            Action::Queue { from, to, .. } => vec![from, to],
+            Action::Flush { from } => vec![from],
+            Action::WatchOnly { wallet, signer } => vec![wallet, signer],
+            // The cold wallet first: it's the one that knows the refill's fee
+            Action::Refill {
+                hot, cold, signer, ..
+            } => vec![cold, hot, signer],
+            Action::Check => vec![],
+        }
+    }
+
+    // A wallet this action needs created without keys
+    fn watch_only(&self) -> Option<&str> {
+        match self {
+            Action::WatchOnly { wallet, .. } => Some(wallet),
+            _ => None,
+        }
+    }
+}
+
+impl Action {
+    // The wallets whose keys the action signs with
+    fn signers(&self) -> Vec<&str> {
+        match self {
+            Action::Send { from, .. } => vec![from],
+            Action::Coinjoin { wallets, .. } => wallets.iter().map(String::as_str).collect(),
+            Action::Refill { signer, .. } => vec![signer],
+            Action::Queue { from, .. } | Action::Flush { from } => vec![from],
+            Action::Fund { .. }
+            | Action::Mine { .. }
+            | Action::WatchOnly { .. }
+            | Action::Check => vec![],
+        }
+    }
+}
+
+impl Step {
+    fn wallets(&self) -> Vec<&str> {
+        let mut wallets = self.action.wallets();
+        wallets.extend(self.assertions.iter().filter_map(|a| match a {
+            Assertion::Balance { wallet, .. } => Some(wallet.as_str()),
+            _ => None,
+        }));
+        wallets
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Action::Fund {
+                wallet,
+                amount: Some(amount),
+            } => write!(f, "fund {wallet} with {amount}"),
+            Action::Fund { wallet, .. } => write!(f, "fund {wallet}"),
+            Action::Send {
+                from,
+                to,
+                amount,
+                label: Some(label),
+                ..
+            } => write!(f, "send {amount} from {from} to {to} ({label})"),
+            Action::Send {
+                from, to, amount, ..
+            } => write!(f, "send {amount} from {from} to {to}"),
+            Action::Mine { blocks, to } => write!(f, "mine {blocks} block(s) to {to}"),
+            Action::Coinjoin {
+                wallets, amount, ..
+            } => write!(f, "coinjoin {amount} each for {}", wallets.join(", ")),
+            Action::Queue { from, to, amount } => {
+                write!(f, "queue {amount} from {from} to {to}")
+            }
+            Action::Flush { from } => write!(f, "flush {from}'s queued payments"),
+            Action::WatchOnly { wallet, signer } => {
+                write!(f, "make {wallet} a watch-only wallet for {signer}")
+            }
+            Action::Refill {
+                hot,
+                cold,
+                below,
+                target,
+                ..
+            } => write!(f, "refill {hot} to {target} from {cold} if below {below}"),
+            Action::Check => write!(f, "check"),
+        }
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tx = |txid: &Option<Txid>| match txid {
+            Some(txid) => txid.to_string(),
+            None => "last tx".to_owned(),
+        };
+        match self {
+            Assertion::Balance {
+                wallet,
+                amount: Some(amount),
+                tolerance,
+                ..
+            } => {
+                write!(f, "{wallet} balance is {amount}")?;
+                match tolerance {
+                    Some(tolerance) => write!(f, " +- {tolerance}"),
+                    None => Ok(()),
+                }
+            }
+            Assertion::Balance {
+                wallet, min, max, ..
+            } => match (min, max) {
+                (Some(min), Some(max)) => write!(f, "{wallet} balance is {min} to {max}"),
+                (Some(min), None) => write!(f, "{wallet} balance is at least {min}"),
+                (None, Some(max)) => write!(f, "{wallet} balance is at most {max}"),
+                (None, None) => write!(f, "{wallet} balance"),
+            },
+            Assertion::Confirmations { txid, min } => {
+                write!(f, "{} has at least {min} confirmation(s)", tx(txid))
+            }
+            Assertion::FeeBelow { txid, amount } => {
+                write!(f, "{} pays less than {amount} in fees", tx(txid))
+            }
+            Assertion::OutputCount { txid, count } => {
+                write!(f, "{} has {count} output(s)", tx(txid))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Rpc(bitcoincore_rpc::Error),
+    // A step could not be carried out
+    Step { index: usize, reason: String },
+    // The scenario ran to the end but some steps did not pass
+    Failed,
+    // Stopped by a signal before running to the end
+    Aborted,
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "could not read scenario: {e}"),
+            ScenarioError::Parse(e) => write!(f, "invalid scenario: {e}"),
+            ScenarioError::Rpc(e) => write!(f, "RPC error: {e}"),
+            ScenarioError::Step { index, reason } => write!(f, "step {}: {reason}", index + 1),
+            ScenarioError::Failed => write!(f, "one or more steps failed"),
+            ScenarioError::Aborted => write!(f, "aborted by signal"),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<std::io::Error> for ScenarioError {
+    fn from(e: std::io::Error) -> Self {
+        ScenarioError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ScenarioError {
+    fn from(e: toml::de::Error) -> Self {
+        ScenarioError::Parse(e)
+    }
+}
+
+impl From<bitcoincore_rpc::Error> for ScenarioError {
+    fn from(e: bitcoincore_rpc::Error) -> Self {
+        ScenarioError::Rpc(e)
+    }
+}
+
+// Machine-readable outcome of a scenario run, for automated grading
+#[derive(Debug, Serialize)]
+pub struct ScenarioReport {
+    pub scenario: Option<String>,
+    pub status: RunStatus,
+    pub passed: bool,
+    pub steps: Vec<StepReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepReport {
+    pub step: usize,
+    pub action: String,
+    pub status: StepStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub assertions: Vec<AssertionReport>,
+    // Who brought and got what, for `coinjoin` steps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coinjoin: Option<CoinjoinReport>,
+    // The sent transaction and its label and comment as the sender's wallet has them, for
+    // `send` steps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub txid: Option<Txid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<TxMetadata>,
+    // The batch a `queue` or `flush` step sent, with what it saved
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<BatchReport>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Passed,
+    // The action ran but at least one assertion did not hold
+    Failed,
+    // The action itself errored, later steps are skipped
+    Error,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AssertionReport {
+    pub assertion: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+// What an action left behind for the assertions and the report
+#[derive(Default)]
+struct ActionOutcome {
+    txid: Option<Txid>,
+    coinjoin: Option<CoinjoinReport>,
+    metadata: Option<TxMetadata>,
+    batch: Option<BatchReport>,
+}
+
+// Most recent transaction sent by the scenario, along with the wallet that sent it
+struct LastTx<'a> {
+    wallet: &'a str,
+    txid: Txid,
+}
+
+impl Scenario {
+    pub fn from_file(path: &Path) -> Result<Self, ScenarioError> {
+        let text = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+
+    // Runs every step and evaluates its assertions. A failed assertion marks the step (and
+    // the scenario) failed but keeps going; an action error skips the remaining steps, and
+    // so does a signal, between steps or while a `fund` step mines.
+    pub fn run(&self, rpc: &Client) -> Result<ScenarioReport, ScenarioError> {
+        if let Some(name) = &self.name {
+            println!("Scenario: {name}");
+        }
+
+        // e1ec30: Load every wallet the script mentions up front, so a typo fails before
+        // anything has been mined or sent
+        shutdown::enter(Phase::Setup);
+        let mut wallets = HashMap::new();
+        let mut _guards = vec![];
+        for name in self.steps.iter().flat_map(Step::wallets) {
+            if !wallets.contains_key(name) {
+                let options = wallet::CreateOptions {
+                    watch_only: self
+                        .steps
+                        .iter()
+                        .any(|s| s.action.watch_only() == Some(name)),
+                    ..Default::default()
+                };
+                let (client, guard) = wallet::open_guarded(rpc, name, options)?;
+                wallets.insert(name, client);
+                _guards.push(guard);
+            }
+        }
+
+        let mut report = ScenarioReport {
+            scenario: self.name.clone(),
+            status: RunStatus::Completed,
+            passed: true,
+            steps: Vec::with_capacity(self.steps.len()),
+        };
+        let mut last_tx = None;
+        let mut errored = false;
+        let mut batchers = Batchers {
+            options: self.batching.options(),
+            queues: HashMap::new(),
+        };
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let mut step_report = StepReport {
+                step: index + 1,
+                action: step.action.to_string(),
+                status: StepStatus::Skipped,
+                error: None,
+                assertions: vec![],
+                coinjoin: None,
+                txid: None,
+                metadata: None,
+                batch: None,
+            };
+            if errored || shutdown::requested() {
+                report.steps.push(step_report);
+                continue;
+            }
+
+            println!("[{}/{}] {}", index + 1, self.steps.len(), step.action);
+            // e1ec30: Encrypted signers stay unlocked for this step only
+            let unlocked = step
+                .action
+                .signers()
+                .into_iter()
+                .map(|name| passphrase::unlock(&wallets[name], name, step.passphrase.as_ref()))
+                .collect::<bitcoincore_rpc::Result<Vec<_>>>();
+            let outcome = unlocked
+                .map_err(ScenarioError::from)
+                .and_then(|_unlocked| run_action(index, &step.action, &wallets, &mut batchers));
+            match outcome {
+                Ok(outcome) => {
+                    step_report.coinjoin = outcome.coinjoin;
+                    step_report.txid = outcome.txid;
+                    step_report.metadata = outcome.metadata;
+                    step_report.batch = outcome.batch;
+                    if let Some(txid) = outcome.txid {
+                        last_tx = Some(LastTx {
+                            wallet: step.action.wallets()[0],
+                            txid,
+                        });
+                    }
+                    check_all(
+                        &mut step_report,
+                        &step.assertions,
+                        &wallets,
+                        last_tx.as_ref(),
+                    );
+                }
+                Err(e) => {
+                    println!("  ERROR {e}");
+                    step_report.status = StepStatus::Error;
+                    step_report.error = Some(e.to_string());
+                    errored = true;
+                }
+            }
+            report.passed &= step_report.status == StepStatus::Passed;
+            report.steps.push(step_report);
+        }
+        report.status = shutdown::status();
+        if report.status == RunStatus::Aborted {
+            report.passed = false;
+        }
+        Ok(report)
+    }
+}
+
+// The outcome has the txid for actions that send a transaction
+fn run_action(
+    index: usize,
+    action: &Action,
+    wallets: &HashMap<&str, Client>,
+    batchers: &mut Batchers,
+) -> Result<ActionOutcome, ScenarioError> {
+    let wallet = |name: &str| &wallets[name];
+
+    match action {
+        Action::Fund { .. } | Action::Mine { .. } => shutdown::enter(Phase::Mining),
+        Action::Send { .. }
+        | Action::Coinjoin { .. }
+        | Action::Refill { .. }
+        | Action::Queue { .. }
+        | Action::Flush { .. } => shutdown::enter(Phase::Sending),
+        Action::WatchOnly { .. } | Action::Check => {}
+    }
+    match action {
+        Action::Fund {
+            wallet: name,
+            amount,
+        } => {
+            let rpc = wallet(name);
+            let target = amount.unwrap_or(Amount::ONE_SAT);
+            let address = rpc.get_new_address(None, None)?.assume_checked();
+            let mut mined = 0;
+            while rpc.get_balance(None, None)? < target {
+                if shutdown::requested() {
+                    return Err(ScenarioError::Aborted);
+                }
+                if mined == coins::MAX_FUNDING_BLOCKS {
+                    return Err(ScenarioError::Step {
+                        index,
+                        reason: format!("{name} still below {target} after {mined} blocks"),
+                    });
+                }
+                rpc.generate_to_address(1, &address)?;
+                mined += 1;
+            }
+            println!("  {name} funded after {mined} block(s)");
+            Ok(ActionOutcome::default())
+        }
+        Action::Send {
+            from,
+            to,
+            amount,
+            label,
+            comment,
+        } => {
+            let address = wallet(to)
+                .get_new_address(label.as_deref(), None)?
+                .assume_checked();
+            if let Some(label) = label {
+                wallet::set_label(wallet(from), &address, label)?;
+            }
+            let txid = wallet(from).send_to_address(
+                &address,
+                *amount,
+                comment.as_deref(),
+                None,
+                None,
+                None,
+                None,
+                None,
+            )?;
+            println!("  txid {txid}");
+            let metadata = wallet::tx_metadata(wallet(from), &txid)?;
+            Ok(ActionOutcome {
+                txid: Some(txid),
+                metadata: (!metadata.is_empty()).then_some(metadata),
+                ..Default::default()
+            })
+        }
+        Action::Mine { blocks, to } => {
+            let rpc = wallet(to);
+            let address = rpc.get_new_address(None, None)?.assume_checked();
+            rpc.generate_to_address(*blocks, &address)?;
+            Ok(ActionOutcome::default())
+        }
+        Action::Coinjoin {
+            wallets: names,
+            amount,
+            fee_rate,
+        } => {
+            let participants: Vec<(&str, &Client)> = names
+                .iter()
+                .map(|name| (name.as_str(), wallet(name)))
+                .collect();
+            let fee_rate = fee_rate
+                .map(|rate| {
+                    FeeRate::from_sat_per_vb(rate).ok_or_else(|| ScenarioError::Step {
+                        index,
+                        reason: format!("fee rate {rate} sat/vB is out of range"),
+                    })
+                })
+                .transpose()?;
+            let joined = coinjoin::run(&participants, *amount, fee_rate).map_err(|e| {
+                ScenarioError::Step {
+                    index,
+                    reason: e.to_string(),
+                }
+            })?;
+            println!("  txid {}", joined.txid);
+            Ok(ActionOutcome {
+                txid: Some(joined.txid),
+                coinjoin: Some(joined),
+                ..Default::default()
+            })
+        }
+        Action::Queue { from, to, amount } => {
+            let address = wallet(to).get_new_address(None, None)?.assume_checked();
+            let batcher = batchers.of(from);
+            let now = Instant::now();
+            batcher.push(
+                Payment {
+                    address,
+                    amount: *amount,
+                },
+                now,
+            );
+            if !batcher.due(now) {
+                println!("  {} payment(s) queued", batcher.queued().len());
+                return Ok(ActionOutcome::default());
+            }
+            flush(index, wallet(from), batcher)
+        }
+        Action::Flush { from } => flush(index, wallet(from), batchers.of(from)),
+        Action::WatchOnly {
+            wallet: name,
+            signer,
+        } => {
+            let imported = coldhot::watch(wallet(name), wallet(signer))?;
+            println!("  {name} imported {imported} public descriptor(s) of {signer}");
+            Ok(ActionOutcome::default())
+        }
+        Action::Refill {
+            hot,
+            cold,
+            signer,
+            below,
+            target,
+            max_fee,
+        } => {
+            let budget = Budget {
+                max_fee: *max_fee,
+                ..Default::default()
+            };
+            let refilled = coldhot::refill(
+                wallet(hot),
+                wallet(cold),
+                wallet(signer),
+                *below,
+                *target,
+                &budget,
+            )
+            .map_err(|e| ScenarioError::Step {
+                index,
+                reason: e.to_string(),
+            })?;
+            match refilled {
+                Some(refill) => {
+                    println!(
+                        "  {hot} had {}, sent {} from {cold} for {} in fees, txid {}",
+                        refill.hot_balance, refill.amount, refill.fee, refill.txid
+                    );
+                    Ok(ActionOutcome {
+                        txid: Some(refill.txid),
+                        ..Default::default()
+                    })
+                }
+                None => {
+                    println!("  {hot} has at least {below}, nothing to refill");
+                    Ok(ActionOutcome::default())
+                }
+            }
+        }
+        Action::Check => Ok(ActionOutcome::default()),
+    }
+}
+
+// Each sender's queue of payments for `queue` steps
+struct Batchers {
+    options: BatchOptions,
+    queues: HashMap<String, Batcher>,
+}
+
+impl Batchers {
+    fn of(&mut self, wallet: &str) -> &mut Batcher {
+        let options = self.options;
+        self.queues
+            .entry(wallet.to_owned())
+            .or_insert_with(|| Batcher::new(options))
+    }
+}
+
+fn flush(
+    index: usize,
+    wallet: &Client,
+    batcher: &mut Batcher,
+) -> Result<ActionOutcome, ScenarioError> {
+    let sent = batcher.flush(wallet).map_err(|e| ScenarioError::Step {
+        index,
+        reason: e.to_string(),
+    })?;
+    match sent {
+        Some(batch) => {
+            println!(
+                "  {} payment(s) in {} for {}, {} less than one by one",
+                batch.payments, batch.txid, batch.fee, batch.savings
+            );
+            Ok(ActionOutcome {
+                txid: Some(batch.txid),
+                batch: Some(batch),
+                ..Default::default()
+            })
+        }
+        None => {
+            println!("  nothing queued");
+            Ok(ActionOutcome::default())
+        }
+    }
+}
+
+// The step's action ran: it passes unless one of its assertions doesn't hold
+fn check_all<R: RpcApi>(
+    step_report: &mut StepReport,
+    assertions: &[Assertion],
+    wallets: &HashMap<&str, R>,
+    last_tx: Option<&LastTx>,
+) {
+    step_report.status = StepStatus::Passed;
+    for assertion in assertions {
+        let result = check(assertion, wallets, last_tx);
+        println!(
+            "  {} {assertion}: {}",
+            if result.passed { "PASS" } else { "FAIL" },
+            result.detail
+        );
+        if !result.passed {
+            step_report.status = StepStatus::Failed;
+        }
+        step_report.assertions.push(result);
+    }
+}
+
+fn check<R: RpcApi>(
+    assertion: &Assertion,
+    wallets: &HashMap<&str, R>,
+    last_tx: Option<&LastTx>,
+) -> AssertionReport {
+    let (passed, detail) = match evaluate(assertion, wallets, last_tx) {
+        Ok(outcome) => outcome,
+        Err(e) => (false, e.to_string()),
+    };
+    AssertionReport {
+        assertion: assertion.to_string(),
+        passed,
+        detail,
+    }
+}
+
+fn evaluate<R: RpcApi>(
+    assertion: &Assertion,
+    wallets: &HashMap<&str, R>,
+    last_tx: Option<&LastTx>,
+) -> Result<(bool, String), Box<dyn std::error::Error>> {
+    // Transactions named explicitly are looked up in any of the scenario's wallets
+    let lookup = |txid: &Option<Txid>| {
+        let (rpcs, txid): (Vec<&R>, Txid) = match (txid, last_tx) {
+            (Some(txid), _) => (wallets.values().collect(), *txid),
+            (None, Some(last)) => (vec![&wallets[last.wallet]], last.txid),
+            (None, None) => return Err("no transaction has been sent yet".to_owned()),
+        };
+        rpcs.iter()
+            .find_map(|rpc| rpc.get_transaction(&txid, None).ok())
+            .ok_or_else(|| format!("transaction {txid} not found in any scenario wallet"))
+    };
+
+    Ok(match assertion {
+        Assertion::Balance {
+            wallet,
+            amount,
+            tolerance,
+            min,
+            max,
+        } => {
+            let (low, high) = balance_bounds(*amount, *tolerance, *min, *max)?;
+            let balance = wallets[wallet.as_str()].get_balance(None, None)?;
+            (
+                (low..=high).contains(&balance),
+                format!("balance {balance}"),
+            )
+        }
+        Assertion::Confirmations { txid, min } => {
+            let confirmations = lookup(txid)?.info.confirmations;
+            (
+                confirmations >= *min,
+                format!("{confirmations} confirmation(s)"),
+            )
+        }
+        Assertion::FeeBelow { txid, amount } => {
+            let fee = lookup(txid)?
+                .fee
+                .ok_or("wallet did not report a fee")?
+                .abs()
+                .to_unsigned()?;
+            (fee < *amount, format!("fee {fee}"))
+        }
+        Assertion::OutputCount { txid, count } => {
+            let outputs = lookup(txid)?.transaction()?.output.len();
+            (outputs == *count, format!("{outputs} output(s)"))
+        }
+    })
+}
+
+// The balances an `assert_balance` accepts, from its exact amount and tolerance or its
+// min and max
+fn balance_bounds(
+    amount: Option<Amount>,
+    tolerance: Option<Amount>,
+    min: Option<Amount>,
+    max: Option<Amount>,
+) -> Result<(Amount, Amount), String> {
+    match (amount, min, max) {
+        (Some(amount), None, None) => {
+            let tolerance = tolerance.unwrap_or(Amount::ZERO);
+            Ok((
+                amount.checked_sub(tolerance).unwrap_or(Amount::ZERO),
+                amount + tolerance,
+            ))
+        }
+        (Some(_), _, _) => Err("assert_balance takes amount or min and max, not both".to_owned()),
+        (None, None, None) => Err("assert_balance needs an amount, a min or a max".to_owned()),
+        (None, _, _) if tolerance.is_some() => {
+            Err("assert_balance's tolerance goes with amount, not min and max".to_owned())
+        }
+        (None, min, max) => Ok((
+            min.unwrap_or(Amount::ZERO),
+            max.unwrap_or(Amount::MAX_MONEY),
+        )),
+    }
+}
+
+fn de_amount<'de, D: Deserializer<'de>>(d: D) -> Result<Amount, D::Error> {
+    let s = String::deserialize(d)?;
+    parse_amount(&s).map_err(serde::de::Error::custom)
+}
+
+fn de_opt_amount<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Amount>, D::Error> {
+    de_amount(d).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::consensus::encode;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{ScriptBuf, Transaction, TxOut};
+    use serde_json::{json, Value};
+
+    fn assertion(toml: &str) -> Assertion {
+        toml::from_str(toml).unwrap()
+    }
+
+    // What gettransaction says about a sent transaction with `outputs` outputs
+    fn sent(confirmations: i32, fee_sat: i64, outputs: usize) -> Value {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![],
+            output: vec![
+                TxOut {
+                    value: Amount::from_sat(10_000),
+                    script_pubkey: ScriptBuf::new(),
+                };
+                outputs
+            ],
+        };
+        json!({
+            "amount": -0.0001,
+            "fee": -(fee_sat as f64) / 1e8,
+            "confirmations": confirmations,
+            "txid": Txid::all_zeros(),
+            "time": 0,
+            "timereceived": 0,
+            "bip125-replaceable": "no",
+            "walletconflicts": [],
+            "details": [],
+            "hex": encode::serialize_hex(&tx),
+        })
+    }
+
+    fn run_check(toml: &str, wallet: MockClient) -> AssertionReport {
+        let wallets = HashMap::from([("Miner", wallet)]);
+        let last = LastTx {
+            wallet: "Miner",
+            txid: Txid::all_zeros(),
+        };
+        let report = check(&assertion(toml), &wallets, Some(&last));
+        wallets["Miner"].assert_done();
+        report
+    }
+
+    fn balance(btc: f64) -> MockClient {
+        MockClient::new().returns("getbalance", json!(btc))
+    }
+
+    #[test]
+    fn checks_balances_exactly_within_a_tolerance_or_a_range() {
+        let exact = "type = 'assert_balance'\nwallet = 'Miner'\namount = '20 BTC'";
+        assert!(run_check(exact, balance(20.0)).passed);
+        let short = run_check(exact, balance(19.9999));
+        assert!(!short.passed);
+        assert_eq!(short.detail, "balance 19.9999 BTC");
+        assert_eq!(short.assertion, "Miner balance is 20 BTC");
+
+        let give_or_take = "type = 'assert_balance'\nwallet = 'Miner'\namount = '20 BTC'\n\
+                            tolerance = '1000 sat'";
+        assert!(run_check(give_or_take, balance(19.99999)).passed);
+        assert!(!run_check(give_or_take, balance(19.9999)).passed);
+
+        let at_least = "type = 'assert_balance'\nwallet = 'Miner'\nmin = '10 BTC'";
+        assert!(run_check(at_least, balance(10.0)).passed);
+        assert!(!run_check(at_least, balance(9.0)).passed);
+        let between = "type = 'assert_balance'\nwallet = 'Miner'\nmin = '1 BTC'\nmax = '2 BTC'";
+        assert!(run_check(between, balance(1.5)).passed);
+        assert!(!run_check(between, balance(2.5)).passed);
+
+        // Nothing to compare against fails before asking the wallet
+        let both = "type = 'assert_balance'\nwallet = 'Miner'\namount = '1 BTC'\nmin = '1 BTC'";
+        let invalid = run_check(both, MockClient::new());
+        assert!(!invalid.passed);
+        assert!(invalid.detail.contains("not both"), "{}", invalid.detail);
+    }
+
+    #[test]
+    fn checks_the_last_transaction() {
+        let confirmed = "type = 'assert_confirmations'\nmin = 1";
+        assert!(
+            run_check(
+                confirmed,
+                MockClient::new().returns("gettransaction", sent(1, 141, 2))
+            )
+            .passed
+        );
+        let pending = run_check(
+            confirmed,
+            MockClient::new().returns("gettransaction", sent(0, 141, 2)),
+        );
+        assert!(!pending.passed);
+        assert_eq!(pending.detail, "0 confirmation(s)");
+
+        let cheap = "type = 'assert_fee_below'\namount = '1000 sat'";
+        assert!(
+            run_check(
+                cheap,
+                MockClient::new().returns("gettransaction", sent(1, 141, 2))
+            )
+            .passed
+        );
+        let dear = run_check(
+            cheap,
+            MockClient::new().returns("gettransaction", sent(1, 1000, 2)),
+        );
+        assert!(!dear.passed);
+        assert_eq!(dear.detail, "fee 0.00001 BTC");
+
+        let two = "type = 'assert_output_count'\ncount = 2";
+        assert!(
+            run_check(
+                two,
+                MockClient::new().returns("gettransaction", sent(1, 141, 2))
+            )
+            .passed
+        );
+        let three = run_check(
+            two,
+            MockClient::new().returns("gettransaction", sent(1, 141, 3)),
+        );
+        assert!(!three.passed);
+        assert_eq!(three.detail, "3 output(s)");
+
+        // Before any send there is no last transaction to look at
+        let wallets = HashMap::from([("Miner", MockClient::new())]);
+        let nothing = check(&assertion(two), &wallets, None);
+        assert!(!nothing.passed);
+        assert_eq!(nothing.detail, "no transaction has been sent yet");
+    }
+
+    #[test]
+    fn reports_each_assertion_and_fails_the_step() {
+        let wallets = HashMap::from([(
+            "Miner",
+            MockClient::new()
+                .returns("getbalance", json!(20.0))
+                .returns("gettransaction", sent(0, 141, 2)),
+        )]);
+        let last = LastTx {
+            wallet: "Miner",
+            txid: Txid::all_zeros(),
+        };
+        let mut step = StepReport {
+            step: 2,
+            action: "send 20 BTC from Miner to Trader".to_owned(),
+            status: StepStatus::Skipped,
+            error: None,
+            assertions: vec![],
+            coinjoin: None,
+            txid: Some(Txid::all_zeros()),
+            metadata: None,
+            batch: None,
+        };
+        let assertions = [
+            assertion("type = 'assert_balance'\nwallet = 'Miner'\nmin = '10 BTC'"),
+            assertion("type = 'assert_confirmations'\nmin = 1"),
+        ];
+        check_all(&mut step, &assertions, &wallets, Some(&last));
+        wallets["Miner"].assert_done();
+        assert_eq!(step.status, StepStatus::Failed);
+
+        let report = ScenarioReport {
+            scenario: Some("Pay the trader".to_owned()),
+            status: RunStatus::Completed,
+            passed: false,
+            steps: vec![step],
+        };
+        assert_eq!(
+            serde_json::to_value(&report).unwrap(),
+            json!({
+                "scenario": "Pay the trader",
+                "status": "completed",
+                "passed": false,
+                "steps": [{
+                    "step": 2,
+                    "action": "send 20 BTC from Miner to Trader",
+                    "status": "failed",
+                    "txid": Txid::all_zeros(),
+                    "assertions": [
+                        {
+                            "assertion": "Miner balance is at least 10 BTC",
+                            "passed": true,
+                            "detail": "balance 20 BTC",
+                        },
+                        {
+                            "assertion": "last tx has at least 1 confirmation(s)",
+                            "passed": false,
+                            "detail": "0 confirmation(s)",
+                        },
+                    ],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn parses_the_bundled_scenarios() {
+        for file in [
+            "capstone.toml",
+            "coinjoin.toml",
+            "cold-hot.toml",
+            "batching.toml",
+        ] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("scenarios")
+                .join(file);
+            let scenario = Scenario::from_file(&path).unwrap_or_else(|e| panic!("{file}: {e}"));
+            assert!(!scenario.steps.is_empty(), "{file}");
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use bitcoincore_rpc::bitcoin::{Amount, Script, Transaction, TxOut};
+use serde::{Deserialize, Serialize};
+
+use crate::coins::{self, DUST_RELAY_FEE};
+
+// Largest OP_RETURN output script relayed by default (-datacarriersize, 83 bytes)
+const MAX_NULL_DATA_SCRIPT: usize = 83;
+// Keys in a bare multisig output that still relays
+const MAX_STANDARD_MULTISIG_KEYS: u8 = 3;
+
+// Output script templates, named as Core's decodescript and getrawtransaction name them so
+// the two can be compared
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ScriptType {
+    #[serde(rename = "pubkey")]
+    P2pk,
+    #[serde(rename = "pubkeyhash")]
+    P2pkh,
+    #[serde(rename = "scripthash")]
+    P2sh,
+    #[serde(rename = "multisig")]
+    Multisig,
+    #[serde(rename = "nulldata")]
+    NullData,
+    #[serde(rename = "witness_v0_keyhash")]
+    P2wpkh,
+    #[serde(rename = "witness_v0_scripthash")]
+    P2wsh,
+    #[serde(rename = "witness_v1_taproot")]
+    P2tr,
+    // Pay-to-anchor, the keyless output for CPFP
+    #[serde(rename = "anchor")]
+    P2a,
+    // A witness version or program length nothing is defined for yet, spendable by anyone
+    // until a soft fork gives it a meaning
+    #[serde(rename = "witness_unknown")]
+    WitnessUnknown,
+    #[serde(rename = "nonstandard")]
+    NonStandard,
+}
+
+impl ScriptType {
+    pub fn of(script: &Script) -> ScriptType {
+        let bytes = script.as_bytes();
+        if script.is_p2pk() {
+            ScriptType::P2pk
+        } else if script.is_p2pkh() {
+            ScriptType::P2pkh
+        } else if script.is_p2sh() {
+            ScriptType::P2sh
+        } else if script.is_multisig() {
+            ScriptType::Multisig
+        } else if script.is_op_return() {
+            if Script::from_bytes(&bytes[1..]).is_push_only() {
+                ScriptType::NullData
+            } else {
+                ScriptType::NonStandard
+            }
+        } else if script.is_p2wpkh() {
+            ScriptType::P2wpkh
+        } else if script.is_p2wsh() {
+            ScriptType::P2wsh
+        } else if script.is_p2tr() {
+            ScriptType::P2tr
+        } else if bytes == [0x51, 0x02, 0x4e, 0x73] {
+            ScriptType::P2a
+        } else if script.is_witness_program() && bytes[0] != 0 {
+            // v0 has only the two program lengths above
+            ScriptType::WitnessUnknown
+        } else {
+            ScriptType::NonStandard
+        }
+    }
+}
+
+// What an output of the reported transaction is, worked out from its script alone
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputInfo {
+    pub vout: u32,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub value: Amount,
+    pub script_type: ScriptType,
+    // Whether a node with default policy would relay a transaction paying it: a standard
+    // template, within the OP_RETURN and bare multisig limits, and not dust
+    pub standard: bool,
+    pub segwit: bool,
+    pub taproot: bool,
+    pub witness_version: Option<u8>,
+}
+
+impl OutputInfo {
+    pub fn of(vout: u32, output: &TxOut) -> OutputInfo {
+        let script = &output.script_pubkey;
+        let script_type = ScriptType::of(script);
+        let within_limits = match script_type {
+            ScriptType::NonStandard => false,
+            ScriptType::NullData => script.len() <= MAX_NULL_DATA_SCRIPT,
+            // `<m> <keys> <n> OP_CHECKMULTISIG`, OP_1 to OP_16 being 0x51 to 0x60
+            ScriptType::Multisig => {
+                script.as_bytes()[script.len() - 2] - 0x50 <= MAX_STANDARD_MULTISIG_KEYS
+            }
+            _ => true,
+        };
+        let witness_version = script.witness_version().map(|v| v.to_num());
+        OutputInfo {
+            vout,
+            value: output.value,
+            script_type,
+            standard: within_limits
+                && output.value >= coins::dust_threshold(script, DUST_RELAY_FEE),
+            segwit: witness_version.is_some(),
+            taproot: script.is_p2tr(),
+            witness_version,
+        }
+    }
+}
+
+pub fn outputs(tx: &Transaction) -> Vec<OutputInfo> {
+    tx.output
+        .iter()
+        .zip(0..)
+        .map(|(output, vout)| OutputInfo::of(vout, output))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::key::{TweakedPublicKey, XOnlyPublicKey};
+    use bitcoincore_rpc::bitcoin::{PubkeyHash, ScriptBuf, WPubkeyHash};
+    use std::str::FromStr;
+
+    fn output(script: ScriptBuf, sat: u64) -> OutputInfo {
+        OutputInfo::of(
+            0,
+            &TxOut {
+                value: Amount::from_sat(sat),
+                script_pubkey: script,
+            },
+        )
+    }
+
+    #[test]
+    fn classifies_output_scripts() {
+        let wpkh = output(
+            ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20])),
+            10_000,
+        );
+        assert_eq!(wpkh.script_type, ScriptType::P2wpkh);
+        assert!(wpkh.standard && wpkh.segwit && !wpkh.taproot);
+        assert_eq!(wpkh.witness_version, Some(0));
+
+        let key = XOnlyPublicKey::from_str(
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+        )
+        .unwrap();
+        let tr = output(
+            ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(key)),
+            10_000,
+        );
+        assert_eq!(tr.script_type, ScriptType::P2tr);
+        assert!(tr.taproot && tr.segwit);
+        assert_eq!(tr.witness_version, Some(1));
+
+        let pkh = output(
+            ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([1; 20])),
+            100,
+        );
+        assert_eq!(pkh.script_type, ScriptType::P2pkh);
+        // Below the 546 sat legacy dust limit
+        assert!(!pkh.standard && !pkh.segwit);
+        assert_eq!(pkh.witness_version, None);
+
+        let data = output(
+            ScriptBuf::from_bytes([vec![0x6a, 0x4c, 80], vec![0; 80]].concat()),
+            0,
+        );
+        assert_eq!(data.script_type, ScriptType::NullData);
+        assert!(data.standard);
+        let too_much = output(
+            ScriptBuf::from_bytes([vec![0x6a, 0x4c, 81], vec![0; 81]].concat()),
+            0,
+        );
+        assert_eq!(too_much.script_type, ScriptType::NullData);
+        assert!(!too_much.standard);
+
+        let anchor = output(ScriptBuf::from_bytes(vec![0x51, 0x02, 0x4e, 0x73]), 240);
+        assert_eq!(anchor.script_type, ScriptType::P2a);
+        let future = output(
+            ScriptBuf::from_bytes([vec![0x52, 32], vec![7; 32]].concat()),
+            10_000,
+        );
+        assert_eq!(future.script_type, ScriptType::WitnessUnknown);
+        assert_eq!(future.witness_version, Some(2));
+
+        assert_eq!(
+            output(ScriptBuf::from_bytes(vec![0xac]), 10_000).script_type,
+            ScriptType::NonStandard
+        );
+        assert_eq!(
+            serde_json::to_value(ScriptType::P2wpkh).unwrap(),
+            "witness_v0_keyhash"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use serde::Serialize;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::error::{Error, Result};
+
+// Exit code for a second signal, which doesn't wait for a safe point (128 + SIGINT)
+const FORCED_EXIT_CODE: i32 = 130;
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static PHASE: AtomicU8 = AtomicU8::new(Phase::Setup as u8);
+
+// What a run is busy with, so an abort can say where it stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(u8)]
+pub enum Phase {
+    // Connecting, loading wallets
+    Setup,
+    // Mining blocks to fund wallets
+    Mining,
+    Sending,
+    // Mining and looking for the confirming block
+    Confirming,
+    Reporting,
+}
+
+impl Phase {
+    // Exit code of a run aborted in this phase
+    pub fn abort_exit_code(self) -> u8 {
+        80 + self as u8
+    }
+
+    fn from_u8(n: u8) -> Phase {
+        match n {
+            0 => Phase::Setup,
+            1 => Phase::Mining,
+            2 => Phase::Sending,
+            3 => Phase::Confirming,
+            _ => Phase::Reporting,
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Setup => "setup",
+            Phase::Mining => "mining",
+            Phase::Sending => "sending",
+            Phase::Confirming => "confirming",
+            Phase::Reporting => "reporting",
+        })
+    }
+}
+
+// Whether a report covers the whole run or only what happened before a signal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    #[default]
+    Completed,
+    Aborted,
+}
+
+// Catch SIGINT and SIGTERM. The first one only asks the run to stop at its next safe
+// point, so locks and wallets are released and partial reports written; a second one
+// exits straight away.
+pub fn install() -> std::result::Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(FORCED_EXIT_CODE);
+        }
+        eprintln!(
+            "Stopping at the next safe point ({}), signal again to quit now",
+            phase()
+        );
+    })
+}
+
+// Ask the run to stop at its next safe point, as a first signal does
+pub fn request() {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+pub fn enter(phase: Phase) {
+    PHASE.store(phase as u8, Ordering::SeqCst);
+}
+
+pub fn phase() -> Phase {
+    Phase::from_u8(PHASE.load(Ordering::SeqCst))
+}
+
+// A safe point: give up here if a signal came in
+pub fn check() -> Result<()> {
+    if requested() {
+        Err(Error::Aborted { phase: phase() })
+    } else {
+        Ok(())
+    }
+}
+
+// Aborted or Completed, for reports of runs that stop early instead of failing
+pub fn status() -> RunStatus {
+    if requested() {
+        RunStatus::Aborted
+    } else {
+        RunStatus::Completed
+    }
+}
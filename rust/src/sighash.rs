@@ -0,0 +1,342 @@
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::ecdsa;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoincore_rpc::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::config;
+use crate::consensus;
+use crate::error::Result;
+use crate::shutdown::{self, Phase};
+use crate::walletless::{self, LocalKey, LocalUtxo};
+
+// Every flag the experiment signs with
+pub const FLAGS: [EcdsaSighashType; 6] = [
+    EcdsaSighashType::All,
+    EcdsaSighashType::None,
+    EcdsaSighashType::Single,
+    EcdsaSighashType::AllPlusAnyoneCanPay,
+    EcdsaSighashType::NonePlusAnyoneCanPay,
+    EcdsaSighashType::SinglePlusAnyoneCanPay,
+];
+
+// Taken off an output, or paid to the extra one. Fees only go up this way.
+const NUDGE: Amount = Amount::from_sat(1_000);
+const FEE: Amount = Amount::from_sat(10_000);
+
+// What is done to a transaction after input 0 was signed. Input 0 always pays output 0,
+// which is the output SIGHASH_SINGLE ties it to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    // Lower output 0, the one at the signed input's index
+    MatchingOutput,
+    // Lower output 1
+    OtherOutput,
+    AddOutput,
+    // Add another coin's input, signed by its own key
+    AddInput,
+}
+
+pub const CHANGES: [Change; 4] = [
+    Change::MatchingOutput,
+    Change::OtherOutput,
+    Change::AddOutput,
+    Change::AddInput,
+];
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Change::MatchingOutput => "lower the matching output",
+            Change::OtherOutput => "lower another output",
+            Change::AddOutput => "add an output",
+            Change::AddInput => "add an input",
+        })
+    }
+}
+
+// Whether a signature with `flag` still holds after `change`: ANYONECANPAY leaves the
+// other inputs out of the signature, NONE every output and SINGLE all but the matching one
+pub fn allows(flag: EcdsaSighashType, change: Change) -> bool {
+    // The low bits pick the outputs, 0x80 is ANYONECANPAY
+    let flag = flag.to_u32();
+    let outputs = flag & 0x1f;
+    match change {
+        Change::AddInput => flag & 0x80 != 0,
+        Change::MatchingOutput => outputs == EcdsaSighashType::None.to_u32(),
+        Change::OtherOutput | Change::AddOutput => outputs != EcdsaSighashType::All.to_u32(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SighashReport {
+    pub signer: LocalUtxo,
+    pub extra: LocalUtxo,
+    pub flags: Vec<FlagReport>,
+    // Every outcome matched `allows`
+    pub passed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FlagReport {
+    pub flag: String,
+    // The sighash byte ending the signature in the witness
+    pub sighash_byte: String,
+    // decoderawtransaction of the signed transaction, before any change
+    pub decoded: Value,
+    pub accepted: bool,
+    pub changes: Vec<ChangeReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangeReport {
+    pub change: Change,
+    pub description: String,
+    pub expected_valid: bool,
+    // Checked here, by hashing the changed transaction again
+    pub signature_valid: bool,
+    // testmempoolaccept's verdict on the changed transaction
+    pub accepted: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reject_reason: Option<String>,
+}
+
+// The transaction every flag signs: `coin` to output 0 and change to output 1
+fn payment(coin: &LocalUtxo, to: &LocalKey, change: &LocalKey) -> Transaction {
+    let paid = coin.amount / 2;
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![input(coin.outpoint)],
+        output: vec![
+            TxOut {
+                value: paid,
+                script_pubkey: to.script_pubkey(),
+            },
+            TxOut {
+                value: coin.amount - paid - FEE,
+                script_pubkey: change.script_pubkey(),
+            },
+        ],
+    }
+}
+
+fn input(outpoint: OutPoint) -> TxIn {
+    TxIn {
+        previous_output: outpoint,
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+        witness: Default::default(),
+    }
+}
+
+// `change` applied to a signed `tx`. Returns the output an added input spends, if any
+fn apply(
+    tx: &mut Transaction,
+    change: Change,
+    extra: &LocalUtxo,
+    extra_key: &LocalKey,
+) -> Result<Option<TxOut>> {
+    match change {
+        Change::MatchingOutput => tx.output[0].value -= NUDGE,
+        Change::OtherOutput => tx.output[1].value -= NUDGE,
+        Change::AddOutput => {
+            tx.output[1].value -= NUDGE;
+            tx.output.push(TxOut {
+                value: NUDGE,
+                script_pubkey: tx.output[0].script_pubkey.clone(),
+            });
+        }
+        Change::AddInput => {
+            tx.input.push(input(extra.outpoint));
+            return Ok(Some(TxOut {
+                value: extra.amount,
+                script_pubkey: extra_key.script_pubkey(),
+            }));
+        }
+    }
+    Ok(None)
+}
+
+// Whether input 0's signature commits to what `tx` is now
+fn signature_holds(tx: &Transaction, prevout: &TxOut, key: &LocalKey) -> Result<bool> {
+    let witness = &tx.input[0].witness;
+    let signature = witness
+        .nth(0)
+        .and_then(|bytes| ecdsa::Signature::from_slice(bytes).ok())
+        .ok_or_else(|| bitcoincore_rpc::Error::ReturnedError("input 0 is not signed".into()))?;
+    let sighash = SighashCache::new(tx)
+        .p2wpkh_signature_hash(0, &prevout.script_pubkey, prevout.value, signature.hash_ty)
+        .map_err(|e| bitcoincore_rpc::Error::ReturnedError(format!("input 0: {e}")))?;
+    let message = Message::from_digest(sighash.to_byte_array());
+    Ok(Secp256k1::verification_only()
+        .verify_ecdsa(&message, &signature.sig, &key.public.inner)
+        .is_ok())
+}
+
+// Mine a coin to each of two local keys, then for every flag sign a payment from the first,
+// change it each way and see whether the signature (and the node) still accept it. Nothing
+// is broadcast, so both coins stay unspent.
+pub fn run(rpc: &Client) -> Result<SighashReport> {
+    shutdown::enter(Phase::Setup);
+    let network = config::active().network;
+    let signer = LocalKey::generate(network);
+    let extra_key = LocalKey::generate(network);
+    let recipient = LocalKey::generate(network);
+
+    shutdown::check()?;
+    shutdown::enter(Phase::Mining);
+    rpc.generate_to_address(1, &signer.address)?;
+    rpc.generate_to_address(1, &extra_key.address)?;
+    rpc.generate_to_address(consensus::params().coinbase_maturity, &recipient.address)?;
+    let coin = |key: &LocalKey| -> Result<LocalUtxo> {
+        walletless::find_utxos(rpc, &key.address)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                bitcoincore_rpc::Error::ReturnedError(format!("no coin for {}", key.address)).into()
+            })
+    };
+    let (coin, extra) = (coin(&signer)?, coin(&extra_key)?);
+    if coin.amount < FEE * 10 {
+        return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+            "the block subsidy is down to {}, too little to experiment with",
+            coin.amount
+        ))
+        .into());
+    }
+    let prevout = TxOut {
+        value: coin.amount,
+        script_pubkey: signer.script_pubkey(),
+    };
+
+    shutdown::check()?;
+    shutdown::enter(Phase::Sending);
+    let mut flags = vec![];
+    for flag in FLAGS {
+        let mut signed = payment(&coin, &recipient, &signer);
+        walletless::sign_with(&mut signed, std::slice::from_ref(&prevout), &signer, flag)?;
+        let decoded: Value = rpc.call(
+            "decoderawtransaction",
+            &[json!(encode::serialize_hex(&signed))],
+        )?;
+        let (accepted, _) = walletless::test_accept(rpc, &signed)?;
+
+        let mut changes = vec![];
+        for change in CHANGES {
+            let mut tx = signed.clone();
+            let mut prevouts = vec![prevout.clone()];
+            if let Some(added) = apply(&mut tx, change, &extra, &extra_key)? {
+                prevouts.push(added);
+                walletless::sign(&mut tx, &prevouts, &extra_key)?;
+            }
+            let (accepted, reject_reason) = walletless::test_accept(rpc, &tx)?;
+            changes.push(ChangeReport {
+                change,
+                description: change.to_string(),
+                expected_valid: allows(flag, change),
+                signature_valid: signature_holds(&tx, &prevout, &signer)?,
+                accepted,
+                reject_reason,
+            });
+        }
+        flags.push(FlagReport {
+            flag: flag.to_string(),
+            sighash_byte: format!("0x{:02x}", flag.to_u32()),
+            decoded,
+            accepted,
+            changes,
+        });
+    }
+
+    let passed = flags.iter().all(|f| {
+        f.accepted
+            && f.changes
+                .iter()
+                .all(|c| c.signature_valid == c.expected_valid && c.accepted == c.expected_valid)
+    });
+    Ok(SighashReport {
+        signer: coin,
+        extra,
+        flags,
+        passed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::secp256k1::SecretKey;
+    use bitcoincore_rpc::bitcoin::{Network, Txid};
+
+    fn key(byte: u8) -> LocalKey {
+        LocalKey::from_secret(
+            SecretKey::from_slice(&[byte; 32]).unwrap(),
+            Network::Regtest,
+        )
+    }
+
+    fn utxo(vout: u32) -> LocalUtxo {
+        LocalUtxo {
+            outpoint: OutPoint::new(Txid::all_zeros(), vout),
+            amount: Amount::from_sat(1_000_000),
+            height: 1,
+        }
+    }
+
+    #[test]
+    fn each_flag_commits_to_what_it_says() {
+        let (signer, extra_key, recipient) = (key(1), key(2), key(3));
+        let (coin, extra) = (utxo(0), utxo(1));
+        let prevout = TxOut {
+            value: coin.amount,
+            script_pubkey: signer.script_pubkey(),
+        };
+        for flag in FLAGS {
+            let mut signed = payment(&coin, &recipient, &signer);
+            walletless::sign_with(&mut signed, std::slice::from_ref(&prevout), &signer, flag)
+                .unwrap();
+            assert!(signature_holds(&signed, &prevout, &signer).unwrap());
+            assert_eq!(
+                *signed.input[0].witness.nth(0).unwrap().last().unwrap() as u32,
+                flag.to_u32()
+            );
+
+            for change in CHANGES {
+                let mut tx = signed.clone();
+                apply(&mut tx, change, &extra, &extra_key).unwrap();
+                assert_eq!(
+                    signature_holds(&tx, &prevout, &signer).unwrap(),
+                    allows(flag, change),
+                    "{flag} after {change}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_anyonecanpay_takes_more_inputs() {
+        let open: Vec<_> = FLAGS
+            .into_iter()
+            .filter(|&f| allows(f, Change::AddInput))
+            .collect();
+        assert_eq!(
+            open,
+            [
+                EcdsaSighashType::AllPlusAnyoneCanPay,
+                EcdsaSighashType::NonePlusAnyoneCanPay,
+                EcdsaSighashType::SinglePlusAnyoneCanPay
+            ]
+        );
+        assert!(!allows(EcdsaSighashType::Single, Change::MatchingOutput));
+        assert!(allows(EcdsaSighashType::None, Change::MatchingOutput));
+    }
+}
@@ -0,0 +1,174 @@
+use bitcoincore_rpc::bitcoin::BlockHash;
+use bitcoincore_rpc::RpcApi;
+use serde::Deserialize;
+use serde_json::json;
+use std::path::Path;
+
+use crate::compat::{Compat, Feature};
+use crate::error::Result;
+
+#[derive(Debug, Deserialize)]
+pub struct DumpTxOutSetResult {
+    pub coins_written: u64,
+    pub base_hash: BlockHash,
+    pub base_height: u64,
+    pub path: String,
+    pub txoutset_hash: String,
+    pub nchaintx: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoadTxOutSetResult {
+    pub coins_loaded: u64,
+    pub tip_hash: BlockHash,
+    pub base_height: u64,
+    pub path: String,
+}
+
+// Dump the UTXO set so a prepared regtest chain state can be loaded into a fresh node.
+// With `height` the node rolls back to that block, dumps, and rolls forward again.
+pub fn export_utxo_set(
+    rpc: &impl RpcApi,
+    compat: &Compat,
+    path: &Path,
+    height: Option<u64>,
+) -> Result<DumpTxOutSetResult> {
+    let path = json!(path.to_string_lossy());
+    let dump = match height {
+        Some(height) => {
+            compat.require(Feature::DumpTxOutSetRollback)?;
+            rpc.call(
+                "dumptxoutset",
+                &[path, json!("rollback"), json!({ "rollback": height })],
+            )?
+        }
+        // 28 added the type argument, "latest" is what older versions always did
+        None if compat.supports(Feature::DumpTxOutSetRollback) => {
+            rpc.call("dumptxoutset", &[path, json!("latest")])?
+        }
+        None => rpc.call("dumptxoutset", &[path])?,
+    };
+    Ok(dump)
+}
+
+// Load a snapshot written by `export_utxo_set`. The node still has to know the headers
+// up to the snapshot's base block, and validates the full chain in the background afterwards.
+pub fn import_utxo_set(
+    rpc: &impl RpcApi,
+    compat: &Compat,
+    path: &Path,
+) -> Result<LoadTxOutSetResult> {
+    compat.require(Feature::LoadTxOutSet)?;
+    Ok(rpc.call("loadtxoutset", &[json!(path.to_string_lossy())])?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compat::NodeVersion;
+    use crate::error::Error;
+    use crate::rpc::mock::MockClient;
+
+    const HASH: &str = "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206";
+
+    fn dumped() -> serde_json::Value {
+        json!({
+            "coins_written": 110,
+            "base_hash": HASH,
+            "base_height": 110,
+            "path": "/tmp/utxo.dat",
+            "txoutset_hash": "6a2c",
+            "nchaintx": 111,
+        })
+    }
+
+    #[test]
+    fn dumps_the_latest_state_unless_asked_to_roll_back() {
+        let path = Path::new("/tmp/utxo.dat");
+        let v28 = Compat {
+            version: NodeVersion(28_00_00),
+        };
+
+        let rpc = MockClient::new()
+            .returns("dumptxoutset", dumped())
+            .returns("dumptxoutset", dumped());
+        export_utxo_set(&rpc, &v28, path, None).unwrap();
+        let dump = export_utxo_set(&rpc, &v28, path, Some(110)).unwrap();
+        assert_eq!(dump.base_height, 110);
+        assert_eq!(rpc.args(0), [json!("/tmp/utxo.dat"), json!("latest")]);
+        assert_eq!(
+            rpc.args(1),
+            [
+                json!("/tmp/utxo.dat"),
+                json!("rollback"),
+                json!({ "rollback": 110 })
+            ]
+        );
+        rpc.assert_done();
+
+        // Before 28 there's no type argument to pass
+        let v27 = Compat {
+            version: NodeVersion(27_01_00),
+        };
+        let rpc = MockClient::new().returns("dumptxoutset", dumped());
+        export_utxo_set(&rpc, &v27, path, None).unwrap();
+        assert_eq!(rpc.args(0), [json!("/tmp/utxo.dat")]);
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn a_rollback_needs_28_and_a_load_26() {
+        let path = Path::new("/tmp/utxo.dat");
+        let v25 = Compat {
+            version: NodeVersion(25_02_00),
+        };
+        let rpc = MockClient::new();
+        assert!(matches!(
+            export_utxo_set(&rpc, &v25, path, Some(110)),
+            Err(Error::UnsupportedNode {
+                feature: Some(Feature::DumpTxOutSetRollback),
+                ..
+            })
+        ));
+        assert!(matches!(
+            import_utxo_set(&rpc, &v25, path),
+            Err(Error::UnsupportedNode {
+                feature: Some(Feature::LoadTxOutSet),
+                ..
+            })
+        ));
+        // Turned away before asking the node anything
+        assert!(rpc.calls().is_empty());
+    }
+
+    #[test]
+    fn passes_on_the_nodes_load_error() {
+        let v28 = Compat {
+            version: NodeVersion(28_00_00),
+        };
+        let rpc = MockClient::new().fails(
+            "loadtxoutset",
+            -32603,
+            "Unable to load UTXO snapshot: assumeutxo block hash in snapshot metadata not recognized",
+        );
+        let error = import_utxo_set(&rpc, &v28, Path::new("/tmp/utxo.dat"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("not recognized"), "{error}");
+        rpc.assert_done();
+
+        let rpc = MockClient::new().returns(
+            "loadtxoutset",
+            json!({
+                "coins_loaded": 110,
+                "tip_hash": HASH,
+                "base_height": 110,
+                "path": "/tmp/utxo.dat",
+            }),
+        );
+        let loaded = import_utxo_set(&rpc, &v28, Path::new("/tmp/utxo.dat")).unwrap();
+        assert_eq!(loaded.coins_loaded, 110);
+        assert_eq!(rpc.args(0), [json!("/tmp/utxo.dat")]);
+        rpc.assert_done();
+    }
+}
@@ -0,0 +1,117 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+
+// A lock on a state file shared between processes, a daemon and the CLI commands run next
+// to it, say. It's taken on `<file>.lock` rather than the file itself because `write`
+// replaces the file, and a lock on the file being replaced would guard nothing. Dropping
+// it unlocks.
+#[derive(Debug)]
+pub struct StateLock {
+    _file: File,
+}
+
+// For a read-modify-write: nothing else reads or writes `path` until this is dropped
+pub fn lock(path: &Path) -> io::Result<StateLock> {
+    acquire(path, true)
+}
+
+// For reading: writers wait, other readers don't
+pub fn lock_shared(path: &Path) -> io::Result<StateLock> {
+    acquire(path, false)
+}
+
+fn acquire(path: &Path, exclusive: bool) -> io::Result<StateLock> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(sibling(path, ".lock"))?;
+    let attempt = if exclusive {
+        file.try_lock()
+    } else {
+        file.try_lock_shared()
+    };
+    match attempt {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            eprintln!("Waiting for another process using {}", path.display());
+            if exclusive {
+                file.lock()?;
+            } else {
+                file.lock_shared()?;
+            }
+        }
+        Err(TryLockError::Error(e)) => return Err(e),
+    }
+    Ok(StateLock { _file: file })
+}
+
+// `path`'s contents, or None if there's no such file yet. The caller holds the lock.
+pub fn read_locked(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        read => read.map(Some),
+    }
+}
+
+// Replace `path` with `contents`, taking the lock for it
+pub fn write(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let _lock = lock(path)?;
+    write_locked(path, contents)
+}
+
+// Written to a temporary file first and renamed over `path`, so a process dying mid-write
+// leaves the old contents (or none) rather than half of the new. The caller holds the lock.
+pub fn write_locked(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary = sibling(path, ".tmp");
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    name.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let path = std::env::temp_dir().join(format!("store-{}.json", std::process::id()));
+        write(&path, b"0").unwrap();
+
+        // Each handle opens the lock file anew, so the threads contend for it as processes would
+        let workers: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        let _lock = lock(&path).unwrap();
+                        let count: u64 =
+                            serde_json::from_slice(&read_locked(&path).unwrap().unwrap()).unwrap();
+                        write_locked(&path, (count + 1).to_string().as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        let held = lock_shared(&path).unwrap();
+        assert_eq!(read_locked(&path).unwrap().unwrap(), b"200");
+        let other = File::open(sibling(&path, ".lock")).unwrap();
+        assert!(matches!(other.try_lock(), Err(TryLockError::WouldBlock)));
+        assert!(other.try_lock_shared().is_ok());
+        drop(held);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(sibling(&path, ".lock")).unwrap();
+        assert_eq!(read_locked(&path).unwrap(), None);
+    }
+}
@@ -0,0 +1,214 @@
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint};
+use bitcoincore_rpc::{Client, RpcApi};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::builder::SequencePolicy;
+use crate::coins::UtxoLock;
+use crate::consensus;
+use crate::error::Result;
+use crate::shutdown::{self, Phase, RunStatus};
+use crate::traffic::Traffic;
+use crate::{send, wallet};
+
+// Most outputs a single funding transaction creates per sender
+const MAX_FUNDING_OUTPUTS: usize = 500;
+
+#[derive(Debug, Clone)]
+pub struct StressConfig {
+    pub senders: usize,
+    // Transactions per second, per sender
+    pub rate: f64,
+    pub duration: Duration,
+    pub traffic: Traffic,
+    // Sender i draws from seed + i, a fresh random seed for each when None
+    pub seed: Option<u64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct SenderStats {
+    pub wallet: String,
+    pub sent: usize,
+    pub failed: usize,
+    // Error message -> occurrences
+    pub errors: BTreeMap<String, usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StressReport {
+    pub status: RunStatus,
+    pub senders: Vec<SenderStats>,
+    pub sent: usize,
+    pub failed: usize,
+    pub elapsed_secs: f64,
+    pub tps: f64,
+    pub error_rate: f64,
+}
+
+// Fire transactions from `config.senders` wallets at once, each on its own thread with its
+// own client. Every send locks its input first, which is what concurrent senders sharing a
+// node would have to do to not trip over each other.
+pub fn run(rpc: &Client, config: &StressConfig) -> Result<StressReport> {
+    let miner = wallet::open(rpc, "Miner")?;
+    let sink = miner
+        .get_new_address(None, None)?
+        .assume_checked()
+        .to_string();
+
+    shutdown::enter(Phase::Setup);
+    let names: Vec<String> = (0..config.senders).map(|i| format!("Stress{i}")).collect();
+    let (wallets, _guards): (Vec<_>, Vec<_>) = names
+        .iter()
+        .map(|name| wallet::open_guarded(rpc, name, wallet::CreateOptions::default()))
+        .collect::<bitcoincore_rpc::Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+
+    fund(rpc, &miner, &wallets, config)?;
+    shutdown::check()?;
+    shutdown::enter(Phase::Sending);
+
+    let start = Instant::now();
+    let senders = thread::scope(|s| {
+        let handles: Vec<_> = names
+            .iter()
+            .zip(&wallets)
+            .enumerate()
+            .map(|(i, (name, rpc))| {
+                let rng = match config.seed {
+                    Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(i as u64)),
+                    None => StdRng::from_entropy(),
+                };
+                let sink = &sink;
+                s.spawn(move || sender_loop(name, rpc, sink, config, rng))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("sender thread panicked"))
+            .collect::<Vec<_>>()
+    });
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let sent = senders.iter().map(|s| s.sent).sum();
+    let failed = senders.iter().map(|s| s.failed).sum::<usize>();
+    let attempts = sent + failed;
+    Ok(StressReport {
+        status: shutdown::status(),
+        senders,
+        sent,
+        failed,
+        elapsed_secs: elapsed,
+        tps: sent as f64 / elapsed,
+        error_rate: if attempts == 0 {
+            0.0
+        } else {
+            failed as f64 / attempts as f64
+        },
+    })
+}
+
+// Give every sender enough confirmed outputs of the largest amount it can draw (plus fee
+// headroom) to never have to wait on its own unconfirmed change
+fn fund(rpc: &Client, miner: &Client, wallets: &[Client], config: &StressConfig) -> Result<()> {
+    let per_sender = ((config.rate * config.duration.as_secs_f64()).ceil() as usize + 5)
+        .min(MAX_FUNDING_OUTPUTS);
+    let output_value = config.traffic.amounts.max() * 2;
+    let needed = output_value * (per_sender * wallets.len()) as u64;
+
+    let miner_address = miner.get_new_address(None, None)?.assume_checked();
+    let balance = miner.get_balance(None, None)?;
+    let target = needed + Amount::from_int_btc(1);
+    shutdown::check()?;
+    shutdown::enter(Phase::Mining);
+    if balance < target {
+        // Enough blocks for the rewards to cover it once mature, at whatever the subsidy
+        // is by now
+        let next_height = rpc.get_block_count()? + 1;
+        let blocks = consensus::blocks_to_earn(next_height, target - balance).ok_or_else(|| {
+            bitcoincore_rpc::Error::ReturnedError(format!(
+                "the block subsidy has run out before earning {target}"
+            ))
+        })?;
+        rpc.generate_to_address(
+            blocks + consensus::params().coinbase_maturity,
+            &miner_address,
+        )?;
+    }
+
+    for wallet in wallets {
+        let mut outputs = Vec::with_capacity(per_sender);
+        for _ in 0..per_sender {
+            let addr = wallet.get_new_address(None, None)?.assume_checked();
+            let mut output = Map::new();
+            output.insert(addr.to_string(), json!(output_value.to_btc()));
+            outputs.push(Value::Object(output));
+        }
+        miner.call::<Value>("send", &[Value::Array(outputs)])?;
+    }
+    rpc.generate_to_address(1, &miner_address)?;
+    println!(
+        "Funded {} sender(s) with {per_sender} outputs of {output_value} each",
+        wallets.len()
+    );
+    Ok(())
+}
+
+fn sender_loop(
+    name: &str,
+    rpc: &Client,
+    sink: &str,
+    config: &StressConfig,
+    mut rng: StdRng,
+) -> SenderStats {
+    let mut stats = SenderStats {
+        wallet: name.to_owned(),
+        ..Default::default()
+    };
+    let traffic = &config.traffic;
+    let deadline = Instant::now() + config.duration;
+    let mut next = Instant::now();
+
+    while Instant::now() < deadline && !shutdown::requested() {
+        let amount = traffic.amounts.sample(&mut rng);
+        let fee_rate = traffic.fee_rates.sample(&mut rng);
+        match send_one(rpc, sink, amount, fee_rate) {
+            Ok(()) => stats.sent += 1,
+            Err(e) => {
+                stats.failed += 1;
+                *stats.errors.entry(e.to_string()).or_default() += 1;
+            }
+        }
+        next += traffic.arrivals.sample(config.rate, &mut rng);
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            thread::sleep(wait);
+        }
+    }
+    stats
+}
+
+fn send_one(rpc: &Client, sink: &str, amount: Amount, fee_rate: Option<f64>) -> Result<()> {
+    // listunspent leaves out locked outputs, so the first confirmed one is free to take
+    let utxo = rpc
+        .list_unspent(Some(1), None, None, None, None)?
+        .into_iter()
+        .next()
+        .ok_or_else(|| bitcoincore_rpc::Error::ReturnedError("out of UTXOs".to_owned()))?;
+    let input = [OutPoint::new(utxo.txid, utxo.vout)];
+    let lock = UtxoLock::acquire(rpc, &input)?;
+    send(
+        rpc,
+        sink,
+        amount,
+        &input,
+        &SequencePolicy::default(),
+        fee_rate,
+    )?;
+    lock.spent();
+    Ok(())
+}
@@ -0,0 +1,455 @@
+use bitcoincore_rpc::bitcoin::{Amount, FeeRate, Txid};
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::amount::parse_amount;
+use crate::builder::{
+    self, TxBuilder, OUTPUT_BASE_VBYTES, P2WPKH_INPUT_VBYTES, P2WPKH_SCRIPT_LEN, TX_OVERHEAD_VBYTES,
+};
+use crate::coins;
+use crate::error::Result;
+use crate::shutdown;
+use crate::wallet;
+
+// More combinations than this is more likely a typo in a range than an experiment
+const MAX_RUNS: usize = 1000;
+
+// A sweep over payment shapes, e.g.
+//
+//   name = "Fee rate against shape"
+//
+//   [parameters]
+//   amount = ["0.1 BTC", "1 BTC"]
+//   inputs = { from = 1, to = 3 }
+//   outputs = [1, 2]
+//   fee_rate = { from = 1, to = 21, step = 10 }
+//
+// Each parameter is a single value, a list, or a range from..=to by `step` (1 unless
+// given). One left out keeps its default. Every combination is one run.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SweepFile {
+    pub name: Option<String>,
+    pub parameters: Parameters,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Parameters {
+    // Paid to each output
+    #[serde(default = "default_amount")]
+    pub amount: Values<Btc>,
+    // Coins the payment spends
+    #[serde(default = "one")]
+    pub inputs: Values<u64>,
+    // Payments in the transaction, change not counted
+    #[serde(default = "one")]
+    pub outputs: Values<u64>,
+    // sat/vB
+    #[serde(default = "one")]
+    pub fee_rate: Values<u64>,
+}
+
+fn default_amount() -> Values<Btc> {
+    Values::One(Btc(Amount::ONE_BTC))
+}
+
+fn one() -> Values<u64> {
+    Values::One(1)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Values<T> {
+    One(T),
+    List(Vec<T>),
+    Range { from: T, to: T, step: Option<T> },
+}
+
+// What a parameter's range can step through
+pub trait Step: Copy + PartialOrd + std::fmt::Debug {
+    const ONE: Self;
+    fn is_zero(self) -> bool;
+    fn checked_add(self, other: Self) -> Option<Self>;
+}
+
+impl Step for u64 {
+    const ONE: Self = 1;
+    fn is_zero(self) -> bool {
+        self == 0
+    }
+    fn checked_add(self, other: Self) -> Option<Self> {
+        u64::checked_add(self, other)
+    }
+}
+
+// An amount as the scenarios write it, "0.5 BTC" or "10000 sat"
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Btc(pub Amount);
+
+impl<'de> Deserialize<'de> for Btc {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Btc, D::Error> {
+        let s = String::deserialize(d)?;
+        parse_amount(&s).map(Btc).map_err(serde::de::Error::custom)
+    }
+}
+
+impl Step for Btc {
+    const ONE: Self = Btc(Amount::ONE_SAT);
+    fn is_zero(self) -> bool {
+        self.0 == Amount::ZERO
+    }
+    fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Btc)
+    }
+}
+
+impl<T: Step> Values<T> {
+    pub fn expand(&self, name: &str) -> bitcoincore_rpc::Result<Vec<T>> {
+        let invalid = |why: String| bitcoincore_rpc::Error::ReturnedError(format!("{name}: {why}"));
+        match self {
+            Values::One(value) => Ok(vec![*value]),
+            Values::List(values) if values.is_empty() => Err(invalid("no values".to_owned())),
+            Values::List(values) => Ok(values.clone()),
+            Values::Range { from, to, step } => {
+                let step = step.unwrap_or(T::ONE);
+                if step.is_zero() {
+                    return Err(invalid("step can't be zero".to_owned()));
+                }
+                if from > to {
+                    return Err(invalid(format!("range {from:?} to {to:?} is empty")));
+                }
+                let mut values = vec![];
+                let mut value = Some(*from);
+                while let Some(v) = value.filter(|v| v <= to) {
+                    if values.len() == MAX_RUNS {
+                        return Err(invalid(format!("more than {MAX_RUNS} values")));
+                    }
+                    values.push(v);
+                    value = v.checked_add(step);
+                }
+                Ok(values)
+            }
+        }
+    }
+}
+
+// One combination of the parameters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Combination {
+    pub amount: Amount,
+    pub inputs: u64,
+    pub outputs: u64,
+    pub fee_rate: u64,
+}
+
+impl Parameters {
+    // Every combination, the last parameter (fee_rate) changing fastest
+    pub fn combinations(&self) -> bitcoincore_rpc::Result<Vec<Combination>> {
+        let amounts = self.amount.expand("amount")?;
+        let inputs = self.inputs.expand("inputs")?;
+        let outputs = self.outputs.expand("outputs")?;
+        let fee_rates = self.fee_rate.expand("fee_rate")?;
+        if inputs.contains(&0) || outputs.contains(&0) {
+            return Err(bitcoincore_rpc::Error::ReturnedError(
+                "inputs and outputs start at 1".to_owned(),
+            ));
+        }
+        let total = amounts.len() * inputs.len() * outputs.len() * fee_rates.len();
+        if total > MAX_RUNS {
+            return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "{total} combinations, at most {MAX_RUNS} are run"
+            )));
+        }
+        let mut all = vec![];
+        for amount in &amounts {
+            for inputs in &inputs {
+                for outputs in &outputs {
+                    for fee_rate in &fee_rates {
+                        all.push(Combination {
+                            amount: amount.0,
+                            inputs: *inputs,
+                            outputs: *outputs,
+                            fee_rate: *fee_rate,
+                        });
+                    }
+                }
+            }
+        }
+        Ok(all)
+    }
+}
+
+// What one run did, or why it didn't
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub index: usize,
+    pub combination: Combination,
+    pub wallet: String,
+    pub outcome: std::result::Result<Measured, String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Measured {
+    pub txid: Txid,
+    pub vsize: u64,
+    pub weight: u64,
+    pub fee: Amount,
+}
+
+impl Measured {
+    pub fn fee_rate(&self) -> f64 {
+        self.fee.to_sat() as f64 / self.vsize as f64
+    }
+}
+
+const CSV_HEADER: &str =
+    "run,amount_sat,inputs,outputs,target_fee_rate,txid,vsize,weight,fee_sat,fee_rate,error";
+
+// Quoted when it has to be, quotes doubled
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+// One row per run, the parameters first then what was measured, failures with the error
+// and the measurements left empty
+pub fn to_csv(runs: &[Run]) -> String {
+    let mut out = String::new();
+    writeln!(out, "{CSV_HEADER}").unwrap();
+    for run in runs {
+        let c = &run.combination;
+        let measured = match &run.outcome {
+            Ok(m) => format!(
+                "{},{},{},{},{:.3},",
+                m.txid,
+                m.vsize,
+                m.weight,
+                m.fee.to_sat(),
+                m.fee_rate()
+            ),
+            Err(e) => format!(",,,,,{}", csv_field(e)),
+        };
+        writeln!(
+            out,
+            "{},{},{},{},{},{measured}",
+            run.index,
+            c.amount.to_sat(),
+            c.inputs,
+            c.outputs,
+            c.fee_rate
+        )
+        .unwrap();
+    }
+    out
+}
+
+pub fn load(path: &Path) -> Result<SweepFile> {
+    let text = fs::read_to_string(path)?;
+    toml::from_str(&text).map_err(|e| {
+        bitcoincore_rpc::Error::ReturnedError(format!("{}: {e}", path.display())).into()
+    })
+}
+
+// Every combination in a fresh wallet of its own, funded by the Miner with exactly
+// `inputs` coins, paying `outputs` addresses of a shared sink wallet at the fee rate.
+// A failed run is recorded and the sweep goes on; a signal stops it after the current run.
+pub fn run(rpc: &Client, sweep: &SweepFile) -> Result<Vec<Run>> {
+    let combinations = sweep.parameters.combinations()?;
+    let miner = wallet::open(rpc, "Miner")?;
+    let sink = wallet::open(rpc, "SweepSink")?;
+    // Names no earlier sweep has used, so every run starts from an empty wallet
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut runs = vec![];
+    for (index, combination) in combinations.into_iter().enumerate() {
+        if shutdown::requested() {
+            break;
+        }
+        let name = format!("Sweep-{started}-{index}");
+        let outcome = run_one(rpc, &miner, &sink, &name, &combination).map_err(|e| e.to_string());
+        match &outcome {
+            Ok(m) => println!(
+                "Run {index} {combination:?}: {} vB, {} ({:.1} sat/vB)",
+                m.vsize,
+                m.fee,
+                m.fee_rate()
+            ),
+            Err(e) => eprintln!("Run {index} {combination:?} failed: {e}"),
+        }
+        if let Err(e) = rpc.unload_wallet(Some(&name)) {
+            eprintln!("Could not unload {name}: {e}");
+        }
+        runs.push(Run {
+            index,
+            combination,
+            wallet: name,
+            outcome,
+        });
+    }
+    Ok(runs)
+}
+
+fn run_one(
+    rpc: &Client,
+    miner: &Client,
+    sink: &Client,
+    name: &str,
+    c: &Combination,
+) -> Result<Measured> {
+    let payer = wallet::open(rpc, name)?;
+    let fee_rate = FeeRate::from_sat_per_vb(c.fee_rate).ok_or_else(|| {
+        bitcoincore_rpc::Error::ReturnedError(format!("fee rate {} is too high", c.fee_rate))
+    })?;
+
+    // Enough per coin that together they cover the payments and the fee at this rate, with
+    // the fee estimate doubled so there's change
+    let vsize = TX_OVERHEAD_VBYTES
+        + P2WPKH_INPUT_VBYTES * c.inputs
+        + (OUTPUT_BASE_VBYTES + P2WPKH_SCRIPT_LEN) * (c.outputs + 1);
+    let fee = fee_rate.fee_vb(vsize).unwrap_or(Amount::MAX_MONEY);
+    let total = c.amount * c.outputs + fee * 2 + coins::FEE_HEADROOM;
+    let per_input = Amount::from_sat(total.to_sat().div_ceil(c.inputs));
+    let mut funding = HashMap::new();
+    for _ in 0..c.inputs {
+        let address = payer.get_new_address(None, None)?.assume_checked();
+        funding.insert(address.to_string(), per_input.to_btc());
+    }
+    coins::mine_until(
+        miner,
+        per_input * c.inputs + coins::FEE_HEADROOM,
+        coins::MAX_FUNDING_BLOCKS,
+    )?;
+    miner.call::<Txid>("sendmany", &["".into(), serde_json::to_value(&funding)?])?;
+    let mine_to = miner.get_new_address(None, None)?.assume_checked();
+    rpc.generate_to_address(1, &mine_to)?;
+
+    let utxos = payer.list_unspent(Some(1), None, None, None, None)?;
+    let mut tx = utxos.iter().fold(TxBuilder::new(), TxBuilder::spend);
+    for _ in 0..c.outputs {
+        tx = tx.pay(
+            &sink.get_new_address(None, None)?.assume_checked(),
+            c.amount,
+        );
+    }
+    let change = payer.get_raw_change_address(None)?.assume_checked();
+    let unsigned = tx.change_to(&change).fee_rate(fee_rate).build()?;
+    let signed = builder::sign(&payer, &unsigned)?;
+    let txid = payer.send_raw_transaction(&signed)?;
+    rpc.generate_to_address(1, &mine_to)?;
+
+    let input_value: Amount = utxos.iter().map(|u| u.amount).sum();
+    let output_value: Amount = signed.output.iter().map(|o| o.value).sum();
+    Ok(Measured {
+        txid,
+        vsize: signed.vsize() as u64,
+        weight: signed.weight().to_wu(),
+        fee: input_value - output_value,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+
+    #[test]
+    fn expands_every_combination() {
+        let sweep: SweepFile = toml::from_str(
+            r#"
+            [parameters]
+            amount = ["0.1 BTC", "1 BTC"]
+            inputs = { from = 1, to = 3 }
+            fee_rate = { from = 1, to = 21, step = 10 }
+            "#,
+        )
+        .unwrap();
+        let all = sweep.parameters.combinations().unwrap();
+        assert_eq!(all.len(), 2 * 3 * 3);
+        assert_eq!(
+            all[1],
+            Combination {
+                amount: Amount::from_sat(10_000_000),
+                inputs: 1,
+                outputs: 1,
+                fee_rate: 11,
+            }
+        );
+        assert_eq!(all.last().unwrap().inputs, 3);
+
+        let amounts: Values<Btc> = toml::from_str::<SweepFile>(
+            r#"
+            [parameters]
+            amount = { from = "1000 sat", to = "3500 sat", step = "1000 sat" }
+            "#,
+        )
+        .unwrap()
+        .parameters
+        .amount;
+        assert_eq!(amounts.expand("amount").unwrap().len(), 3);
+
+        let zero = Values::Range {
+            from: 1u64,
+            to: 5,
+            step: Some(0),
+        };
+        assert!(zero.expand("inputs").is_err());
+        let none: SweepFile = toml::from_str("[parameters]\ninputs = [0, 1]").unwrap();
+        assert!(none.parameters.combinations().is_err());
+        assert!(toml::from_str::<SweepFile>("[parameters]\nfeerate = 1").is_err());
+    }
+
+    #[test]
+    fn writes_one_row_per_run() {
+        let combination = Combination {
+            amount: Amount::from_sat(50_000),
+            inputs: 2,
+            outputs: 1,
+            fee_rate: 5,
+        };
+        let runs = [
+            Run {
+                index: 0,
+                combination,
+                wallet: "Sweep-0-0".into(),
+                outcome: Ok(Measured {
+                    txid: Txid::all_zeros(),
+                    vsize: 209,
+                    weight: 833,
+                    fee: Amount::from_sat(1_045),
+                }),
+            },
+            Run {
+                index: 1,
+                combination,
+                wallet: "Sweep-0-1".into(),
+                outcome: Err("insufficient funds, \"Miner\" is empty".into()),
+            },
+        ];
+        let csv = to_csv(&runs);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            format!("0,50000,2,1,5,{},209,833,1045,5.000,", Txid::all_zeros())
+        );
+        assert_eq!(
+            lines[2],
+            "1,50000,2,1,5,,,,,,\"insufficient funds, \"\"Miner\"\" is empty\""
+        );
+        assert_eq!(
+            lines[2].split(',').count(),
+            CSV_HEADER.split(',').count() + 1,
+            "the quoted comma is the only extra"
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use bitcoincore_rpc::bitcoin::{Amount, Txid};
+use bitcoincore_rpc::json::{
+    GetBlockTemplateModes, GetBlockTemplateResult, GetBlockTemplateResultTransaction,
+    GetBlockTemplateRules,
+};
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::consensus::subsidy;
+
+// What Core's BlockAssembler keeps free for the coinbase when filling a template, in
+// weight units and sigop cost
+const COINBASE_RESERVED_WEIGHT: u64 = 4000;
+const COINBASE_RESERVED_SIGOPS: u64 = 400;
+
+// getblocktemplate's view of the next block, checked the way a miner would before working
+// on it
+#[derive(Debug, Serialize)]
+pub struct TemplateReport {
+    pub height: u64,
+    pub transactions: usize,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub subsidy: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fees: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub coinbase_value: Amount,
+    // Both with the coinbase reservation included
+    pub weight: u64,
+    pub weight_limit: u64,
+    pub sigops: u64,
+    pub sigop_limit: u64,
+    pub passed: bool,
+    pub checks: Vec<TemplateCheck>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateCheck {
+    pub check: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+pub fn fetch(rpc: &impl RpcApi) -> bitcoincore_rpc::Result<GetBlockTemplateResult> {
+    rpc.get_block_template(
+        GetBlockTemplateModes::Template,
+        &[GetBlockTemplateRules::SegWit],
+        &[],
+    )
+}
+
+pub fn check(template: &GetBlockTemplateResult) -> TemplateReport {
+    check_transactions(
+        template.height,
+        template.coinbase_value,
+        template.weight_limit.into(),
+        template.sigop_limit.into(),
+        &template.transactions,
+    )
+}
+
+// The checks themselves: the coinbase may claim exactly subsidy plus fees, the block must
+// stay within the weight and sigop limits, every transaction's data must match what the
+// template says about it, and transactions must come after the ones they spend, as
+// listed in `depends`.
+fn check_transactions(
+    height: u64,
+    coinbase_value: Amount,
+    weight_limit: u64,
+    sigop_limit: u64,
+    transactions: &[GetBlockTemplateResultTransaction],
+) -> TemplateReport {
+    let mut checks = vec![];
+    let mut check = |check, passed, detail| {
+        checks.push(TemplateCheck {
+            check,
+            passed,
+            detail,
+        })
+    };
+
+    let fees: Amount = transactions.iter().map(|t| t.fee).sum();
+    let expected = subsidy(height) + fees;
+    check(
+        "coinbase value",
+        coinbase_value == expected,
+        format!(
+            "{coinbase_value}, subsidy {} + fees {fees} = {expected}",
+            subsidy(height)
+        ),
+    );
+
+    let weight =
+        COINBASE_RESERVED_WEIGHT + transactions.iter().map(|t| t.weight as u64).sum::<u64>();
+    check(
+        "weight",
+        weight <= weight_limit,
+        format!("{weight} of {weight_limit} WU, {COINBASE_RESERVED_WEIGHT} kept for the coinbase"),
+    );
+
+    let sigops = COINBASE_RESERVED_SIGOPS
+        + transactions
+            .iter()
+            .map(|t| u64::from(t.sigops))
+            .sum::<u64>();
+    check(
+        "sigops",
+        sigops <= sigop_limit,
+        format!("cost {sigops} of {sigop_limit}, {COINBASE_RESERVED_SIGOPS} kept for the coinbase"),
+    );
+
+    // 1-based, like `depends`
+    let positions: HashMap<Txid, u32> = transactions
+        .iter()
+        .enumerate()
+        .map(|(i, t)| (t.txid, i as u32 + 1))
+        .collect();
+    let mut data_errors = vec![];
+    let mut order_errors = vec![];
+    for (i, entry) in transactions.iter().enumerate() {
+        let position = i as u32 + 1;
+        let tx = match entry.transaction() {
+            Ok(tx) => tx,
+            Err(e) => {
+                data_errors.push(format!("#{position} does not decode: {e}"));
+                continue;
+            }
+        };
+        if tx.txid() != entry.txid || tx.wtxid() != entry.wtxid {
+            data_errors.push(format!(
+                "#{position} hashes to {}, not {}",
+                tx.txid(),
+                entry.txid
+            ));
+        }
+        if tx.weight().to_wu() != entry.weight as u64 {
+            data_errors.push(format!(
+                "#{position} weighs {} WU, not {}",
+                tx.weight().to_wu(),
+                entry.weight
+            ));
+        }
+
+        let mut parents: Vec<u32> = tx
+            .input
+            .iter()
+            .filter_map(|input| positions.get(&input.previous_output.txid).copied())
+            .collect();
+        parents.sort_unstable();
+        parents.dedup();
+        if let Some(later) = parents.iter().find(|&&p| p >= position) {
+            order_errors.push(format!("#{position} spends #{later}, which comes after it"));
+        }
+        let mut depends = entry.depends.clone();
+        depends.sort_unstable();
+        if depends != parents {
+            order_errors.push(format!(
+                "#{position} depends on {depends:?} but spends {parents:?}"
+            ));
+        }
+    }
+    let summary = |errors: Vec<String>, ok: &str| {
+        if errors.is_empty() {
+            ok.to_owned()
+        } else {
+            errors.join("; ")
+        }
+    };
+    check(
+        "transaction data",
+        data_errors.is_empty(),
+        summary(data_errors, "txids, wtxids and weights match"),
+    );
+    check(
+        "ordering",
+        order_errors.is_empty(),
+        summary(order_errors, "every transaction comes after its parents"),
+    );
+
+    TemplateReport {
+        height,
+        transactions: transactions.len(),
+        subsidy: subsidy(height),
+        fees,
+        coinbase_value,
+        weight,
+        weight_limit,
+        sigops,
+        sigop_limit,
+        passed: checks.iter().all(|c| c.passed),
+        checks,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::consensus::encode;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{
+        OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness,
+    };
+
+    fn entry(spends: OutPoint, fee: u64, depends: Vec<u32>) -> GetBlockTemplateResultTransaction {
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: spends,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        GetBlockTemplateResultTransaction {
+            txid: tx.txid(),
+            wtxid: tx.wtxid(),
+            raw_tx: encode::serialize(&tx),
+            fee: Amount::from_sat(fee),
+            sigops: 4,
+            weight: tx.weight().to_wu() as usize,
+            depends,
+        }
+    }
+
+    fn chain() -> Vec<GetBlockTemplateResultTransaction> {
+        let parent = entry(OutPoint::new(Txid::all_zeros(), 0), 300, vec![]);
+        let child = entry(OutPoint::new(parent.txid, 0), 200, vec![1]);
+        vec![parent, child]
+    }
+
+    #[test]
+    fn accepts_a_consistent_template() {
+        let coinbase_value = subsidy(200) + Amount::from_sat(500);
+        let report = check_transactions(200, coinbase_value, 4_000_000, 80_000, &chain());
+        assert!(report.passed, "{:?}", report.checks);
+        assert_eq!(report.fees, Amount::from_sat(500));
+        assert_eq!(report.sigops, 408);
+    }
+
+    #[test]
+    fn flags_a_greedy_coinbase_and_limits() {
+        let coinbase_value = subsidy(200) + Amount::from_sat(501);
+        let report = check_transactions(200, coinbase_value, 4_000, 400, &chain());
+        let failed: Vec<_> = report
+            .checks
+            .iter()
+            .filter(|c| !c.passed)
+            .map(|c| c.check)
+            .collect();
+        assert_eq!(failed, ["coinbase value", "weight", "sigops"]);
+    }
+
+    #[test]
+    fn flags_a_child_before_its_parent() {
+        let mut transactions = chain();
+        transactions.swap(0, 1);
+        transactions[0].depends = vec![];
+        transactions[1].depends = vec![];
+        let coinbase_value = subsidy(200) + Amount::from_sat(500);
+        let report = check_transactions(200, coinbase_value, 4_000_000, 80_000, &transactions);
+        let ordering = report
+            .checks
+            .iter()
+            .find(|c| c.check == "ordering")
+            .unwrap();
+        assert!(!ordering.passed);
+        assert!(
+            ordering.detail.contains("#1 spends #2"),
+            "{}",
+            ordering.detail
+        );
+    }
+}
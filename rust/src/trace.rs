@@ -0,0 +1,377 @@
+use bitcoincore_rpc::bitcoin::{Amount, OutPoint, Transaction, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::rpc::CachingClient;
+
+// How a traced output ends up
+#[derive(Debug)]
+pub enum Fate {
+    // Created by a coinbase transaction at this height
+    Coinbase(u64),
+    // Still in the UTXO set
+    Unspent,
+    // Spent by this transaction, which is still unconfirmed
+    SpentInMempool(Txid),
+    // Spent by this confirmed transaction
+    Spent(Txid),
+    // Spent by a transaction we could not find
+    SpentUnknown,
+    // Depth limit reached before the trace ended
+    Truncated,
+}
+
+// One output in a coin's history, with the outputs it came from (backward traces) or
+// went to (forward traces)
+#[derive(Debug)]
+pub struct TraceNode {
+    pub outpoint: OutPoint,
+    pub value: Amount,
+    pub fate: Option<Fate>,
+    pub children: Vec<TraceNode>,
+}
+
+pub struct Tracer<'a, R> {
+    rpc: &'a CachingClient<R>,
+    max_depth: usize,
+    // Spends seen in the blocks scanned so far, and the next block height to scan
+    spends: HashMap<OutPoint, Txid>,
+    next_height: Option<u64>,
+}
+
+impl<'a, R: RpcApi> Tracer<'a, R> {
+    pub fn new(rpc: &'a CachingClient<R>, max_depth: usize) -> Self {
+        Tracer {
+            rpc,
+            max_depth,
+            spends: HashMap::new(),
+            next_height: None,
+        }
+    }
+
+    fn tx(&self, txid: &Txid) -> bitcoincore_rpc::Result<Transaction> {
+        self.rpc.get_raw_transaction(txid, None)
+    }
+
+    fn value(&self, outpoint: &OutPoint) -> bitcoincore_rpc::Result<Amount> {
+        let tx = self.tx(&outpoint.txid)?;
+        tx.output
+            .get(outpoint.vout as usize)
+            .map(|o| o.value)
+            .ok_or_else(|| {
+                bitcoincore_rpc::Error::ReturnedError(format!("{outpoint} does not exist"))
+            })
+    }
+
+    // Height of the block containing `txid`, None while it's in the mempool
+    fn height_of(&self, txid: &Txid) -> bitcoincore_rpc::Result<Option<u64>> {
+        match self.rpc.get_raw_transaction_info(txid, None)?.blockhash {
+            Some(hash) => Ok(Some(self.rpc.get_block_header_info(&hash)?.height as u64)),
+            None => Ok(None),
+        }
+    }
+
+    // Walk back through the inputs that funded `outpoint` until coinbases are reached
+    pub fn backward(&mut self, outpoint: OutPoint) -> bitcoincore_rpc::Result<TraceNode> {
+        self.backward_from(outpoint, 0)
+    }
+
+    fn backward_from(
+        &mut self,
+        outpoint: OutPoint,
+        depth: usize,
+    ) -> bitcoincore_rpc::Result<TraceNode> {
+        let tx = self.tx(&outpoint.txid)?;
+        let mut node = TraceNode {
+            outpoint,
+            value: self.value(&outpoint)?,
+            fate: None,
+            children: vec![],
+        };
+        if tx.is_coinbase() {
+            let height = self.height_of(&outpoint.txid)?.unwrap_or_default();
+            node.fate = Some(Fate::Coinbase(height));
+        } else if depth == self.max_depth {
+            node.fate = Some(Fate::Truncated);
+        } else {
+            for input in &tx.input {
+                node.children
+                    .push(self.backward_from(input.previous_output, depth + 1)?);
+            }
+        }
+        Ok(node)
+    }
+
+    // Follow `outpoint` through the transactions spending it until unspent outputs remain
+    pub fn forward(&mut self, outpoint: OutPoint) -> bitcoincore_rpc::Result<TraceNode> {
+        if self.next_height.is_none() {
+            // Nothing can spend the output before the block that created it
+            self.next_height = self.height_of(&outpoint.txid)?;
+        }
+        self.forward_from(outpoint, 0)
+    }
+
+    fn forward_from(
+        &mut self,
+        outpoint: OutPoint,
+        depth: usize,
+    ) -> bitcoincore_rpc::Result<TraceNode> {
+        let mut node = TraceNode {
+            outpoint,
+            value: self.value(&outpoint)?,
+            fate: None,
+            children: vec![],
+        };
+        let fate = self.spender(&outpoint)?;
+        let spent_by = match fate {
+            Fate::Spent(txid) | Fate::SpentInMempool(txid) => Some(txid),
+            _ => None,
+        };
+        node.fate = Some(fate);
+
+        if let Some(txid) = spent_by {
+            if depth == self.max_depth {
+                node.fate = Some(Fate::Truncated);
+                return Ok(node);
+            }
+            let outputs = self.tx(&txid)?.output.len() as u32;
+            for vout in 0..outputs {
+                node.children
+                    .push(self.forward_from(OutPoint::new(txid, vout), depth + 1)?);
+            }
+        }
+        Ok(node)
+    }
+
+    fn spender(&mut self, outpoint: &OutPoint) -> bitcoincore_rpc::Result<Fate> {
+        if let Some(txid) = self.spends.get(outpoint) {
+            return Ok(Fate::Spent(*txid));
+        }
+        if self
+            .rpc
+            .get_tx_out(&outpoint.txid, outpoint.vout, Some(true))?
+            .is_some()
+        {
+            return Ok(Fate::Unspent);
+        }
+        if let Some(txid) = self.mempool_spender(outpoint) {
+            return Ok(Fate::SpentInMempool(txid));
+        }
+
+        // e1ec30: Core has no index from outpoint to spender, so index the spends of every
+        // block from the creating one onwards, resuming where the last lookup stopped
+        let tip = self.rpc.get_block_count()?;
+        while let Some(height) = self.next_height.filter(|h| *h <= tip) {
+            let hash = self.rpc.get_block_hash(height)?;
+            for tx in self.rpc.get_block(&hash)?.txdata {
+                let txid = tx.txid();
+                for input in &tx.input {
+                    self.spends.insert(input.previous_output, txid);
+                }
+            }
+            self.next_height = Some(height + 1);
+            if let Some(txid) = self.spends.get(outpoint) {
+                return Ok(Fate::Spent(*txid));
+            }
+        }
+        Ok(Fate::SpentUnknown)
+    }
+
+    // gettxspendingprevout only exists since Bitcoin Core 24, older nodes just get None
+    fn mempool_spender(&self, outpoint: &OutPoint) -> Option<Txid> {
+        #[derive(Deserialize)]
+        struct Spending {
+            spendingtxid: Option<Txid>,
+        }
+        let args = [json!([{ "txid": outpoint.txid, "vout": outpoint.vout }])];
+        self.rpc
+            .call::<Vec<Spending>>("gettxspendingprevout", &args)
+            .ok()?
+            .into_iter()
+            .next()?
+            .spendingtxid
+    }
+}
+
+impl fmt::Display for Fate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fate::Coinbase(height) => write!(f, "coinbase at height {height}"),
+            Fate::Unspent => write!(f, "unspent"),
+            Fate::SpentInMempool(txid) => write!(f, "spent by {txid} (mempool)"),
+            Fate::Spent(txid) => write!(f, "spent by {txid}"),
+            Fate::SpentUnknown => write!(f, "spent, spender not found"),
+            Fate::Truncated => write!(f, "..."),
+        }
+    }
+}
+
+impl fmt::Display for TraceNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn write_node(
+            f: &mut fmt::Formatter,
+            node: &TraceNode,
+            prefix: &str,
+            last: bool,
+            root: bool,
+        ) -> fmt::Result {
+            let (branch, indent) = match (root, last) {
+                (true, _) => ("", ""),
+                (false, true) => ("└── ", "    "),
+                (false, false) => ("├── ", "│   "),
+            };
+            write!(f, "{prefix}{branch}{} {}", node.outpoint, node.value)?;
+            match &node.fate {
+                Some(fate) => writeln!(f, " [{fate}]")?,
+                None => writeln!(f)?,
+            }
+            let prefix = format!("{prefix}{indent}");
+            for (i, child) in node.children.iter().enumerate() {
+                write_node(f, child, &prefix, i + 1 == node.children.len(), false)?;
+            }
+            Ok(())
+        }
+        write_node(f, self, "", true, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::consensus::encode;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{BlockHash, ScriptBuf, Sequence, TxIn, TxOut, Witness};
+    use serde_json::Value;
+
+    const HEIGHT: u64 = 101;
+
+    fn tx(spends: OutPoint, value: u64) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: spends,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_op_return([1, 2, 3]),
+            }],
+        }
+    }
+
+    fn raw(tx: &Transaction) -> Value {
+        json!(encode::serialize_hex(tx))
+    }
+
+    // `tx` confirmed at HEIGHT: the verbose getrawtransaction, then the block's header
+    fn confirmed(rpc: MockClient, tx: &Transaction) -> MockClient {
+        let hash = BlockHash::all_zeros();
+        rpc.returns(
+            "getrawtransaction",
+            json!({
+                "hex": encode::serialize_hex(tx),
+                "txid": tx.txid(),
+                "hash": tx.wtxid(),
+                "size": tx.total_size(),
+                "vsize": tx.vsize(),
+                "version": 2,
+                "locktime": 0,
+                "vin": [],
+                "vout": [],
+                "blockhash": hash,
+                "confirmations": 1,
+            }),
+        )
+        .returns(
+            "getblockheader",
+            json!({
+                "hash": hash,
+                "confirmations": 1,
+                "height": HEIGHT,
+                "version": 0x20000000,
+                "merkleroot": tx.txid(),
+                "time": 0,
+                "nonce": 0,
+                "bits": "207fffff",
+                "difficulty": 0.0,
+                "chainwork": "00",
+                "nTx": 1,
+            }),
+        )
+    }
+
+    #[test]
+    fn walks_back_to_the_coinbase() {
+        let coinbase = tx(OutPoint::null(), 5_000_000_000);
+        let child = tx(OutPoint::new(coinbase.txid(), 0), 4_999_000_000);
+        let rpc = MockClient::new()
+            .returns("getrawtransaction", raw(&child))
+            .returns("getrawtransaction", raw(&coinbase));
+        let cached = CachingClient::new(confirmed(rpc, &coinbase));
+
+        let trace = Tracer::new(&cached, 5)
+            .backward(OutPoint::new(child.txid(), 0))
+            .unwrap();
+        assert_eq!(trace.value, Amount::from_sat(4_999_000_000));
+        assert!(trace.fate.is_none());
+        let [origin] = &trace.children[..] else {
+            panic!("expected one input, got {trace:?}");
+        };
+        assert_eq!(origin.outpoint, OutPoint::new(coinbase.txid(), 0));
+        assert!(matches!(origin.fate, Some(Fate::Coinbase(HEIGHT))));
+        assert!(origin.children.is_empty());
+        cached.inner().assert_done();
+    }
+
+    #[test]
+    fn follows_an_output_still_unspent() {
+        let funding = tx(OutPoint::new(Txid::all_zeros(), 0), 10_000);
+        let rpc = confirmed(MockClient::new(), &funding)
+            .returns("getrawtransaction", raw(&funding))
+            .returns(
+                "gettxout",
+                json!({
+                    "bestblock": BlockHash::all_zeros(),
+                    "confirmations": 1,
+                    "value": 0.0001,
+                    "scriptPubKey": { "asm": "", "hex": "6a03010203", "type": "nulldata" },
+                    "coinbase": false,
+                }),
+            );
+        let cached = CachingClient::new(rpc);
+
+        let trace = Tracer::new(&cached, 5)
+            .forward(OutPoint::new(funding.txid(), 0))
+            .unwrap();
+        assert_eq!(trace.value, Amount::from_sat(10_000));
+        assert!(matches!(trace.fate, Some(Fate::Unspent)));
+        assert!(trace.children.is_empty());
+        cached.inner().assert_done();
+    }
+
+    #[test]
+    fn an_output_past_the_last_is_an_error() {
+        let funding = tx(OutPoint::new(Txid::all_zeros(), 0), 10_000);
+        let cached =
+            CachingClient::new(MockClient::new().returns("getrawtransaction", raw(&funding)));
+        let outpoint = OutPoint::new(funding.txid(), 99);
+        let error = Tracer::new(&cached, 5)
+            .backward(outpoint)
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains(&format!("{outpoint} does not exist")),
+            "{error}"
+        );
+        cached.inner().assert_done();
+    }
+}
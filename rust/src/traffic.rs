@@ -0,0 +1,170 @@
+use bitcoincore_rpc::bitcoin::Amount;
+use clap::ValueEnum;
+use rand::Rng;
+use rand_distr::{Distribution, Exp, LogNormal};
+use std::time::Duration;
+
+// Smallest payment drawn, comfortably above the dust limit
+const MIN_AMOUNT: Amount = Amount::from_sat(1_000);
+
+// How payment values are drawn
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AmountDist {
+    Fixed(Amount),
+    // Most payments small, a long tail of big ones, like on mainnet
+    LogNormal { median: Amount, sigma: f64 },
+}
+
+// When the next transaction goes out, at `rate` per second on average
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArrivalDist {
+    Fixed,
+    // A Poisson process: independent arrivals with exponential gaps
+    Exponential,
+}
+
+// Fee rates in sat/vB, None leaves them to the wallet's estimate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FeeRateDist {
+    Wallet,
+    // Two crowds: the patient ones around `low`, the urgent `high_share` around `high`
+    Bimodal {
+        low: f64,
+        high: f64,
+        high_share: f64,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum AmountKind {
+    Fixed,
+    Lognormal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArrivalKind {
+    Fixed,
+    Exponential,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FeeRateKind {
+    Wallet,
+    Bimodal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Traffic {
+    pub amounts: AmountDist,
+    pub arrivals: ArrivalDist,
+    pub fee_rates: FeeRateDist,
+}
+
+impl AmountDist {
+    // Upper bound of what `sample` returns, funding outputs have to cover it
+    pub fn max(&self) -> Amount {
+        match *self {
+            AmountDist::Fixed(amount) => amount,
+            // Three standard deviations out, samples beyond are clamped
+            AmountDist::LogNormal { median, sigma } => {
+                Amount::from_sat((median.to_sat() as f64 * (3.0 * sigma).exp()) as u64)
+            }
+        }
+    }
+
+    pub fn sample(&self, rng: &mut impl Rng) -> Amount {
+        match *self {
+            AmountDist::Fixed(amount) => amount,
+            AmountDist::LogNormal { median, sigma } => {
+                let dist = LogNormal::new((median.to_sat() as f64).ln(), sigma)
+                    .expect("sigma must be finite and non-negative");
+                let sat = dist.sample(rng) as u64;
+                Amount::from_sat(sat).clamp(MIN_AMOUNT, self.max().max(MIN_AMOUNT))
+            }
+        }
+    }
+}
+
+impl ArrivalDist {
+    pub fn sample(&self, rate: f64, rng: &mut impl Rng) -> Duration {
+        match self {
+            ArrivalDist::Fixed => Duration::from_secs_f64(1.0 / rate),
+            ArrivalDist::Exponential => {
+                let dist = Exp::new(rate).expect("rate must be positive");
+                Duration::from_secs_f64(dist.sample(rng))
+            }
+        }
+    }
+}
+
+impl FeeRateDist {
+    pub fn sample(&self, rng: &mut impl Rng) -> Option<f64> {
+        match *self {
+            FeeRateDist::Wallet => None,
+            FeeRateDist::Bimodal {
+                low,
+                high,
+                high_share,
+            } => {
+                let center = if rng.gen_bool(high_share.clamp(0.0, 1.0)) {
+                    high
+                } else {
+                    low
+                };
+                // Spread each crowd +-25% around its center, never below the relay minimum
+                let rate = center * rng.gen_range(0.75..=1.25);
+                Some((rate * 1000.0).round().max(1000.0) / 1000.0)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn lognormal_amounts_center_on_the_median() {
+        let dist = AmountDist::LogNormal {
+            median: Amount::from_sat(100_000),
+            sigma: 1.0,
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut samples: Vec<Amount> = (0..2001).map(|_| dist.sample(&mut rng)).collect();
+        samples.sort();
+        let median = samples[1000].to_sat();
+        assert!((70_000..140_000).contains(&median), "median {median}");
+        assert!(samples.iter().all(|a| *a >= MIN_AMOUNT && *a <= dist.max()));
+    }
+
+    #[test]
+    fn exponential_arrivals_average_the_rate() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let total: f64 = (0..4000)
+            .map(|_| ArrivalDist::Exponential.sample(4.0, &mut rng).as_secs_f64())
+            .sum();
+        let mean = total / 4000.0;
+        assert!((0.22..0.28).contains(&mean), "mean gap {mean}s");
+        assert_eq!(
+            ArrivalDist::Fixed.sample(4.0, &mut rng),
+            Duration::from_millis(250)
+        );
+    }
+
+    #[test]
+    fn bimodal_fee_rates_split_by_share() {
+        let dist = FeeRateDist::Bimodal {
+            low: 2.0,
+            high: 40.0,
+            high_share: 0.25,
+        };
+        let mut rng = StdRng::seed_from_u64(3);
+        let rates: Vec<f64> = (0..4000).map(|_| dist.sample(&mut rng).unwrap()).collect();
+        let high = rates.iter().filter(|r| **r > 20.0).count();
+        assert!((800..1200).contains(&high), "{high} high-fee samples");
+        assert!(rates.iter().all(|r| *r >= 1.0 && *r <= 50.0));
+        assert_eq!(FeeRateDist::Wallet.sample(&mut rng), None);
+    }
+}
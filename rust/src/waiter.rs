@@ -0,0 +1,147 @@
+use bitcoincore_rpc::bitcoin::{Block, Txid};
+use bitcoincore_rpc::RpcApi;
+use serde_json::{json, Value};
+use std::cmp;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chain;
+use crate::error::Result;
+use crate::rpc::error_code;
+
+// Longest a single waitforblockheight call is allowed to block. The HTTP client gives up on
+// a request after 15 seconds, so longer waits are made of several calls.
+const LONG_POLL_CHUNK: Duration = Duration::from_secs(10);
+// How often getblockcount is asked when the node can't be long-polled
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// JSON-RPC's "method not found", for nodes (or proxies) that don't offer waitforblockheight
+const RPC_METHOD_NOT_FOUND: i32 = -32601;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    // waitforblockheight: the node answers as soon as the block is connected
+    LongPoll,
+    // getblockcount every POLL_INTERVAL
+    Poll,
+}
+
+// Waits for blocks without ZMQ, by long-polling the node when it can and polling it when not
+#[derive(Debug, Clone, Copy)]
+pub struct BlockWaiter {
+    pub strategy: Strategy,
+}
+
+impl BlockWaiter {
+    // Ask for a height that's already there: a node that can long-poll answers right away,
+    // one that can't (or an -rpcwhitelist that doesn't allow it) fails
+    pub fn detect(rpc: &impl RpcApi) -> BlockWaiter {
+        let probe = rpc.call::<Value>("waitforblockheight", &[json!(0), json!(1)]);
+        let strategy = match probe {
+            Ok(_) => Strategy::LongPoll,
+            Err(e) => {
+                if error_code(&e) != Some(RPC_METHOD_NOT_FOUND) {
+                    eprintln!("waitforblockheight is unavailable ({e}), polling for blocks");
+                }
+                Strategy::Poll
+            }
+        };
+        BlockWaiter { strategy }
+    }
+
+    // Block until the tip is at least `height` or `timeout` has passed, and return the tip
+    // height, which is below `height` on a timeout
+    pub fn wait_for_height(
+        &self,
+        rpc: &impl RpcApi,
+        height: u64,
+        timeout: Duration,
+    ) -> Result<u64> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let tip = match self.strategy {
+                Strategy::LongPoll => {
+                    let chunk = cmp::min(left, LONG_POLL_CHUNK).as_millis() as u64;
+                    // A zero timeout would mean waiting forever
+                    let tip: Value =
+                        rpc.call("waitforblockheight", &[json!(height), json!(chunk.max(1))])?;
+                    tip["height"].as_u64().unwrap_or_default()
+                }
+                Strategy::Poll => rpc.get_block_count()?,
+            };
+            if tip >= height || left.is_zero() {
+                return Ok(tip);
+            }
+            if self.strategy == Strategy::Poll {
+                thread::sleep(cmp::min(left, POLL_INTERVAL));
+            }
+        }
+    }
+
+    // The block that confirmed `txid`, waiting up to `timeout` for it to be mined. Each miss
+    // waits for the block after the tip seen before looking, so one found in between isn't
+    // missed.
+    pub fn wait_for_confirmation(
+        &self,
+        rpc: &impl RpcApi,
+        txid: &Txid,
+        depth: u64,
+        timeout: Duration,
+    ) -> Result<Option<Block>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let tip = rpc.get_block_count()?;
+            if let Some(block) = chain::find_confirmation(rpc, txid, depth)? {
+                return Ok(Some(block));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                return Ok(None);
+            }
+            self.wait_for_height(rpc, tip + 1, left)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+
+    #[test]
+    fn detects_long_polling() {
+        let rpc = MockClient::new().returns("waitforblockheight", json!({ "height": 5 }));
+        assert_eq!(BlockWaiter::detect(&rpc).strategy, Strategy::LongPoll);
+
+        let rpc = MockClient::new().fails("waitforblockheight", -32601, "Method not found");
+        assert_eq!(BlockWaiter::detect(&rpc).strategy, Strategy::Poll);
+    }
+
+    #[test]
+    fn waits_until_the_height_is_reached() {
+        let waiter = BlockWaiter {
+            strategy: Strategy::LongPoll,
+        };
+        let rpc = MockClient::new()
+            .returns("waitforblockheight", json!({ "height": 5 }))
+            .returns("waitforblockheight", json!({ "height": 6 }));
+        let tip = waiter
+            .wait_for_height(&rpc, 6, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(tip, 6);
+        rpc.assert_done();
+
+        let waiter = BlockWaiter {
+            strategy: Strategy::Poll,
+        };
+        let rpc = MockClient::new()
+            .returns("getblockcount", json!(5))
+            .returns("getblockcount", json!(7));
+        let tip = waiter
+            .wait_for_height(&rpc, 6, Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(tip, 7);
+        rpc.assert_done();
+    }
+}
@@ -0,0 +1,537 @@
+use bitcoincore_rpc::bitcoin::{self, Address, BlockHash, ScriptBuf, Txid};
+use bitcoincore_rpc::json::{
+    AddressType, ImportDescriptors, ImportMultiResult, ScanningDetails, Timestamp,
+};
+use bitcoincore_rpc::{Client, RpcApi};
+use std::ops::{Bound, ControlFlow, RangeBounds};
+use std::thread;
+use std::time::Duration;
+
+use crate::compat::Discovery;
+use crate::descriptor;
+use crate::report::TxMetadata;
+use crate::rpc::error_code;
+use crate::shutdown;
+use crate::{get_client_at_url, script_to_addr};
+
+// How often getwalletinfo is polled while a rescan is running
+const RESCAN_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Wallet error codes from Bitcoin Core's rpc/protocol.h
+const RPC_WALLET_ERROR: i32 = -4;
+const RPC_WALLET_NOT_FOUND: i32 = -18;
+const RPC_WALLET_ALREADY_LOADED: i32 = -35;
+const RPC_WALLET_ALREADY_EXISTS: i32 = -36;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletStatus {
+    Loaded,
+    Created,
+    // Loaded before this call, possibly by someone racing us
+    AlreadyLoaded,
+}
+
+// e1ec30: A little helper to first try loading the wallet before creating it
+// Goes by the node's error codes. Another process can create or load the same wallet in
+// between, so "already exists" and "already loaded" answers count as success. Versions
+// before 25 report those as a generic wallet error (e.g. "Wallet file verification
+// failed"), which is taken as a race only if the wallet really is loaded by now.
+pub fn load_or_create_wallet(
+    name: &str,
+    rpc: &impl RpcApi,
+) -> bitcoincore_rpc::Result<WalletStatus> {
+    load_or_create(name, rpc, CreateOptions::default())
+}
+
+// How a wallet that doesn't exist yet is created
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CreateOptions {
+    // Blank and without private keys, ready for public descriptors to be imported
+    pub watch_only: bool,
+    // Set avoid_reuse, so the wallet doesn't spend from addresses that were paid twice
+    // unless told to
+    pub avoid_reuse: bool,
+}
+
+fn load_or_create(
+    name: &str,
+    rpc: &impl RpcApi,
+    options: CreateOptions,
+) -> bitcoincore_rpc::Result<WalletStatus> {
+    match rpc.load_wallet(name) {
+        Ok(_) => Ok(WalletStatus::Loaded),
+        Err(e) => match error_code(&e) {
+            Some(RPC_WALLET_ALREADY_LOADED) => Ok(WalletStatus::AlreadyLoaded),
+            Some(RPC_WALLET_NOT_FOUND) => create_wallet(name, rpc, options),
+            Some(RPC_WALLET_ERROR) => loaded_after_race(name, rpc, e),
+            _ => Err(e),
+        },
+    }
+}
+
+fn create_wallet(
+    name: &str,
+    rpc: &impl RpcApi,
+    options: CreateOptions,
+) -> bitcoincore_rpc::Result<WalletStatus> {
+    let blank = options.watch_only.then_some(true);
+    let avoid_reuse = options.avoid_reuse.then_some(true);
+    match rpc.create_wallet(name, blank, blank, None, avoid_reuse) {
+        Ok(_) => Ok(WalletStatus::Created),
+        Err(e) => match error_code(&e) {
+            // Created by someone else since our loadwallet, load theirs
+            Some(RPC_WALLET_ALREADY_EXISTS) => match rpc.load_wallet(name) {
+                Ok(_) => Ok(WalletStatus::Loaded),
+                Err(e) if error_code(&e) == Some(RPC_WALLET_ALREADY_LOADED) => {
+                    Ok(WalletStatus::AlreadyLoaded)
+                }
+                Err(e) => loaded_after_race(name, rpc, e),
+            },
+            Some(RPC_WALLET_ERROR) => loaded_after_race(name, rpc, e),
+            _ => Err(e),
+        },
+    }
+}
+
+fn loaded_after_race(
+    name: &str,
+    rpc: &impl RpcApi,
+    e: bitcoincore_rpc::Error,
+) -> bitcoincore_rpc::Result<WalletStatus> {
+    if rpc.list_wallets()?.iter().any(|w| w == name) {
+        Ok(WalletStatus::AlreadyLoaded)
+    } else {
+        Err(e)
+    }
+}
+
+// Make sure `name` is loaded (creating it if needed) and return a client bound to it
+pub fn open(rpc: &Client, name: &str) -> bitcoincore_rpc::Result<Client> {
+    open_with(rpc, name, CreateOptions::default())
+}
+
+// Like `open`, creating a missing wallet with `options`
+pub fn open_with(
+    rpc: &Client,
+    name: &str,
+    options: CreateOptions,
+) -> bitcoincore_rpc::Result<Client> {
+    if !rpc.list_wallets()?.iter().any(|w| w == name) {
+        load_or_create(name, rpc, options)?;
+    }
+    get_client_at_url(&format!("/wallet/{name}"))
+}
+
+// Create `name` blank: it has private keys, but none until descriptors are imported
+pub fn create_blank(rpc: &Client, name: &str) -> bitcoincore_rpc::Result<Client> {
+    rpc.create_wallet(name, None, Some(true), None, None)?;
+    get_client_at_url(&format!("/wallet/{name}"))
+}
+
+// Like `open`, but a wallet created here has no keys of its own
+pub fn open_watch_only(rpc: &Client, name: &str) -> bitcoincore_rpc::Result<Client> {
+    let options = CreateOptions {
+        watch_only: true,
+        ..Default::default()
+    };
+    open_with(rpc, name, options)
+}
+
+// Turn avoid_reuse on for a wallet created without it. Returns whether it had to be set.
+pub fn set_avoid_reuse(wallet_rpc: &impl RpcApi) -> bitcoincore_rpc::Result<bool> {
+    if wallet_rpc.get_wallet_info()?.avoid_reuse == Some(true) {
+        return Ok(false);
+    }
+    wallet_rpc.call::<serde_json::Value>("setwalletflag", &["avoid_reuse".into(), true.into()])?;
+    Ok(true)
+}
+
+// Put `address` in the wallet's address book under `label`, for addresses it doesn't own
+// too, so the wallet's transaction details name where a payment went
+pub fn set_label(
+    wallet_rpc: &impl RpcApi,
+    address: &Address,
+    label: &str,
+) -> bitcoincore_rpc::Result<()> {
+    wallet_rpc
+        .call::<serde_json::Value>("setlabel", &[address.to_string().into(), label.into()])?;
+    Ok(())
+}
+
+// Label and comment of a transaction as the wallet that has it tells them. gettransaction
+// is read untyped, the typed result has no comment.
+pub fn tx_metadata(wallet_rpc: &impl RpcApi, txid: &Txid) -> bitcoincore_rpc::Result<TxMetadata> {
+    let tx: serde_json::Value = wallet_rpc.call("gettransaction", &[txid.to_string().into()])?;
+    let non_empty = |v: &serde_json::Value| v.as_str().filter(|s| !s.is_empty()).map(str::to_owned);
+    let label = tx["details"]
+        .as_array()
+        .and_then(|details| details.iter().find_map(|d| non_empty(&d["label"])));
+    Ok(TxMetadata {
+        label,
+        comment: non_empty(&tx["comment"]),
+    })
+}
+
+// Unloads a wallet this run loaded itself if the run is cut short by a signal, so an
+// aborted run leaves the node with the wallets it started with
+#[derive(Debug)]
+pub struct WalletGuard {
+    name: String,
+    loaded_here: bool,
+}
+
+impl Drop for WalletGuard {
+    fn drop(&mut self) {
+        if !self.loaded_here || !shutdown::requested() {
+            return;
+        }
+        let unloaded = get_client_at_url("").and_then(|rpc| rpc.unload_wallet(Some(&self.name)));
+        match unloaded {
+            Ok(_) => println!("Unloaded {}", self.name),
+            Err(e) => eprintln!("Could not unload {}: {e}", self.name),
+        }
+    }
+}
+
+// `open_with`, plus a guard that unloads the wallet again on abort unless it was loaded
+// before
+pub fn open_guarded(
+    rpc: &Client,
+    name: &str,
+    options: CreateOptions,
+) -> bitcoincore_rpc::Result<(Client, WalletGuard)> {
+    let loaded_here = !rpc.list_wallets()?.iter().any(|w| w == name);
+    let client = open_with(rpc, name, options)?;
+    let guard = WalletGuard {
+        name: name.to_owned(),
+        loaded_here,
+    };
+    Ok((client, guard))
+}
+
+// The getrawchangeaddress/send change_type matching `address`, None if Core has none
+pub fn change_type_for(address: &Address) -> Option<AddressType> {
+    match address.address_type()? {
+        bitcoin::AddressType::P2pkh => Some(AddressType::Legacy),
+        bitcoin::AddressType::P2sh => Some(AddressType::P2shSegwit),
+        bitcoin::AddressType::P2wpkh | bitcoin::AddressType::P2wsh => Some(AddressType::Bech32),
+        bitcoin::AddressType::P2tr => Some(AddressType::Bech32m),
+        _ => None,
+    }
+}
+
+// e1ec30: Check if address in script belongs to wallet
+pub fn is_mine(rpc: &Client, script: &ScriptBuf) -> bool {
+    let addr = script_to_addr(script);
+    rpc.get_address_info(&addr).unwrap().is_mine.unwrap()
+}
+
+// Block range actually covered by a finished rescan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RescanResult {
+    pub start_height: usize,
+    pub stop_height: Option<usize>,
+}
+
+// Rescan `range` of the chain for transactions belonging to `wallet`.
+//
+// The rescan itself blocks the RPC connection it runs on, so progress is polled from
+// `getwalletinfo.scanning` over a second connection. `progress` gets the fraction done
+// (0.0 to 1.0) on every poll; returning `ControlFlow::Break` aborts the rescan, in which
+// case `Ok(None)` is returned.
+pub fn rescan(
+    wallet: &str,
+    range: impl RangeBounds<usize>,
+    mut progress: impl FnMut(f32) -> ControlFlow<()>,
+) -> bitcoincore_rpc::Result<Option<RescanResult>> {
+    let start = match range.start_bound() {
+        Bound::Included(&h) => Some(h),
+        Bound::Excluded(&h) => Some(h + 1),
+        Bound::Unbounded => None,
+    };
+    let stop = match range.end_bound() {
+        Bound::Included(&h) => Some(h),
+        Bound::Excluded(&h) => Some(h.saturating_sub(1)),
+        Bound::Unbounded => None,
+    };
+
+    let url = format!("/wallet/{wallet}");
+    let scanner = get_client_at_url(&url)?;
+    let monitor = get_client_at_url(&url)?;
+
+    thread::scope(|s| {
+        let scan = s.spawn(|| scanner.rescan_blockchain(start, stop));
+
+        let mut aborted = false;
+        while !scan.is_finished() {
+            thread::sleep(RESCAN_POLL_INTERVAL);
+            if aborted {
+                continue;
+            }
+            // The rescan may finish between the check above and this call
+            if let Some(ScanningDetails::Scanning { progress: p, .. }) =
+                monitor.get_wallet_info()?.scanning
+            {
+                if progress(p).is_break() {
+                    aborted = monitor.call::<bool>("abortrescan", &[])?;
+                }
+            }
+        }
+
+        match scan.join().expect("rescan thread panicked") {
+            Ok((start_height, stop_height)) => {
+                let _ = progress(1.0);
+                Ok(Some(RescanResult {
+                    start_height,
+                    stop_height,
+                }))
+            }
+            Err(_) if aborted => Ok(None),
+            Err(e) => Err(e),
+        }
+    })
+}
+
+// Import descriptors into `wallet` and rescan right away, so watch-only balances show up
+// without having to call rescanblockchain by hand
+pub fn import_descriptors(
+    wallet: &str,
+    descriptors: &[&str],
+    label: Option<&str>,
+) -> bitcoincore_rpc::Result<Vec<ImportMultiResult>> {
+    let rpc = get_client_at_url(&format!("/wallet/{wallet}"))?;
+    // A missing or wrong checksum would fail the whole import
+    let requests = descriptors
+        .iter()
+        .map(|desc| {
+            Ok(ImportDescriptors {
+                descriptor: descriptor::with_checksum(desc)?,
+                // "now" skips the implicit (silent) rescan, we do our own below
+                timestamp: Timestamp::Now,
+                label: label.map(str::to_owned),
+                ..Default::default()
+            })
+        })
+        .collect::<bitcoincore_rpc::Result<Vec<_>>>()?;
+    let results: Vec<ImportMultiResult> =
+        rpc.call("importdescriptors", &[serde_json::to_value(requests)?])?;
+
+    if results.iter().any(|r| r.success) {
+        rescan(wallet, .., |p| {
+            println!("Rescanning {wallet}: {:.0}%", p * 100.0);
+            ControlFlow::Continue(())
+        })?;
+    }
+    Ok(results)
+}
+
+// Tell the wallet `name` (`wallet` being its client) about `txid`, a confirmed payment to
+// one of its addresses that it didn't see arrive, e.g. because the address was imported
+// afterwards. `block_hash` is the confirming block, without it the node has to find the
+// transaction itself, which takes -txindex once it's confirmed. A rescan covers that one
+// block; with `Discovery::PrunedImport` the wallet takes the transaction and its proof
+// instead, so blocks a pruned node no longer has aren't needed. A pruned node can't keep
+// a txindex, so there `block_hash` is required.
+pub fn learn_transaction(
+    node: &impl RpcApi,
+    wallet: &impl RpcApi,
+    name: &str,
+    txid: &Txid,
+    block_hash: Option<BlockHash>,
+    discovery: Discovery,
+) -> bitcoincore_rpc::Result<()> {
+    let block_hash = match block_hash {
+        Some(hash) => hash,
+        None if discovery == Discovery::PrunedImport => {
+            return Err(bitcoincore_rpc::Error::ReturnedError(format!(
+                "a pruned node has no -txindex to find {txid} with, \
+                 pass --block-hash with the block that confirmed it"
+            )));
+        }
+        None => node
+            .get_raw_transaction_info(txid, None)?
+            .blockhash
+            .ok_or_else(|| {
+                bitcoincore_rpc::Error::ReturnedError(format!("{txid} isn't confirmed yet"))
+            })?,
+    };
+    match discovery {
+        Discovery::PrunedImport => {
+            let hex = node.get_raw_transaction_hex(txid, Some(&block_hash))?;
+            let proof: String = node.call(
+                "gettxoutproof",
+                &[vec![txid.to_string()].into(), block_hash.to_string().into()],
+            )?;
+            wallet.call::<serde_json::Value>("importprunedfunds", &[hex.into(), proof.into()])?;
+        }
+        Discovery::Rescan => {
+            let height = node.get_block_header_info(&block_hash)?.height;
+            rescan(name, height..=height, |p| {
+                println!("Rescanning {name}: {:.0}%", p * 100.0);
+                ControlFlow::Continue(())
+            })?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::MockClient;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use serde_json::{json, Value};
+
+    fn loaded(name: &str) -> Value {
+        json!({ "name": name, "warning": "" })
+    }
+
+    #[test]
+    fn loads_existing_wallet() {
+        let rpc = MockClient::new().returns("loadwallet", loaded("Miner"));
+        assert_eq!(
+            load_or_create_wallet("Miner", &rpc).unwrap(),
+            WalletStatus::Loaded
+        );
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn creates_missing_wallet() {
+        let rpc = MockClient::new()
+            .fails("loadwallet", RPC_WALLET_NOT_FOUND, "Path does not exist")
+            .returns("createwallet", loaded("Miner"));
+        assert_eq!(
+            load_or_create_wallet("Miner", &rpc).unwrap(),
+            WalletStatus::Created
+        );
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn already_loaded_is_fine() {
+        let rpc = MockClient::new().fails(
+            "loadwallet",
+            RPC_WALLET_ALREADY_LOADED,
+            "Wallet \"Miner\" is already loaded.",
+        );
+        assert_eq!(
+            load_or_create_wallet("Miner", &rpc).unwrap(),
+            WalletStatus::AlreadyLoaded
+        );
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn concurrent_create_loads_the_other_wallet() {
+        let rpc = MockClient::new()
+            .fails("loadwallet", RPC_WALLET_NOT_FOUND, "Path does not exist")
+            .fails(
+                "createwallet",
+                RPC_WALLET_ALREADY_EXISTS,
+                "Database already exists",
+            )
+            .fails("loadwallet", RPC_WALLET_ALREADY_LOADED, "already loaded");
+        assert_eq!(
+            load_or_create_wallet("Miner", &rpc).unwrap(),
+            WalletStatus::AlreadyLoaded
+        );
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn verification_failure_while_loaded_elsewhere() {
+        let rpc = MockClient::new()
+            .fails(
+                "loadwallet",
+                RPC_WALLET_ERROR,
+                "Wallet file verification failed.",
+            )
+            .returns("listwallets", json!(["Miner"]));
+        assert_eq!(
+            load_or_create_wallet("Miner", &rpc).unwrap(),
+            WalletStatus::AlreadyLoaded
+        );
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn verification_failure_is_reported() {
+        let rpc = MockClient::new()
+            .fails(
+                "loadwallet",
+                RPC_WALLET_ERROR,
+                "Wallet file verification failed.",
+            )
+            .returns("listwallets", json!([]));
+        let err = load_or_create_wallet("Miner", &rpc).unwrap_err();
+        assert_eq!(error_code(&err), Some(RPC_WALLET_ERROR));
+        rpc.assert_done();
+    }
+
+    #[test]
+    fn other_errors_are_not_swallowed() {
+        let rpc = MockClient::new().fails("loadwallet", -28, "Loading block index...");
+        let err = load_or_create_wallet("Miner", &rpc).unwrap_err();
+        assert_eq!(error_code(&err), Some(-28));
+        assert_eq!(rpc.calls(), ["loadwallet"]);
+    }
+
+    #[test]
+    fn reads_label_and_comment_back() {
+        let rpc = MockClient::new().returns(
+            "gettransaction",
+            json!({
+                "comment": "first of three",
+                "details": [{ "category": "send", "label": "" }, { "category": "send", "label": "rent" }],
+            }),
+        );
+        let metadata = tx_metadata(&rpc, &Txid::all_zeros()).unwrap();
+        assert_eq!(metadata.label.as_deref(), Some("rent"));
+        assert_eq!(metadata.comment.as_deref(), Some("first of three"));
+
+        let rpc = MockClient::new().returns("gettransaction", json!({ "details": [] }));
+        assert!(tx_metadata(&rpc, &Txid::all_zeros()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pruned_nodes_import_with_a_proof() {
+        let txid = Txid::all_zeros();
+        let node = MockClient::new()
+            .returns("getrawtransaction", json!("0200"))
+            .returns("gettxoutproof", json!("00ff"));
+        let wallet = MockClient::new().returns("importprunedfunds", Value::Null);
+        learn_transaction(
+            &node,
+            &wallet,
+            "Trader",
+            &txid,
+            Some(BlockHash::all_zeros()),
+            Discovery::PrunedImport,
+        )
+        .unwrap();
+        node.assert_done();
+        wallet.assert_done();
+        assert_eq!(wallet.args(0), vec![json!("0200"), json!("00ff")]);
+        assert_eq!(node.args(1)[0], json!([txid]));
+    }
+
+    #[test]
+    fn pruned_nodes_need_the_block_hash() {
+        let node = MockClient::new();
+        let wallet = MockClient::new();
+        let error = learn_transaction(
+            &node,
+            &wallet,
+            "Trader",
+            &Txid::all_zeros(),
+            None,
+            Discovery::PrunedImport,
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("--block-hash"), "{error}");
+        assert!(node.calls().is_empty());
+        assert!(wallet.calls().is_empty());
+    }
+}
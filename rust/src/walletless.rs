@@ -0,0 +1,392 @@
+use bitcoincore_rpc::bitcoin::bip32::DerivationPath;
+use bitcoincore_rpc::bitcoin::consensus::encode;
+use bitcoincore_rpc::bitcoin::ecdsa;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::secp256k1::{rand, Message, Secp256k1, SecretKey};
+use bitcoincore_rpc::bitcoin::sighash::{EcdsaSighashType, SighashCache};
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, BlockHash, Network, OutPoint, PublicKey, ScriptBuf, Transaction, TxOut, Txid,
+    Witness,
+};
+use bitcoincore_rpc::json::ScanTxOutRequest;
+use bitcoincore_rpc::{Client, RpcApi};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::audit;
+use crate::builder::TxBuilder;
+use crate::coins::{self, FEE_HEADROOM};
+use crate::config;
+use crate::consensus::{self, blocks_to_earn};
+use crate::error::{Error, Result};
+use crate::hd::{AccountManager, KeyChain, Purpose};
+use crate::shutdown::{self, Phase};
+
+// A key held here instead of in a Core wallet, paying to its P2WPKH address (or, from
+// `taproot`, its BIP86 key path address, which `sign` can't spend)
+#[derive(Debug, Clone)]
+pub struct LocalKey {
+    secret: SecretKey,
+    pub public: PublicKey,
+    pub address: Address,
+}
+
+impl LocalKey {
+    pub fn generate(network: Network) -> LocalKey {
+        let (secret, _) = Secp256k1::new().generate_keypair(&mut rand::thread_rng());
+        LocalKey::from_secret(secret, network)
+    }
+
+    pub fn from_secret(secret: SecretKey, network: Network) -> LocalKey {
+        let public = PublicKey::new(secret.public_key(&Secp256k1::new()));
+        LocalKey {
+            secret,
+            public,
+            // Keys from secp256k1 are always compressed, as P2WPKH needs
+            address: Address::p2wpkh(&public, network).expect("compressed public key"),
+        }
+    }
+
+    pub fn taproot(secret: SecretKey, network: Network) -> LocalKey {
+        let secp = Secp256k1::new();
+        let public = PublicKey::new(secret.public_key(&secp));
+        LocalKey {
+            secret,
+            public,
+            address: Address::p2tr(&secp, public.inner.into(), None, network),
+        }
+    }
+
+    pub fn script_pubkey(&self) -> ScriptBuf {
+        self.address.script_pubkey()
+    }
+}
+
+// An output paying a local key, as scantxoutset finds it
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalUtxo {
+    pub outpoint: OutPoint,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub amount: Amount,
+    pub height: u64,
+}
+
+impl LocalUtxo {
+    // scantxoutset here doesn't say which outputs are coinbases, so every output waits as
+    // long as a coinbase would
+    pub fn is_mature(&self, tip: u64) -> bool {
+        tip + 1 >= self.height + consensus::params().coinbase_maturity
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct WalletlessReport {
+    pub txid: Txid,
+    pub sender_address: Address,
+    pub recipient_address: Address,
+    pub change_address: Address,
+    // Where the keys came from, with an account manager
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub derivation_paths: Option<DerivationPaths>,
+    pub inputs: Vec<OutPoint>,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub input_amount: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub payment: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub change: Amount,
+    #[serde(with = "bitcoincore_rpc::bitcoin::amount::serde::as_btc")]
+    pub fee: Amount,
+    pub block_height: u64,
+    pub block_hash: BlockHash,
+    // What scantxoutset shows the recipient holding afterwards
+    pub recipient_utxos: Vec<LocalUtxo>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DerivationPaths {
+    pub sender: DerivationPath,
+    pub change: DerivationPath,
+    pub recipient: DerivationPath,
+}
+
+// Sign every input of `tx` spending `key`'s script, `prevouts` being the outputs the
+// inputs spend in order (BIP143 signs the value). Returns how many inputs were signed.
+pub fn sign(tx: &mut Transaction, prevouts: &[TxOut], key: &LocalKey) -> Result<usize> {
+    sign_with(tx, prevouts, key, EcdsaSighashType::All)
+}
+
+// `sign` with another sighash flag, e.g. to leave outputs or other inputs open to change
+pub fn sign_with(
+    tx: &mut Transaction,
+    prevouts: &[TxOut],
+    key: &LocalKey,
+    sighash_type: EcdsaSighashType,
+) -> Result<usize> {
+    let secp = Secp256k1::new();
+    let script = key.script_pubkey();
+    let mut witnesses = vec![];
+    let mut cache = SighashCache::new(&*tx);
+    for (index, prevout) in prevouts.iter().enumerate() {
+        if prevout.script_pubkey != script {
+            continue;
+        }
+        let sighash = cache
+            .p2wpkh_signature_hash(index, &script, prevout.value, sighash_type)
+            .map_err(|e| bitcoincore_rpc::Error::ReturnedError(format!("input {index}: {e}")))?;
+        let message = Message::from_digest(sighash.to_byte_array());
+        let signature = ecdsa::Signature {
+            sig: secp.sign_ecdsa(&message, &key.secret),
+            hash_ty: sighash_type,
+        };
+        witnesses.push((index, Witness::p2wpkh(&signature, &key.public.inner)));
+    }
+    let signed = witnesses.len();
+    let mut inputs_signed = vec![];
+    for (index, witness) in witnesses {
+        tx.input[index].witness = witness;
+        inputs_signed.push(tx.input[index].previous_output);
+    }
+    audit::note(audit::Record {
+        timestamp: 0,
+        wallet: format!("key:{}", key.address),
+        method: format!("sign ({sighash_type})"),
+        txid: Some(tx.txid()),
+        psbt_sha256: None,
+        complete: signed == tx.input.len(),
+        inputs_signed: Some(inputs_signed),
+        previous: None,
+    });
+    Ok(signed)
+}
+
+// Outputs currently paying `address`, from the UTXO set rather than a wallet
+pub fn find_utxos(rpc: &impl RpcApi, address: &Address) -> Result<Vec<LocalUtxo>> {
+    let request = ScanTxOutRequest::Single(format!("addr({address})"));
+    let result = rpc.scan_tx_out_set_blocking(&[request])?;
+    Ok(result
+        .unspents
+        .into_iter()
+        .map(|u| LocalUtxo {
+            outpoint: OutPoint::new(u.txid, u.vout),
+            amount: u.amount,
+            height: u.height,
+        })
+        .collect())
+}
+
+// testmempoolaccept's verdict on `tx` and why it was rejected, without the fee rate cap so
+// only validity and policy decide
+pub fn test_accept(rpc: &Client, tx: &Transaction) -> Result<(bool, Option<String>)> {
+    let results: Vec<Value> = rpc.call(
+        "testmempoolaccept",
+        &[json!([encode::serialize_hex(tx)]), json!(0)],
+    )?;
+    let result = results.first().cloned().unwrap_or_default();
+    Ok((
+        result["allowed"].as_bool().unwrap_or(false),
+        result["reject-reason"].as_str().map(str::to_owned),
+    ))
+}
+
+// The capstone payment without any Core wallet: keys are made here, coinbases mined to the
+// sender's address are found with scantxoutset, the payment is built and signed here and
+// goes out with sendrawtransaction. Only chain RPCs are used.
+//
+// Keys are throwaway ones unless `accounts` is given: then the sender is the next receive
+// address of BIP84 account 0, change goes to that account's change chain and the recipient
+// is account 1. The used indexes are saved before anything is mined to them.
+pub fn run(
+    rpc: &Client,
+    amount: Amount,
+    accounts: Option<&mut AccountManager>,
+) -> Result<WalletlessReport> {
+    shutdown::enter(Phase::Setup);
+    let network = config::active().network;
+    let (sender, change_key, recipient, derivation_paths) = match accounts {
+        Some(accounts) => {
+            let sender = accounts.next(Purpose::Bip84, 0, KeyChain::Receive)?;
+            let change = accounts.next(Purpose::Bip84, 0, KeyChain::Change)?;
+            let recipient = accounts.next(Purpose::Bip84, 1, KeyChain::Receive)?;
+            accounts.save()?;
+            let paths = DerivationPaths {
+                sender: sender.path,
+                change: change.path,
+                recipient: recipient.path,
+            };
+            (sender.key, change.key, recipient.key, Some(paths))
+        }
+        None => {
+            let sender = LocalKey::generate(network);
+            (sender.clone(), sender, LocalKey::generate(network), None)
+        }
+    };
+    let target = amount + FEE_HEADROOM;
+
+    shutdown::check()?;
+    shutdown::enter(Phase::Mining);
+    // Enough coinbases to cover the payment, and enough blocks on top for them to mature
+    let tip = rpc.get_block_count()?;
+    let needed = blocks_to_earn(tip + 1, target).ok_or(Error::InsufficientFunds {
+        wallet: sender.address.to_string(),
+        needed: target,
+        available: Amount::ZERO,
+    })?;
+    rpc.generate_to_address(
+        needed + consensus::params().coinbase_maturity,
+        &sender.address,
+    )?;
+    let tip = rpc.get_block_count()?;
+
+    let mut mature: Vec<LocalUtxo> = find_utxos(rpc, &sender.address)?
+        .into_iter()
+        .filter(|u| u.is_mature(tip))
+        .collect();
+    mature.sort_by_key(|u| std::cmp::Reverse(u.amount));
+    let mut selected = vec![];
+    let mut input_amount = Amount::ZERO;
+    for utxo in mature {
+        if input_amount >= target {
+            break;
+        }
+        input_amount += utxo.amount;
+        selected.push(utxo);
+    }
+    if input_amount < target {
+        return Err(Error::InsufficientFunds {
+            wallet: sender.address.to_string(),
+            needed: target,
+            available: input_amount,
+        });
+    }
+
+    shutdown::check()?;
+    shutdown::enter(Phase::Sending);
+    let mut builder = TxBuilder::new()
+        .pay(&recipient.address, amount)
+        .change_to(&change_key.address)
+        .fee_rate(coins::current_fee_rate(rpc)?);
+    for utxo in &selected {
+        builder = builder.spend_output(utxo.outpoint, utxo.amount);
+    }
+    let mut tx = builder.build()?;
+    let prevouts: Vec<TxOut> = selected
+        .iter()
+        .map(|u| TxOut {
+            value: u.amount,
+            script_pubkey: sender.script_pubkey(),
+        })
+        .collect();
+    sign(&mut tx, &prevouts, &sender)?;
+    let txid = rpc.send_raw_transaction(&tx)?;
+
+    shutdown::enter(Phase::Confirming);
+    let block_hash = rpc.generate_to_address(1, &sender.address)?[0];
+    let block = rpc.get_block(&block_hash)?;
+    if !block.txdata.iter().any(|t| t.txid() == txid) {
+        return Err(Error::Unconfirmed { txid, depth: 1 });
+    }
+
+    let change = tx
+        .output
+        .iter()
+        .filter(|o| o.script_pubkey == change_key.script_pubkey())
+        .map(|o| o.value)
+        .sum();
+    Ok(WalletlessReport {
+        txid,
+        inputs: selected.iter().map(|u| u.outpoint).collect(),
+        input_amount,
+        payment: amount,
+        change,
+        fee: input_amount - amount - change,
+        block_height: tip + 1,
+        block_hash,
+        recipient_utxos: find_utxos(rpc, &recipient.address)?,
+        sender_address: sender.address,
+        recipient_address: recipient.address,
+        change_address: change_key.address,
+        derivation_paths,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::absolute::LockTime;
+    use bitcoincore_rpc::bitcoin::transaction::Version;
+    use bitcoincore_rpc::bitcoin::{Sequence, TxIn};
+
+    fn key(byte: u8) -> LocalKey {
+        LocalKey::from_secret(
+            SecretKey::from_slice(&[byte; 32]).unwrap(),
+            Network::Regtest,
+        )
+    }
+
+    fn spend(prevouts: &[TxOut]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: (0..prevouts.len() as u32)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint::new(Txid::all_zeros(), vout),
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: key(2).script_pubkey(),
+            }],
+        }
+    }
+
+    #[test]
+    fn signs_only_its_own_inputs() {
+        let ours = key(1);
+        let prevouts = [
+            TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: ours.script_pubkey(),
+            },
+            TxOut {
+                value: Amount::from_sat(50_000),
+                script_pubkey: key(3).script_pubkey(),
+            },
+        ];
+        let mut tx = spend(&prevouts);
+        assert_eq!(sign(&mut tx, &prevouts, &ours).unwrap(), 1);
+        assert!(tx.input[1].witness.is_empty());
+
+        // The witness is <signature> <pubkey>, and the signature commits to the sighash
+        let witness = &tx.input[0].witness;
+        assert_eq!(witness.len(), 2);
+        assert_eq!(witness.nth(1).unwrap(), ours.public.to_bytes());
+        let signature = ecdsa::Signature::from_slice(witness.nth(0).unwrap()).unwrap();
+        let sighash = SighashCache::new(&tx)
+            .p2wpkh_signature_hash(
+                0,
+                &ours.script_pubkey(),
+                prevouts[0].value,
+                EcdsaSighashType::All,
+            )
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        Secp256k1::new()
+            .verify_ecdsa(&message, &signature.sig, &ours.public.inner)
+            .unwrap();
+    }
+
+    #[test]
+    fn waits_for_coinbase_maturity() {
+        let utxo = LocalUtxo {
+            outpoint: OutPoint::null(),
+            amount: Amount::ONE_BTC,
+            height: 10,
+        };
+        assert!(!utxo.is_mature(108));
+        assert!(utxo.is_mature(109));
+    }
+}
@@ -0,0 +1,175 @@
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::{BlockHash, Work};
+use bitcoincore_rpc::RpcApi;
+use serde::Serialize;
+
+// Blocks looked at by default, a day's worth on mainnet
+pub const DEFAULT_WINDOW: u64 = 144;
+
+// Difficulty, target and chain work at the tip, and how fast the last `window` blocks came
+#[derive(Debug, Serialize)]
+pub struct WorkReport {
+    pub tip_height: u64,
+    pub tip_hash: BlockHash,
+    // The window is [from_height, tip_height]
+    pub from_height: u64,
+    pub blocks: usize,
+    // The tip's nBits and the target they encode, in hex
+    pub bits: String,
+    pub target: String,
+    pub difficulty: f64,
+    // Expected number of hashes behind the whole chain, as the node reports it, in hex
+    pub chainwork: String,
+    // The same for the window, summed up from the headers here
+    pub window_work: String,
+    // Whether the node's chainwork grew by exactly `window_work` over the window
+    pub chainwork_matches: bool,
+    #[serde(flatten)]
+    pub intervals: Intervals,
+    // Hashes per second it took to find the window's blocks in the time they took
+    pub estimated_hashrate: Option<f64>,
+}
+
+// Seconds between consecutive block timestamps. These can be negative: a timestamp only
+// has to be above the median of the previous eleven.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Intervals {
+    pub average_interval: Option<f64>,
+    pub min_interval: Option<i64>,
+    pub max_interval: Option<i64>,
+}
+
+// `headers` oldest first
+pub fn intervals(headers: &[Header]) -> Intervals {
+    let gaps: Vec<i64> = headers
+        .windows(2)
+        .map(|pair| i64::from(pair[1].time) - i64::from(pair[0].time))
+        .collect();
+    if gaps.is_empty() {
+        return Intervals::default();
+    }
+    Intervals {
+        average_interval: Some(gaps.iter().sum::<i64>() as f64 / gaps.len() as f64),
+        min_interval: gaps.iter().min().copied(),
+        max_interval: gaps.iter().max().copied(),
+    }
+}
+
+// Work of all `headers` together
+pub fn total_work(headers: &[Header]) -> Work {
+    headers
+        .iter()
+        .map(Header::work)
+        .fold(Work::from_be_bytes([0; 32]), |total, work| total + work)
+}
+
+// The work done finding every block but the first (which was found before the window's
+// clock starts) over the time from the first to the last, None without positive time
+pub fn estimated_hashrate(headers: &[Header]) -> Option<f64> {
+    let (first, last) = (headers.first()?, headers.last()?);
+    let elapsed = i64::from(last.time) - i64::from(first.time);
+    if elapsed <= 0 {
+        return None;
+    }
+    let work = total_work(&headers[1..]);
+    Some(work.log2().exp2() / elapsed as f64)
+}
+
+pub fn report(rpc: &impl RpcApi, window: u64) -> bitcoincore_rpc::Result<WorkReport> {
+    let tip_hash = rpc.get_best_block_hash()?;
+    let tip = rpc.get_block_header_info(&tip_hash)?;
+    let tip_height = tip.height as u64;
+    let from_height = (tip_height + 1).saturating_sub(window.max(1));
+
+    // Walked back from the tip so a reorg in between can't mix two chains
+    let mut headers = vec![];
+    let mut hash = tip_hash;
+    loop {
+        let header = rpc.get_block_header(&hash)?;
+        let prev = header.prev_blockhash;
+        headers.push(header);
+        if tip_height + 1 - headers.len() as u64 == from_height {
+            break;
+        }
+        hash = prev;
+    }
+    headers.reverse();
+
+    let chainwork = chainwork(&tip.chainwork)?;
+    let before = match from_height {
+        0 => Work::from_be_bytes([0; 32]),
+        _ => chainwork_at(rpc, &headers[0].prev_blockhash)?,
+    };
+    let window_work = total_work(&headers);
+    let target = headers[headers.len() - 1].target();
+
+    Ok(WorkReport {
+        tip_height,
+        tip_hash,
+        from_height,
+        blocks: headers.len(),
+        bits: format!("{:08x}", headers[headers.len() - 1].bits.to_consensus()),
+        target: format!("{target:064x}"),
+        difficulty: target.difficulty_float(),
+        chainwork: format!("{chainwork:x}"),
+        window_work: format!("{window_work:x}"),
+        chainwork_matches: before + window_work == chainwork,
+        intervals: intervals(&headers),
+        estimated_hashrate: estimated_hashrate(&headers),
+    })
+}
+
+fn chainwork_at(rpc: &impl RpcApi, hash: &BlockHash) -> bitcoincore_rpc::Result<Work> {
+    chainwork(&rpc.get_block_header_info(hash)?.chainwork)
+}
+
+fn chainwork(bytes: &[u8]) -> bitcoincore_rpc::Result<Work> {
+    let bytes: [u8; 32] = bytes.try_into().map_err(|_| {
+        bitcoincore_rpc::Error::ReturnedError(format!(
+            "chainwork is {} bytes, expected 32",
+            bytes.len()
+        ))
+    })?;
+    Ok(Work::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincore_rpc::bitcoin::block::Version;
+    use bitcoincore_rpc::bitcoin::hashes::Hash;
+    use bitcoincore_rpc::bitcoin::{CompactTarget, TxMerkleNode};
+
+    // Regtest's minimum difficulty, every block is worth two hashes
+    fn header(time: u32) -> Header {
+        Header {
+            version: Version::TWO,
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::all_zeros(),
+            time,
+            bits: CompactTarget::from_consensus(0x207fffff),
+            nonce: 0,
+        }
+    }
+
+    #[test]
+    fn measures_intervals_including_backwards_ones() {
+        let headers: Vec<Header> = [100, 700, 650, 1300].map(header).into();
+        let stats = intervals(&headers);
+        assert_eq!(stats.average_interval, Some(400.0));
+        assert_eq!(stats.min_interval, Some(-50));
+        assert_eq!(stats.max_interval, Some(650));
+        assert_eq!(intervals(&headers[..1]), Intervals::default());
+    }
+
+    #[test]
+    fn sums_work_and_estimates_hashrate() {
+        let headers: Vec<Header> = [0, 1, 2].map(header).into();
+        let mut six = [0; 32];
+        six[31] = 6;
+        assert_eq!(total_work(&headers), Work::from_be_bytes(six));
+        let rate = estimated_hashrate(&headers).unwrap();
+        assert!((rate - 2.0).abs() < 1e-9, "{rate}");
+        assert_eq!(estimated_hashrate(&[header(5), header(5)]), None);
+    }
+}